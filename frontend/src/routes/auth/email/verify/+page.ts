@@ -0,0 +1,32 @@
+import {initializeI18n} from '$lib/i18n';
+import {addFlash} from '$lib/stores';
+
+import {goto} from '$app/navigation';
+
+import {apiVerifyEmail} from './api';
+
+export async function load({fetch, url}): Promise<void> {
+    const {i18n} = initializeI18n();
+
+    const result = await apiVerifyEmail(url.searchParams.get('token') ?? '', fetch);
+    if (result.success) {
+        // Verification successful! Add flash.
+        addFlash({
+            message: i18n.t('auth.prose--email-verified'),
+            severity: 'success',
+            icon: 'fa-circle-check',
+        });
+    } else {
+        // Verification failed, e.g. because the link has expired
+        addFlash({
+            message: i18n.t('auth.prose--email-verification-error', {
+                message: result.errorDescription,
+            }),
+            severity: 'error',
+            icon: 'fa-circle-exclamation',
+        });
+    }
+
+    // Redirect to home
+    await goto('/', {invalidateAll: true, replaceState: true});
+}
@@ -0,0 +1,39 @@
+import {error} from '@sveltejs/kit';
+
+import type {SvelteKitFetch} from '$lib';
+import {apiPost, extractApiError, extractResponseError, type ApiSuccessOrError} from '$lib/api';
+import {ensureClientOrServerErrorCode} from '$lib/errors';
+
+/**
+ * Verify e-mail address with a token from a verification link via API.
+ */
+export async function apiVerifyEmail(
+    token: string,
+    fetch: SvelteKitFetch,
+): Promise<ApiSuccessOrError> {
+    const res = await apiPost('/api/v1/auth/email/verify', {token}, fetch);
+    switch (res.status) {
+        case 204:
+            return {success: true};
+        case 422: {
+            try {
+                const apiError = await extractApiError(res);
+                return {
+                    success: false,
+                    errorReason: apiError.error.reason,
+                    errorDescription: apiError.error.description,
+                };
+            } catch (e) {
+                throw error(
+                    ensureClientOrServerErrorCode(res.status),
+                    `Could not verify e-mail address: Unknown error response`,
+                );
+            }
+        }
+        default:
+            throw error(
+                ensureClientOrServerErrorCode(res.status),
+                `Could not verify e-mail address: ${await extractResponseError(res)}`,
+            );
+    }
+}
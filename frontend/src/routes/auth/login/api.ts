@@ -5,6 +5,11 @@ import {ensureClientOrServerErrorCode} from '$lib/errors';
 
 export interface LoginResult {
     readonly success: boolean;
+    /**
+     * Whether a two-factor authentication code must be submitted with {@link apiLoginTotp} to
+     * complete the login.
+     */
+    readonly totpRequired?: boolean;
 }
 
 /**
@@ -18,6 +23,8 @@ export async function apiLogin(username: string, password: string): Promise<Logi
     switch (res.status) {
         case 204:
             return {success: true};
+        case 202:
+            return {success: false, totpRequired: true};
         case 403:
             return {success: false};
         default:
@@ -27,3 +34,27 @@ export async function apiLogin(username: string, password: string): Promise<Logi
             );
     }
 }
+
+/**
+ * Complete a login with a two-factor authentication code (or a recovery code) via API.
+ */
+export async function apiLoginTotp(code: string): Promise<LoginResult> {
+    const trimmed = code.trim();
+    // TOTP codes consist of 6 digits, everything else is treated as recovery code
+    const body = /^\d{6}$/u.test(trimmed) ? {code: trimmed} : {recoveryCode: trimmed};
+    const res = await apiPost('/api/v1/auth/login/totp', body);
+    switch (res.status) {
+        case 204:
+            return {success: true};
+        case 403:
+            return {success: false, totpRequired: true};
+        case 401:
+            // The pending login expired or was invalidated after too many wrong codes
+            return {success: false};
+        default:
+            throw error(
+                ensureClientOrServerErrorCode(res.status),
+                `Could not log in: ${await extractResponseError(res)}`,
+            );
+    }
+}
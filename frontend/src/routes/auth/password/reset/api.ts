@@ -0,0 +1,65 @@
+import {error} from '@sveltejs/kit';
+
+import {apiPost, extractApiError, extractResponseError, type ApiSuccessOrError} from '$lib/api';
+import {ensureClientOrServerErrorCode} from '$lib/errors';
+
+/**
+ * Request a password reset link via API.
+ *
+ * Note: The API does not reveal whether an account with this e-mail address exists.
+ */
+export async function apiRequestPasswordReset(email: string): Promise<ApiSuccessOrError> {
+    const res = await apiPost('/api/v1/auth/password/reset/request', {email});
+    switch (res.status) {
+        case 204:
+            return {success: true};
+        case 429:
+            return {
+                success: false,
+                errorReason: 'TooManyRequests',
+                errorDescription: 'Too many requests',
+            };
+        default:
+            throw error(
+                ensureClientOrServerErrorCode(res.status),
+                `Could not request password reset: ${await extractResponseError(res)}`,
+            );
+    }
+}
+
+/**
+ * Reset password with a token from a password reset link via API.
+ */
+export async function apiResetPassword(
+    token: string,
+    newPassword: string,
+): Promise<ApiSuccessOrError> {
+    const res = await apiPost('/api/v1/auth/password/reset', {
+        token,
+        newPassword,
+    });
+    switch (res.status) {
+        case 204:
+            return {success: true};
+        case 422: {
+            try {
+                const apiError = await extractApiError(res);
+                return {
+                    success: false,
+                    errorReason: apiError.error.reason,
+                    errorDescription: apiError.error.description,
+                };
+            } catch (e) {
+                throw error(
+                    ensureClientOrServerErrorCode(res.status),
+                    `Could not reset password: Unknown error response`,
+                );
+            }
+        }
+        default:
+            throw error(
+                ensureClientOrServerErrorCode(res.status),
+                `Could not reset password: ${await extractResponseError(res)}`,
+            );
+    }
+}
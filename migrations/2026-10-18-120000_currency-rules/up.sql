@@ -0,0 +1,15 @@
+-- Currency rules: Minimum number of flights and/or airtime within a time window
+CREATE TABLE currency_rules (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    -- Minimum number of flights within the window
+    min_flights INTEGER NULL CHECK (min_flights > 0),
+    -- Minimum airtime in seconds within the window
+    min_seconds INTEGER NULL CHECK (min_seconds > 0),
+    -- Length of the window in days, counted back from today
+    window_days INTEGER NOT NULL CHECK (window_days > 0),
+    -- If set, only flights with this glider are counted
+    glider_id INTEGER NULL REFERENCES gliders(id) ON DELETE CASCADE,
+    CHECK (min_flights IS NOT NULL OR min_seconds IS NOT NULL)
+);
@@ -0,0 +1,2 @@
+DROP TABLE glider_maintenance_intervals;
+DROP TABLE glider_maintenance_events;
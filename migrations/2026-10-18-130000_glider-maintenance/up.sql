@@ -0,0 +1,25 @@
+-- Maintenance events (e.g. a check or trim) performed on a glider
+CREATE TABLE glider_maintenance_events (
+    id SERIAL PRIMARY KEY,
+    glider_id INTEGER NOT NULL REFERENCES gliders(id) ON DELETE CASCADE,
+    kind TEXT NOT NULL CHECK (kind IN ('check', 'trim', 'reserve_repack')),
+    date DATE NOT NULL,
+    notes TEXT NULL
+);
+CREATE INDEX glider_maintenance_events_glider_id_idx ON glider_maintenance_events(glider_id);
+
+-- Maintenance intervals per glider. If multiple limits are set, whichever
+-- is reached first makes the maintenance due.
+CREATE TABLE glider_maintenance_intervals (
+    id SERIAL PRIMARY KEY,
+    glider_id INTEGER NOT NULL REFERENCES gliders(id) ON DELETE CASCADE,
+    kind TEXT NOT NULL CHECK (kind IN ('check', 'trim', 'reserve_repack')),
+    -- Airtime in seconds since the last event of this kind
+    interval_seconds INTEGER NULL CHECK (interval_seconds > 0),
+    -- Number of flights since the last event of this kind
+    interval_flights INTEGER NULL CHECK (interval_flights > 0),
+    -- Calendar months since the last event of this kind
+    interval_months INTEGER NULL CHECK (interval_months > 0),
+    CHECK (interval_seconds IS NOT NULL OR interval_flights IS NOT NULL OR interval_months IS NOT NULL),
+    UNIQUE (glider_id, kind)
+);
@@ -0,0 +1,3 @@
+DROP TABLE equipment_repacks;
+DROP TABLE flight_equipment;
+DROP TABLE equipment;
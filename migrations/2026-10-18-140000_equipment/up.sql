@@ -0,0 +1,38 @@
+-- Equipment other than gliders (e.g. harness or reserve parachute)
+CREATE TABLE equipment (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    kind TEXT NOT NULL CHECK (kind IN ('harness', 'reserve', 'instrument', 'helmet', 'speed_system')),
+    manufacturer VARCHAR(255) NOT NULL,
+    model VARCHAR(255) NOT NULL,
+    -- When was the equipment acquired?
+    since DATE,
+    -- When was the equipment sold / given away / thrown away?
+    until DATE,
+    -- Where did you get the equipment from? (e.g. a shop, or a website)
+    source TEXT,
+    -- How much did the equipment cost, in your currency?
+    cost INTEGER,
+    -- Add arbitrary comments about this equipment
+    comment TEXT,
+    -- Reserves only: Number of months between two repacks
+    repack_interval_months INTEGER CHECK (repack_interval_months > 0),
+    CONSTRAINT equipment_unique_per_user UNIQUE (user_id, kind, manufacturer, model)
+);
+
+-- Equipment used during a flight
+CREATE TABLE flight_equipment (
+    flight_id INTEGER NOT NULL REFERENCES flights(id) ON DELETE CASCADE,
+    equipment_id INTEGER NOT NULL REFERENCES equipment(id) ON DELETE CASCADE,
+    PRIMARY KEY (flight_id, equipment_id)
+);
+CREATE INDEX flight_equipment_equipment_id_idx ON flight_equipment(equipment_id);
+
+-- Reserve repacks
+CREATE TABLE equipment_repacks (
+    id SERIAL PRIMARY KEY,
+    equipment_id INTEGER NOT NULL REFERENCES equipment(id) ON DELETE CASCADE,
+    date DATE NOT NULL,
+    notes TEXT
+);
+CREATE INDEX equipment_repacks_equipment_id_idx ON equipment_repacks(equipment_id);
@@ -0,0 +1,14 @@
+-- Personal API tokens, used for scripted access via "Authorization: Bearer"
+CREATE TABLE api_tokens (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    -- Hex encoded SHA-256 hash of the token. The token itself is never stored.
+    token_hash TEXT NOT NULL UNIQUE,
+    -- The first few characters of the token, to help identify it
+    token_prefix TEXT NOT NULL,
+    scopes TEXT[] NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    last_used_at TIMESTAMP WITH TIME ZONE NULL
+);
+CREATE INDEX api_tokens_user_id_idx ON api_tokens(user_id);
@@ -0,0 +1,10 @@
+-- Single-use tokens for resetting a forgotten password
+CREATE TABLE password_reset_tokens (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- Hex encoded SHA-256 hash of the token. The token itself is never stored.
+    token_hash TEXT NOT NULL UNIQUE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
+CREATE INDEX password_reset_tokens_user_id_idx ON password_reset_tokens(user_id);
@@ -0,0 +1,15 @@
+ALTER TABLE users ADD COLUMN email_verified BOOLEAN NOT NULL DEFAULT false;
+
+-- Single-use tokens for confirming an e-mail address. When confirmed, the
+-- address is stored as the user's e-mail address and marked as verified.
+CREATE TABLE email_verification_tokens (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- The e-mail address to be confirmed
+    email TEXT NOT NULL,
+    -- Hex encoded SHA-256 hash of the token. The token itself is never stored.
+    token_hash TEXT NOT NULL UNIQUE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
+CREATE INDEX email_verification_tokens_user_id_idx ON email_verification_tokens(user_id);
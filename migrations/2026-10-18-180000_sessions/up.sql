@@ -0,0 +1,14 @@
+-- Server-side login sessions. The session cookie contains the token.
+CREATE TABLE sessions (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- Hex encoded SHA-256 hash of the token. The token itself is never stored.
+    token_hash TEXT NOT NULL UNIQUE,
+    -- User agent of the client that logged in
+    user_agent TEXT NULL,
+    -- IP address of the client that logged in
+    ip_address TEXT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    last_seen_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+CREATE INDEX sessions_user_id_idx ON sessions(user_id);
@@ -0,0 +1,8 @@
+-- Failed login attempts, used by the login rate limiter (if configured to
+-- use the database store). Keys identify a client IP or a username.
+CREATE TABLE login_attempts (
+    key TEXT PRIMARY KEY,
+    failures INTEGER NOT NULL,
+    last_failure_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    locked_until TIMESTAMP WITH TIME ZONE NULL
+);
@@ -0,0 +1,21 @@
+-- TOTP two-factor authentication. A credential is created on enrollment and
+-- is only enabled once the user has confirmed a code.
+CREATE TABLE totp_credentials (
+    user_id INTEGER PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    -- Base32 encoded shared secret
+    secret TEXT NOT NULL,
+    enabled BOOLEAN NOT NULL DEFAULT false,
+    -- The last time step that was used to log in, prevents code reuse
+    last_used_step BIGINT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+
+-- Single-use recovery codes, used to log in without the TOTP device
+CREATE TABLE totp_recovery_codes (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- Hex encoded SHA-256 hash of the code
+    code_hash TEXT NOT NULL,
+    used_at TIMESTAMP WITH TIME ZONE NULL
+);
+CREATE INDEX totp_recovery_codes_user_id_idx ON totp_recovery_codes(user_id);
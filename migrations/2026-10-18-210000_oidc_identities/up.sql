@@ -0,0 +1,12 @@
+-- External OpenID Connect identities linked to a user.
+CREATE TABLE oidc_identities (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- Issuer URL of the identity provider
+    issuer TEXT NOT NULL,
+    -- Subject identifier ("sub" claim), unique per issuer
+    subject TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    UNIQUE (issuer, subject)
+);
+CREATE INDEX oidc_identities_user_id_idx ON oidc_identities(user_id);
@@ -0,0 +1,5 @@
+ALTER TABLE users
+    -- Administrators can access the administration API
+    ADD COLUMN is_admin BOOLEAN NOT NULL DEFAULT false,
+    -- Disabled users cannot log in
+    ADD COLUMN disabled BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,2 @@
+ALTER TABLE locations DROP COLUMN site_id;
+DROP TABLE sites;
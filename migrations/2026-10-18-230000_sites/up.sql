@@ -0,0 +1,16 @@
+-- Shared catalog of public launch and landing sites.
+CREATE TABLE sites (
+    id SERIAL PRIMARY KEY,
+    name TEXT NOT NULL,
+    country CHAR(2) NOT NULL,
+    elevation INTEGER NOT NULL,
+    geog geography(POINT) NOT NULL,
+    -- Whether the site is used for launching, landing or both
+    site_type TEXT NOT NULL DEFAULT 'both' CHECK (site_type IN ('launch', 'landing', 'both')),
+    -- Alternative names of the site, used for searching
+    aliases TEXT[] NOT NULL DEFAULT '{}'
+);
+
+-- Personal locations can be linked to a shared site
+ALTER TABLE locations ADD COLUMN site_id INTEGER REFERENCES sites(id) ON DELETE SET NULL;
+CREATE INDEX locations_site_id_idx ON locations(site_id);
@@ -0,0 +1,2 @@
+ALTER TABLE locations DROP COLUMN match_radius;
+ALTER TABLE users DROP COLUMN match_radius;
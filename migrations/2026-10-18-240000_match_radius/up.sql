@@ -0,0 +1,4 @@
+-- Radius in meters within which IGC launch and landing points are matched to
+-- a location. The user's default applies to locations without own radius.
+ALTER TABLE users ADD COLUMN match_radius INTEGER NOT NULL DEFAULT 1000 CHECK (match_radius > 0);
+ALTER TABLE locations ADD COLUMN match_radius INTEGER CHECK (match_radius > 0);
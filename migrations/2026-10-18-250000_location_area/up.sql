@@ -0,0 +1,3 @@
+-- Optional outline of a location (e.g. a landing field). IGC launch and
+-- landing points are matched to locations with an area by containment.
+ALTER TABLE locations ADD COLUMN area geography(POLYGON);
@@ -0,0 +1,7 @@
+ALTER TABLE flights
+    DROP COLUMN weather_notes,
+    DROP COLUMN turbulence,
+    DROP COLUMN cloud_base,
+    DROP COLUMN thermal_strength,
+    DROP COLUMN wind_speed,
+    DROP COLUMN wind_direction;
@@ -0,0 +1,8 @@
+-- Structured flight conditions, all optional
+ALTER TABLE flights
+    ADD COLUMN wind_direction SMALLINT CHECK (wind_direction BETWEEN 0 AND 359),
+    ADD COLUMN wind_speed SMALLINT CHECK (wind_speed >= 0),
+    ADD COLUMN thermal_strength REAL CHECK (thermal_strength >= 0),
+    ADD COLUMN cloud_base INTEGER CHECK (cloud_base BETWEEN 0 AND 10000),
+    ADD COLUMN turbulence SMALLINT CHECK (turbulence BETWEEN 1 AND 5),
+    ADD COLUMN weather_notes TEXT;
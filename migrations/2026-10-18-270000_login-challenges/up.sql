@@ -0,0 +1,10 @@
+-- Pending second login steps (TOTP code) of users whose password was
+-- verified. The login challenge cookie references a challenge.
+CREATE TABLE login_challenges (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    -- Number of wrong codes submitted for this challenge
+    failures INTEGER NOT NULL DEFAULT 0
+);
+CREATE INDEX login_challenges_user_id_idx ON login_challenges(user_id);
@@ -0,0 +1,30 @@
+#!/usr/bin/env bash
+#
+# Download the default geographic datasets into `data/geodata/` (or the
+# directory passed as first argument):
+#
+# - places.txt: GeoNames places with more than 15000 inhabitants (CC BY 4.0)
+# - countries.geojson: Natural Earth 1:50m admin 0 countries (public domain)
+#
+# Datasets in this directory are loaded on startup unless other paths are
+# configured in the `geodata` section of `Rocket.toml`.
+set -euo pipefail
+
+TARGET_DIR="${1:-data/geodata}"
+PLACES_URL="https://download.geonames.org/export/dump/cities15000.zip"
+COUNTRIES_URL="https://raw.githubusercontent.com/nvkelso/natural-earth-vector/v5.1.2/geojson/ne_50m_admin_0_countries.geojson"
+
+mkdir -p "$TARGET_DIR"
+TMP_DIR="$(mktemp -d)"
+trap 'rm -rf "$TMP_DIR"' EXIT
+
+echo "Downloading places from $PLACES_URL"
+curl -fsSL -o "$TMP_DIR/places.zip" "$PLACES_URL"
+unzip -p "$TMP_DIR/places.zip" cities15000.txt > "$TMP_DIR/places.txt"
+mv "$TMP_DIR/places.txt" "$TARGET_DIR/places.txt"
+
+echo "Downloading country boundaries from $COUNTRIES_URL"
+curl -fsSL -o "$TMP_DIR/countries.geojson" "$COUNTRIES_URL"
+mv "$TMP_DIR/countries.geojson" "$TARGET_DIR/countries.geojson"
+
+echo "Geodata stored in $TARGET_DIR"
@@ -0,0 +1,766 @@
+//! Account data export and account deletion.
+
+use std::{
+    collections::HashMap,
+    io::{Cursor, Write},
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use diesel::{PgConnection, QueryResult};
+use log::{error, info};
+use rocket::{
+    delete, get,
+    http::{ContentType, CookieJar, Status},
+    routes,
+    serde::json::Json,
+    Route,
+};
+use serde::{Deserialize, Serialize};
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+use crate::{
+    auth, data,
+    flights::FileAttachment,
+    models::{
+        CurrencyRule, Equipment, EquipmentRepack, Flight, GliderMaintenanceEvent, GliderMaintenanceInterval,
+        User,
+    },
+    responders::{ApiError, RocketError},
+    totp,
+};
+
+/// Maximum age of the login session in minutes to delete the account without
+/// password.
+const RECENT_LOGIN_MINUTES: i64 = 10;
+
+// Export
+
+/// Profile data in the export. The password hash is not exported.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportProfile<'a> {
+    username: &'a str,
+    email: &'a str,
+    email_verified: bool,
+    signed_up: DateTime<Utc>,
+    news_opt_in: bool,
+}
+
+/// Equipment in the export, with repacks and the IDs of the flights it was
+/// used on.
+#[derive(Serialize)]
+struct ExportEquipment {
+    #[serde(flatten)]
+    equipment: Equipment,
+    repacks: Vec<EquipmentRepack>,
+    flight_ids: Vec<i32>,
+}
+
+/// Glider maintenance in the export.
+#[derive(Serialize)]
+struct ExportMaintenance {
+    intervals: Vec<GliderMaintenanceInterval>,
+    events: Vec<GliderMaintenanceEvent>,
+}
+
+/// An API token in the export. The token hash is not exported.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportApiToken {
+    name: String,
+    token_prefix: String,
+    scopes: Vec<String>,
+    created_at: DateTime<Utc>,
+    last_used_at: Option<DateTime<Utc>>,
+}
+
+/// A login session in the export. The token hash is not exported.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportSession {
+    user_agent: Option<String>,
+    ip_address: Option<String>,
+    created_at: DateTime<Utc>,
+    last_seen_at: DateTime<Utc>,
+}
+
+/// Two-factor authentication in the export. The secret and the recovery
+/// codes are not exported.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportTotp {
+    enabled: bool,
+    created_at: DateTime<Utc>,
+    recovery_codes_remaining: i64,
+}
+
+/// Login and API access metadata in the export.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportSecurity {
+    api_tokens: Vec<ExportApiToken>,
+    sessions: Vec<ExportSession>,
+    totp: Option<ExportTotp>,
+}
+
+/// A flight in the CSV export, in the format accepted by the CSV import.
+#[derive(Serialize)]
+struct ExportCsvRecord {
+    number: Option<i32>,
+    date: Option<String>,
+    glider: Option<String>,
+    launch_site: Option<String>,
+    launch_time_utc: Option<String>,
+    landing_site: Option<String>,
+    landing_time_utc: Option<String>,
+    track_distance: Option<f32>,
+    hikeandfly: bool,
+    comment: Option<String>,
+    xcontest_url: Option<String>,
+    xcontest_tracktype: Option<String>,
+    xcontest_scored_distance: Option<f32>,
+    video_url: Option<String>,
+    wind_direction: Option<i16>,
+    wind_speed: Option<i16>,
+    thermal_strength: Option<f32>,
+    cloud_base: Option<i32>,
+    turbulence: Option<i16>,
+    weather_notes: Option<String>,
+}
+
+/// Serialize the flights as CSV.
+fn flights_csv(conn: &mut PgConnection, user: &User, flights: &[Flight]) -> Result<Vec<u8>> {
+    let gliders = data::get_gliders_for_user(conn, user);
+    let location_ids: Vec<i32> = flights
+        .iter()
+        .flat_map(|flight| [flight.launch_at, flight.landing_at])
+        .flatten()
+        .collect();
+    let locations = data::get_locations_with_ids(conn, &location_ids);
+    let location_name = |id: Option<i32>| {
+        id.and_then(|id| locations.iter().find(|location| location.id == id))
+            .map(|location| location.name.clone())
+    };
+
+    let mut writer = csv::Writer::from_writer(vec![]);
+    for flight in flights {
+        writer.serialize(ExportCsvRecord {
+            number: flight.number,
+            date: flight.launch_time.map(|time| time.format("%Y-%m-%d").to_string()),
+            glider: flight
+                .glider_id
+                .and_then(|id| gliders.iter().find(|glider| glider.id == id))
+                .map(|glider| format!("{} {}", glider.manufacturer, glider.model)),
+            launch_site: location_name(flight.launch_at),
+            launch_time_utc: flight.launch_time.map(|time| time.format("%H:%M:%S").to_string()),
+            landing_site: location_name(flight.landing_at),
+            landing_time_utc: flight
+                .landing_time
+                .map(|time| time.format("%H:%M:%S").to_string()),
+            track_distance: flight.track_distance,
+            hikeandfly: flight.hikeandfly,
+            comment: flight.comment.clone(),
+            xcontest_url: flight.xcontest_url.clone(),
+            xcontest_tracktype: flight.xcontest_tracktype.clone(),
+            xcontest_scored_distance: flight.xcontest_distance,
+            video_url: flight.video_url.clone(),
+            wind_direction: flight.wind_direction,
+            wind_speed: flight.wind_speed,
+            thermal_strength: flight.thermal_strength,
+            cloud_base: flight.cloud_base,
+            turbulence: flight.turbulence,
+            weather_notes: flight.weather_notes.clone(),
+        })?;
+    }
+    writer.into_inner().context("Could not write CSV")
+}
+
+/// Return the login and API access metadata of the user, without secrets.
+fn security_metadata(conn: &mut PgConnection, user: &User) -> ExportSecurity {
+    let api_tokens = data::get_api_tokens_for_user(conn, user)
+        .into_iter()
+        .map(|token| ExportApiToken {
+            name: token.name,
+            token_prefix: token.token_prefix,
+            scopes: token.scopes,
+            created_at: token.created_at,
+            last_used_at: token.last_used_at,
+        })
+        .collect();
+    let sessions = data::get_sessions_for_user(conn, user)
+        .into_iter()
+        .map(|session| ExportSession {
+            user_agent: session.user_agent,
+            ip_address: session.ip_address,
+            created_at: session.created_at,
+            last_seen_at: session.last_seen_at,
+        })
+        .collect();
+    let totp = data::get_totp_credential(conn, user).map(|credential| ExportTotp {
+        enabled: credential.enabled,
+        created_at: credential.created_at,
+        recovery_codes_remaining: data::count_unused_totp_recovery_codes(conn, user),
+    });
+    ExportSecurity {
+        api_tokens,
+        sessions,
+        totp,
+    }
+}
+
+/// Create a ZIP archive with all data of the user:
+///
+/// - `profile.json`, `gliders.json`, `locations.json` and `flights.json`
+/// - `equipment.json` (with repacks), `maintenance.json` (glider maintenance
+///   intervals and events) and `currency_rules.json`
+/// - `security.json` (API tokens, sessions and two-factor authentication,
+///   without secrets)
+/// - `flights.csv` (in the CSV import format)
+/// - `igc/flight<id>.igc` for every flight with an IGC file
+fn create_export(conn: &mut PgConnection, user: &User) -> Result<Vec<u8>> {
+    let gliders = data::get_gliders_for_user(conn, user);
+    let locations = data::get_locations_for_user(conn, user);
+    let flights = data::get_flights_for_user(conn, user);
+    let mut equipment_flights: HashMap<i32, Vec<i32>> = HashMap::new();
+    for flight in &flights {
+        for equipment_id in data::get_equipment_ids_for_flight(conn, flight) {
+            equipment_flights.entry(equipment_id).or_default().push(flight.id);
+        }
+    }
+    let equipment: Vec<ExportEquipment> = data::get_equipment_for_user(conn, user)
+        .into_iter()
+        .map(|equipment| ExportEquipment {
+            repacks: data::get_repacks_for_equipment(conn, &equipment),
+            flight_ids: equipment_flights.remove(&equipment.id).unwrap_or_default(),
+            equipment,
+        })
+        .collect();
+    let maintenance = ExportMaintenance {
+        intervals: data::get_maintenance_intervals_for_user(conn, user),
+        events: data::get_maintenance_events_for_user(conn, user),
+    };
+    let currency_rules: Vec<CurrencyRule> = data::get_currency_rules_for_user(conn, user);
+    let security = security_metadata(conn, user);
+    let profile = ExportProfile {
+        username: &user.username,
+        email: &user.email,
+        email_verified: user.email_verified,
+        signed_up: user.signed_up,
+        news_opt_in: user.news_opt_in,
+    };
+
+    let mut zip = ZipWriter::new(Cursor::new(vec![]));
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    let add_file = |zip: &mut ZipWriter<_>, name: &str, contents: &[u8]| -> Result<()> {
+        zip.start_file(name, options)?;
+        zip.write_all(contents)?;
+        Ok(())
+    };
+    add_file(&mut zip, "profile.json", &serde_json_vec(&profile)?)?;
+    add_file(&mut zip, "gliders.json", &serde_json_vec(&gliders)?)?;
+    add_file(&mut zip, "locations.json", &serde_json_vec(&locations)?)?;
+    add_file(&mut zip, "flights.json", &serde_json_vec(&flights)?)?;
+    add_file(&mut zip, "equipment.json", &serde_json_vec(&equipment)?)?;
+    add_file(&mut zip, "maintenance.json", &serde_json_vec(&maintenance)?)?;
+    add_file(&mut zip, "currency_rules.json", &serde_json_vec(&currency_rules)?)?;
+    add_file(&mut zip, "security.json", &serde_json_vec(&security)?)?;
+    add_file(&mut zip, "flights.csv", &flights_csv(conn, user, &flights)?)?;
+    for flight in &flights {
+        if let Some(igc) = data::get_igc_for_flight(conn, flight) {
+            add_file(&mut zip, &format!("igc/flight{}.igc", flight.id), &igc.data)?;
+        }
+    }
+    Ok(zip.finish()?.into_inner())
+}
+
+fn serde_json_vec(value: &impl Serialize) -> Result<Vec<u8>> {
+    rocket::serde::json::to_pretty_string(value)
+        .map(String::into_bytes)
+        .context("Could not serialize JSON")
+}
+
+// Forms
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountDeletion {
+    /// The current password. Can be omitted if the user logged in recently
+    /// (e.g. users without a known password after logging in through OIDC).
+    password: Option<String>,
+    /// Code from the authenticator app, if TOTP is enabled
+    code: Option<String>,
+    /// Alternatively, a TOTP recovery code
+    recovery_code: Option<String>,
+}
+
+/// Outcome of an account deletion request.
+enum DeletionOutcome {
+    Deleted,
+    InvalidPassword,
+    LoginNotRecent,
+    InvalidSecondFactor,
+}
+
+// API endpoints
+
+/// Download all data of the user as ZIP archive.
+#[get("/account/export")]
+pub async fn export(user: auth::AuthUser, database: data::Database) -> Result<FileAttachment, Status> {
+    let user = user.into_inner();
+    let user_id = user.id;
+    let archive = database
+        .run(move |db| create_export(db, &user))
+        .await
+        .map_err(|e| {
+            error!("Could not export data of user {}: {:#}", user_id, e);
+            Status::InternalServerError
+        })?;
+    Ok(FileAttachment::new(
+        archive,
+        ContentType::ZIP,
+        format!("flugbuech-export-{}.zip", Utc::now().format("%Y-%m-%d")),
+    ))
+}
+
+#[get("/account/export", rank = 2)]
+pub fn export_nologin() -> ApiError {
+    ApiError::MissingAuthentication
+}
+
+/// Delete the account with all flights, IGC files, gliders, locations and
+/// other data.
+///
+/// The deletion must be confirmed with the current password, or the user must
+/// have logged in within the last `RECENT_LOGIN_MINUTES` minutes. If TOTP is
+/// enabled, a code or a recovery code is required as well.
+///
+/// - Return "HTTP 204 No Content" if the account was deleted.
+/// - Return "HTTP 403 Forbidden" if no password was provided and the login is
+///   not recent.
+/// - Return "HTTP 422 Unprocessable Entity" if the password or the TOTP code
+///   is wrong.
+#[delete("/account", data = "<data>")]
+pub async fn delete(
+    cookies: &CookieJar<'_>,
+    database: data::Database,
+    user: auth::AuthUser,
+    data: Json<AccountDeletion>,
+) -> Result<Status, (Status, Json<RocketError>)> {
+    let session_id = user.session_id();
+    let user = user.into_inner();
+    let user_id = user.id;
+    let AccountDeletion {
+        password,
+        code,
+        recovery_code,
+    } = data.into_inner();
+    let result = database
+        .run(move |db| -> QueryResult<DeletionOutcome> {
+            // Confirm with password or recent login
+            match password {
+                Some(password) => match data::validate_login(db, &user.username, &password) {
+                    Some(u) if u.id == user.id => {}
+                    _ => return Ok(DeletionOutcome::InvalidPassword),
+                },
+                None => {
+                    let recent = session_id
+                        .and_then(|id| data::get_session_by_id(db, id))
+                        .is_some_and(|session| {
+                            Utc::now() - session.created_at <= Duration::minutes(RECENT_LOGIN_MINUTES)
+                        });
+                    if !recent {
+                        return Ok(DeletionOutcome::LoginNotRecent);
+                    }
+                }
+            }
+
+            // Second factor
+            if data::is_totp_enabled(db, &user)
+                && !totp::verify_second_factor(db, &user, code.as_deref(), recovery_code.as_deref())?
+            {
+                return Ok(DeletionOutcome::InvalidSecondFactor);
+            }
+
+            data::delete_user(db, &user)?;
+            Ok(DeletionOutcome::Deleted)
+        })
+        .await;
+    match result {
+        Ok(DeletionOutcome::Deleted) => {
+            info!("Deleted user {}", user_id);
+            auth::remove_auth_cookies(cookies);
+            Ok(Status::NoContent)
+        }
+        Ok(DeletionOutcome::InvalidPassword) => Err(RocketError::new(
+            Status::UnprocessableEntity,
+            "InvalidPassword",
+            "Invalid password",
+        )),
+        Ok(DeletionOutcome::LoginNotRecent) => Err(RocketError::new(
+            Status::Forbidden,
+            "LoginNotRecent",
+            "Please provide your password or log in again",
+        )),
+        Ok(DeletionOutcome::InvalidSecondFactor) => Err(RocketError::new(
+            Status::UnprocessableEntity,
+            "InvalidTotpCode",
+            "Invalid or missing two-factor authentication code",
+        )),
+        Err(e) => {
+            error!("Could not delete user {}: {}", user_id, e);
+            Err(RocketError::new(
+                Status::InternalServerError,
+                "DeletionError",
+                "Could not delete account",
+            ))
+        }
+    }
+}
+
+#[delete("/account", rank = 2)]
+pub fn delete_nologin() -> ApiError {
+    ApiError::MissingAuthentication
+}
+
+/// Return vec of all API routes.
+pub fn api_routes() -> Vec<Route> {
+    routes![export, export_nologin, delete, delete_nologin]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use chrono::NaiveDate;
+    use diesel::prelude::*;
+    use rocket::{local::blocking::Client, serde::json::Value};
+
+    use crate::{
+        models::{
+            NewCurrencyRule, NewEquipment, NewEquipmentRepack, NewFlight, NewGlider,
+            NewGliderMaintenanceEvent, NewGliderMaintenanceInterval, NewLocation,
+        },
+        schema::{flights, igcs, sessions, users},
+        test_utils::{make_test_config, utc_datetime, DbTestContext},
+    };
+
+    use super::*;
+
+    fn make_client() -> Client {
+        let app = rocket::custom(make_test_config())
+            .attach(data::Database::fairing())
+            .mount("/", api_routes());
+        Client::tracked(app).expect("valid rocket instance")
+    }
+
+    /// Create a glider, two locations and a flight with IGC for the user.
+    /// Return the flight.
+    fn create_data(conn: &mut PgConnection, user: &User) -> Flight {
+        let glider = data::create_glider(
+            conn,
+            NewGlider {
+                user_id: user.id,
+                manufacturer: "Advance".into(),
+                model: "Epsilon 9".into(),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let mut location = |name: &str| {
+            data::create_location(
+                conn,
+                NewLocation {
+                    name: name.into(),
+                    country: "CH".into(),
+                    elevation: 1000,
+                    user_id: user.id,
+                    geog: None,
+                    match_radius: None,
+                    area: None,
+                },
+            )
+        };
+        let launch = location("Ebenalp");
+        let landing = location("Wasserauen");
+        data::create_flight(
+            conn,
+            &NewFlight {
+                number: Some(7),
+                user_id: user.id,
+                glider_id: Some(glider.id),
+                launch_at: Some(launch.id),
+                landing_at: Some(landing.id),
+                launch_time: Some(utc_datetime(2024, 5, 1, 10, 0, 0)),
+                landing_time: Some(utc_datetime(2024, 5, 1, 10, 30, 0)),
+                comment: Some("Smooth, \"nice\"".into()),
+                ..Default::default()
+            },
+            Some(b"AXXX\r\n".to_vec()),
+        )
+    }
+
+    /// Create equipment, maintenance, a currency rule, an API token and a
+    /// TOTP credential for the user of the flight.
+    fn create_other_data(conn: &mut PgConnection, user: &User, flight: &Flight) {
+        let reserve = data::create_equipment(
+            conn,
+            NewEquipment {
+                user_id: user.id,
+                kind: "reserve".into(),
+                manufacturer: "Gin".into(),
+                model: "Yeti Cross".into(),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        data::create_repack(
+            conn,
+            NewEquipmentRepack {
+                equipment_id: reserve.id,
+                date: NaiveDate::from_ymd_opt(2024, 4, 1).unwrap(),
+                notes: Some("Repacked by Hans".into()),
+            },
+        )
+        .unwrap();
+        data::set_flight_equipment(conn, flight.id, &[reserve.id]).unwrap();
+        let glider_id = flight.glider_id.unwrap();
+        data::create_maintenance_interval(
+            conn,
+            NewGliderMaintenanceInterval {
+                glider_id,
+                kind: "check".into(),
+                interval_months: Some(24),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        data::create_maintenance_event(
+            conn,
+            NewGliderMaintenanceEvent {
+                glider_id,
+                kind: "trim".into(),
+                date: NaiveDate::from_ymd_opt(2024, 3, 1).unwrap(),
+                notes: Some("Trimmed at the factory".into()),
+            },
+        )
+        .unwrap();
+        data::create_currency_rule(
+            conn,
+            NewCurrencyRule {
+                user_id: user.id,
+                name: "Tandem currency".into(),
+                min_flights: Some(3),
+                window_days: 90,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        data::create_api_token(conn, user, "Landing zone sync", &["flights:read".to_string()]).unwrap();
+        data::create_totp_credential(conn, user).unwrap();
+    }
+
+    #[test]
+    fn export_zip() {
+        let ctx = DbTestContext::new();
+        let flight = create_data(&mut ctx.force_get_conn(), &ctx.testuser1.user);
+        create_other_data(&mut ctx.force_get_conn(), &ctx.testuser1.user, &flight);
+        let client = make_client();
+
+        let resp = client
+            .get("/account/export")
+            .private_cookie(ctx.auth_cookie_user1())
+            .cookie(ctx.username_cookie())
+            .dispatch();
+        assert_eq!(resp.status(), Status::Ok);
+        assert_eq!(resp.content_type(), Some(ContentType::ZIP));
+        let bytes = resp.into_bytes().unwrap();
+
+        let mut archive = zip::ZipArchive::new(Cursor::new(bytes)).unwrap();
+        let mut names: Vec<&str> = archive.file_names().collect();
+        names.sort_unstable();
+        let igc_name = format!("igc/flight{}.igc", flight.id);
+        assert_eq!(
+            names,
+            [
+                "currency_rules.json",
+                "equipment.json",
+                "flights.csv",
+                "flights.json",
+                "gliders.json",
+                igc_name.as_str(),
+                "locations.json",
+                "maintenance.json",
+                "profile.json",
+                "security.json",
+            ]
+        );
+        let mut read = |name: &str| {
+            let mut contents = String::new();
+            archive
+                .by_name(name)
+                .unwrap()
+                .read_to_string(&mut contents)
+                .unwrap();
+            contents
+        };
+        let profile = read("profile.json");
+        assert!(profile.contains(r#""username": "testuser1""#));
+        assert!(!profile.contains("password"));
+        assert!(read("gliders.json").contains("Epsilon 9"));
+        assert!(read("locations.json").contains("Wasserauen"));
+        assert!(read("flights.json").contains(r#""number": 7"#));
+        assert_eq!(
+            read("flights.csv"),
+            "number,date,glider,launch_site,launch_time_utc,landing_site,landing_time_utc,track_distance,hikeandfly,comment,xcontest_url,xcontest_tracktype,xcontest_scored_distance,video_url,wind_direction,wind_speed,thermal_strength,cloud_base,turbulence,weather_notes\n\
+             7,2024-05-01,Advance Epsilon 9,Ebenalp,10:00:00,Wasserauen,10:30:00,,false,\"Smooth, \"\"nice\"\"\",,,,,,,,,,\n"
+        );
+        assert_eq!(read(&igc_name), "AXXX\r\n");
+
+        let equipment: Value = rocket::serde::json::from_str(&read("equipment.json")).unwrap();
+        assert_eq!(equipment[0]["model"], "Yeti Cross");
+        assert_eq!(equipment[0]["repacks"][0]["notes"], "Repacked by Hans");
+        assert_eq!(
+            equipment[0]["flight_ids"],
+            rocket::serde::json::json!([flight.id])
+        );
+        let maintenance = read("maintenance.json");
+        assert!(maintenance.contains(r#""interval_months": 24"#));
+        assert!(maintenance.contains("Trimmed at the factory"));
+        assert!(read("currency_rules.json").contains("Tandem currency"));
+
+        // Metadata only, no secrets
+        let security = read("security.json");
+        assert!(security.contains("Landing zone sync"));
+        assert!(security.contains(r#""userAgent""#));
+        assert!(security.contains(r#""enabled": false"#));
+        assert!(!security.contains("hash"));
+        assert!(!security.contains("secret"));
+    }
+
+    #[test]
+    fn delete_account() {
+        let ctx = DbTestContext::new();
+        let user1 = ctx.testuser1.user.clone();
+        let flight = create_data(&mut ctx.force_get_conn(), &user1);
+        create_data(&mut ctx.force_get_conn(), &ctx.testuser2.user);
+
+        // A flight of another user referencing a location of the deleted user
+        diesel::update(flights::table.filter(flights::user_id.eq(ctx.testuser2.user.id)))
+            .set(flights::launch_at.eq(flight.launch_at))
+            .execute(&mut *ctx.force_get_conn())
+            .unwrap();
+
+        let client = make_client();
+        let delete = |password: &str| {
+            client
+                .delete("/account")
+                .header(ContentType::JSON)
+                .body(format!(r#"{{"password": "{}"}}"#, password))
+                .private_cookie(ctx.auth_cookie_user1())
+                .cookie(ctx.username_cookie())
+                .dispatch()
+                .status()
+        };
+        assert_eq!(delete("wrong"), Status::UnprocessableEntity);
+        assert_eq!(delete("testpass"), Status::NoContent);
+
+        // All data of the user is gone
+        let conn = &mut *ctx.force_get_conn();
+        let count_users: i64 = users::table
+            .filter(users::id.eq(user1.id))
+            .count()
+            .get_result(conn)
+            .unwrap();
+        assert_eq!(count_users, 0);
+        assert!(data::get_flights_for_user(conn, &user1).is_empty());
+        assert!(data::get_gliders_for_user(conn, &user1).is_empty());
+        assert!(data::get_locations_for_user(conn, &user1).is_empty());
+        assert!(data::get_igc_for_flight(conn, &flight).is_none());
+
+        // Data of other users is kept
+        let user2 = &ctx.testuser2.user;
+        let flights2 = data::get_flights_for_user(conn, user2);
+        assert_eq!(flights2.len(), 1);
+        assert_eq!(flights2[0].launch_at, None);
+        assert_eq!(data::get_gliders_for_user(conn, user2).len(), 1);
+        assert_eq!(data::get_locations_for_user(conn, user2).len(), 2);
+        let count_igcs: i64 = igcs::table.count().get_result(conn).unwrap();
+        assert_eq!(count_igcs, 1);
+
+        // The session is gone as well
+        assert_eq!(delete("testpass"), Status::Unauthorized);
+    }
+
+    #[test]
+    fn delete_account_with_recent_login() {
+        let ctx = DbTestContext::new();
+        let client = make_client();
+        let delete = |body: &str, cookie| {
+            client
+                .delete("/account")
+                .header(ContentType::JSON)
+                .body(body)
+                .private_cookie(cookie)
+                .cookie(ctx.username_cookie())
+                .dispatch()
+                .status()
+        };
+        let user_exists = |user: &User| data::get_user(&mut ctx.force_get_conn(), user.id).is_some();
+
+        // Without password, the login must be recent
+        diesel::update(sessions::table.filter(sessions::user_id.eq(ctx.testuser1.user.id)))
+            .set(sessions::created_at.eq(Utc::now() - Duration::minutes(RECENT_LOGIN_MINUTES + 1)))
+            .execute(&mut *ctx.force_get_conn())
+            .unwrap();
+        assert_eq!(delete("{}", ctx.auth_cookie_user1()), Status::Forbidden);
+        assert!(user_exists(&ctx.testuser1.user));
+
+        // E.g. after logging in through OIDC, without known password
+        assert_eq!(delete("{}", ctx.auth_cookie_user2()), Status::NoContent);
+        assert!(!user_exists(&ctx.testuser2.user));
+    }
+
+    #[test]
+    fn delete_account_with_totp() {
+        let ctx = DbTestContext::new();
+        let client = make_client();
+        let user = ctx.testuser1.user.clone();
+        let secret = {
+            let conn = &mut *ctx.force_get_conn();
+            let credential = data::create_totp_credential(conn, &user).unwrap();
+            data::enable_totp(conn, &user, totp::time_step(Utc::now())).unwrap();
+            credential.secret
+        };
+        let delete = |body: String| {
+            client
+                .delete("/account")
+                .header(ContentType::JSON)
+                .body(body)
+                .private_cookie(ctx.auth_cookie_user1())
+                .cookie(ctx.username_cookie())
+                .dispatch()
+                .status()
+        };
+
+        // The password or a recent login alone is not sufficient
+        assert_eq!(
+            delete(r#"{"password": "testpass"}"#.into()),
+            Status::UnprocessableEntity
+        );
+        assert_eq!(delete("{}".into()), Status::UnprocessableEntity);
+        assert_eq!(
+            delete(r#"{"password": "testpass", "code": "000000"}"#.into()),
+            Status::UnprocessableEntity
+        );
+        assert!(data::get_user(&mut ctx.force_get_conn(), user.id).is_some());
+
+        // With the second factor
+        let code = totp::code_at_step(&secret, totp::time_step(Utc::now()) + 1).unwrap();
+        assert_eq!(
+            delete(format!(r#"{{"password": "testpass", "code": "{}"}}"#, code)),
+            Status::NoContent
+        );
+        assert!(data::get_user(&mut ctx.force_get_conn(), user.id).is_none());
+    }
+}
@@ -0,0 +1,517 @@
+//! Administration API.
+//!
+//! All endpoints require a user with the admin role (see `auth::AdminUser`).
+//! The role can be granted with the `--grant-admin <username>` command line
+//! argument.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use rocket::{get, http::Status, post, routes, serde::json::Json, Route, State};
+use serde::{Deserialize, Serialize};
+
+use crate::{auth, data, mail::Mailer, models::UserWithStats, responders::ApiError, Config};
+
+/// Default number of users returned by the user list.
+const DEFAULT_USER_LIMIT: i64 = 50;
+
+/// Maximum number of users returned by the user list.
+const MAX_USER_LIMIT: i64 = 500;
+
+// API types
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiAdminUser {
+    id: i32,
+    username: String,
+    email: String,
+    email_verified: bool,
+    signed_up: DateTime<Utc>,
+    is_admin: bool,
+    disabled: bool,
+    /// Number of flights
+    flights: i64,
+    /// Total size of all IGC files in bytes
+    igc_bytes: i64,
+}
+
+impl From<UserWithStats> for ApiAdminUser {
+    fn from(user: UserWithStats) -> Self {
+        Self {
+            id: user.id,
+            username: user.username,
+            email: user.email,
+            email_verified: user.email_verified,
+            signed_up: user.signed_up,
+            is_admin: user.is_admin,
+            disabled: user.disabled,
+            flights: user.flights,
+            igc_bytes: user.igc_bytes,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiMonthStats {
+    /// The month (YYYY-MM)
+    month: String,
+    new_users: i64,
+    new_flights: i64,
+    /// Number of users at the end of the month
+    total_users: i64,
+    /// Number of flights at the end of the month
+    total_flights: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiInstanceStats {
+    user_count: i64,
+    glider_count: i64,
+    flight_count: i64,
+    /// Total size of all IGC files in bytes
+    igc_bytes: i64,
+    /// Growth of the instance over time, ordered by month
+    months: Vec<ApiMonthStats>,
+}
+
+// API endpoints
+
+/// List users, optionally filtered by a search string that is matched
+/// against username and e-mail address.
+#[get("/admin/users?<search>&<limit>&<offset>")]
+pub async fn users(
+    _admin: auth::AdminUser,
+    database: data::Database,
+    search: Option<String>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+) -> Json<Vec<ApiAdminUser>> {
+    let limit = limit.unwrap_or(DEFAULT_USER_LIMIT).clamp(1, MAX_USER_LIMIT);
+    let offset = offset.unwrap_or(0).max(0);
+    let users = database
+        .run(move |db| {
+            data::search_users_with_stats(
+                db,
+                search.as_deref().filter(|s| !s.trim().is_empty()),
+                limit,
+                offset,
+            )
+        })
+        .await;
+    Json(users.into_iter().map(Into::into).collect())
+}
+
+#[get("/admin/users", rank = 2)]
+pub fn users_nologin() -> ApiError {
+    ApiError::MissingAuthentication
+}
+
+/// Return a single user, including storage usage.
+#[get("/admin/users/<id>")]
+pub async fn user(
+    _admin: auth::AdminUser,
+    database: data::Database,
+    id: i32,
+) -> Result<Json<ApiAdminUser>, ApiError> {
+    database
+        .run(move |db| data::get_user_with_stats(db, id))
+        .await
+        .map(|user| Json(user.into()))
+        .ok_or(ApiError::NotFound)
+}
+
+#[get("/admin/users/<_id>", rank = 2)]
+pub fn user_nologin(_id: i32) -> ApiError {
+    ApiError::MissingAuthentication
+}
+
+async fn set_disabled(
+    admin: auth::AdminUser,
+    database: data::Database,
+    id: i32,
+    disabled: bool,
+) -> Result<Status, ApiError> {
+    let admin = admin.into_inner();
+    if admin.id == id {
+        return Err(ApiError::InvalidData {
+            message: "You cannot disable or enable your own account".into(),
+        });
+    }
+    let user = database
+        .run(move |db| data::get_user(db, id))
+        .await
+        .ok_or(ApiError::NotFound)?;
+    database
+        .run(move |db| data::set_user_disabled(db, &user, disabled))
+        .await
+        .map_err(|e| {
+            log::error!("Could not update user {}: {}", id, e);
+            ApiError::IoError {
+                message: "Could not update user".into(),
+            }
+        })?;
+    log::info!(
+        "Admin {} {} user {}",
+        admin.id,
+        if disabled { "disabled" } else { "enabled" },
+        id
+    );
+    Ok(Status::NoContent)
+}
+
+/// Disable a user account. The user is logged out and cannot log in anymore.
+#[post("/admin/users/<id>/disable")]
+pub async fn disable(admin: auth::AdminUser, database: data::Database, id: i32) -> Result<Status, ApiError> {
+    set_disabled(admin, database, id, true).await
+}
+
+#[post("/admin/users/<_id>/disable", rank = 2)]
+pub fn disable_nologin(_id: i32) -> ApiError {
+    ApiError::MissingAuthentication
+}
+
+/// Enable a previously disabled user account.
+#[post("/admin/users/<id>/enable")]
+pub async fn enable(admin: auth::AdminUser, database: data::Database, id: i32) -> Result<Status, ApiError> {
+    set_disabled(admin, database, id, false).await
+}
+
+#[post("/admin/users/<_id>/enable", rank = 2)]
+pub fn enable_nologin(_id: i32) -> ApiError {
+    ApiError::MissingAuthentication
+}
+
+/// Send a password reset link to the user.
+#[post("/admin/users/<id>/password-reset")]
+pub async fn password_reset(
+    admin: auth::AdminUser,
+    database: data::Database,
+    mailer: &State<Mailer>,
+    config: &State<Config>,
+    id: i32,
+) -> Result<Status, ApiError> {
+    let admin = admin.into_inner();
+    let user = database
+        .run(move |db| data::get_user(db, id))
+        .await
+        .ok_or(ApiError::NotFound)?;
+    log::info!("Admin {} requested a password reset for user {}", admin.id, id);
+    if auth::send_password_reset(&database, mailer, config, user).await {
+        Ok(Status::NoContent)
+    } else {
+        Err(ApiError::IoError {
+            message: "Could not send password reset mail".into(),
+        })
+    }
+}
+
+#[post("/admin/users/<_id>/password-reset", rank = 2)]
+pub fn password_reset_nologin(_id: i32) -> ApiError {
+    ApiError::MissingAuthentication
+}
+
+/// Return instance statistics, including growth over time.
+#[get("/admin/stats")]
+pub async fn stats(_admin: auth::AdminUser, database: data::Database) -> Json<ApiInstanceStats> {
+    let (user_count, glider_count, flight_count, igc_bytes, signups, flights) = database
+        .run(|db| {
+            (
+                data::get_user_count(db),
+                data::get_glider_count(db),
+                data::get_flight_count(db),
+                data::get_igc_bytes(db),
+                data::get_monthly_signup_counts(db),
+                data::get_monthly_flight_counts(db),
+            )
+        })
+        .await;
+
+    // Merge monthly counts
+    let mut months: BTreeMap<String, ApiMonthStats> = BTreeMap::new();
+    for signup in signups {
+        months.entry(signup.month.clone()).or_default().new_users = signup.count;
+    }
+    for flight in flights {
+        months.entry(flight.month.clone()).or_default().new_flights = flight.count;
+    }
+    let (mut total_users, mut total_flights) = (0, 0);
+    let months = months
+        .into_iter()
+        .map(|(month, stats)| {
+            total_users += stats.new_users;
+            total_flights += stats.new_flights;
+            ApiMonthStats {
+                month,
+                total_users,
+                total_flights,
+                ..stats
+            }
+        })
+        .collect();
+
+    Json(ApiInstanceStats {
+        user_count,
+        glider_count,
+        flight_count,
+        igc_bytes,
+        months,
+    })
+}
+
+#[get("/admin/stats", rank = 2)]
+pub fn stats_nologin() -> ApiError {
+    ApiError::MissingAuthentication
+}
+
+/// Return vec of all API routes.
+pub fn api_routes() -> Vec<Route> {
+    routes![
+        users,
+        users_nologin,
+        user,
+        user_nologin,
+        disable,
+        disable_nologin,
+        enable,
+        enable_nologin,
+        password_reset,
+        password_reset_nologin,
+        stats,
+        stats_nologin,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel::prelude::*;
+    use rocket::{catchers, local::blocking::Client};
+
+    use crate::{
+        models::NewFlight,
+        schema::users,
+        test_utils::{DbTestContext, TestMailbox},
+    };
+
+    use super::*;
+
+    fn make_client(mailbox: &TestMailbox) -> Client {
+        let app = rocket::custom(crate::test_utils::make_test_config())
+            .attach(data::Database::fairing())
+            .manage(mailbox.mailer())
+            .manage(mailbox.config())
+            .register("/", catchers![crate::forbidden])
+            .mount("/", [api_routes(), auth::api_routes()].concat());
+        Client::untracked(app).expect("valid rocket instance")
+    }
+
+    fn make_admin(ctx: &DbTestContext) {
+        data::set_user_admin(&mut ctx.force_get_conn(), &ctx.testuser1.user.username, true).unwrap();
+    }
+
+    #[test]
+    fn requires_admin() {
+        let ctx = DbTestContext::new();
+        let mailbox = TestMailbox::new();
+        let client = make_client(&mailbox);
+
+        // Not logged in
+        let resp = client.get("/admin/users").dispatch();
+        assert_eq!(resp.status(), Status::Unauthorized);
+
+        // Not an admin
+        let resp = client
+            .get("/admin/users")
+            .private_cookie(ctx.auth_cookie_user1())
+            .cookie(ctx.username_cookie())
+            .dispatch();
+        assert_eq!(resp.status(), Status::Forbidden);
+
+        // Admin
+        make_admin(&ctx);
+        let resp = client
+            .get("/admin/users")
+            .private_cookie(ctx.auth_cookie_user1())
+            .cookie(ctx.username_cookie())
+            .dispatch();
+        assert_eq!(resp.status(), Status::Ok);
+    }
+
+    #[test]
+    fn list_and_search_users() {
+        let ctx = DbTestContext::new();
+        let mailbox = TestMailbox::new();
+        let client = make_client(&mailbox);
+        make_admin(&ctx);
+        data::create_flight(
+            &mut ctx.force_get_conn(),
+            &NewFlight {
+                user_id: ctx.testuser2.user.id,
+                ..Default::default()
+            },
+            Some(vec![0; 1234]),
+        );
+
+        let list = |query: &str| {
+            client
+                .get(format!("/admin/users{}", query))
+                .private_cookie(ctx.auth_cookie_user1())
+                .cookie(ctx.username_cookie())
+                .dispatch()
+                .into_json::<Vec<ApiAdminUser>>()
+                .unwrap()
+        };
+
+        let users = list("");
+        assert_eq!(users.len(), 2);
+        assert!(users[0].is_admin);
+        assert_eq!((users[0].flights, users[0].igc_bytes), (0, 0));
+        assert_eq!((users[1].flights, users[1].igc_bytes), (1, 1234));
+
+        // Search by username or e-mail (case insensitive)
+        let users = list("?search=USER2");
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0].username, "testuser2");
+        assert_eq!(list("?search=user1%40example").len(), 1);
+        assert_eq!(list("?search=%25").len(), 0);
+
+        // Pagination
+        let users = list("?limit=1&offset=1");
+        assert_eq!(users.len(), 1);
+        assert_eq!(users[0].username, "testuser2");
+
+        // Single user
+        let resp = client
+            .get(format!("/admin/users/{}", ctx.testuser2.user.id))
+            .private_cookie(ctx.auth_cookie_user1())
+            .cookie(ctx.username_cookie())
+            .dispatch();
+        assert_eq!(resp.into_json::<ApiAdminUser>().unwrap().igc_bytes, 1234);
+        let resp = client
+            .get("/admin/users/9999")
+            .private_cookie(ctx.auth_cookie_user1())
+            .cookie(ctx.username_cookie())
+            .dispatch();
+        assert_eq!(resp.status(), Status::NotFound);
+    }
+
+    #[test]
+    fn disable_and_enable_user() {
+        let ctx = DbTestContext::new();
+        let mailbox = TestMailbox::new();
+        let client = make_client(&mailbox);
+        make_admin(&ctx);
+
+        let post = |path: String| {
+            client
+                .post(path)
+                .private_cookie(ctx.auth_cookie_user1())
+                .cookie(ctx.username_cookie())
+                .dispatch()
+                .status()
+        };
+        let login = || data::validate_login(&mut ctx.force_get_conn(), "testuser2", "testpass").is_some();
+        let user2_id = ctx.testuser2.user.id;
+
+        // Admins cannot disable themselves
+        assert_eq!(
+            post(format!("/admin/users/{}/disable", ctx.testuser1.user.id)),
+            Status::BadRequest
+        );
+
+        // Disabled users are logged out and cannot log in
+        assert_eq!(
+            post(format!("/admin/users/{}/disable", user2_id)),
+            Status::NoContent
+        );
+        {
+            let conn = &mut *ctx.force_get_conn();
+            assert!(data::get_user(conn, user2_id).unwrap().disabled);
+            assert!(data::validate_session(conn, &ctx.testuser2.session_token).is_none());
+        }
+        assert!(!login());
+
+        // Enabled again
+        assert_eq!(
+            post(format!("/admin/users/{}/enable", user2_id)),
+            Status::NoContent
+        );
+        assert!(login());
+        assert_eq!(post("/admin/users/9999/enable".into()), Status::NotFound);
+    }
+
+    #[test]
+    fn password_reset_mail() {
+        let ctx = DbTestContext::new();
+        let mailbox = TestMailbox::new();
+        let client = make_client(&mailbox);
+        make_admin(&ctx);
+
+        let resp = client
+            .post(format!("/admin/users/{}/password-reset", ctx.testuser2.user.id))
+            .private_cookie(ctx.auth_cookie_user1())
+            .cookie(ctx.username_cookie())
+            .dispatch();
+        assert_eq!(resp.status(), Status::NoContent);
+        let mails = mailbox.mails();
+        assert_eq!(mails.len(), 1);
+        assert!(mails[0].contains("To: user2@example.com"));
+        assert!(mailbox.last_token("/auth/password/reset").is_some());
+    }
+
+    #[test]
+    fn instance_stats() {
+        let ctx = DbTestContext::new();
+        let mailbox = TestMailbox::new();
+        let client = make_client(&mailbox);
+        make_admin(&ctx);
+        {
+            let conn = &mut *ctx.force_get_conn();
+            diesel::update(users::table.find(ctx.testuser2.user.id))
+                .set(users::signed_up.eq(crate::test_utils::utc_datetime(2024, 3, 10, 12, 0, 0)))
+                .execute(conn)
+                .unwrap();
+            data::create_flight(
+                conn,
+                &NewFlight {
+                    user_id: ctx.testuser2.user.id,
+                    ..Default::default()
+                },
+                Some(vec![0; 100]),
+            );
+        }
+
+        let resp = client
+            .get("/admin/stats")
+            .private_cookie(ctx.auth_cookie_user1())
+            .cookie(ctx.username_cookie())
+            .dispatch();
+        let stats = resp.into_json::<ApiInstanceStats>().unwrap();
+        assert_eq!(
+            (stats.user_count, stats.flight_count, stats.igc_bytes),
+            (2, 1, 100)
+        );
+        let this_month = Utc::now().format("%Y-%m").to_string();
+        assert_eq!(
+            stats.months,
+            vec![
+                ApiMonthStats {
+                    month: "2024-03".into(),
+                    new_users: 1,
+                    new_flights: 0,
+                    total_users: 1,
+                    total_flights: 0,
+                },
+                ApiMonthStats {
+                    month: this_month,
+                    new_users: 1,
+                    new_flights: 1,
+                    total_users: 2,
+                    total_flights: 1,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,710 @@
+//! Airspaces and airspace infringement checks.
+//!
+//! Airspaces are read from OpenAir files on startup. The files are
+//! configured in the `airspace` section of the Rocket config:
+//!
+//! - `files`: Paths to OpenAir files (e.g. from <https://www.openaip.net/> or
+//!   a national airspace publication).
+//!
+//! Supported OpenAir records are `AC` (class), `AN` (name), `AL` / `AH`
+//! (floor and ceiling), `DP` (polygon point), `V X=` / `V D=` (arc center and
+//! direction), `DC` (circle), `DA` (arc by angles) and `DB` (arc by
+//! coordinates). Other records are ignored. Airspaces that cannot be parsed
+//! are skipped with a warning.
+
+use std::{
+    fmt::{self, Display},
+    fs,
+    path::PathBuf,
+    sync::{Arc, OnceLock},
+};
+
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    geodata::{ring_contains, Geodata},
+    process_igc::TrackFix,
+};
+
+/// One nautical mile in meters.
+const NAUTICAL_MILE_METERS: f64 = 1852.0;
+
+/// One foot in meters.
+const FOOT_METERS: f64 = 0.3048;
+
+/// Mean earth radius in meters.
+const EARTH_RADIUS_METERS: f64 = 6_371_000.0;
+
+/// Angle between two points when approximating arcs and circles (in degrees).
+const ARC_STEP_DEGREES: f64 = 5.0;
+
+/// Regex for OpenAir coordinates, see `parse_coordinate`.
+fn coordinate_regex() -> &'static Regex {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    REGEX.get_or_init(|| {
+        Regex::new(
+            r"(?x)
+            (\d+):(\d+(?:\.\d+)?)(?::(\d+(?:\.\d+)?))?\s*([NS])
+            \s*,?\s*
+            (\d+):(\d+(?:\.\d+)?)(?::(\d+(?:\.\d+)?))?\s*([EW])",
+        )
+        .unwrap()
+    })
+}
+
+/// Regex for OpenAir altitudes with a value, see `AltitudeLimit::parse`.
+fn altitude_regex() -> &'static Regex {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    REGEX.get_or_init(|| {
+        Regex::new(r"^(\d+(?:\.\d+)?)\s*(FT|F|M|MT|METERS?)?\s*(MSL|AMSL|ALT|AGL|AGND|ASFC|GND|SFC)?$")
+            .unwrap()
+    })
+}
+
+// Config
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct AirspaceConfig {
+    /// Paths to OpenAir files.
+    #[serde(default)]
+    pub files: Vec<PathBuf>,
+}
+
+// Data
+
+/// Lower or upper limit of an airspace.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AltitudeLimit {
+    /// The ground
+    Ground,
+    /// Meters above mean sea level
+    Msl(f64),
+    /// Meters above ground level
+    Agl(f64),
+    /// Flight level (hundreds of feet of pressure altitude)
+    FlightLevel(u16),
+    /// No upper limit
+    Unlimited,
+}
+
+impl AltitudeLimit {
+    /// Parse an OpenAir altitude (e.g. `GND`, `1500ft AMSL`, `300m AGL` or
+    /// `FL95`). Values without unit are in feet, values without reference
+    /// are above mean sea level.
+    fn parse(value: &str) -> Option<Self> {
+        let value = value.trim().to_ascii_uppercase();
+        match value.as_str() {
+            "GND" | "SFC" => return Some(Self::Ground),
+            "UNL" | "UNLIM" | "UNLTD" | "UNLIMITED" => return Some(Self::Unlimited),
+            _ => {}
+        }
+        if let Some(level) = value.strip_prefix("FL") {
+            return level.trim().parse().ok().map(Self::FlightLevel);
+        }
+        let captures = altitude_regex().captures(&value)?;
+        let number: f64 = captures[1].parse().ok()?;
+        let meters = match captures.get(2).map(|unit| unit.as_str()) {
+            None | Some("FT") | Some("F") => number * FOOT_METERS,
+            Some(_) => number,
+        };
+        match captures.get(3).map(|reference| reference.as_str()) {
+            None | Some("MSL") | Some("AMSL") | Some("ALT") => Some(Self::Msl(meters)),
+            Some(_) if meters == 0.0 => Some(Self::Ground),
+            Some(_) => Some(Self::Agl(meters)),
+        }
+    }
+
+    /// Return the limit in meters above mean sea level at the position of the
+    /// specified fix. Returns `None` for AGL limits if the terrain elevation
+    /// is unknown.
+    fn meters_msl(&self, fix: &TrackFix, geodata: &Geodata) -> Option<f64> {
+        match *self {
+            Self::Ground => Some(f64::NEG_INFINITY),
+            Self::Msl(meters) => Some(meters),
+            Self::Agl(meters) => geodata
+                .elevation_at(fix.lat, fix.lng)
+                .map(|elevation| f64::from(elevation) + meters),
+            Self::FlightLevel(level) => Some(f64::from(level) * 100.0 * FOOT_METERS),
+            Self::Unlimited => Some(f64::INFINITY),
+        }
+    }
+
+    /// Return the altitude of the fix that is compared with this limit.
+    /// Flight levels refer to the pressure altitude, all other limits to the
+    /// GPS altitude.
+    fn fix_altitude(&self, fix: &TrackFix) -> f64 {
+        match self {
+            Self::FlightLevel(_) if fix.pressure_alt != 0 => f64::from(fix.pressure_alt),
+            _ => f64::from(fix.gps_alt),
+        }
+    }
+}
+
+impl Display for AltitudeLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ground => write!(f, "GND"),
+            Self::Msl(meters) => write!(f, "{:.0} m MSL", meters),
+            Self::Agl(meters) => write!(f, "{:.0} m AGL", meters),
+            Self::FlightLevel(level) => write!(f, "FL{}", level),
+            Self::Unlimited => write!(f, "UNL"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Airspace {
+    /// Airspace class or type (e.g. `C`, `D`, `CTR` or `R`)
+    pub class: String,
+    pub name: String,
+    pub floor: AltitudeLimit,
+    pub ceiling: AltitudeLimit,
+    /// Outline as closed ring, points are (lon, lat)
+    pub outline: Vec<(f64, f64)>,
+    /// Bounding box of the outline (min lon, min lat, max lon, max lat)
+    bbox: (f64, f64, f64, f64),
+}
+
+impl Airspace {
+    /// Return whether the airspace laterally contains the specified point.
+    fn contains(&self, lat: f64, lon: f64) -> bool {
+        let (min_lon, min_lat, max_lon, max_lat) = self.bbox;
+        if lon < min_lon || lon > max_lon || lat < min_lat || lat > max_lat {
+            return false;
+        }
+        ring_contains(&self.outline, lon, lat)
+    }
+
+    /// Return whether the altitude of the fix is between floor and ceiling.
+    /// Returns `None` if this cannot be determined.
+    fn contains_altitude(&self, fix: &TrackFix, geodata: &Geodata) -> Option<bool> {
+        let floor = self.floor.meters_msl(fix, geodata)?;
+        let ceiling = self.ceiling.meters_msl(fix, geodata)?;
+        Some(self.floor.fix_altitude(fix) >= floor && self.ceiling.fix_altitude(fix) <= ceiling)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InfringementKind {
+    /// The airspace was entered through its lateral boundary
+    Horizontal,
+    /// The airspace was entered through its floor or ceiling
+    Vertical,
+}
+
+/// A part of a track within an airspace.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Infringement {
+    pub airspace_name: String,
+    pub airspace_class: String,
+    pub floor: String,
+    pub ceiling: String,
+    pub kind: InfringementKind,
+    /// Whether it is unknown if the track is within the vertical limits,
+    /// because the terrain elevation for an AGL limit is missing
+    pub undetermined: bool,
+    /// Time of the first fix within the airspace (UTC)
+    pub start_time_hms: (u8, u8, u8),
+    /// Time of the last fix within the airspace (UTC)
+    pub end_time_hms: (u8, u8, u8),
+    /// Position of the first fix within the airspace
+    pub lat: f64,
+    pub lng: f64,
+    /// GPS altitude of the first fix within the airspace in meters
+    pub altitude: i16,
+    /// Highest GPS altitude within the airspace in meters
+    pub max_altitude: i16,
+    /// Lowest GPS altitude within the airspace in meters
+    pub min_altitude: i16,
+}
+
+/// All loaded airspaces. They are shared between clones.
+#[derive(Debug, Default, Clone)]
+pub struct Airspaces {
+    airspaces: Arc<Vec<Airspace>>,
+}
+
+impl Airspaces {
+    /// Load all configured OpenAir files.
+    pub fn load(config: &AirspaceConfig) -> Result<Self> {
+        let mut airspaces = vec![];
+        for path in &config.files {
+            // OpenAir files are often Latin-1 encoded
+            let bytes = fs::read(path).with_context(|| format!("Could not open {}", path.display()))?;
+            let parsed = parse_openair(&String::from_utf8_lossy(&bytes));
+            log::info!("Loaded {} airspaces from {}", parsed.len(), path.display());
+            airspaces.extend(parsed);
+        }
+        Ok(Self {
+            airspaces: Arc::new(airspaces),
+        })
+    }
+
+    /// Whether no airspaces are loaded.
+    pub fn is_empty(&self) -> bool {
+        self.airspaces.is_empty()
+    }
+
+    /// Check a track against all airspaces. Returns all parts of the track
+    /// that are within an airspace, ordered by time.
+    ///
+    /// AGL limits need the terrain elevation from the geodata. Where it is
+    /// unknown, the parts of the track within the lateral boundary are
+    /// returned as undetermined.
+    pub fn check_track(&self, track: &[TrackFix], geodata: &Geodata) -> Vec<Infringement> {
+        let mut infringements = vec![];
+        for airspace in self.airspaces.iter() {
+            let mut current: Option<Infringement> = None;
+            let mut previous_laterally_inside = false;
+            for fix in track {
+                let laterally_inside = airspace.contains(fix.lat, fix.lng);
+                let inside = if laterally_inside {
+                    airspace.contains_altitude(fix, geodata)
+                } else {
+                    Some(false)
+                };
+                let undetermined = inside.is_none();
+                if inside == Some(false)
+                    || current
+                        .as_ref()
+                        .is_some_and(|infringement| infringement.undetermined != undetermined)
+                {
+                    infringements.extend(current.take());
+                }
+                match (inside, current.as_mut()) {
+                    (Some(false), _) => {}
+                    (_, Some(infringement)) => {
+                        infringement.end_time_hms = fix.time_hms;
+                        infringement.max_altitude = infringement.max_altitude.max(fix.gps_alt);
+                        infringement.min_altitude = infringement.min_altitude.min(fix.gps_alt);
+                    }
+                    (_, None) => {
+                        current = Some(Infringement {
+                            airspace_name: airspace.name.clone(),
+                            airspace_class: airspace.class.clone(),
+                            floor: airspace.floor.to_string(),
+                            ceiling: airspace.ceiling.to_string(),
+                            kind: if previous_laterally_inside {
+                                InfringementKind::Vertical
+                            } else {
+                                InfringementKind::Horizontal
+                            },
+                            undetermined,
+                            start_time_hms: fix.time_hms,
+                            end_time_hms: fix.time_hms,
+                            lat: fix.lat,
+                            lng: fix.lng,
+                            altitude: fix.gps_alt,
+                            max_altitude: fix.gps_alt,
+                            min_altitude: fix.gps_alt,
+                        });
+                    }
+                }
+                previous_laterally_inside = laterally_inside;
+            }
+            infringements.extend(current);
+        }
+        infringements.sort_by_key(|infringement| infringement.start_time_hms);
+        infringements
+    }
+}
+
+/// Parse an OpenAir coordinate (e.g. `46:30:00 N 009:15:30 E` or
+/// `46:30.5 N 9:15.5 E`) into (lat, lon).
+fn parse_coordinate(value: &str) -> Option<(f64, f64)> {
+    let captures = coordinate_regex().captures(value)?;
+    let degrees = |d: usize, m: usize, s: usize| -> Option<f64> {
+        let seconds = match captures.get(s) {
+            Some(seconds) => seconds.as_str().parse::<f64>().ok()?,
+            None => 0.0,
+        };
+        Some(captures[d].parse::<f64>().ok()? + captures[m].parse::<f64>().ok()? / 60.0 + seconds / 3600.0)
+    };
+    let mut lat = degrees(1, 2, 3)?;
+    let mut lon = degrees(5, 6, 7)?;
+    if &captures[4] == "S" {
+        lat = -lat;
+    }
+    if &captures[8] == "W" {
+        lon = -lon;
+    }
+    if lat.abs() > 90.0 || lon.abs() > 180.0 {
+        return None;
+    }
+    Some((lat, lon))
+}
+
+/// Return the point at the specified distance and bearing (in degrees) from
+/// the center, as (lat, lon).
+fn destination((lat, lon): (f64, f64), bearing: f64, distance_meters: f64) -> (f64, f64) {
+    let (lat, lon, bearing) = (lat.to_radians(), lon.to_radians(), bearing.to_radians());
+    let angle = distance_meters / EARTH_RADIUS_METERS;
+    let lat2 = (lat.sin() * angle.cos() + lat.cos() * angle.sin() * bearing.cos()).asin();
+    let lon2 = lon + (bearing.sin() * angle.sin() * lat.cos()).atan2(angle.cos() - lat.sin() * lat2.sin());
+    (lat2.to_degrees(), (lon2.to_degrees() + 540.0) % 360.0 - 180.0)
+}
+
+/// Return the initial bearing (in degrees) from the first to the second point.
+fn bearing(from: (f64, f64), to: (f64, f64)) -> f64 {
+    let (lat1, lat2) = (from.0.to_radians(), to.0.to_radians());
+    let delta_lon = (to.1 - from.1).to_radians();
+    let y = delta_lon.sin() * lat2.cos();
+    let x = lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * delta_lon.cos();
+    y.atan2(x).to_degrees()
+}
+
+/// Great-circle distance between two points in meters.
+fn distance(a: (f64, f64), b: (f64, f64)) -> f64 {
+    crate::locations::distance_meters(a.0, a.1, b.0, b.1)
+}
+
+/// Return the points of an arc around the center from the start to the end
+/// bearing (in degrees), as (lat, lon).
+fn arc(center: (f64, f64), radius_meters: f64, start: f64, end: f64, clockwise: bool) -> Vec<(f64, f64)> {
+    let mut sweep = if clockwise { end - start } else { start - end }.rem_euclid(360.0);
+    if sweep == 0.0 && start != end {
+        // Full circle
+        sweep = 360.0;
+    }
+    if !clockwise {
+        sweep = -sweep;
+    }
+    let steps = ((sweep.abs() / ARC_STEP_DEGREES).ceil() as usize).max(1);
+    (0..=steps)
+        .map(|step| destination(center, start + sweep * step as f64 / steps as f64, radius_meters))
+        .collect()
+}
+
+/// Airspace that is being parsed.
+#[derive(Default)]
+struct AirspaceBuilder {
+    class: String,
+    name: Option<String>,
+    floor: Option<AltitudeLimit>,
+    ceiling: Option<AltitudeLimit>,
+    /// Points as (lat, lon)
+    points: Vec<(f64, f64)>,
+    center: Option<(f64, f64)>,
+    clockwise: bool,
+    /// Parse error, the airspace is skipped
+    error: Option<String>,
+}
+
+impl AirspaceBuilder {
+    fn new(class: &str) -> Self {
+        Self {
+            class: class.trim().to_string(),
+            clockwise: true,
+            ..Default::default()
+        }
+    }
+
+    fn fail(&mut self, line_number: usize, message: &str) {
+        if self.error.is_none() {
+            self.error = Some(format!("Line {}: {}", line_number, message));
+        }
+    }
+
+    /// Process a record of this airspace.
+    fn add_record(&mut self, line_number: usize, record: &str, value: &str) {
+        match record {
+            "AN" => self.name = Some(value.to_string()),
+            "AL" | "AH" => match AltitudeLimit::parse(value) {
+                Some(limit) if record == "AL" => self.floor = Some(limit),
+                Some(limit) => self.ceiling = Some(limit),
+                None => self.fail(line_number, "Invalid altitude"),
+            },
+            "DP" => match parse_coordinate(value) {
+                Some(point) => self.points.push(point),
+                None => self.fail(line_number, "Invalid coordinate"),
+            },
+            "V" => {
+                let (key, value) = value.split_once('=').unwrap_or(("", ""));
+                match key.trim().to_ascii_uppercase().as_str() {
+                    "X" => match parse_coordinate(value) {
+                        Some(center) => self.center = Some(center),
+                        None => self.fail(line_number, "Invalid center"),
+                    },
+                    "D" => self.clockwise = value.trim() != "-",
+                    _ => {}
+                }
+            }
+            "DC" | "DA" | "DB" => {
+                let center = match self.center {
+                    Some(center) => center,
+                    None => return self.fail(line_number, "Arc without center"),
+                };
+                let points = match record {
+                    "DC" => value
+                        .trim()
+                        .parse::<f64>()
+                        .ok()
+                        .map(|radius| arc(center, radius * NAUTICAL_MILE_METERS, 0.0, 360.0, true)),
+                    "DA" => {
+                        let numbers = value
+                            .split(',')
+                            .map(|number| number.trim().parse::<f64>().ok())
+                            .collect::<Option<Vec<_>>>();
+                        match numbers.as_deref() {
+                            Some(&[radius, start, end]) => Some(arc(
+                                center,
+                                radius * NAUTICAL_MILE_METERS,
+                                start,
+                                end,
+                                self.clockwise,
+                            )),
+                            _ => None,
+                        }
+                    }
+                    _ => value.split_once(',').and_then(|(from, to)| {
+                        let (from, to) = (parse_coordinate(from)?, parse_coordinate(to)?);
+                        let mut points = arc(
+                            center,
+                            distance(center, from),
+                            bearing(center, from),
+                            bearing(center, to),
+                            self.clockwise,
+                        );
+                        // Use the exact end points
+                        *points.first_mut()? = from;
+                        *points.last_mut()? = to;
+                        Some(points)
+                    }),
+                };
+                match points {
+                    Some(points) => self.points.extend(points),
+                    None => self.fail(line_number, "Invalid arc"),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn build(self) -> Result<Airspace, String> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        let name = self.name.ok_or("Name is missing")?;
+        let (floor, ceiling) = match (self.floor, self.ceiling) {
+            (Some(floor), Some(ceiling)) => (floor, ceiling),
+            _ => return Err(format!("{}: Floor or ceiling is missing", name)),
+        };
+        let mut outline: Vec<(f64, f64)> = self.points.into_iter().map(|(lat, lon)| (lon, lat)).collect();
+        if outline.len() < 3 {
+            return Err(format!("{}: Outline has less than three points", name));
+        }
+        if outline.first() != outline.last() {
+            outline.push(outline[0]);
+        }
+        let bbox = outline.iter().fold(
+            (f64::INFINITY, f64::INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+            |(min_lon, min_lat, max_lon, max_lat), &(lon, lat)| {
+                (
+                    min_lon.min(lon),
+                    min_lat.min(lat),
+                    max_lon.max(lon),
+                    max_lat.max(lat),
+                )
+            },
+        );
+        Ok(Airspace {
+            class: self.class,
+            name,
+            floor,
+            ceiling,
+            outline,
+            bbox,
+        })
+    }
+}
+
+/// Parse the airspaces of an OpenAir file. Invalid airspaces are skipped.
+fn parse_openair(text: &str) -> Vec<Airspace> {
+    let mut airspaces = vec![];
+    let mut current: Option<AirspaceBuilder> = None;
+    let mut finish = |builder: Option<AirspaceBuilder>| {
+        if let Some(builder) = builder {
+            match builder.build() {
+                Ok(airspace) => airspaces.push(airspace),
+                Err(e) => log::warn!("Skipping invalid airspace: {}", e),
+            }
+        }
+    };
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('*') {
+            continue;
+        }
+        let (record, value) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let record = record.to_ascii_uppercase();
+        if record == "AC" {
+            finish(current.replace(AirspaceBuilder::new(value)));
+        } else if let Some(builder) = current.as_mut() {
+            builder.add_record(index + 1, &record, value.trim());
+        }
+    }
+    finish(current);
+    airspaces
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::{geodata::tests::test_geodata, process_igc::parse_track};
+
+    use super::*;
+
+    /// Load the test airspaces.
+    pub fn test_airspaces() -> Airspaces {
+        Airspaces::load(&AirspaceConfig {
+            files: vec!["testdata/airspace/test.txt".into()],
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn parse_altitude() {
+        assert_eq!(AltitudeLimit::parse("GND"), Some(AltitudeLimit::Ground));
+        assert_eq!(AltitudeLimit::parse("sfc"), Some(AltitudeLimit::Ground));
+        assert_eq!(AltitudeLimit::parse("0 AGL"), Some(AltitudeLimit::Ground));
+        assert_eq!(AltitudeLimit::parse("UNL"), Some(AltitudeLimit::Unlimited));
+        assert_eq!(
+            AltitudeLimit::parse("FL 95"),
+            Some(AltitudeLimit::FlightLevel(95))
+        );
+        assert_eq!(
+            AltitudeLimit::parse("2000m MSL"),
+            Some(AltitudeLimit::Msl(2000.0))
+        );
+        assert_eq!(
+            AltitudeLimit::parse("1000ft AMSL"),
+            Some(AltitudeLimit::Msl(304.8))
+        );
+        assert_eq!(AltitudeLimit::parse("1000"), Some(AltitudeLimit::Msl(304.8)));
+        assert_eq!(AltitudeLimit::parse("300 M AGL"), Some(AltitudeLimit::Agl(300.0)));
+        assert_eq!(AltitudeLimit::parse("1000F SFC"), Some(AltitudeLimit::Agl(304.8)));
+        assert_eq!(AltitudeLimit::parse("high"), None);
+        assert_eq!(AltitudeLimit::Msl(304.8).to_string(), "305 m MSL");
+    }
+
+    #[test]
+    fn parse_coordinates() {
+        assert_eq!(parse_coordinate("46:30:00 N 009:15:00 E"), Some((46.5, 9.25)));
+        assert_eq!(parse_coordinate("46:30.0N 9:15.0W"), Some((46.5, -9.25)));
+        assert_eq!(
+            parse_coordinate("13:09:36 S, 072:32:42 W"),
+            Some((-13.16, -72.545))
+        );
+        assert_eq!(parse_coordinate("96:00:00 N 009:00:00 E"), None);
+        assert_eq!(parse_coordinate("46.5 9.25"), None);
+    }
+
+    #[test]
+    fn parse_file() {
+        let airspaces = test_airspaces().airspaces;
+        let names = airspaces.iter().map(|a| a.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                "Vrin Field",
+                "Lumnezia TMA",
+                "Vrin Terrain",
+                "Vaduz CTR",
+                "Vals Sector"
+            ]
+        );
+
+        // Polygon
+        let field = &airspaces[0];
+        assert_eq!(field.class, "D");
+        assert_eq!(field.floor, AltitudeLimit::Ground);
+        assert_eq!(field.ceiling, AltitudeLimit::Msl(2000.0));
+        assert_eq!(field.outline.len(), 5);
+        assert_eq!(field.outline.first(), field.outline.last());
+
+        // Circle with a radius of 2 NM
+        let vaduz = &airspaces[3];
+        assert_eq!(vaduz.floor, AltitudeLimit::Ground);
+        assert_eq!(vaduz.ceiling, AltitudeLimit::FlightLevel(100));
+        let center = (47.14, 9.52);
+        for &(lon, lat) in &vaduz.outline {
+            assert!((distance(center, (lat, lon)) - 3704.0).abs() < 1.0);
+        }
+        assert!(vaduz.contains(47.15, 9.53));
+        assert!(!vaduz.contains(47.2, 9.52));
+
+        // Counterclockwise arcs by angles and by coordinates
+        let sector = &airspaces[4];
+        assert!(sector.contains(46.62, 9.15));
+        assert!(!sector.contains(46.58, 9.15));
+        assert!(!sector.contains(46.62, 9.05));
+    }
+
+    #[test]
+    fn check_track() {
+        let track = parse_track(include_bytes!("../testdata/skytraxx.igc"));
+        let infringements = test_airspaces().check_track(&track, &test_geodata());
+        let summary = infringements
+            .iter()
+            .map(|i| (i.airspace_name.as_str(), i.kind, i.start_time_hms, i.end_time_hms))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                // Entered through the lateral boundary while descending
+                (
+                    "Vrin Field",
+                    InfringementKind::Horizontal,
+                    (13, 43, 52),
+                    (13, 46, 7)
+                ),
+                // Descended through the ceiling
+                (
+                    "Lumnezia TMA",
+                    InfringementKind::Vertical,
+                    (13, 43, 59),
+                    (13, 46, 7)
+                ),
+                // Descended below 1000 ft above the terrain
+                (
+                    "Vrin Terrain",
+                    InfringementKind::Vertical,
+                    (13, 45, 10),
+                    (13, 46, 7)
+                ),
+            ]
+        );
+        let tma = &infringements[1];
+        assert_eq!(tma.ceiling, "1400 m MSL");
+        assert_eq!(tma.altitude, 1400);
+        assert_eq!(tma.min_altitude, 1299);
+
+        assert!(infringements.iter().all(|i| !i.undetermined));
+
+        // Without terrain data, AGL airspaces cannot be checked
+        let infringements = test_airspaces().check_track(&track, &Geodata::default());
+        let summary = infringements
+            .iter()
+            .map(|i| {
+                (
+                    i.airspace_name.as_str(),
+                    i.undetermined,
+                    i.start_time_hms,
+                    i.end_time_hms,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                // The whole track is within the lateral boundary
+                ("Vrin Terrain", true, (13, 42, 26), (13, 46, 7)),
+                ("Vrin Field", false, (13, 43, 52), (13, 46, 7)),
+                ("Lumnezia TMA", false, (13, 43, 59), (13, 46, 7)),
+            ]
+        );
+    }
+}
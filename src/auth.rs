@@ -2,6 +2,7 @@
 
 use std::sync::Arc;
 
+use diesel::QueryResult;
 use log::{error, warn};
 use rocket::{
     http::{Cookie, CookieJar, SameSite, Status},
@@ -11,18 +12,35 @@ use rocket::{
     routes,
     serde::json::Json,
     time::{Duration, OffsetDateTime},
-    Route,
+    Route, State,
 };
 use serde::{Deserialize, Serialize};
 
 use crate::{
     data::{self, Database},
+    mail::{Mail, Mailer},
     models::User,
+    rate_limit::LoginAllowed,
     responders::{ApiError, RocketError},
+    tokens, totp, Config,
 };
 
-pub const USER_COOKIE_ID: &str = "user_id";
+pub const SESSION_COOKIE_ID: &str = "session_id";
 pub const USER_COOKIE_NAME: &str = "user_name";
+const LOGIN_CHALLENGE_COOKIE_ID: &str = "login_challenge";
+
+/// How long the second login step (TOTP) may take after the password was
+/// verified.
+const LOGIN_CHALLENGE_VALIDITY_MINUTES: i64 = 5;
+
+/// Number of wrong TOTP codes after which a login challenge is invalidated.
+const LOGIN_CHALLENGE_MAX_FAILURES: i32 = 3;
+
+/// How long a password reset token stays valid.
+const PASSWORD_RESET_VALIDITY_HOURS: i64 = 1;
+
+/// How long an e-mail verification token stays valid.
+const EMAIL_VERIFICATION_VALIDITY_HOURS: i64 = 48;
 
 // Forms
 
@@ -33,6 +51,13 @@ pub struct Login {
     password: String,
 }
 
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TotpLogin {
+    code: Option<String>,
+    recovery_code: Option<String>,
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Registration {
@@ -49,9 +74,55 @@ pub struct PasswordChange {
     new_password: String,
 }
 
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PasswordResetRequest {
+    email: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PasswordReset {
+    token: String,
+    new_password: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct EmailVerification {
+    token: String,
+}
+
 /// User newtype, wraps the user model, provides guard transparency.
 #[derive(Debug)]
-pub struct AuthUser(User);
+pub struct AuthUser {
+    user: User,
+    /// The login session, if the request was authenticated by session cookie
+    session_id: Option<i32>,
+}
+
+/// A logged in user with the admin role.
+pub struct AdminUser {
+    user: User,
+}
+
+/// Information about the client, stored with login sessions.
+pub struct ClientInfo {
+    user_agent: Option<String>,
+    ip_address: Option<String>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ClientInfo {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        Outcome::Success(ClientInfo {
+            user_agent: request.headers().get_one("User-Agent").map(ToString::to_string),
+            ip_address: request.client_ip().map(|ip| ip.to_string()),
+        })
+    }
+}
 
 fn make_cookie(name: &'static str, value: String) -> Cookie<'static> {
     // Cookie expiration: 1 year
@@ -66,19 +137,104 @@ fn make_cookie(name: &'static str, value: String) -> Cookie<'static> {
     cookie
 }
 
-/// Add auth cookies to the specified cookie jar.
-fn add_auth_cookies(cookies: &CookieJar, user: &User) {
-    cookies.add_private(make_cookie(USER_COOKIE_ID, user.id.to_string()));
-    cookies.add(make_cookie(USER_COOKIE_NAME, user.username.clone()));
+/// Create a login session and add the auth cookies to the specified cookie jar.
+pub async fn start_session(
+    cookies: &CookieJar<'_>,
+    database: &Database,
+    client: ClientInfo,
+    user: User,
+) -> Result<(), (Status, Json<RocketError>)> {
+    let username = user.username.clone();
+    let token = database
+        .run(move |db| {
+            data::create_session(
+                db,
+                &user,
+                client.user_agent.as_deref(),
+                client.ip_address.as_deref(),
+            )
+        })
+        .await
+        .map(|(_session, token)| token)
+        .map_err(|e| {
+            error!("Could not create session: {}", e);
+            RocketError::new(
+                Status::InternalServerError,
+                "SessionError",
+                "Could not create session",
+            )
+        })?;
+    cookies.add_private(make_cookie(SESSION_COOKIE_ID, token));
+    cookies.add(make_cookie(USER_COOKIE_NAME, username));
+    Ok(())
+}
+
+/// A pending second login step (TOTP code).
+///
+/// The challenge is stored in the database and referenced by a private cookie
+/// as `<challenge_id>:<username>`. The username is used for rate limiting.
+pub struct LoginChallengeCookie {
+    pub challenge_id: i32,
+    pub username: String,
+}
+
+/// Return the pending login challenge referenced by the private cookie.
+///
+/// The challenge itself may have expired or may have been deleted.
+pub fn get_login_challenge(cookies: &CookieJar) -> Option<LoginChallengeCookie> {
+    let cookie = cookies.get_private(LOGIN_CHALLENGE_COOKIE_ID)?;
+    let (challenge_id, username) = cookie.value().split_once(':')?;
+    Some(LoginChallengeCookie {
+        challenge_id: challenge_id.parse().ok()?,
+        username: username.to_string(),
+    })
+}
+
+/// Start the second login step for a user with two-factor authentication,
+/// after the first factor was verified. The login must be completed at
+/// `/auth/login/totp`.
+pub async fn start_login_challenge(
+    cookies: &CookieJar<'_>,
+    database: &Database,
+    user: User,
+) -> Result<(), (Status, Json<RocketError>)> {
+    let username = user.username.clone();
+    let challenge = database
+        .run(move |db| {
+            data::create_login_challenge(
+                db,
+                &user,
+                chrono::Duration::minutes(LOGIN_CHALLENGE_VALIDITY_MINUTES),
+            )
+        })
+        .await
+        .map_err(|e| {
+            error!("Could not create login challenge: {}", e);
+            RocketError::new(
+                Status::InternalServerError,
+                "SessionError",
+                "Could not start login",
+            )
+        })?;
+    let value = format!("{}:{}", challenge.id, username);
+    let mut cookie = Cookie::new(LOGIN_CHALLENGE_COOKIE_ID, value);
+    cookie.set_max_age(Duration::minutes(LOGIN_CHALLENGE_VALIDITY_MINUTES));
+    cookie.set_same_site(SameSite::Lax);
+    cookies.add_private(cookie);
+    Ok(())
 }
 
 /// Remove auth cookies from the specified cookie jar.
-fn remove_auth_cookies(cookies: &CookieJar) {
-    cookies.remove_private(Cookie::from(USER_COOKIE_ID));
+pub fn remove_auth_cookies(cookies: &CookieJar) {
+    cookies.remove_private(Cookie::from(SESSION_COOKIE_ID));
     cookies.remove(Cookie::from(USER_COOKIE_NAME));
 }
 
-/// Get the user model from a request cookie.
+/// Get the user model from an API token or from a request cookie.
+///
+/// If an `Authorization: Bearer` header is present, the token must be valid
+/// and its scopes must permit the request. Otherwise, the request is
+/// authenticated through the session token in the private login cookie.
 #[rocket::async_trait]
 impl<'r> FromRequest<'r> for AuthUser {
     type Error = ();
@@ -91,19 +247,40 @@ impl<'r> FromRequest<'r> for AuthUser {
             Outcome::Success(database) => database,
         };
 
+        // Look up API token
+        let bearer_token = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|header| header.strip_prefix("Bearer "))
+            .map(|token| token.trim().to_string());
+        if let Some(token) = bearer_token {
+            return match database.run(move |db| data::validate_api_token(db, &token)).await {
+                Some((api_token, user)) => {
+                    if tokens::scopes_permit(
+                        &api_token.scopes,
+                        request.method(),
+                        request.uri().path().as_str(),
+                    ) {
+                        Outcome::Success(AuthUser {
+                            user,
+                            session_id: None,
+                        })
+                    } else {
+                        warn!("API token {} lacks scope for {}", api_token.id, request.uri());
+                        Outcome::Error((Status::Forbidden, ()))
+                    }
+                }
+                None => Outcome::Forward(Status::Unauthorized),
+            };
+        }
+
         // Look up login cookie
         let cookies = request.cookies();
-        let user_cookie = match cookies.get_private(USER_COOKIE_ID) {
-            Some(cookie) => cookie,
+        let session_token = match cookies.get_private(SESSION_COOKIE_ID) {
+            Some(cookie) => cookie.value().to_string(),
             None => return Outcome::Forward(Status::Unauthorized),
         };
 
-        // Extract user ID
-        let user_id: i32 = match user_cookie.value().parse() {
-            Ok(int) => int,
-            Err(_) => return Outcome::Forward(Status::Unauthorized),
-        };
-
         // Ensure that username cookie is set as well
         if cookies.get(USER_COOKIE_NAME).is_none() {
             error!("Login cookie but no username cookie found. Removing auth cookies.");
@@ -111,11 +288,17 @@ impl<'r> FromRequest<'r> for AuthUser {
             return Outcome::Forward(Status::Unauthorized);
         }
 
-        // A login cookie was found. Look up the corresponding database user.
-        match database.run(move |db| data::get_user(db, user_id)).await {
-            Some(user) => Outcome::Success(AuthUser(user)),
+        // A login cookie was found. Look up the corresponding session.
+        match database
+            .run(move |db| data::validate_session(db, &session_token))
+            .await
+        {
+            Some((session, user)) => Outcome::Success(AuthUser {
+                user,
+                session_id: Some(session.id),
+            }),
             None => {
-                error!("Login cookie with invalid user id found. Removing cookie.");
+                warn!("Login cookie with invalid or revoked session found. Removing cookie.");
                 remove_auth_cookies(cookies);
                 Outcome::Forward(Status::Unauthorized)
             }
@@ -123,10 +306,47 @@ impl<'r> FromRequest<'r> for AuthUser {
     }
 }
 
+/// Get the user model of a logged in administrator.
+///
+/// Requests of users without the admin role are rejected with "HTTP 403
+/// Forbidden". Requests without authentication are forwarded.
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AdminUser {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        match request.guard::<AuthUser>().await {
+            Outcome::Success(auth_user) if auth_user.user.is_admin => {
+                Outcome::Success(AdminUser { user: auth_user.user })
+            }
+            Outcome::Success(auth_user) => {
+                warn!("User {} is not an admin", auth_user.user.id);
+                Outcome::Error((Status::Forbidden, ()))
+            }
+            Outcome::Error(e) => Outcome::Error(e),
+            Outcome::Forward(status) => Outcome::Forward(status),
+        }
+    }
+}
+
+impl AdminUser {
+    /// Convert this guard type into the inner user model.
+    pub fn into_inner(self) -> User {
+        self.user
+    }
+}
+
 impl AuthUser {
     /// Convert this guard type into the inner user model.
     pub fn into_inner(self) -> User {
-        self.0
+        self.user
+    }
+
+    /// Return the ID of the login session that authenticated this request.
+    ///
+    /// Requests authenticated with an API token have no session.
+    pub fn session_id(&self) -> Option<i32> {
+        self.session_id
     }
 }
 
@@ -135,12 +355,18 @@ impl AuthUser {
 /// Login handler.
 ///
 /// - Return "HTTP 204 No Content" if login was successful.
+/// - Return "HTTP 202 Accepted" if the password was correct, but a TOTP code
+///   must be submitted to `/auth/login/totp` to complete the login.
 /// - Return "HTTP 400 Bad Request" if request was malformed.
 /// - Return "HTTP 403 Forbidden" if username or password were wrong.
+/// - Return "HTTP 429 Too Many Requests" if there were too many failed
+///   attempts (see `rate_limit` module).
 #[post("/auth/login", data = "<login>")]
 pub async fn login(
+    _allowed: LoginAllowed,
     cookies: &CookieJar<'_>,
     database: Database,
+    client: ClientInfo,
     login: Json<Login>,
 ) -> Result<Status, (Status, Json<RocketError>)> {
     let username = login.username.clone();
@@ -149,8 +375,18 @@ pub async fn login(
         .await
     {
         Some(user) => {
-            // Success, add auth cookies
-            add_auth_cookies(cookies, &user);
+            // If two-factor authentication is enabled, a second step is required
+            let user_clone = user.clone();
+            if database
+                .run(move |db| data::is_totp_enabled(db, &user_clone))
+                .await
+            {
+                start_login_challenge(cookies, &database, user).await?;
+                return Ok(Status::Accepted);
+            }
+
+            // Success, create session
+            start_session(cookies, &database, client, user).await?;
             Ok(Status::NoContent)
         }
         None => {
@@ -165,9 +401,128 @@ pub async fn login(
     }
 }
 
-/// Logout handler.
+/// Result of the second login step.
+enum TotpLoginOutcome {
+    /// There is no valid login challenge.
+    NoChallenge,
+    /// The code was correct.
+    Success(User),
+    /// The code was wrong. The challenge is deleted after too many wrong codes.
+    BadCode { user_id: i32, challenge_deleted: bool },
+}
+
+/// Second login step for users with two-factor authentication. Either a TOTP
+/// `code` or a `recoveryCode` must be provided.
+///
+/// After `LOGIN_CHALLENGE_MAX_FAILURES` wrong codes, the login challenge is
+/// invalidated and the login must be restarted with username and password.
+///
+/// - Return "HTTP 204 No Content" if login was successful.
+/// - Return "HTTP 401 Unauthorized" if there is no pending login (or if it
+///   expired or was invalidated).
+/// - Return "HTTP 403 Forbidden" if the code was wrong.
+/// - Return "HTTP 429 Too Many Requests" if there were too many failed
+///   attempts (see `rate_limit` module).
+#[post("/auth/login/totp", data = "<totp_login>")]
+pub async fn login_totp(
+    _allowed: LoginAllowed,
+    cookies: &CookieJar<'_>,
+    database: Database,
+    client: ClientInfo,
+    totp_login: Json<TotpLogin>,
+) -> Result<Status, (Status, Json<RocketError>)> {
+    let no_challenge = || {
+        RocketError::new(
+            Status::Unauthorized,
+            "NoLoginChallenge",
+            "No pending login, please log in with username and password",
+        )
+    };
+    let challenge_id = get_login_challenge(cookies)
+        .ok_or_else(no_challenge)?
+        .challenge_id;
+    let totp_login = totp_login.into_inner();
+    let outcome = database
+        .run(move |db| -> QueryResult<TotpLoginOutcome> {
+            let validity = chrono::Duration::minutes(LOGIN_CHALLENGE_VALIDITY_MINUTES);
+            let challenge = match data::get_login_challenge(db, challenge_id, validity)? {
+                Some(challenge) => challenge,
+                None => return Ok(TotpLoginOutcome::NoChallenge),
+            };
+            let user = match data::get_user(db, challenge.user_id) {
+                Some(user) => user,
+                None => return Ok(TotpLoginOutcome::NoChallenge),
+            };
+            let valid = totp::verify_second_factor(
+                db,
+                &user,
+                totp_login.code.as_deref(),
+                totp_login.recovery_code.as_deref(),
+            )?;
+            if valid {
+                data::delete_login_challenge(db, challenge.id)?;
+                Ok(TotpLoginOutcome::Success(user))
+            } else {
+                let remaining =
+                    data::register_login_challenge_failure(db, challenge.id, LOGIN_CHALLENGE_MAX_FAILURES)?;
+                Ok(TotpLoginOutcome::BadCode {
+                    user_id: user.id,
+                    challenge_deleted: !remaining,
+                })
+            }
+        })
+        .await
+        .map_err(|e| {
+            error!("Could not verify two-factor authentication code: {}", e);
+            RocketError::new(
+                Status::InternalServerError,
+                "TotpError",
+                "Could not verify two-factor authentication code",
+            )
+        })?;
+    match outcome {
+        TotpLoginOutcome::NoChallenge => {
+            cookies.remove_private(Cookie::from(LOGIN_CHALLENGE_COOKIE_ID));
+            Err(no_challenge())
+        }
+        TotpLoginOutcome::Success(user) => {
+            cookies.remove_private(Cookie::from(LOGIN_CHALLENGE_COOKIE_ID));
+            start_session(cookies, &database, client, user).await?;
+            Ok(Status::NoContent)
+        }
+        TotpLoginOutcome::BadCode {
+            user_id,
+            challenge_deleted,
+        } => {
+            warn!("Two-factor authentication failed for user {}", user_id);
+            if challenge_deleted {
+                warn!(
+                    "Invalidated login challenge of user {} after too many wrong codes",
+                    user_id
+                );
+                cookies.remove_private(Cookie::from(LOGIN_CHALLENGE_COOKIE_ID));
+            }
+            Err(RocketError::new(
+                Status::Forbidden,
+                "BadCode",
+                "Wrong two-factor authentication code",
+            ))
+        }
+    }
+}
+
+/// Logout handler. The current session is revoked.
 #[post("/auth/logout")]
-pub fn logout(cookies: &CookieJar<'_>) -> Status {
+pub async fn logout(cookies: &CookieJar<'_>, database: Database) -> Status {
+    if let Some(cookie) = cookies.get_private(SESSION_COOKIE_ID) {
+        let token = cookie.value().to_string();
+        if let Err(e) = database
+            .run(move |db| data::delete_session_by_token(db, &token))
+            .await
+        {
+            error!("Could not delete session: {}", e);
+        }
+    }
     remove_auth_cookies(cookies);
     Status::NoContent
 }
@@ -180,6 +535,9 @@ pub fn logout(cookies: &CookieJar<'_>) -> Status {
 pub async fn registration(
     cookies: &CookieJar<'_>,
     database: Database,
+    mailer: &State<Mailer>,
+    config: &State<Config>,
+    client: ClientInfo,
     registration: Json<Registration>,
 ) -> Result<Status, (Status, Json<RocketError>)> {
     // TODO: Transation for registration
@@ -209,7 +567,9 @@ pub async fn registration(
                     )
                 })
                 .await;
-            add_auth_cookies(cookies, &new_user);
+            let email = new_user.email.clone();
+            start_session(cookies, &database, client, new_user.clone()).await?;
+            send_email_verification(&database, mailer, config, new_user, email).await;
             Ok(Status::NoContent)
         }
         Err(data::RegistrationError::NonUniqueUsername) => Err(RocketError::new(
@@ -235,6 +595,8 @@ pub async fn registration(
 
 /// Password change handler
 ///
+/// All other sessions of the user are revoked.
+///
 /// - Return "HTTP 204 No Content" if password change was successful.
 /// - Return "HTTP 422 Unprocessable Entity" if password change data was invalid.
 #[post("/auth/password/change", data = "<password_change>")]
@@ -243,6 +605,7 @@ pub async fn password_change(
     database: Database,
     password_change: Json<PasswordChange>,
 ) -> Result<Status, (Status, Json<RocketError>)> {
+    let session_id = user.session_id();
     let user = Arc::new(user.into_inner());
 
     macro_rules! fail {
@@ -279,9 +642,14 @@ pub async fn password_change(
     }
 
     // Update password
+    let user_clone = user.clone();
     database
-        .run(move |db| data::update_password(db, &user, &new_password))
+        .run(move |db| data::update_password(db, &user_clone, &new_password))
         .await;
+
+    // Revoke other sessions
+    revoke_sessions(&database, &user, session_id).await;
+
     Ok(Status::NoContent)
 }
 
@@ -290,14 +658,294 @@ pub fn password_change_nologin() -> ApiError {
     ApiError::MissingAuthentication
 }
 
+/// Password reset request handler
+///
+/// If one or more users with the specified e-mail address exist, a password
+/// reset link is sent to that address. The lookup and the mail are handled in
+/// a background task, so that the response time does not reveal whether an
+/// e-mail address is registered.
+///
+/// - Return "HTTP 204 No Content" in all cases, in order not to reveal whether
+///   an e-mail address is registered.
+/// - Return "HTTP 429 Too Many Requests" if too many resets were requested.
+#[post("/auth/password/reset/request", data = "<reset_request>")]
+pub async fn password_reset_request(
+    _allowed: LoginAllowed,
+    database: Database,
+    mailer: &State<Mailer>,
+    config: &State<Config>,
+    reset_request: Json<PasswordResetRequest>,
+) -> Status {
+    let email = reset_request.into_inner().email;
+    let mailer = mailer.inner().clone();
+    let config = config.inner().clone();
+    rocket::tokio::spawn(async move {
+        let users = database.run(move |db| data::get_users_by_email(db, &email)).await;
+        for user in users {
+            send_password_reset(&database, &mailer, &config, user).await;
+        }
+    });
+    Status::NoContent
+}
+
+/// Password reset handler
+///
+/// All sessions of the user are revoked.
+///
+/// - Return "HTTP 204 No Content" if the password was reset.
+/// - Return "HTTP 422 Unprocessable Entity" if the token or the new password
+///   was invalid.
+#[post("/auth/password/reset", data = "<password_reset>")]
+pub async fn password_reset(
+    database: Database,
+    password_reset: Json<PasswordReset>,
+) -> Result<Status, (Status, Json<RocketError>)> {
+    let PasswordReset { token, new_password } = password_reset.into_inner();
+
+    // Ensure minimum length of new password
+    if new_password.len() < data::MIN_PASSWORD_LENGTH {
+        return Err(RocketError::new(
+            Status::UnprocessableEntity,
+            "PasswordResetError",
+            "New password must have at least 8 characters",
+        ));
+    }
+
+    // Consume token
+    let user = match database
+        .run(move |db| data::consume_password_reset_token(db, token.trim()))
+        .await
+    {
+        Some(user) => user,
+        None => {
+            warn!("Password reset with invalid or expired token");
+            return Err(RocketError::new(
+                Status::UnprocessableEntity,
+                "PasswordResetError",
+                "Invalid or expired password reset token",
+            ));
+        }
+    };
+
+    // Update password
+    log::info!("Resetting password for user {}", user.id);
+    let user = database
+        .run(move |db| data::update_password(db, &user, &new_password))
+        .await;
+
+    // Revoke all sessions
+    revoke_sessions(&database, &user, None).await;
+
+    Ok(Status::NoContent)
+}
+
+/// Revoke all sessions of a user, except for the session with the ID
+/// `keep_id` (if specified).
+///
+/// Errors are logged, but not returned.
+pub async fn revoke_sessions(database: &Database, user: &User, keep_id: Option<i32>) {
+    let user = user.clone();
+    let user_id = user.id;
+    match database
+        .run(move |db| data::delete_sessions_for_user(db, &user, keep_id))
+        .await
+    {
+        Ok(count) => log::info!("Revoked {} session(s) of user {}", count, user_id),
+        Err(e) => error!("Could not revoke sessions of user {}: {}", user_id, e),
+    }
+}
+
+/// Send a mail with a password reset link to the user. Return whether the
+/// mail was sent.
+///
+/// Errors are logged, but not returned.
+pub async fn send_password_reset(database: &Database, mailer: &Mailer, config: &Config, user: User) -> bool {
+    let user_id = user.id;
+    let to = user.email.clone();
+    let token = match database
+        .run(move |db| {
+            data::create_password_reset_token(
+                db,
+                &user,
+                chrono::Duration::hours(PASSWORD_RESET_VALIDITY_HOURS),
+            )
+        })
+        .await
+    {
+        Ok(token) => token,
+        Err(e) => {
+            error!(
+                "Could not create password reset token for user {}: {}",
+                user_id, e
+            );
+            return false;
+        }
+    };
+    let body = format!(
+        "Hi!\n\n\
+        Someone (hopefully you) requested a password reset for your Flugbuech account.\n\n\
+        To set a new password, open the following link within the next {} hour(s):\n\n\
+        {}/auth/password/reset?token={}\n\n\
+        If you did not request a password reset, you can ignore this e-mail.\n",
+        PASSWORD_RESET_VALIDITY_HOURS,
+        config.base_url(),
+        token,
+    );
+    let mail = Mail {
+        to,
+        subject: "Flugbuech: Password reset".into(),
+        body,
+    };
+    match mailer.send(mail).await {
+        Ok(()) => {
+            log::info!("Sent password reset mail to user {}", user_id);
+            true
+        }
+        Err(e) => {
+            error!("Could not send password reset mail to user {}: {:#}", user_id, e);
+            false
+        }
+    }
+}
+
+/// Send a mail with a confirmation link to the specified e-mail address.
+/// Once confirmed, the address becomes the user's verified e-mail address.
+///
+/// Errors are logged, but not returned.
+pub async fn send_email_verification(
+    database: &Database,
+    mailer: &Mailer,
+    config: &Config,
+    user: User,
+    email: String,
+) {
+    let user_id = user.id;
+    let email_clone = email.clone();
+    let token = match database
+        .run(move |db| {
+            data::create_email_verification_token(
+                db,
+                &user,
+                &email_clone,
+                chrono::Duration::hours(EMAIL_VERIFICATION_VALIDITY_HOURS),
+            )
+        })
+        .await
+    {
+        Ok(token) => token,
+        Err(e) => {
+            error!(
+                "Could not create e-mail verification token for user {}: {}",
+                user_id, e
+            );
+            return;
+        }
+    };
+    let body = format!(
+        "Hi!\n\n\
+        Please confirm your e-mail address for your Flugbuech account by opening \
+        the following link within the next {} hours:\n\n\
+        {}/auth/email/verify?token={}\n\n\
+        If you did not sign up or change your e-mail address, you can ignore this e-mail.\n",
+        EMAIL_VERIFICATION_VALIDITY_HOURS,
+        config.base_url(),
+        token,
+    );
+    let mail = Mail {
+        to: email,
+        subject: "Flugbuech: Confirm your e-mail address".into(),
+        body,
+    };
+    match mailer.send(mail).await {
+        Ok(()) => log::info!("Sent e-mail verification mail to user {}", user_id),
+        Err(e) => error!(
+            "Could not send e-mail verification mail to user {}: {:#}",
+            user_id, e
+        ),
+    }
+}
+
+/// E-mail verification handler
+///
+/// - Return "HTTP 204 No Content" if the e-mail address was verified.
+/// - Return "HTTP 422 Unprocessable Entity" if the token was invalid.
+#[post("/auth/email/verify", data = "<verification>")]
+pub async fn email_verify(
+    database: Database,
+    verification: Json<EmailVerification>,
+) -> Result<Status, (Status, Json<RocketError>)> {
+    let token = verification.into_inner().token;
+    match database
+        .run(move |db| data::consume_email_verification_token(db, token.trim()))
+        .await
+    {
+        Ok(Some(user)) => {
+            log::info!("Verified e-mail address of user {}", user.id);
+            Ok(Status::NoContent)
+        }
+        Ok(None) => {
+            warn!("E-mail verification with invalid or expired token");
+            Err(RocketError::new(
+                Status::UnprocessableEntity,
+                "EmailVerificationError",
+                "Invalid or expired e-mail verification token",
+            ))
+        }
+        Err(e) => {
+            // This happens if another user registered the address in the meantime
+            error!("Could not verify e-mail address: {}", e);
+            Err(RocketError::new(
+                Status::UnprocessableEntity,
+                "EmailVerificationError",
+                "E-mail address could not be verified",
+            ))
+        }
+    }
+}
+
+/// Resend the verification mail for the current e-mail address.
+///
+/// - Return "HTTP 204 No Content" if the mail was sent.
+/// - Return "HTTP 422 Unprocessable Entity" if the address is already verified.
+#[post("/auth/email/verify/resend")]
+pub async fn email_verify_resend(
+    user: AuthUser,
+    database: Database,
+    mailer: &State<Mailer>,
+    config: &State<Config>,
+) -> Result<Status, (Status, Json<RocketError>)> {
+    let user = user.into_inner();
+    if user.email_verified {
+        return Err(RocketError::new(
+            Status::UnprocessableEntity,
+            "EmailVerificationError",
+            "E-mail address is already verified",
+        ));
+    }
+    let email = user.email.clone();
+    send_email_verification(&database, mailer, config, user, email).await;
+    Ok(Status::NoContent)
+}
+
+#[post("/auth/email/verify/resend", rank = 2)]
+pub fn email_verify_resend_nologin() -> ApiError {
+    ApiError::MissingAuthentication
+}
+
 /// Return vec of all API routes.
 pub fn api_routes() -> Vec<Route> {
     routes![
         login,
+        login_totp,
         logout,
         registration,
         password_change,
         password_change_nologin,
+        password_reset_request,
+        password_reset,
+        email_verify,
+        email_verify_resend,
+        email_verify_resend_nologin,
     ]
 }
 
@@ -310,14 +958,27 @@ mod tests {
         serde::json,
     };
 
-    use crate::test_utils::{make_test_config, DbTestContext};
+    use crate::test_utils::{make_test_config, DbTestContext, TestMailbox};
 
     use super::*;
 
-    /// Create a new test client with cookie tracking.
+    /// Create a new test client with cookie tracking. Mails are written to the log.
     fn make_api_client() -> Client {
         let app = rocket::custom(make_test_config())
             .attach(data::Database::fairing())
+            .manage(Mailer::from_config(&Default::default()).unwrap())
+            .manage(Config::default())
+            .mount("/", api_routes());
+        Client::tracked(app).expect("valid rocket instance")
+    }
+
+    /// Create a new test client with cookie tracking. Mails are sent to the
+    /// specified mailbox.
+    fn make_api_client_with_mailbox(mailbox: &TestMailbox) -> Client {
+        let app = rocket::custom(make_test_config())
+            .attach(data::Database::fairing())
+            .manage(mailbox.mailer())
+            .manage(mailbox.config())
             .mount("/", api_routes());
         Client::tracked(app).expect("valid rocket instance")
     }
@@ -339,7 +1000,7 @@ mod tests {
             .dispatch();
 
         // Login wrong: No cookies, error response
-        assert_eq!(resp.cookies().get_private(USER_COOKIE_ID), None);
+        assert_eq!(resp.cookies().get_private(SESSION_COOKIE_ID), None);
         assert_eq!(resp.cookies().get(USER_COOKIE_NAME), None);
         assert_eq!(resp.status(), Status::Forbidden);
         assert_eq!(
@@ -371,7 +1032,7 @@ mod tests {
             "Body: {}",
             resp.into_string().unwrap()
         );
-        assert!(resp.cookies().get_private(USER_COOKIE_ID).is_some());
+        assert!(resp.cookies().get_private(SESSION_COOKIE_ID).is_some());
         assert!(resp.cookies().get(USER_COOKIE_NAME).is_some());
     }
 
@@ -449,4 +1110,164 @@ mod tests {
         );
         assert!(password_valid!("abcdefgh"), "New password doesn't work");
     }
+
+    fn password_reset_request(client: &Client, token: &str, new: &str) -> Status {
+        client
+            .post("/auth/password/reset")
+            .header(ContentType::JSON)
+            .body(
+                json::to_string(&PasswordReset {
+                    token: token.into(),
+                    new_password: new.into(),
+                })
+                .unwrap(),
+            )
+            .dispatch()
+            .status()
+    }
+
+    #[test]
+    fn password_reset() {
+        let ctx = DbTestContext::new();
+        let mailbox = TestMailbox::new();
+        let client = make_api_client_with_mailbox(&mailbox);
+
+        macro_rules! request_reset {
+            ($email:expr) => {
+                client
+                    .post("/auth/password/reset/request")
+                    .header(ContentType::JSON)
+                    .body(
+                        json::to_string(&PasswordResetRequest {
+                            email: $email.into(),
+                        })
+                        .unwrap(),
+                    )
+                    .dispatch()
+                    .status()
+            };
+        }
+        macro_rules! password_valid {
+            ($pw:expr) => {
+                data::validate_login(&mut ctx.force_get_conn(), &ctx.testuser1.user.username, $pw).is_some()
+            };
+        }
+
+        // Unknown e-mail address: Success, but no mail is sent
+        assert_eq!(request_reset!("nobody@example.com"), Status::NoContent);
+
+        // Request reset (case insensitive). Mails are sent in the background.
+        assert_eq!(request_reset!("User1@Example.com"), Status::NoContent);
+        let mails = mailbox.wait_for_mails(1);
+        assert_eq!(mails.len(), 1);
+        assert!(mails[0].contains("To: user1@example.com"));
+        let token = mailbox
+            .last_token("/auth/password/reset")
+            .expect("Reset link not found in mail");
+
+        // Invalid token or password
+        assert_eq!(
+            password_reset_request(&client, "abc", "newpassword"),
+            Status::UnprocessableEntity
+        );
+        assert_eq!(
+            password_reset_request(&client, &token, "short"),
+            Status::UnprocessableEntity
+        );
+        assert!(password_valid!(&ctx.testuser1.password));
+
+        // Successful reset
+        assert_eq!(
+            password_reset_request(&client, &token, "newpassword"),
+            Status::NoContent
+        );
+        assert!(!password_valid!(&ctx.testuser1.password));
+        assert!(password_valid!("newpassword"));
+
+        // Tokens can only be used once
+        assert_eq!(
+            password_reset_request(&client, &token, "otherpassword"),
+            Status::UnprocessableEntity
+        );
+
+        // Expired tokens are rejected
+        let expired = data::create_password_reset_token(
+            &mut ctx.force_get_conn(),
+            &ctx.testuser1.user,
+            chrono::Duration::minutes(-1),
+        )
+        .unwrap();
+        assert_eq!(
+            password_reset_request(&client, &expired, "otherpassword"),
+            Status::UnprocessableEntity
+        );
+        assert!(password_valid!("newpassword"));
+    }
+
+    #[test]
+    fn registration_email_verification() {
+        let ctx = DbTestContext::new();
+        let mailbox = TestMailbox::new();
+        let client = make_api_client_with_mailbox(&mailbox);
+
+        // Register
+        let resp = client
+            .post("/auth/registration")
+            .header(ContentType::JSON)
+            .body(
+                json::to_string(&Registration {
+                    username: "newuser".into(),
+                    email: "newuser@example.com".into(),
+                    password: "newpassword".into(),
+                    news_opt_in: false,
+                })
+                .unwrap(),
+            )
+            .dispatch();
+        assert_eq!(resp.status(), Status::NoContent);
+        let user = data::validate_login(&mut ctx.force_get_conn(), "newuser", "newpassword").unwrap();
+        assert!(!user.email_verified);
+
+        // Verification mail was sent
+        let mails = mailbox.mails();
+        assert_eq!(mails.len(), 1);
+        assert!(mails[0].contains("To: newuser@example.com"));
+        let token = mailbox.last_token("/auth/email/verify").unwrap();
+
+        // Resend mail
+        let resp = client.post("/auth/email/verify/resend").dispatch();
+        assert_eq!(resp.status(), Status::NoContent);
+        assert_eq!(mailbox.mails().len(), 2);
+        let resent_token = mailbox.last_token("/auth/email/verify").unwrap();
+        assert_ne!(token, resent_token);
+
+        // Verify
+        macro_rules! verify {
+            ($token:expr) => {
+                client
+                    .post("/auth/email/verify")
+                    .header(ContentType::JSON)
+                    .body(
+                        json::to_string(&EmailVerification {
+                            token: $token.into(),
+                        })
+                        .unwrap(),
+                    )
+                    .dispatch()
+                    .status()
+            };
+        }
+        assert_eq!(verify!("invalid"), Status::UnprocessableEntity);
+        assert_eq!(verify!(resent_token.clone()), Status::NoContent);
+        let user = data::get_user(&mut ctx.force_get_conn(), user.id).unwrap();
+        assert!(user.email_verified);
+        assert_eq!(user.email, "newuser@example.com");
+
+        // All tokens are consumed
+        assert_eq!(verify!(token), Status::UnprocessableEntity);
+
+        // Verified addresses are not verified again
+        let resp = client.post("/auth/email/verify/resend").dispatch();
+        assert_eq!(resp.status(), Status::UnprocessableEntity);
+    }
 }
@@ -0,0 +1,538 @@
+//! Currency (recency) tracking.
+//!
+//! A currency rule requires a minimum number of flights and/or a minimum
+//! airtime within a window of days counted back from today, optionally only
+//! counting flights with a specific glider.
+
+use chrono::{DateTime, Duration, Utc};
+use rocket::{delete, get, http::Status, post, routes, serde::json::Json, Route};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth, data,
+    data::FlightTimeEntry,
+    models::{CurrencyRule, NewCurrencyRule, User},
+    responders::ApiError,
+};
+
+/// Maximal window of a rule (about ten years).
+const MAX_WINDOW_DAYS: i32 = 3650;
+
+// Rules engine
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CurrencyStatus {
+    /// Whether all requirements of the rule are currently met
+    current: bool,
+    /// Number of counted flights within the window
+    flights: u32,
+    /// Counted airtime within the window, in seconds
+    seconds: u64,
+    /// When currency lapses if no further flights are added
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lapses_at: Option<DateTime<Utc>>,
+}
+
+/// Evaluate a currency rule at the point in time `now`.
+///
+/// The `flights` must be sorted by launch time, most recent flight first.
+fn evaluate_rule(rule: &CurrencyRule, flights: &[FlightTimeEntry], now: DateTime<Utc>) -> CurrencyStatus {
+    let window = Duration::days(rule.window_days.into());
+    let window_start = now.checked_sub_signed(window).unwrap_or(DateTime::<Utc>::MIN_UTC);
+    let counted = flights
+        .iter()
+        .filter(|flight| flight.launch_time <= now && flight.launch_time >= window_start)
+        .filter(|flight| rule.glider_id.is_none() || flight.glider_id == rule.glider_id)
+        .collect::<Vec<_>>();
+
+    // For every requirement, find the oldest flight that is still needed to
+    // satisfy it. Once that flight drops out of the window, currency lapses.
+    let mut current = true;
+    let mut lapses_at: Option<DateTime<Utc>> = None;
+    let mut requirement_met_by = |flight: Option<&&FlightTimeEntry>| match flight {
+        Some(flight) => {
+            let lapse = flight
+                .launch_time
+                .checked_add_signed(window)
+                .unwrap_or(DateTime::<Utc>::MAX_UTC);
+            lapses_at = Some(lapses_at.map_or(lapse, |other| other.min(lapse)));
+        }
+        None => current = false,
+    };
+    if let Some(min_flights) = rule.min_flights {
+        requirement_met_by(counted.get((min_flights.max(1) - 1) as usize));
+    }
+    if let Some(min_seconds) = rule.min_seconds {
+        let mut total = 0;
+        requirement_met_by(counted.iter().find(|flight| {
+            total += flight.seconds;
+            total >= i64::from(min_seconds)
+        }));
+    }
+
+    CurrencyStatus {
+        current,
+        flights: counted.len() as u32,
+        seconds: counted.iter().map(|flight| flight.seconds.max(0) as u64).sum(),
+        lapses_at: if current { lapses_at } else { None },
+    }
+}
+
+// API types
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiCurrencyRule {
+    id: i32,
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    min_flights: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    min_seconds: Option<i32>,
+    window_days: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    glider_id: Option<i32>,
+    status: CurrencyStatus,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiCurrency {
+    /// Whether all rules are currently met
+    current: bool,
+    rules: Vec<ApiCurrencyRule>,
+}
+
+// Forms
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CurrencyRuleCreateUpdateForm {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    min_flights: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    min_seconds: Option<i32>,
+    window_days: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    glider_id: Option<i32>,
+}
+
+impl CurrencyRuleCreateUpdateForm {
+    /// Validate the form and convert it to a `NewCurrencyRule` model.
+    ///
+    /// If validation fails, return error message.
+    async fn into_new_currency_rule(
+        self,
+        user: &User,
+        database: &data::Database,
+    ) -> Result<NewCurrencyRule, String> {
+        if self.name.trim().is_empty() {
+            return Err("Name must not be empty".into());
+        }
+        if !(1..=MAX_WINDOW_DAYS).contains(&self.window_days) {
+            return Err(format!("Window must be between 1 and {} days", MAX_WINDOW_DAYS));
+        }
+        match (self.min_flights, self.min_seconds) {
+            (None, None) => return Err("Either minimum flights or minimum airtime must be specified".into()),
+            (Some(flights), _) if flights < 1 => return Err("Minimum flights must be positive".into()),
+            (_, Some(seconds)) if seconds < 1 => return Err("Minimum airtime must be positive".into()),
+            _ => {}
+        }
+
+        // Look up and validate glider
+        if let Some(glider_id) = self.glider_id {
+            match database
+                .run(move |db| data::get_glider_by_id(db, glider_id))
+                .await
+            {
+                Some(glider) if glider.user_id == user.id => {}
+                _ => return Err("Invalid glider".into()),
+            }
+        }
+
+        Ok(NewCurrencyRule {
+            user_id: user.id,
+            name: self.name,
+            min_flights: self.min_flights,
+            min_seconds: self.min_seconds,
+            window_days: self.window_days,
+            glider_id: self.glider_id,
+        })
+    }
+}
+
+// API endpoints
+
+#[get("/currency")]
+pub async fn list(database: data::Database, user: auth::AuthUser) -> Json<ApiCurrency> {
+    let user = user.into_inner();
+    let now = Utc::now();
+
+    let (rules, flights) = database
+        .run(move |db| {
+            let rules = data::get_currency_rules_for_user(db, &user);
+            let max_window_days = rules.iter().map(|rule| rule.window_days).max().unwrap_or(0);
+            let flights = data::get_flight_times_since_for_user(
+                db,
+                &user,
+                now.checked_sub_signed(Duration::days(max_window_days.into()))
+                    .unwrap_or(DateTime::<Utc>::MIN_UTC),
+            );
+            (rules, flights)
+        })
+        .await;
+
+    let rules = rules
+        .into_iter()
+        .map(|rule| {
+            let status = evaluate_rule(&rule, &flights, now);
+            ApiCurrencyRule {
+                id: rule.id,
+                name: rule.name,
+                min_flights: rule.min_flights,
+                min_seconds: rule.min_seconds,
+                window_days: rule.window_days,
+                glider_id: rule.glider_id,
+                status,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    Json(ApiCurrency {
+        current: rules.iter().all(|rule| rule.status.current),
+        rules,
+    })
+}
+
+#[get("/currency", rank = 2)]
+pub fn list_nologin() -> ApiError {
+    ApiError::MissingAuthentication
+}
+
+#[post("/currency/rules", data = "<data>")]
+pub async fn add(
+    user: auth::AuthUser,
+    database: data::Database,
+    data: Json<CurrencyRuleCreateUpdateForm>,
+) -> Result<Status, ApiError> {
+    let user = user.into_inner();
+
+    // Validate data
+    let rule = data
+        .into_inner()
+        .into_new_currency_rule(&user, &database)
+        .await
+        .map_err(|e| ApiError::InvalidData {
+            message: format!("Invalid currency rule: {}", e),
+        })?;
+
+    // Create database entry
+    match database.run(move |db| data::create_currency_rule(db, rule)).await {
+        Ok(_) => {
+            log::info!("Created currency rule for user {}", user.id);
+            Ok(Status::Created)
+        }
+        Err(e) => {
+            log::error!("Could not create currency rule: {}", e);
+            Err(ApiError::InvalidData {
+                message: "Could not create currency rule".into(),
+            })
+        }
+    }
+}
+
+#[post("/currency/rules", rank = 2)]
+pub fn add_nologin() -> ApiError {
+    ApiError::MissingAuthentication
+}
+
+#[post("/currency/rules/<id>", data = "<data>")]
+pub async fn edit(
+    user: auth::AuthUser,
+    database: data::Database,
+    id: i32,
+    data: Json<CurrencyRuleCreateUpdateForm>,
+) -> Result<Status, ApiError> {
+    let user = user.into_inner();
+
+    // Get rule
+    let mut rule = database
+        .run(move |db| data::get_currency_rule_by_id(db, id))
+        .await
+        .ok_or(ApiError::NotFound)?;
+
+    // Ownership check
+    if rule.user_id != user.id {
+        return Err(ApiError::NotFound);
+    }
+
+    // Validate data
+    let new_rule = data
+        .into_inner()
+        .into_new_currency_rule(&user, &database)
+        .await
+        .map_err(|e| ApiError::InvalidData {
+            message: format!("Invalid currency rule: {}", e),
+        })?;
+
+    // Update rule
+    rule.name = new_rule.name;
+    rule.min_flights = new_rule.min_flights;
+    rule.min_seconds = new_rule.min_seconds;
+    rule.window_days = new_rule.window_days;
+    rule.glider_id = new_rule.glider_id;
+    database
+        .run(move |db| data::update_currency_rule(db, &rule))
+        .await
+        .map_err(|e| {
+            log::error!("Could not update currency rule with ID {}: {}", id, e);
+            ApiError::InvalidData {
+                message: "Could not update currency rule".into(),
+            }
+        })?;
+    Ok(Status::NoContent)
+}
+
+#[post("/currency/rules/<id>", rank = 2)]
+#[allow(unused_variables)]
+pub fn edit_nologin(id: i32) -> ApiError {
+    ApiError::MissingAuthentication
+}
+
+#[delete("/currency/rules/<id>")]
+pub async fn delete(user: auth::AuthUser, database: data::Database, id: i32) -> Result<Status, ApiError> {
+    let user = user.into_inner();
+
+    // Get rule
+    let rule = database
+        .run(move |db| data::get_currency_rule_by_id(db, id))
+        .await
+        .ok_or(ApiError::NotFound)?;
+
+    // Ownership check
+    if rule.user_id != user.id {
+        return Err(ApiError::NotFound);
+    }
+
+    // Delete database entry
+    database
+        .run(move |db| data::delete_currency_rule_by_id(db, id))
+        .await
+        .map(|()| {
+            log::info!("Deleted currency rule with ID {}", id);
+            Status::NoContent
+        })
+        .map_err(|e| {
+            log::error!("Could not delete currency rule with ID {}: {}", id, e);
+            ApiError::IoError {
+                message: "Could not delete currency rule".into(),
+            }
+        })
+}
+
+#[delete("/currency/rules/<id>", rank = 2)]
+#[allow(unused_variables)]
+pub fn delete_nologin(id: i32) -> ApiError {
+    ApiError::MissingAuthentication
+}
+
+/// Return vec of all API routes.
+pub fn api_routes() -> Vec<Route> {
+    routes![
+        list,
+        list_nologin,
+        add,
+        add_nologin,
+        edit,
+        edit_nologin,
+        delete,
+        delete_nologin,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::{self, http::ContentType, local::blocking::Client, serde::json::Value};
+
+    use crate::{
+        models::NewFlight,
+        test_utils::{make_test_config, utc_datetime, DbTestContext},
+    };
+
+    use super::*;
+
+    fn rule(min_flights: Option<i32>, min_seconds: Option<i32>, window_days: i32) -> CurrencyRule {
+        CurrencyRule {
+            id: 1,
+            user_id: 1,
+            name: "Test".into(),
+            min_flights,
+            min_seconds,
+            window_days,
+            glider_id: None,
+        }
+    }
+
+    fn flight(launch_time: DateTime<Utc>, seconds: i64) -> FlightTimeEntry {
+        FlightTimeEntry {
+            glider_id: None,
+            launch_time,
+            seconds,
+        }
+    }
+
+    #[test]
+    fn evaluate_min_flights() {
+        let now = utc_datetime(2024, 6, 30, 12, 0, 0);
+        let flights = [
+            flight(utc_datetime(2024, 6, 20, 12, 0, 0), 600),
+            flight(utc_datetime(2024, 5, 10, 12, 0, 0), 600),
+            flight(utc_datetime(2024, 4, 15, 12, 0, 0), 600),
+            flight(utc_datetime(2023, 8, 1, 12, 0, 0), 600),
+        ];
+
+        // Three flights within 90 days: Current until the third-last flight
+        // drops out of the window
+        let status = evaluate_rule(&rule(Some(3), None, 90), &flights, now);
+        assert_eq!(
+            status,
+            CurrencyStatus {
+                current: true,
+                flights: 3,
+                seconds: 1800,
+                lapses_at: Some(utc_datetime(2024, 7, 14, 12, 0, 0)),
+            }
+        );
+
+        // Four flights within 90 days: Not current
+        let status = evaluate_rule(&rule(Some(4), None, 90), &flights, now);
+        assert!(!status.current);
+        assert_eq!(status.flights, 3);
+        assert_eq!(status.lapses_at, None);
+    }
+
+    #[test]
+    fn evaluate_min_seconds_and_flights() {
+        let now = utc_datetime(2024, 6, 30, 12, 0, 0);
+        let flights = [
+            flight(utc_datetime(2024, 6, 1, 12, 0, 0), 3600),
+            flight(utc_datetime(2024, 3, 1, 12, 0, 0), 7200),
+            flight(utc_datetime(2023, 12, 1, 12, 0, 0), 36000),
+        ];
+
+        // Two hours in a year: Reached with the second flight
+        let status = evaluate_rule(&rule(None, Some(7200), 365), &flights, now);
+        assert!(status.current);
+        assert_eq!(status.lapses_at, Some(utc_datetime(2025, 3, 1, 12, 0, 0)));
+
+        // Both requirements: The earlier lapse date wins
+        let status = evaluate_rule(&rule(Some(1), Some(36000), 365), &flights, now);
+        assert!(status.current);
+        assert_eq!(status.lapses_at, Some(utc_datetime(2024, 11, 30, 12, 0, 0)));
+
+        // Airtime requirement not met
+        let status = evaluate_rule(&rule(Some(1), Some(100_000), 365), &flights, now);
+        assert!(!status.current);
+
+        // Huge windows (e.g. rules stored before validation) don't overflow
+        let status = evaluate_rule(&rule(Some(3), None, i32::MAX), &flights, now);
+        assert!(status.current);
+        assert_eq!(status.flights, 3);
+        assert_eq!(status.lapses_at, Some(DateTime::<Utc>::MAX_UTC));
+    }
+
+    /// Create a new test client. Cookie tracking is disabled.
+    fn make_client() -> Client {
+        let app = rocket::custom(make_test_config())
+            .attach(data::Database::fairing())
+            .mount("/", api_routes());
+        Client::untracked(app).expect("valid rocket instance")
+    }
+
+    #[test]
+    fn add_and_list_rules() {
+        let ctx = DbTestContext::new();
+        let client = make_client();
+
+        macro_rules! add_rule {
+            ($body:expr, $cookie:expr) => {
+                client
+                    .post("/currency/rules")
+                    .header(ContentType::JSON)
+                    .body($body)
+                    .private_cookie($cookie)
+                    .cookie(ctx.username_cookie())
+                    .dispatch()
+            };
+        }
+        macro_rules! get_currency {
+            ($cookie:expr) => {
+                client
+                    .get("/currency")
+                    .private_cookie($cookie)
+                    .cookie(ctx.username_cookie())
+                    .dispatch()
+                    .into_json::<Value>()
+                    .unwrap()
+            };
+        }
+
+        // No rules
+        let currency = get_currency!(ctx.auth_cookie_user1());
+        assert_eq!(currency["current"], true);
+        assert_eq!(currency["rules"].as_array().unwrap().len(), 0);
+
+        // Invalid rules
+        let resp = add_rule!(
+            r#"{"name": "Nothing", "windowDays": 90}"#,
+            ctx.auth_cookie_user1()
+        );
+        assert_eq!(resp.status(), Status::BadRequest);
+        let resp = add_rule!(
+            r#"{"name": "Negative", "minFlights": -1, "windowDays": 90}"#,
+            ctx.auth_cookie_user1()
+        );
+        assert_eq!(resp.status(), Status::BadRequest);
+        let resp = add_rule!(
+            r#"{"name": "Forever", "minFlights": 1, "windowDays": 2000000000}"#,
+            ctx.auth_cookie_user1()
+        );
+        assert_eq!(resp.status(), Status::BadRequest);
+
+        // Add rule
+        let resp = add_rule!(
+            r#"{"name": "Recency", "minFlights": 1, "windowDays": 90}"#,
+            ctx.auth_cookie_user1()
+        );
+        assert_eq!(resp.status(), Status::Created);
+
+        // Rule is not met without flights
+        let currency = get_currency!(ctx.auth_cookie_user1());
+        assert_eq!(currency["current"], false);
+        assert_eq!(currency["rules"][0]["name"], "Recency");
+        assert_eq!(currency["rules"][0]["status"]["current"], false);
+
+        // Add a recent flight
+        let launch_time = Utc::now() - Duration::days(10);
+        data::create_flight(
+            &mut ctx.force_get_conn(),
+            &NewFlight {
+                user_id: ctx.testuser1.user.id,
+                launch_time: Some(launch_time),
+                landing_time: Some(launch_time + Duration::minutes(30)),
+                ..Default::default()
+            },
+            None,
+        );
+        let currency = get_currency!(ctx.auth_cookie_user1());
+        assert_eq!(currency["current"], true);
+        assert_eq!(currency["rules"][0]["status"]["flights"], 1);
+        assert_eq!(currency["rules"][0]["status"]["seconds"], 1800);
+
+        // Other users don't see the rule
+        let currency = get_currency!(ctx.auth_cookie_user2());
+        assert_eq!(currency["rules"].as_array().unwrap().len(), 0);
+    }
+}
@@ -1,38 +1,70 @@
 use std::{env, fmt};
 
+use chrono::{DateTime, NaiveDate, Utc};
+
 use diesel::{
     dsl::{count, exists, select},
     prelude::*,
     result::{Error, QueryResult},
-    sql_types::{BigInt, Bool, Double, Integer, Nullable, SmallInt, Text},
-    {sql_function, sql_query, PgConnection},
+    sql_types::{Array, BigInt, Bool, Bytea, Date, Double, Integer, Nullable, SmallInt, Text, Timestamptz},
+    {define_sql_function, sql_query, PgConnection},
 };
 use diesel_geography::{sql_types::Geography, types::GeogPoint};
 use diesel_migrations::{embed_migrations, EmbeddedMigrations, HarnessWithOutput, MigrationHarness};
-use log::error;
 use regex::Regex;
 use rocket_sync_db_pools::database;
 use serde::Serialize;
+use sha2::{Digest, Sha256};
 
 use crate::{
     models::{
-        Flight, Glider, GliderWithStats, Igc, Location, LocationWithCount, LocationWithDistance, NewFlight,
-        NewGlider, NewLocation, User,
+        ApiToken, CurrencyRule, Equipment, EquipmentRepack, EquipmentWithStats, Flight, FlightEquipment,
+        Glider, GliderMaintenanceEvent, GliderMaintenanceInterval, GliderWithStats, Igc, Location,
+        LocationWithCount, LocationWithDistance, LoginAttempts, LoginChallenge, MonthCount, NewCurrencyRule,
+        NewEquipment, NewEquipmentRepack, NewFlight, NewGlider, NewGliderMaintenanceEvent,
+        NewGliderMaintenanceInterval, NewLocation, NewSite, Session, Site, TotpCredential, User,
+        UserWithStats,
+    },
+    passwords,
+    schema::{
+        api_tokens, currency_rules, email_verification_tokens, equipment, equipment_repacks,
+        flight_equipment, flights, glider_maintenance_events, glider_maintenance_intervals, gliders, igcs,
+        locations, login_attempts, login_challenges, oidc_identities, password_reset_tokens, sessions, sites,
+        totp_credentials, totp_recovery_codes, users,
     },
-    schema::{flights, gliders, igcs, locations, users},
 };
 
-sql_function! {
-    /// The pgcrypto "crypt" function.
-    fn crypt(pw: Text, salt: Text) -> Text;
+define_sql_function! {
+    /// The pgcrypto "gen_random_bytes" function.
+    fn gen_random_bytes(count: Integer) -> Bytea;
+}
+
+define_sql_function! {
+    /// The PostgreSQL "lower" function.
+    fn lower(text: Text) -> Text;
 }
 
-sql_function! {
-    /// The pgcrypto "gen_salt" function.
-    fn gen_salt(type_: Text, iter_count: Integer) -> Text;
+define_sql_function! {
+    /// The PostgreSQL "encode" function.
+    fn encode(data: Bytea, format: Text) -> Text;
 }
 
-const PW_SALT_ITERATIONS: i32 = 10;
+define_sql_function! {
+    /// The PostgreSQL "octet_length" function.
+    fn octet_length(data: Bytea) -> Integer;
+}
+
+/// Prefix of all API tokens, makes them easy to recognize (e.g. by secret scanners).
+pub const API_TOKEN_PREFIX: &str = "flb_";
+
+/// Number of random bytes in an API token.
+const API_TOKEN_RANDOM_BYTES: i32 = 24;
+
+/// Number of characters of an API token that are stored in plaintext.
+const API_TOKEN_DISPLAY_LENGTH: usize = 12;
+
+/// Sessions that were not used for this many weeks are no longer valid.
+pub const SESSION_MAX_IDLE_WEEKS: i64 = 52;
 pub const MIN_PASSWORD_LENGTH: usize = 8;
 
 /// Database connection state object.
@@ -55,13 +87,25 @@ pub fn run_migrations() -> Result<(), String> {
     }
 }
 
+/// Grant the admin role to the user with the specified username.
+pub fn grant_admin(username: &str) -> Result<(), String> {
+    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+    let mut conn = PgConnection::establish(&database_url)
+        .map_err(|e| format!("Could not connect to database: {}", e))?;
+    match set_user_admin(&mut conn, username, true) {
+        Ok(0) => Err(format!("User {} not found", username)),
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("Could not grant admin role: {}", e)),
+    }
+}
+
 /// Return the user model with the specified user id.
 pub fn get_user(conn: &mut PgConnection, id: i32) -> Option<User> {
     users::table
         .find(id)
         .first(conn)
         .map_err(|e| {
-            error!("Could not query user: {}", e);
+            log::error!("Could not query user: {}", e);
             e
         })
         .ok()
@@ -109,19 +153,46 @@ fn validate_unique_registration_fields(
     username: &str,
     email: &str,
 ) -> Result<(), RegistrationError> {
-    let user: Option<User> = users::table
-        .filter(users::username.eq(username))
-        .or_filter(users::email.eq(email))
-        .first(conn)
-        .ok();
-    match user {
-        Some(user) if user.username == username => Err(RegistrationError::NonUniqueUsername),
-        Some(user) if user.email == email => Err(RegistrationError::InvalidEmail),
-        Some(_) => unreachable!("Returned user must match with either email or username"),
-        None => Ok(()),
+    let username_taken: bool = select(exists(users::table.filter(users::username.eq(username))))
+        .get_result(conn)
+        .expect("Error checking username uniqueness");
+    if username_taken {
+        Err(RegistrationError::NonUniqueUsername)
+    } else if is_email_taken(conn, None, email).expect("Error checking e-mail uniqueness") {
+        Err(RegistrationError::InvalidEmail)
+    } else {
+        Ok(())
+    }
+}
+
+/// Validate a new e-mail address for an existing user. The address must be
+/// well-formed and must not be used by another user (compared
+/// case-insensitively).
+pub fn validate_email_change(
+    conn: &mut PgConnection,
+    user: &User,
+    email: &str,
+) -> Result<(), RegistrationError> {
+    validate_email(email)?;
+    if is_email_taken(conn, Some(user.id), email).expect("Error checking e-mail uniqueness") {
+        Err(RegistrationError::InvalidEmail)
+    } else {
+        Ok(())
     }
 }
 
+/// Return whether a user (other than `except_user_id`) uses the e-mail
+/// address (compared case-insensitively).
+fn is_email_taken(conn: &mut PgConnection, except_user_id: Option<i32>, email: &str) -> QueryResult<bool> {
+    let mut query = users::table
+        .filter(lower(users::email).eq(email.trim().to_lowercase()))
+        .into_boxed();
+    if let Some(user_id) = except_user_id {
+        query = query.filter(users::id.ne(user_id));
+    }
+    select(exists(query)).get_result(conn)
+}
+
 /// Validates the email based on an email regex which should cover most cases
 fn validate_email(email: &str) -> Result<(), RegistrationError> {
     Regex::new(r"^[^@\s]+@[^@\s]+\.[^@\s]+$")
@@ -142,12 +213,22 @@ fn validate_password_format(password: &str) -> Result<(), RegistrationError> {
 }
 
 /// Validate username / password combination. Return the corresponding user model if it is valid.
+///
+/// If the stored password hash is outdated, it is replaced with a new hash.
 pub fn validate_login(conn: &mut PgConnection, username: &str, password: &str) -> Option<User> {
-    users::table
+    let user: User = users::table
         .filter(users::username.eq(username))
-        .filter(users::password.eq(crypt(password, users::password)))
+        .filter(users::disabled.eq(false))
         .first(conn)
-        .ok()
+        .ok()?;
+    match passwords::verify_password(password, &user.password) {
+        passwords::Verification::Invalid => None,
+        passwords::Verification::Valid { needs_rehash: false } => Some(user),
+        passwords::Verification::Valid { needs_rehash: true } => {
+            log::info!("Rehashing outdated password hash of user {}", user.id);
+            Some(update_password(conn, &user, password))
+        }
+    }
 }
 
 pub fn get_user_count(conn: &mut PgConnection) -> i64 {
@@ -157,6 +238,121 @@ pub fn get_user_count(conn: &mut PgConnection) -> i64 {
         .expect("Error loading user count")
 }
 
+const USERS_WITH_STATS_QUERY: &str = "
+    SELECT u.id, u.username, u.email, u.signed_up, u.email_verified, u.is_admin, u.disabled,
+           count(f.id) as flights,
+           coalesce(sum(octet_length(i.data)), 0)::bigint as igc_bytes
+      FROM users u
+           LEFT JOIN flights f ON f.user_id = u.id
+           LEFT JOIN igcs i ON i.flight_id = f.id";
+
+/// Return an (I)LIKE pattern that matches strings containing `search`.
+fn contains_pattern(search: &str) -> String {
+    format!(
+        "%{}%",
+        search
+            .trim()
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_")
+    )
+}
+
+/// Retrieve users including flight count and IGC storage usage, ordered by
+/// ID. If `search` is set, only users whose username or e-mail address
+/// contains the search string (case insensitive) are returned.
+pub fn search_users_with_stats(
+    conn: &mut PgConnection,
+    search: Option<&str>,
+    limit: i64,
+    offset: i64,
+) -> Vec<UserWithStats> {
+    let pattern = search.map(contains_pattern);
+    sql_query(format!(
+        "{}
+          WHERE $1 IS NULL OR u.username ILIKE $1 OR u.email ILIKE $1
+          GROUP BY u.id
+          ORDER BY u.id
+          LIMIT $2 OFFSET $3",
+        USERS_WITH_STATS_QUERY
+    ))
+    .bind::<Nullable<Text>, _>(pattern)
+    .bind::<BigInt, _>(limit)
+    .bind::<BigInt, _>(offset)
+    .load(conn)
+    .expect("Error loading users with stats")
+}
+
+/// Retrieve the user with the specified ID including flight count and IGC
+/// storage usage.
+pub fn get_user_with_stats(conn: &mut PgConnection, id: i32) -> Option<UserWithStats> {
+    sql_query(format!(
+        "{}
+          WHERE u.id = $1
+          GROUP BY u.id",
+        USERS_WITH_STATS_QUERY
+    ))
+    .bind::<Integer, _>(id)
+    .get_result(conn)
+    .optional()
+    .expect("Error loading user with stats")
+}
+
+/// Disable or enable a user. When disabling, all sessions of the user are
+/// revoked.
+pub fn set_user_disabled(conn: &mut PgConnection, user: &User, disabled: bool) -> QueryResult<User> {
+    conn.transaction(|conn| {
+        let user = diesel::update(user)
+            .set(users::disabled.eq(disabled))
+            .get_result(conn)?;
+        if disabled {
+            delete_sessions_for_user(conn, &user, None)?;
+        }
+        Ok(user)
+    })
+}
+
+/// Grant or revoke the admin role of the user with the specified username.
+/// Return the number of updated users.
+pub fn set_user_admin(conn: &mut PgConnection, username: &str, is_admin: bool) -> QueryResult<usize> {
+    diesel::update(users::table.filter(users::username.eq(username)))
+        .set(users::is_admin.eq(is_admin))
+        .execute(conn)
+}
+
+/// Return the number of signups per month, ordered by month.
+pub fn get_monthly_signup_counts(conn: &mut PgConnection) -> Vec<MonthCount> {
+    sql_query(
+        "SELECT to_char(signed_up, 'YYYY-MM') as month, count(*) as count
+           FROM users
+          GROUP BY month
+          ORDER BY month",
+    )
+    .load(conn)
+    .expect("Error loading monthly signup counts")
+}
+
+/// Return the number of created flights per month, ordered by month.
+pub fn get_monthly_flight_counts(conn: &mut PgConnection) -> Vec<MonthCount> {
+    sql_query(
+        "SELECT to_char(created_at, 'YYYY-MM') as month, count(*) as count
+           FROM flights
+          GROUP BY month
+          ORDER BY month",
+    )
+    .load(conn)
+    .expect("Error loading monthly flight counts")
+}
+
+/// Return the total size of all IGC files in bytes.
+pub fn get_igc_bytes(conn: &mut PgConnection) -> i64 {
+    igcs::table
+        .select(diesel::dsl::sum(octet_length(igcs::data)))
+        .first::<Option<i64>>(conn)
+        .expect("Error loading IGC size")
+        .unwrap_or(0)
+}
+
 /// Create a user in the database. The password will be hashed.
 pub fn create_user(
     conn: &mut PgConnection,
@@ -168,7 +364,7 @@ pub fn create_user(
     diesel::insert_into(users::table)
         .values(&(
             users::username.eq(username.into()),
-            users::password.eq(crypt(password.into(), gen_salt("bf", PW_SALT_ITERATIONS))),
+            users::password.eq(passwords::hash_password(&password.into())),
             users::email.eq(email.into()),
             users::news_opt_in.eq(news_opt_in),
         ))
@@ -179,11 +375,44 @@ pub fn create_user(
 /// Update a user password, return the updated user model.
 pub fn update_password(conn: &mut PgConnection, user: &User, password: impl Into<String>) -> User {
     diesel::update(user)
-        .set(users::password.eq(crypt(password.into(), gen_salt("bf", PW_SALT_ITERATIONS))))
+        .set(users::password.eq(passwords::hash_password(&password.into())))
         .get_result(conn)
         .expect("Could not update user password")
 }
 
+/// Delete a user and all associated data in one transaction.
+///
+/// Flights, IGC files, gliders and locations are deleted explicitly. All
+/// other data referencing the user is removed through `ON DELETE CASCADE`.
+/// Flights of other users that reference a deleted location lose the
+/// reference.
+pub fn delete_user(conn: &mut PgConnection, user: &User) -> QueryResult<()> {
+    conn.transaction(|conn| {
+        let flight_ids = flights::table
+            .filter(flights::user_id.eq(user.id))
+            .select(flights::id);
+        diesel::delete(igcs::table.filter(igcs::flight_id.eq_any(flight_ids))).execute(conn)?;
+        diesel::delete(flights::table.filter(flights::user_id.eq(user.id))).execute(conn)?;
+        diesel::delete(gliders::table.filter(gliders::user_id.eq(user.id))).execute(conn)?;
+
+        let location_ids = || {
+            locations::table
+                .filter(locations::user_id.eq(user.id))
+                .select(locations::id.nullable())
+        };
+        diesel::update(flights::table.filter(flights::launch_at.eq_any(location_ids())))
+            .set(flights::launch_at.eq(None::<i32>))
+            .execute(conn)?;
+        diesel::update(flights::table.filter(flights::landing_at.eq_any(location_ids())))
+            .set(flights::landing_at.eq(None::<i32>))
+            .execute(conn)?;
+        diesel::delete(locations::table.filter(locations::user_id.eq(user.id))).execute(conn)?;
+
+        diesel::delete(user).execute(conn)?;
+        Ok(())
+    })
+}
+
 /// Update a user's newsletter opt-in, return the updated user model.
 pub fn update_news_opt_in(conn: &mut PgConnection, user: &User, opt_in: bool) -> QueryResult<User> {
     diesel::update(user)
@@ -191,6 +420,573 @@ pub fn update_news_opt_in(conn: &mut PgConnection, user: &User, opt_in: bool) ->
         .get_result(conn)
 }
 
+/// Update the user's default radius for matching IGC launch and landing
+/// points to locations.
+pub fn update_match_radius(conn: &mut PgConnection, user: &User, match_radius: i32) -> QueryResult<User> {
+    diesel::update(user)
+        .set(users::match_radius.eq(match_radius))
+        .get_result(conn)
+}
+
+/// Create a new login session for the specified user. Return the session
+/// model and the plaintext session token.
+pub fn create_session(
+    conn: &mut PgConnection,
+    user: &User,
+    user_agent: Option<&str>,
+    ip_address: Option<&str>,
+) -> QueryResult<(Session, String)> {
+    let token = generate_random_token(conn)?;
+    let session = diesel::insert_into(sessions::table)
+        .values(&(
+            sessions::user_id.eq(user.id),
+            sessions::token_hash.eq(hash_token(&token)),
+            sessions::user_agent.eq(user_agent),
+            sessions::ip_address.eq(ip_address),
+        ))
+        .get_result(conn)?;
+    Ok((session, token))
+}
+
+/// Look up a plaintext session token. Return the session model and the
+/// corresponding user if the session exists and was used within the last
+/// `SESSION_MAX_IDLE_WEEKS` weeks.
+///
+/// The "last seen" timestamp of the session is updated.
+pub fn validate_session(conn: &mut PgConnection, token: &str) -> Option<(Session, User)> {
+    let (session, user): (Session, User) = sessions::table
+        .inner_join(users::table)
+        .filter(sessions::token_hash.eq(hash_token(token)))
+        .filter(users::disabled.eq(false))
+        .filter(sessions::last_seen_at.gt(Utc::now() - chrono::Duration::weeks(SESSION_MAX_IDLE_WEEKS)))
+        .first(conn)
+        .optional()
+        .expect("Error loading session")?;
+    let session = diesel::update(&session)
+        .set(sessions::last_seen_at.eq(Utc::now()))
+        .get_result(conn)
+        .expect("Could not update session");
+    Some((session, user))
+}
+
+/// Retrieve all sessions of a specific user, most recently used first.
+pub fn get_sessions_for_user(conn: &mut PgConnection, user: &User) -> Vec<Session> {
+    Session::belonging_to(user)
+        .order(sessions::last_seen_at.desc())
+        .load(conn)
+        .expect("Error loading sessions")
+}
+
+/// Retrieve the session with the specified ID.
+pub fn get_session_by_id(conn: &mut PgConnection, id: i32) -> Option<Session> {
+    sessions::table
+        .find(id)
+        .first(conn)
+        .optional()
+        .expect("Error loading session by ID")
+}
+
+/// Delete a session by ID.
+pub fn delete_session_by_id(conn: &mut PgConnection, id: i32) -> QueryResult<()> {
+    let delete_count = diesel::delete(sessions::table.filter(sessions::id.eq(&id))).execute(conn)?;
+    assert_eq!(delete_count, 1); // Sanity check
+    Ok(())
+}
+
+/// Delete a session by its plaintext token. Unknown tokens are ignored.
+pub fn delete_session_by_token(conn: &mut PgConnection, token: &str) -> QueryResult<()> {
+    diesel::delete(sessions::table.filter(sessions::token_hash.eq(hash_token(token)))).execute(conn)?;
+    Ok(())
+}
+
+/// Delete all sessions of a user, except for the session with the ID
+/// `keep_id` (if specified). Return the number of deleted sessions.
+pub fn delete_sessions_for_user(
+    conn: &mut PgConnection,
+    user: &User,
+    keep_id: Option<i32>,
+) -> QueryResult<usize> {
+    diesel::delete(
+        sessions::table
+            .filter(sessions::user_id.eq(user.id))
+            .filter(sessions::id.ne(keep_id.unwrap_or(-1))),
+    )
+    .execute(conn)
+}
+
+/// Retrieve the failed login attempts for a rate limiting key.
+pub fn get_login_attempts(conn: &mut PgConnection, key: &str) -> QueryResult<Option<LoginAttempts>> {
+    login_attempts::table.find(key).first(conn).optional()
+}
+
+/// Atomically register a failed login attempt at `now` for a rate limiting
+/// key and return the updated attempts.
+///
+/// Previous failures are forgotten if there was no failure or lockout during
+/// `window`. The lockout is determined from the new number of failures by
+/// `lockout`.
+pub fn register_login_failure(
+    conn: &mut PgConnection,
+    key: &str,
+    now: DateTime<Utc>,
+    window: chrono::Duration,
+    lockout: impl Fn(i32) -> Option<chrono::Duration>,
+) -> QueryResult<LoginAttempts> {
+    conn.transaction(|conn| {
+        // The upsert locks the row until the end of the transaction
+        let attempts: LoginAttempts = sql_query(
+            "INSERT INTO login_attempts (key, failures, last_failure_at, locked_until)
+            VALUES ($1, 1, $2, NULL)
+            ON CONFLICT (key) DO UPDATE SET
+                failures = CASE
+                    WHEN GREATEST(login_attempts.last_failure_at, login_attempts.locked_until) <= $3 THEN 1
+                    ELSE login_attempts.failures + 1
+                END,
+                last_failure_at = EXCLUDED.last_failure_at,
+                locked_until = NULL
+            RETURNING *",
+        )
+        .bind::<Text, _>(key)
+        .bind::<Timestamptz, _>(now)
+        .bind::<Timestamptz, _>(now - window)
+        .get_result(conn)?;
+        match lockout(attempts.failures) {
+            Some(duration) => diesel::update(&attempts)
+                .set(login_attempts::locked_until.eq(now + duration))
+                .get_result(conn),
+            None => Ok(attempts),
+        }
+    })
+}
+
+/// Delete the failed login attempts for a rate limiting key.
+pub fn delete_login_attempts(conn: &mut PgConnection, key: &str) -> QueryResult<()> {
+    diesel::delete(login_attempts::table.find(key)).execute(conn)?;
+    Ok(())
+}
+
+/// Create a login challenge for a user whose password was verified.
+///
+/// Expired challenges (of all users) are deleted.
+pub fn create_login_challenge(
+    conn: &mut PgConnection,
+    user: &User,
+    validity: chrono::Duration,
+) -> QueryResult<LoginChallenge> {
+    diesel::delete(login_challenges::table.filter(login_challenges::created_at.le(Utc::now() - validity)))
+        .execute(conn)?;
+    diesel::insert_into(login_challenges::table)
+        .values(login_challenges::user_id.eq(user.id))
+        .get_result(conn)
+}
+
+/// Retrieve a login challenge, if it was created within `validity`.
+pub fn get_login_challenge(
+    conn: &mut PgConnection,
+    id: i32,
+    validity: chrono::Duration,
+) -> QueryResult<Option<LoginChallenge>> {
+    login_challenges::table
+        .find(id)
+        .filter(login_challenges::created_at.gt(Utc::now() - validity))
+        .first(conn)
+        .optional()
+}
+
+/// Count a wrong code for a login challenge. The challenge is deleted once
+/// `max_failures` wrong codes were submitted. Return whether the challenge
+/// still exists.
+pub fn register_login_challenge_failure(
+    conn: &mut PgConnection,
+    id: i32,
+    max_failures: i32,
+) -> QueryResult<bool> {
+    conn.transaction(|conn| {
+        let failures: Option<i32> = diesel::update(login_challenges::table.find(id))
+            .set(login_challenges::failures.eq(login_challenges::failures + 1))
+            .returning(login_challenges::failures)
+            .get_result(conn)
+            .optional()?;
+        match failures {
+            Some(failures) if failures < max_failures => Ok(true),
+            Some(_) => delete_login_challenge(conn, id).map(|_| false),
+            None => Ok(false),
+        }
+    })
+}
+
+/// Delete a login challenge.
+pub fn delete_login_challenge(conn: &mut PgConnection, id: i32) -> QueryResult<()> {
+    diesel::delete(login_challenges::table.find(id)).execute(conn)?;
+    Ok(())
+}
+
+/// Number of recovery codes generated when enabling TOTP.
+pub const TOTP_RECOVERY_CODE_COUNT: usize = 10;
+
+/// Number of random bytes in a TOTP secret (160 bits, as recommended by RFC 4226).
+const TOTP_SECRET_BYTES: i32 = 20;
+
+/// Retrieve the TOTP credential of a user.
+pub fn get_totp_credential(conn: &mut PgConnection, user: &User) -> Option<TotpCredential> {
+    totp_credentials::table
+        .find(user.id)
+        .first(conn)
+        .optional()
+        .expect("Error loading TOTP credential")
+}
+
+/// Return whether the user has enabled TOTP.
+pub fn is_totp_enabled(conn: &mut PgConnection, user: &User) -> bool {
+    get_totp_credential(conn, user).is_some_and(|credential| credential.enabled)
+}
+
+/// Create a new (not yet enabled) TOTP credential with a random secret,
+/// replacing any existing credential of the user.
+pub fn create_totp_credential(conn: &mut PgConnection, user: &User) -> QueryResult<TotpCredential> {
+    let secret_bytes: Vec<u8> = select(gen_random_bytes(TOTP_SECRET_BYTES)).get_result(conn)?;
+    let secret = base32::encode(base32::Alphabet::Rfc4648 { padding: false }, &secret_bytes);
+    diesel::insert_into(totp_credentials::table)
+        .values((
+            totp_credentials::user_id.eq(user.id),
+            totp_credentials::secret.eq(&secret),
+        ))
+        .on_conflict(totp_credentials::user_id)
+        .do_update()
+        .set((
+            totp_credentials::secret.eq(&secret),
+            totp_credentials::enabled.eq(false),
+            totp_credentials::last_used_step.eq(None::<i64>),
+            totp_credentials::created_at.eq(Utc::now()),
+        ))
+        .get_result(conn)
+}
+
+/// Enable the TOTP credential of a user and generate new recovery codes.
+/// Return the plaintext recovery codes.
+pub fn enable_totp(conn: &mut PgConnection, user: &User, step: i64) -> QueryResult<Vec<String>> {
+    conn.transaction(|conn| {
+        diesel::update(totp_credentials::table.find(user.id))
+            .set((
+                totp_credentials::enabled.eq(true),
+                totp_credentials::last_used_step.eq(Some(step)),
+            ))
+            .execute(conn)?;
+        diesel::delete(totp_recovery_codes::table.filter(totp_recovery_codes::user_id.eq(user.id)))
+            .execute(conn)?;
+        let mut codes = Vec::with_capacity(TOTP_RECOVERY_CODE_COUNT);
+        for _ in 0..TOTP_RECOVERY_CODE_COUNT {
+            let random: String = select(encode(gen_random_bytes(5), "hex")).get_result(conn)?;
+            let code = format!("{}-{}", &random[..5], &random[5..]);
+            diesel::insert_into(totp_recovery_codes::table)
+                .values((
+                    totp_recovery_codes::user_id.eq(user.id),
+                    totp_recovery_codes::code_hash.eq(hash_token(&code)),
+                ))
+                .execute(conn)?;
+            codes.push(code);
+        }
+        Ok(codes)
+    })
+}
+
+/// Record the use of a TOTP time step. Return `false` if the same or a later
+/// step was already used (i.e. the code was replayed).
+pub fn use_totp_step(conn: &mut PgConnection, user: &User, step: i64) -> QueryResult<bool> {
+    let updated = diesel::update(
+        totp_credentials::table.find(user.id).filter(
+            totp_credentials::last_used_step
+                .is_null()
+                .or(totp_credentials::last_used_step.lt(step)),
+        ),
+    )
+    .set(totp_credentials::last_used_step.eq(Some(step)))
+    .execute(conn)?;
+    Ok(updated == 1)
+}
+
+/// Mark an unused recovery code of the user as used. Return `false` if the
+/// code is invalid or was already used.
+pub fn use_totp_recovery_code(conn: &mut PgConnection, user: &User, code: &str) -> QueryResult<bool> {
+    let code = code.trim().to_lowercase();
+    let updated = diesel::update(
+        totp_recovery_codes::table
+            .filter(totp_recovery_codes::user_id.eq(user.id))
+            .filter(totp_recovery_codes::code_hash.eq(hash_token(&code)))
+            .filter(totp_recovery_codes::used_at.is_null()),
+    )
+    .set(totp_recovery_codes::used_at.eq(Some(Utc::now())))
+    .execute(conn)?;
+    Ok(updated == 1)
+}
+
+/// Return the number of unused recovery codes of the user.
+pub fn count_unused_totp_recovery_codes(conn: &mut PgConnection, user: &User) -> i64 {
+    totp_recovery_codes::table
+        .filter(totp_recovery_codes::user_id.eq(user.id))
+        .filter(totp_recovery_codes::used_at.is_null())
+        .count()
+        .get_result(conn)
+        .expect("Error counting recovery codes")
+}
+
+/// Delete the TOTP credential and the recovery codes of a user.
+pub fn delete_totp(conn: &mut PgConnection, user: &User) -> QueryResult<()> {
+    conn.transaction(|conn| {
+        diesel::delete(totp_recovery_codes::table.filter(totp_recovery_codes::user_id.eq(user.id)))
+            .execute(conn)?;
+        diesel::delete(totp_credentials::table.find(user.id)).execute(conn)?;
+        Ok(())
+    })
+}
+
+/// Return the user linked to the specified external OpenID Connect identity.
+pub fn get_user_by_oidc_identity(conn: &mut PgConnection, issuer: &str, subject: &str) -> Option<User> {
+    oidc_identities::table
+        .inner_join(users::table)
+        .filter(oidc_identities::issuer.eq(issuer))
+        .filter(oidc_identities::subject.eq(subject))
+        .select(users::all_columns)
+        .first(conn)
+        .optional()
+        .expect("Could not query OIDC identity")
+}
+
+/// Link an external OpenID Connect identity to a user.
+pub fn link_oidc_identity(
+    conn: &mut PgConnection,
+    user: &User,
+    issuer: &str,
+    subject: &str,
+) -> QueryResult<()> {
+    diesel::insert_into(oidc_identities::table)
+        .values(&(
+            oidc_identities::user_id.eq(user.id),
+            oidc_identities::issuer.eq(issuer),
+            oidc_identities::subject.eq(subject),
+        ))
+        .execute(conn)
+        .map(|_| ())
+}
+
+/// Create a user for an external OpenID Connect identity and link the
+/// identity. The e-mail address is considered verified.
+///
+/// The user gets a random password. A password can be set through the
+/// password reset.
+pub fn create_oidc_user(
+    conn: &mut PgConnection,
+    username: &str,
+    email: &str,
+    issuer: &str,
+    subject: &str,
+) -> QueryResult<User> {
+    conn.transaction(|conn| {
+        let password = generate_random_token(conn)?;
+        let user: User = diesel::insert_into(users::table)
+            .values(&(
+                users::username.eq(username),
+                users::password.eq(passwords::hash_password(&password)),
+                users::email.eq(email),
+                users::email_verified.eq(true),
+            ))
+            .get_result(conn)?;
+        link_oidc_identity(conn, &user, issuer, subject)?;
+        Ok(user)
+    })
+}
+
+/// Return whether the specified username is already in use.
+pub fn is_username_taken(conn: &mut PgConnection, username: &str) -> bool {
+    select(exists(users::table.filter(users::username.eq(username))))
+        .get_result(conn)
+        .expect("Could not query username")
+}
+
+/// Return the hex encoded SHA-256 hash of a token, as stored in the database.
+///
+/// Tokens are hashed here instead of in SQL, so that plaintext tokens never
+/// appear in statements (and thus in statement logs).
+fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Generate a random hex encoded token.
+fn generate_random_token(conn: &mut PgConnection) -> QueryResult<String> {
+    select(encode(gen_random_bytes(API_TOKEN_RANDOM_BYTES), "hex")).get_result(conn)
+}
+
+/// Retrieve all API tokens of a specific user.
+pub fn get_api_tokens_for_user(conn: &mut PgConnection, user: &User) -> Vec<ApiToken> {
+    ApiToken::belonging_to(user)
+        .order(api_tokens::id)
+        .load(conn)
+        .expect("Error loading API tokens")
+}
+
+/// Retrieve API token with the specified ID.
+pub fn get_api_token_by_id(conn: &mut PgConnection, id: i32) -> Option<ApiToken> {
+    api_tokens::table
+        .find(id)
+        .first(conn)
+        .optional()
+        .expect("Error loading API token by id")
+}
+
+/// Create a new random API token for the specified user.
+///
+/// Only a hash of the token is stored in the database. Return the token
+/// model, together with the plaintext token.
+pub fn create_api_token(
+    conn: &mut PgConnection,
+    user: &User,
+    name: &str,
+    scopes: &[String],
+) -> QueryResult<(ApiToken, String)> {
+    let token = format!("{}{}", API_TOKEN_PREFIX, generate_random_token(conn)?);
+    let api_token = diesel::insert_into(api_tokens::table)
+        .values(&(
+            api_tokens::user_id.eq(user.id),
+            api_tokens::name.eq(name),
+            api_tokens::token_hash.eq(hash_token(&token)),
+            api_tokens::token_prefix.eq(&token[..API_TOKEN_DISPLAY_LENGTH]),
+            api_tokens::scopes.eq(scopes),
+        ))
+        .get_result(conn)?;
+    Ok((api_token, token))
+}
+
+/// Look up a plaintext API token. Return the token model and the
+/// corresponding user if the token is valid.
+///
+/// The last usage timestamp of the token is updated.
+pub fn validate_api_token(conn: &mut PgConnection, token: &str) -> Option<(ApiToken, User)> {
+    let (api_token, user): (ApiToken, User) = api_tokens::table
+        .inner_join(users::table)
+        .filter(api_tokens::token_hash.eq(hash_token(token)))
+        .filter(users::disabled.eq(false))
+        .first(conn)
+        .optional()
+        .expect("Error loading API token")?;
+    diesel::update(&api_token)
+        .set(api_tokens::last_used_at.eq(Some(Utc::now())))
+        .execute(conn)
+        .expect("Could not update API token usage");
+    Some((api_token, user))
+}
+
+/// Delete an API token by ID.
+pub fn delete_api_token_by_id(conn: &mut PgConnection, id: i32) -> QueryResult<()> {
+    let delete_count = diesel::delete(api_tokens::table.filter(api_tokens::id.eq(&id))).execute(conn)?;
+    assert_eq!(delete_count, 1); // Sanity check
+    Ok(())
+}
+
+/// Retrieve all users with the specified e-mail address (case insensitive).
+pub fn get_users_by_email(conn: &mut PgConnection, email: &str) -> Vec<User> {
+    users::table
+        .filter(lower(users::email).eq(email.trim().to_lowercase()))
+        .load(conn)
+        .expect("Error loading users")
+}
+
+/// Create a password reset token for the specified user, valid for the
+/// specified duration. Return the plaintext token.
+pub fn create_password_reset_token(
+    conn: &mut PgConnection,
+    user: &User,
+    validity: chrono::Duration,
+) -> QueryResult<String> {
+    let token = generate_random_token(conn)?;
+    diesel::insert_into(password_reset_tokens::table)
+        .values(&(
+            password_reset_tokens::user_id.eq(user.id),
+            password_reset_tokens::token_hash.eq(hash_token(&token)),
+            password_reset_tokens::expires_at.eq(Utc::now() + validity),
+        ))
+        .execute(conn)?;
+    Ok(token)
+}
+
+/// Consume a plaintext password reset token. Return the corresponding user if
+/// the token is valid and not expired.
+///
+/// All reset tokens of the user are invalidated, so a token can only be used once.
+pub fn consume_password_reset_token(conn: &mut PgConnection, token: &str) -> Option<User> {
+    conn.transaction(|conn| {
+        let user: Option<User> = password_reset_tokens::table
+            .inner_join(users::table)
+            .filter(password_reset_tokens::token_hash.eq(hash_token(token)))
+            .filter(password_reset_tokens::expires_at.gt(Utc::now()))
+            .select(users::all_columns)
+            .first(conn)
+            .optional()?;
+        if let Some(ref user) = user {
+            diesel::delete(password_reset_tokens::table.filter(password_reset_tokens::user_id.eq(user.id)))
+                .execute(conn)?;
+        }
+        Ok::<_, Error>(user)
+    })
+    .expect("Error consuming password reset token")
+}
+
+/// Create an e-mail verification token for the specified user and address,
+/// valid for the specified duration. Return the plaintext token.
+pub fn create_email_verification_token(
+    conn: &mut PgConnection,
+    user: &User,
+    email: &str,
+    validity: chrono::Duration,
+) -> QueryResult<String> {
+    let token = generate_random_token(conn)?;
+    diesel::insert_into(email_verification_tokens::table)
+        .values(&(
+            email_verification_tokens::user_id.eq(user.id),
+            email_verification_tokens::email.eq(email),
+            email_verification_tokens::token_hash.eq(hash_token(&token)),
+            email_verification_tokens::expires_at.eq(Utc::now() + validity),
+        ))
+        .execute(conn)?;
+    Ok(token)
+}
+
+/// Consume a plaintext e-mail verification token. If the token is valid and
+/// not expired, the e-mail address it was issued for becomes the user's
+/// verified e-mail address. Return the updated user.
+///
+/// All verification tokens of the user are invalidated.
+pub fn consume_email_verification_token(conn: &mut PgConnection, token: &str) -> QueryResult<Option<User>> {
+    conn.transaction(|conn| {
+        let found: Option<(i32, String)> = email_verification_tokens::table
+            .filter(email_verification_tokens::token_hash.eq(hash_token(token)))
+            .filter(email_verification_tokens::expires_at.gt(Utc::now()))
+            .select((
+                email_verification_tokens::user_id,
+                email_verification_tokens::email,
+            ))
+            .first(conn)
+            .optional()?;
+        let Some((user_id, email)) = found else {
+            return Ok(None);
+        };
+        if is_email_taken(conn, Some(user_id), &email)? {
+            // Another user registered the address in the meantime
+            return Err(diesel::result::Error::RollbackTransaction);
+        }
+        diesel::delete(
+            email_verification_tokens::table.filter(email_verification_tokens::user_id.eq(user_id)),
+        )
+        .execute(conn)?;
+        diesel::update(users::table.find(user_id))
+            .set((users::email.eq(email), users::email_verified.eq(true)))
+            .get_result(conn)
+            .map(Some)
+    })
+}
+
 pub fn get_glider_count(conn: &mut PgConnection) -> i64 {
     gliders::table
         .select(count(gliders::id))
@@ -252,9 +1048,305 @@ pub fn delete_glider_by_id(conn: &mut PgConnection, id: i32) -> QueryResult<()>
     Ok(())
 }
 
+/// Retrieve all maintenance events of all gliders of a specific user, most
+/// recent event first.
+pub fn get_maintenance_events_for_user(conn: &mut PgConnection, user: &User) -> Vec<GliderMaintenanceEvent> {
+    glider_maintenance_events::table
+        .inner_join(gliders::table)
+        .filter(gliders::user_id.eq(user.id))
+        .select(glider_maintenance_events::all_columns)
+        .order((
+            glider_maintenance_events::date.desc(),
+            glider_maintenance_events::id.desc(),
+        ))
+        .load(conn)
+        .expect("Error loading maintenance events")
+}
+
+/// Retrieve maintenance event with the specified ID.
+pub fn get_maintenance_event_by_id(conn: &mut PgConnection, id: i32) -> Option<GliderMaintenanceEvent> {
+    glider_maintenance_events::table
+        .find(id)
+        .first(conn)
+        .optional()
+        .expect("Error loading maintenance event by id")
+}
+
+/// Create a new maintenance event.
+pub fn create_maintenance_event(
+    conn: &mut PgConnection,
+    event: NewGliderMaintenanceEvent,
+) -> QueryResult<GliderMaintenanceEvent> {
+    diesel::insert_into(glider_maintenance_events::table)
+        .values(event)
+        .get_result(conn)
+}
+
+/// Delete a maintenance event by ID.
+pub fn delete_maintenance_event_by_id(conn: &mut PgConnection, id: i32) -> QueryResult<()> {
+    let delete_count =
+        diesel::delete(glider_maintenance_events::table.filter(glider_maintenance_events::id.eq(&id)))
+            .execute(conn)?;
+    assert_eq!(delete_count, 1); // Sanity check
+    Ok(())
+}
+
+/// Retrieve all maintenance intervals of all gliders of a specific user.
+pub fn get_maintenance_intervals_for_user(
+    conn: &mut PgConnection,
+    user: &User,
+) -> Vec<GliderMaintenanceInterval> {
+    glider_maintenance_intervals::table
+        .inner_join(gliders::table)
+        .filter(gliders::user_id.eq(user.id))
+        .select(glider_maintenance_intervals::all_columns)
+        .order(glider_maintenance_intervals::id)
+        .load(conn)
+        .expect("Error loading maintenance intervals")
+}
+
+/// Retrieve maintenance interval with the specified ID.
+pub fn get_maintenance_interval_by_id(conn: &mut PgConnection, id: i32) -> Option<GliderMaintenanceInterval> {
+    glider_maintenance_intervals::table
+        .find(id)
+        .first(conn)
+        .optional()
+        .expect("Error loading maintenance interval by id")
+}
+
+/// Create a new maintenance interval.
+pub fn create_maintenance_interval(
+    conn: &mut PgConnection,
+    interval: NewGliderMaintenanceInterval,
+) -> QueryResult<GliderMaintenanceInterval> {
+    diesel::insert_into(glider_maintenance_intervals::table)
+        .values(interval)
+        .get_result(conn)
+}
+
+/// Save an updated maintenance interval in the database.
+pub fn update_maintenance_interval(
+    conn: &mut PgConnection,
+    interval: &GliderMaintenanceInterval,
+) -> QueryResult<()> {
+    diesel::update(interval).set(interval).execute(conn)?;
+    Ok(())
+}
+
+/// Delete a maintenance interval by ID.
+pub fn delete_maintenance_interval_by_id(conn: &mut PgConnection, id: i32) -> QueryResult<()> {
+    let delete_count =
+        diesel::delete(glider_maintenance_intervals::table.filter(glider_maintenance_intervals::id.eq(&id)))
+            .execute(conn)?;
+    assert_eq!(delete_count, 1); // Sanity check
+    Ok(())
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, QueryableByName)]
+pub struct GliderUsage {
+    #[diesel(sql_type = BigInt)]
+    pub flights: i64,
+    #[diesel(sql_type = BigInt)]
+    pub seconds: i64,
+}
+
+/// Usage of a glider since the last maintenance of an interval's kind.
+#[derive(Debug, Clone, Copy, PartialEq, QueryableByName)]
+pub struct MaintenanceIntervalUsage {
+    #[diesel(sql_type = Integer)]
+    pub interval_id: i32,
+    #[diesel(embed)]
+    pub usage: GliderUsage,
+}
+
+/// Return the number of flights and the airtime of the glider for every
+/// maintenance interval of a user.
+///
+/// Only flights launched after the last maintenance of the interval's kind
+/// are counted (flights on the day of a maintenance may have happened before
+/// it). If the maintenance was never performed, all flights with the glider
+/// are counted.
+pub fn get_maintenance_usage_for_user(conn: &mut PgConnection, user: &User) -> Vec<MaintenanceIntervalUsage> {
+    sql_query(
+        "SELECT i.id as interval_id,
+                count(f.id) as flights,
+                coalesce(extract(epoch from sum(f.landing_time - f.launch_time))::bigint, 0) as seconds
+           FROM glider_maintenance_intervals i
+                JOIN gliders g ON g.id = i.glider_id
+                LEFT JOIN (SELECT glider_id, kind, max(date) as last_performed
+                             FROM glider_maintenance_events
+                            GROUP BY glider_id, kind) e ON e.glider_id = i.glider_id AND e.kind = i.kind
+                LEFT JOIN flights f ON f.glider_id = i.glider_id
+                                   AND (e.last_performed IS NULL OR f.launch_time >= e.last_performed + 1)
+          WHERE g.user_id = $1
+          GROUP BY i.id",
+    )
+    .bind::<Integer, _>(user.id)
+    .load(conn)
+    .expect("Error loading maintenance interval usage")
+}
+
+/// Retrieve all equipment of a specific user.
+pub fn get_equipment_for_user(conn: &mut PgConnection, user: &User) -> Vec<Equipment> {
+    Equipment::belonging_to(user)
+        .order((equipment::manufacturer, equipment::model))
+        .load(conn)
+        .expect("Error loading equipment")
+}
+
+/// Equipment with usage and repack stats. Like for glider maintenance, flights
+/// on the day of the last repack are not counted as flights since the repack.
+const EQUIPMENT_WITH_STATS_QUERY: &str = "
+    SELECT e.*,
+           count(f.id) as flights,
+           coalesce(extract(epoch from sum(f.landing_time - f.launch_time))::bigint, 0) as seconds,
+           r.last_repack,
+           coalesce(r.repack_count, 0) as repack_count,
+           count(f.id) FILTER (WHERE r.last_repack IS NULL OR f.launch_time >= r.last_repack + 1) as flights_since_repack
+      FROM equipment e
+           LEFT JOIN flight_equipment fe ON e.id = fe.equipment_id
+           LEFT JOIN flights f ON f.id = fe.flight_id
+           LEFT JOIN (SELECT equipment_id, max(date) as last_repack, count(*) as repack_count
+                        FROM equipment_repacks
+                       GROUP BY equipment_id) r ON e.id = r.equipment_id";
+
+/// Retrieve all equipment of a specific user, including usage and repack stats.
+pub fn get_equipment_with_stats_for_user(conn: &mut PgConnection, user: &User) -> Vec<EquipmentWithStats> {
+    sql_query(format!(
+        "{}
+          WHERE e.user_id = $1
+          GROUP BY e.id, r.last_repack, r.repack_count
+          ORDER BY e.id DESC",
+        EQUIPMENT_WITH_STATS_QUERY
+    ))
+    .bind::<Integer, _>(user.id)
+    .load(conn)
+    .expect("Error loading equipment with stats")
+}
+
+/// Retrieve equipment with the specified ID.
+pub fn get_equipment_by_id(conn: &mut PgConnection, id: i32) -> Option<Equipment> {
+    equipment::table
+        .find(id)
+        .first(conn)
+        .optional()
+        .expect("Error loading equipment by id")
+}
+
+/// Retrieve equipment with the specified ID, including usage and repack stats.
+pub fn get_equipment_with_stats_by_id(conn: &mut PgConnection, id: i32) -> Option<EquipmentWithStats> {
+    sql_query(format!(
+        "{}
+          WHERE e.id = $1
+          GROUP BY e.id, r.last_repack, r.repack_count",
+        EQUIPMENT_WITH_STATS_QUERY
+    ))
+    .bind::<Integer, _>(id)
+    .get_result(conn)
+    .optional()
+    .expect("Error loading equipment with stats by id")
+}
+
+/// Create a new piece of equipment.
+pub fn create_equipment(conn: &mut PgConnection, equipment: NewEquipment) -> QueryResult<Equipment> {
+    diesel::insert_into(equipment::table)
+        .values(equipment)
+        .get_result(conn)
+}
+
+/// Save updated equipment in the database.
+pub fn update_equipment(conn: &mut PgConnection, equipment: &Equipment) -> QueryResult<()> {
+    diesel::update(equipment).set(equipment).execute(conn)?;
+    Ok(())
+}
+
+/// Delete equipment by ID.
+pub fn delete_equipment_by_id(conn: &mut PgConnection, id: i32) -> QueryResult<()> {
+    let delete_count = diesel::delete(equipment::table.filter(equipment::id.eq(&id))).execute(conn)?;
+    assert_eq!(delete_count, 1); // Sanity check
+    Ok(())
+}
+
+/// Retrieve all repacks of a reserve, most recent repack first.
+pub fn get_repacks_for_equipment(conn: &mut PgConnection, equipment: &Equipment) -> Vec<EquipmentRepack> {
+    EquipmentRepack::belonging_to(equipment)
+        .order((equipment_repacks::date.desc(), equipment_repacks::id.desc()))
+        .load(conn)
+        .expect("Error loading repacks")
+}
+
+/// Retrieve repack with the specified ID.
+pub fn get_repack_by_id(conn: &mut PgConnection, id: i32) -> Option<EquipmentRepack> {
+    equipment_repacks::table
+        .find(id)
+        .first(conn)
+        .optional()
+        .expect("Error loading repack by id")
+}
+
+/// Create a new repack.
+pub fn create_repack(conn: &mut PgConnection, repack: NewEquipmentRepack) -> QueryResult<EquipmentRepack> {
+    diesel::insert_into(equipment_repacks::table)
+        .values(repack)
+        .get_result(conn)
+}
+
+/// Delete a repack by ID.
+pub fn delete_repack_by_id(conn: &mut PgConnection, id: i32) -> QueryResult<()> {
+    let delete_count =
+        diesel::delete(equipment_repacks::table.filter(equipment_repacks::id.eq(&id))).execute(conn)?;
+    assert_eq!(delete_count, 1); // Sanity check
+    Ok(())
+}
+
+/// Return the IDs of all equipment used during a flight.
+pub fn get_equipment_ids_for_flight(conn: &mut PgConnection, flight: &Flight) -> Vec<i32> {
+    FlightEquipment::belonging_to(flight)
+        .select(flight_equipment::equipment_id)
+        .order(flight_equipment::equipment_id)
+        .load(conn)
+        .expect("Error loading flight equipment")
+}
+
+/// Replace the equipment used during a flight.
+pub fn set_flight_equipment(
+    conn: &mut PgConnection,
+    flight_id: i32,
+    equipment_ids: &[i32],
+) -> QueryResult<()> {
+    conn.transaction(|conn| {
+        diesel::delete(flight_equipment::table.filter(flight_equipment::flight_id.eq(flight_id)))
+            .execute(conn)?;
+        let rows = equipment_ids
+            .iter()
+            .map(|&equipment_id| FlightEquipment {
+                flight_id,
+                equipment_id,
+            })
+            .collect::<Vec<_>>();
+        diesel::insert_into(flight_equipment::table)
+            .values(&rows)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+        Ok(())
+    })
+}
+
 /// Create a new flight.
+#[cfg(test)]
 pub fn create_flight(conn: &mut PgConnection, flight: &NewFlight, igc: Option<Vec<u8>>) -> Flight {
-    conn.transaction::<Flight, Error, _>(|conn| {
+    create_flight_with_equipment(conn, flight, igc, None).expect("Transaction for create_flight failed")
+}
+
+/// Create a new flight, optionally with the equipment used. All data is
+/// stored in a single transaction.
+pub fn create_flight_with_equipment(
+    conn: &mut PgConnection,
+    flight: &NewFlight,
+    igc: Option<Vec<u8>>,
+    equipment_ids: Option<&[i32]>,
+) -> QueryResult<Flight> {
+    conn.transaction(|conn| {
         // Create flight
         let flight: Flight = diesel::insert_into(flights::table)
             .values(flight)
@@ -269,9 +1361,13 @@ pub fn create_flight(conn: &mut PgConnection, flight: &NewFlight, igc: Option<Ve
             diesel::insert_into(igcs::table).values(val).execute(conn)?;
         }
 
+        // Store equipment
+        if let Some(equipment_ids) = equipment_ids {
+            set_flight_equipment(conn, flight.id, equipment_ids)?;
+        }
+
         Ok(flight)
     })
-    .expect("Transaction for create_flight failed")
 }
 
 /// Create multiple new flights. Return the number of rows affected.
@@ -281,12 +1377,31 @@ pub fn create_flights(conn: &mut PgConnection, flights: &[NewFlight]) -> QueryRe
     diesel::insert_into(flights::table).values(flights).execute(conn)
 }
 
-/// Save an updated flight in the database.
-pub fn update_flight(conn: &mut PgConnection, flight: &Flight) {
-    diesel::update(flight)
-        .set(flight)
-        .execute(conn)
-        .expect("Could not update flight");
+/// Save an updated flight and, if specified, the equipment used in a single
+/// transaction. The IGC data is only stored if the flight has no IGC data
+/// yet, existing IGC data is never modified.
+pub fn update_flight_with_equipment(
+    conn: &mut PgConnection,
+    flight: &Flight,
+    equipment_ids: Option<&[i32]>,
+    igc: Option<Vec<u8>>,
+) -> QueryResult<()> {
+    conn.transaction(|conn| {
+        diesel::update(flight).set(flight).execute(conn)?;
+        if let Some(equipment_ids) = equipment_ids {
+            set_flight_equipment(conn, flight.id, equipment_ids)?;
+        }
+        if let Some(data) = igc {
+            diesel::insert_into(igcs::table)
+                .values(Igc {
+                    flight_id: flight.id,
+                    data,
+                })
+                .on_conflict_do_nothing()
+                .execute(conn)?;
+        }
+        Ok(())
+    })
 }
 
 /// Delete a flight.
@@ -329,23 +1444,6 @@ pub fn get_flight_with_id(conn: &mut PgConnection, id: i32) -> Option<Flight> {
         .expect("Error loading flight by id")
 }
 
-/// Save an updated IGC entry in the database.
-///
-/// If no IGC entry existed before, add one.
-pub fn update_igc(conn: &mut PgConnection, flight: &Flight, data: &[u8]) {
-    let val = Igc {
-        flight_id: flight.id,
-        data: data.to_vec(),
-    };
-    diesel::insert_into(igcs::table)
-        .values(val)
-        .on_conflict(igcs::flight_id)
-        .do_update()
-        .set(igcs::data.eq(data))
-        .execute(conn)
-        .expect("Could not update IGC entry for flight");
-}
-
 /// Retrieve IGC data for the specified flight.
 pub fn get_igc_for_flight(conn: &mut PgConnection, flight: &Flight) -> Option<Igc> {
     igcs::table
@@ -441,14 +1539,39 @@ pub fn get_visited_locations_with_stats_for_user(
     .expect("Error loading locations")
 }
 
-/// Retrieve all locations for the specified user within a specified radius
-/// from the specified coordinates.
+/// Whether a location is searched for a launch or for a landing.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SiteUsage {
+    Launch,
+    Landing,
+}
+
+impl SiteUsage {
+    /// The `site_type` of shared sites that may be used this way (in addition
+    /// to "both").
+    fn site_type(self) -> &'static str {
+        match self {
+            SiteUsage::Launch => "launch",
+            SiteUsage::Landing => "landing",
+        }
+    }
+}
+
+/// Retrieve all locations for the specified user and all shared sites that
+/// match the specified coordinates, ordered by distance.
+///
+/// Locations with an area match if the area covers the coordinates, at a
+/// distance of 0. Other locations match within their own matching radius, or
+/// the user's default radius if not set. Shared sites always use the user's
+/// default radius. They are only included if they can be used for the
+/// specified `usage` and if the user does not already have a location copied
+/// from that site.
 pub fn get_locations_around_point(
     conn: &mut PgConnection,
     user: &User,
     lat: f64,
     lng: f64,
-    max_distance_meters: f64,
+    usage: SiteUsage,
 ) -> Vec<LocationWithDistance> {
     let point = GeogPoint {
         x: lng,
@@ -456,15 +1579,24 @@ pub fn get_locations_around_point(
         srid: None,
     };
     sql_query(
-        "SELECT id, name, country, elevation, user_id, geog, ST_Distance($1, geog) AS distance
+        "SELECT id, name, country, elevation, geog, site_id, false AS shared,
+                CASE WHEN area IS NULL THEN ST_Distance($1, geog) ELSE 0 END AS distance
            FROM locations
           WHERE user_id = $2
+            AND ((area IS NULL AND ST_DWithin(geog, $1, COALESCE(match_radius, $3)))
+                 OR (area IS NOT NULL AND ST_Covers(area, $1)))
+         UNION ALL
+         SELECT id, name, country, elevation, geog, id AS site_id, true AS shared, ST_Distance($1, geog) AS distance
+           FROM sites s
+          WHERE site_type IN ('both', $4)
             AND ST_DWithin(geog, $1, $3)
-          ORDER BY distance ASC",
+            AND NOT EXISTS (SELECT 1 FROM locations l WHERE l.user_id = $2 AND l.site_id = s.id)
+          ORDER BY distance ASC, shared ASC",
     )
     .bind::<Geography, _>(point)
     .bind::<Integer, _>(user.id)
-    .bind::<Double, _>(max_distance_meters)
+    .bind::<Integer, _>(user.match_radius)
+    .bind::<Text, _>(usage.site_type())
     .load(conn)
     .expect("Error loading locations")
 }
@@ -501,148 +1633,881 @@ pub fn create_location(conn: &mut PgConnection, location: NewLocation) -> Locati
         .expect("Could not create location")
 }
 
-/// Save an updated location in the database.
-pub fn update_location(conn: &mut PgConnection, location: &Location) {
-    diesel::update(location)
-        .set(location)
-        .execute(conn)
-        .expect("Could not update location");
+/// Create multiple new locations.
+pub fn create_locations(conn: &mut PgConnection, locations: &[NewLocation]) -> QueryResult<usize> {
+    diesel::insert_into(locations::table)
+        .values(locations)
+        .execute(conn)
+}
+
+/// Save an updated location in the database.
+pub fn update_location(conn: &mut PgConnection, location: &Location) {
+    diesel::update(location)
+        .set(location)
+        .execute(conn)
+        .expect("Could not update location");
+}
+
+/// Delete a location by ID.
+pub fn delete_location_by_id(conn: &mut PgConnection, id: i32) -> QueryResult<()> {
+    let delete_count = diesel::delete(locations::table.filter(locations::id.eq(&id))).execute(conn)?;
+    assert_eq!(delete_count, 1); // Sanity check
+    Ok(())
+}
+
+/// Retrieve shared sites, ordered by name. If `search` is set, only sites
+/// whose name or one of its aliases contains the search string (case
+/// insensitive) are returned.
+pub fn search_sites(conn: &mut PgConnection, search: Option<&str>, limit: i64) -> Vec<Site> {
+    sql_query(
+        "SELECT *
+           FROM sites
+          WHERE $1 IS NULL
+             OR name ILIKE $1
+             OR EXISTS (SELECT 1 FROM unnest(aliases) alias WHERE alias ILIKE $1)
+          ORDER BY name, id
+          LIMIT $2",
+    )
+    .bind::<Nullable<Text>, _>(search.map(contains_pattern))
+    .bind::<BigInt, _>(limit)
+    .load(conn)
+    .expect("Error loading sites")
+}
+
+/// Retrieve shared site with the specified ID.
+pub fn get_site_by_id(conn: &mut PgConnection, id: i32) -> Option<Site> {
+    sites::table
+        .find(id)
+        .first(conn)
+        .optional()
+        .expect("Error loading site by id")
+}
+
+/// Create a new shared site.
+pub fn create_site(conn: &mut PgConnection, site: NewSite) -> QueryResult<Site> {
+    diesel::insert_into(sites::table).values(site).get_result(conn)
+}
+
+/// Save an updated shared site in the database.
+pub fn update_site(conn: &mut PgConnection, site: &Site) -> QueryResult<()> {
+    diesel::update(site).set(site).execute(conn).map(|_| ())
+}
+
+/// Delete a shared site by ID. Locations copied from this site are kept, but
+/// are no longer linked to it.
+pub fn delete_site_by_id(conn: &mut PgConnection, id: i32) -> QueryResult<usize> {
+    diesel::delete(sites::table.find(id)).execute(conn)
+}
+
+/// Return the location of the user that was copied from the specified shared
+/// site. If there is no such location yet, it is created.
+pub fn get_or_create_location_from_site(
+    conn: &mut PgConnection,
+    user: &User,
+    site: &Site,
+) -> QueryResult<Location> {
+    conn.transaction(|conn| {
+        let existing = Location::belonging_to(user)
+            .filter(locations::site_id.eq(site.id))
+            .order(locations::id)
+            .first(conn)
+            .optional()?;
+        if let Some(location) = existing {
+            return Ok(location);
+        }
+        let location = NewLocation {
+            name: site.name.clone(),
+            country: site.country.clone(),
+            elevation: site.elevation,
+            user_id: user.id,
+            geog: Some(site.geog.clone()),
+            match_radius: None,
+            area: None,
+        };
+        diesel::insert_into(locations::table)
+            .values((location, locations::site_id.eq(site.id)))
+            .get_result(conn)
+    })
+}
+
+/// Merge the source locations into the target location: All flights are
+/// reassigned to the target location and the source locations are deleted.
+///
+/// If the target location is not linked to a shared site, it takes over the
+/// link of the first linked source location.
+pub fn merge_locations(conn: &mut PgConnection, target: &Location, source_ids: &[i32]) -> QueryResult<()> {
+    conn.transaction(|conn| {
+        diesel::update(flights::table.filter(flights::launch_at.eq_any(source_ids)))
+            .set(flights::launch_at.eq(target.id))
+            .execute(conn)?;
+        diesel::update(flights::table.filter(flights::landing_at.eq_any(source_ids)))
+            .set(flights::landing_at.eq(target.id))
+            .execute(conn)?;
+        if target.site_id.is_none() {
+            let site_id: Option<i32> = locations::table
+                .filter(locations::id.eq_any(source_ids))
+                .filter(locations::site_id.is_not_null())
+                .order(locations::id)
+                .select(locations::site_id)
+                .first(conn)
+                .optional()?
+                .flatten();
+            if site_id.is_some() {
+                diesel::update(locations::table.find(target.id))
+                    .set(locations::site_id.eq(site_id))
+                    .execute(conn)?;
+            }
+        }
+        diesel::delete(locations::table.filter(locations::id.eq_any(source_ids))).execute(conn)?;
+        Ok(())
+    })
+}
+
+/// Create a new glider.
+pub fn create_glider(conn: &mut PgConnection, glider: NewGlider) -> QueryResult<Glider> {
+    diesel::insert_into(gliders::table)
+        .values(glider)
+        .get_result(conn)
+}
+
+/// Save an updated glider in the database.
+pub fn update_glider(conn: &mut PgConnection, glider: &Glider) {
+    diesel::update(glider)
+        .set(glider)
+        .execute(conn)
+        .expect("Could not update glider");
+}
+
+/// Update the "last glider" of a user.
+pub fn update_user_last_glider(conn: &mut PgConnection, user: &User, glider_id: i32) {
+    diesel::update(user)
+        .set(users::last_glider_id.eq(glider_id))
+        .execute(conn)
+        .expect("Could not set user last glider id");
+}
+
+/// Retrieve all currency rules of a specific user.
+pub fn get_currency_rules_for_user(conn: &mut PgConnection, user: &User) -> Vec<CurrencyRule> {
+    CurrencyRule::belonging_to(user)
+        .order(currency_rules::id)
+        .load(conn)
+        .expect("Error loading currency rules")
+}
+
+/// Retrieve currency rule with the specified ID.
+pub fn get_currency_rule_by_id(conn: &mut PgConnection, id: i32) -> Option<CurrencyRule> {
+    currency_rules::table
+        .find(id)
+        .first(conn)
+        .optional()
+        .expect("Error loading currency rule by id")
+}
+
+/// Create a new currency rule.
+pub fn create_currency_rule(conn: &mut PgConnection, rule: NewCurrencyRule) -> QueryResult<CurrencyRule> {
+    diesel::insert_into(currency_rules::table)
+        .values(rule)
+        .get_result(conn)
+}
+
+/// Save an updated currency rule in the database.
+pub fn update_currency_rule(conn: &mut PgConnection, rule: &CurrencyRule) -> QueryResult<()> {
+    diesel::update(rule).set(rule).execute(conn)?;
+    Ok(())
+}
+
+/// Delete a currency rule by ID.
+pub fn delete_currency_rule_by_id(conn: &mut PgConnection, id: i32) -> QueryResult<()> {
+    let delete_count =
+        diesel::delete(currency_rules::table.filter(currency_rules::id.eq(&id))).execute(conn)?;
+    assert_eq!(delete_count, 1); // Sanity check
+    Ok(())
+}
+
+#[derive(Debug, QueryableByName)]
+pub struct FlightTimeEntry {
+    #[diesel(sql_type = Nullable<Integer>)]
+    pub glider_id: Option<i32>,
+    #[diesel(sql_type = Timestamptz)]
+    pub launch_time: DateTime<Utc>,
+    /// Flight duration in seconds, 0 if the landing time is unknown
+    #[diesel(sql_type = BigInt)]
+    pub seconds: i64,
+}
+
+/// Retrieve launch time and duration of all flights of a specific user that
+/// launched at or after `since`, most recent flight first.
+pub fn get_flight_times_since_for_user(
+    conn: &mut PgConnection,
+    user: &User,
+    since: DateTime<Utc>,
+) -> Vec<FlightTimeEntry> {
+    sql_query(
+        "SELECT glider_id,
+                launch_time,
+                coalesce(greatest(extract(epoch from (landing_time - launch_time)), 0)::bigint, 0) as seconds
+           FROM flights
+          WHERE user_id = $1
+            AND launch_time >= $2
+          ORDER BY launch_time DESC",
+    )
+    .bind::<Integer, _>(user.id)
+    .bind::<Timestamptz, _>(since)
+    .load(conn)
+    .expect("Error loading flight times")
+}
+
+#[derive(Debug, QueryableByName)]
+pub struct FlightCount {
+    #[diesel(sql_type = SmallInt)]
+    pub year: i16,
+    #[diesel(sql_type = BigInt)]
+    pub count: i64,
+}
+
+/// Get flight count per year for the specified user.
+pub fn get_flight_count_per_year_for_user(conn: &mut PgConnection, user: &User) -> Vec<FlightCount> {
+    sql_query(
+        "SELECT date_part('year', launch_time)::smallint as year,
+                count(*) as count
+           FROM flights
+          WHERE user_id = $1
+            AND launch_time IS NOT NULL
+          GROUP BY year
+          ORDER BY year DESC",
+    )
+    .bind::<Integer, _>(user.id)
+    .load::<FlightCount>(conn)
+    .expect("Error loading flight count stats")
+}
+
+/// Get hike&fly count per year for the specified user.
+pub fn get_hikeandfly_count_per_year_for_user(conn: &mut PgConnection, user: &User) -> Vec<FlightCount> {
+    sql_query(
+        "SELECT date_part('year', launch_time)::smallint as year,
+                count(*) as count
+           FROM flights
+          WHERE user_id = $1
+            AND launch_time IS NOT NULL
+            AND hikeandfly = true
+          GROUP BY year
+          ORDER BY year DESC",
+    )
+    .bind::<Integer, _>(user.id)
+    .load::<FlightCount>(conn)
+    .expect("Error loading hike&fly count stats")
+}
+
+#[derive(Debug, QueryableByName)]
+pub struct FlightTime {
+    #[diesel(sql_type = SmallInt)]
+    pub year: i16,
+    #[diesel(sql_type = BigInt)]
+    pub seconds: i64,
+}
+
+/// Get flight hours per year for the specified user.
+pub fn get_flight_time_per_year_for_user(conn: &mut PgConnection, user: &User) -> Vec<FlightTime> {
+    sql_query(
+        "SELECT date_part('year', launch_time)::smallint as year,
+                extract(epoch from sum(landing_time - launch_time))::bigint as seconds
+           FROM flights
+          WHERE user_id = $1
+            AND launch_time IS NOT NULL
+          GROUP BY year
+          ORDER BY year DESC",
+    )
+    .bind::<Integer, _>(user.id)
+    .load::<FlightTime>(conn)
+    .expect("Error loading flight time stats")
+}
+
+pub fn get_flight_count_without_launch_time(conn: &mut PgConnection, user: &User) -> i64 {
+    Flight::belonging_to(user)
+        .filter(flights::launch_time.is_null())
+        .select(count(flights::id))
+        .first::<i64>(conn)
+        .expect("Error loading flight count without launch time")
+}
+
+#[derive(Debug, QueryableByName, Serialize)]
+pub struct FlightDistance {
+    #[diesel(sql_type = SmallInt)]
+    pub year: i16,
+    #[diesel(sql_type = Nullable<Integer>)]
+    pub track: Option<i32>,
+    #[diesel(sql_type = Bool)]
+    pub track_incomplete: bool,
+    #[diesel(sql_type = Nullable<Integer>)]
+    pub scored: Option<i32>,
+    #[diesel(sql_type = Bool)]
+    pub scored_incomplete: bool,
+}
+
+/// Get flight distance per year for the specified user.
+pub fn get_flight_distance_per_year_for_user(conn: &mut PgConnection, user: &User) -> Vec<FlightDistance> {
+    sql_query(
+        "SELECT date_part('year', launch_time)::smallint as year,
+                sum(track_distance)::int as track,
+                count(*) - count(track_distance) > 0 as track_incomplete,
+                sum(xcontest_distance)::int as scored,
+                count(*) - count(xcontest_distance) > 0 as scored_incomplete
+           FROM flights
+          WHERE user_id = $1
+            AND launch_time IS NOT NULL
+          GROUP BY year
+          ORDER BY year DESC",
+    )
+    .bind::<Integer, _>(user.id)
+    .load::<FlightDistance>(conn)
+    .expect("Error loading flight distance stats")
+}
+
+#[derive(Debug, PartialEq)]
+pub enum StatsPeriod {
+    /// Calendar month (1-12) within a calendar year
+    Month,
+    /// ISO 8601 week (1-53) within an ISO week-numbering year
+    IsoWeek,
+}
+
+#[derive(Debug, QueryableByName)]
+pub struct FlightPeriodStats {
+    #[diesel(sql_type = SmallInt)]
+    pub year: i16,
+    #[diesel(sql_type = SmallInt)]
+    pub period: i16,
+    #[diesel(sql_type = BigInt)]
+    pub count: i64,
+    #[diesel(sql_type = BigInt)]
+    pub seconds: i64,
+}
+
+/// Get flight count and flight time per month or per ISO week for the
+/// specified user.
+pub fn get_flight_stats_per_period_for_user(
+    conn: &mut PgConnection,
+    user: &User,
+    period: StatsPeriod,
+) -> Vec<FlightPeriodStats> {
+    let (year_field, period_field) = match period {
+        StatsPeriod::Month => ("year", "month"),
+        StatsPeriod::IsoWeek => ("isoyear", "week"),
+    };
+    sql_query(format!(
+        "SELECT date_part('{}', launch_time)::smallint as year,
+                date_part('{}', launch_time)::smallint as period,
+                count(*) as count,
+                coalesce(extract(epoch from sum(landing_time - launch_time))::bigint, 0) as seconds
+           FROM flights
+          WHERE user_id = $1
+            AND launch_time IS NOT NULL
+          GROUP BY year, period
+          ORDER BY year DESC, period ASC",
+        year_field, period_field,
+    ))
+    .bind::<Integer, _>(user.id)
+    .load::<FlightPeriodStats>(conn)
+    .expect("Error loading flight period stats")
+}
+
+#[derive(Debug, PartialEq)]
+pub enum StatsBucket {
+    /// ISO day of week (1 = Monday, 7 = Sunday)
+    Weekday,
+    /// Hour of day of the launch (0-23)
+    LaunchHour,
+}
+
+#[derive(Debug, QueryableByName)]
+pub struct FlightBucketStats {
+    #[diesel(sql_type = SmallInt)]
+    pub bucket: i16,
+    #[diesel(sql_type = BigInt)]
+    pub count: i64,
+    #[diesel(sql_type = BigInt)]
+    pub seconds: i64,
+}
+
+/// Get flight count and flight time per day of week or per launch hour for
+/// the specified user, aggregated over all years.
+pub fn get_flight_stats_per_bucket_for_user(
+    conn: &mut PgConnection,
+    user: &User,
+    bucket: StatsBucket,
+) -> Vec<FlightBucketStats> {
+    sql_query(format!(
+        "SELECT date_part('{}', launch_time)::smallint as bucket,
+                count(*) as count,
+                coalesce(extract(epoch from sum(landing_time - launch_time))::bigint, 0) as seconds
+           FROM flights
+          WHERE user_id = $1
+            AND launch_time IS NOT NULL
+          GROUP BY bucket
+          ORDER BY bucket ASC",
+        match bucket {
+            StatsBucket::Weekday => "isodow",
+            StatsBucket::LaunchHour => "hour",
+        },
+    ))
+    .bind::<Integer, _>(user.id)
+    .load::<FlightBucketStats>(conn)
+    .expect("Error loading flight bucket stats")
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ConditionBucket {
+    /// Wind direction, rounded to the 8 main compass points (in degrees)
+    WindDirection,
+    /// Wind speed in steps of 10 km/h
+    WindSpeed,
+    /// Thermal strength in steps of 1 m/s
+    ThermalStrength,
+    /// Cloud base in steps of 500 m
+    CloudBase,
+    /// Turbulence rating (1-5)
+    Turbulence,
+}
+
+/// Get flight count and flight time per flight condition bucket for the
+/// specified user. Flights without a value for the condition are ignored.
+pub fn get_flight_stats_per_condition_for_user(
+    conn: &mut PgConnection,
+    user: &User,
+    bucket: ConditionBucket,
+) -> Vec<FlightBucketStats> {
+    let (expression, column) = match bucket {
+        ConditionBucket::WindDirection => ("(wind_direction + 22) / 45 % 8 * 45", "wind_direction"),
+        ConditionBucket::WindSpeed => ("wind_speed / 10 * 10", "wind_speed"),
+        ConditionBucket::ThermalStrength => ("floor(thermal_strength)", "thermal_strength"),
+        ConditionBucket::CloudBase => ("cloud_base / 500 * 500", "cloud_base"),
+        ConditionBucket::Turbulence => ("turbulence", "turbulence"),
+    };
+    sql_query(format!(
+        "SELECT ({})::smallint as bucket,
+                count(*) as count,
+                coalesce(extract(epoch from sum(landing_time - launch_time))::bigint, 0) as seconds
+           FROM flights
+          WHERE user_id = $1
+            AND {} IS NOT NULL
+          GROUP BY bucket
+          ORDER BY bucket ASC",
+        expression, column,
+    ))
+    .bind::<Integer, _>(user.id)
+    .load::<FlightBucketStats>(conn)
+    .expect("Error loading flight condition stats")
+}
+
+#[derive(Debug, QueryableByName)]
+pub struct FlightStreaks {
+    #[diesel(sql_type = SmallInt)]
+    pub year: i16,
+    /// Number of distinct days with at least one flight
+    #[diesel(sql_type = BigInt)]
+    pub flying_days: i64,
+    /// Longest run of consecutive flying days
+    #[diesel(sql_type = Integer)]
+    pub longest_streak: i32,
+    /// Longest number of days without a flight between two flying days
+    #[diesel(sql_type = Nullable<Integer>)]
+    pub longest_gap: Option<i32>,
+}
+
+/// Get flying day streaks and gaps per year for the specified user.
+///
+/// Streaks and gaps do not span across the turn of the year.
+pub fn get_flight_streaks_per_year_for_user(conn: &mut PgConnection, user: &User) -> Vec<FlightStreaks> {
+    sql_query(
+        "WITH days AS (
+                SELECT DISTINCT launch_time::date as day
+                  FROM flights
+                 WHERE user_id = $1
+                   AND launch_time IS NOT NULL
+             ),
+             islands AS (
+                SELECT date_part('year', day)::smallint as year,
+                       day - (row_number() OVER (PARTITION BY date_part('year', day) ORDER BY day))::int as island,
+                       day - lag(day) OVER (PARTITION BY date_part('year', day) ORDER BY day) - 1 as gap
+                  FROM days
+             ),
+             streaks AS (
+                SELECT year, count(*) as length
+                  FROM islands
+                 GROUP BY year, island
+             )
+        SELECT i.year,
+               count(*) as flying_days,
+               (SELECT max(s.length) FROM streaks s WHERE s.year = i.year)::int as longest_streak,
+               max(i.gap) as longest_gap
+          FROM islands i
+         GROUP BY i.year
+         ORDER BY i.year DESC",
+    )
+    .bind::<Integer, _>(user.id)
+    .load::<FlightStreaks>(conn)
+    .expect("Error loading flight streak stats")
 }
 
-/// Delete a location by ID.
-pub fn delete_location_by_id(conn: &mut PgConnection, id: i32) -> QueryResult<()> {
-    let delete_count = diesel::delete(locations::table.filter(locations::id.eq(&id))).execute(conn)?;
-    assert_eq!(delete_count, 1); // Sanity check
-    Ok(())
+#[derive(Debug, PartialEq)]
+pub enum FlightRecordBy {
+    /// Flight duration in seconds
+    Duration,
+    /// GPS track distance in km
+    TrackDistance,
+    /// Elevation of the launch location in meters
+    LaunchElevation,
 }
 
-/// Create a new glider.
-pub fn create_glider(conn: &mut PgConnection, glider: NewGlider) -> QueryResult<Glider> {
-    diesel::insert_into(gliders::table)
-        .values(glider)
-        .get_result(conn)
+/// A flight that holds a personal record.
+#[derive(Debug, QueryableByName)]
+pub struct FlightRecord {
+    #[diesel(sql_type = Integer)]
+    pub flight_id: i32,
+    #[diesel(sql_type = Nullable<Integer>)]
+    pub number: Option<i32>,
+    #[diesel(sql_type = Nullable<Timestamptz>)]
+    pub launch_time: Option<DateTime<Utc>>,
+    #[diesel(sql_type = Nullable<Integer>)]
+    pub launch_at: Option<i32>,
+    #[diesel(sql_type = Nullable<Text>)]
+    pub launch_name: Option<String>,
+    /// The record value (unit depends on the record type)
+    #[diesel(sql_type = Double)]
+    pub value: f64,
 }
 
-/// Save an updated glider in the database.
-pub fn update_glider(conn: &mut PgConnection, glider: &Glider) {
-    diesel::update(glider)
-        .set(glider)
-        .execute(conn)
-        .expect("Could not update glider");
+/// Get the flight with the highest value for the specified record type.
+///
+/// If multiple flights share the record, the earliest one is returned.
+pub fn get_flight_record_for_user(
+    conn: &mut PgConnection,
+    user: &User,
+    record_by: FlightRecordBy,
+) -> Option<FlightRecord> {
+    sql_query(format!(
+        "SELECT f.id as flight_id,
+                f.number,
+                f.launch_time,
+                f.launch_at,
+                l.name as launch_name,
+                ({0})::float8 as value
+           FROM flights f
+                LEFT JOIN locations l ON l.id = f.launch_at
+          WHERE f.user_id = $1
+            AND ({0}) IS NOT NULL
+          ORDER BY value DESC, f.launch_time ASC NULLS LAST, f.id ASC
+          LIMIT 1",
+        match record_by {
+            FlightRecordBy::Duration => "extract(epoch from (f.landing_time - f.launch_time))",
+            FlightRecordBy::TrackDistance => "f.track_distance",
+            FlightRecordBy::LaunchElevation => "l.elevation",
+        },
+    ))
+    .bind::<Integer, _>(user.id)
+    .get_result(conn)
+    .optional()
+    .expect("Error loading flight record")
 }
 
-/// Update the "last glider" of a user.
-pub fn update_user_last_glider(conn: &mut PgConnection, user: &User, glider_id: i32) {
-    diesel::update(user)
-        .set(users::last_glider_id.eq(glider_id))
-        .execute(conn)
-        .expect("Could not set user last glider id");
+#[derive(Debug, QueryableByName)]
+pub struct TracktypeFlightRecord {
+    #[diesel(sql_type = Text)]
+    pub tracktype: String,
+    #[diesel(embed)]
+    pub record: FlightRecord,
+}
+
+/// Get the flight with the highest XContest scored distance per tracktype.
+pub fn get_scored_distance_records_for_user(
+    conn: &mut PgConnection,
+    user: &User,
+) -> Vec<TracktypeFlightRecord> {
+    sql_query(
+        "SELECT DISTINCT ON (f.xcontest_tracktype)
+                f.xcontest_tracktype as tracktype,
+                f.id as flight_id,
+                f.number,
+                f.launch_time,
+                f.launch_at,
+                l.name as launch_name,
+                f.xcontest_distance::float8 as value
+           FROM flights f
+                LEFT JOIN locations l ON l.id = f.launch_at
+          WHERE f.user_id = $1
+            AND f.xcontest_tracktype IS NOT NULL
+            AND f.xcontest_distance IS NOT NULL
+          ORDER BY f.xcontest_tracktype, f.xcontest_distance DESC, f.launch_time ASC NULLS LAST, f.id ASC",
+    )
+    .bind::<Integer, _>(user.id)
+    .load(conn)
+    .expect("Error loading scored distance records")
 }
 
 #[derive(Debug, QueryableByName)]
-pub struct FlightCount {
-    #[diesel(sql_type = SmallInt)]
-    pub year: i16,
+pub struct DayFlightCount {
+    #[diesel(sql_type = Date)]
+    pub day: NaiveDate,
     #[diesel(sql_type = BigInt)]
     pub count: i64,
 }
 
-/// Get flight count per year for the specified user.
-pub fn get_flight_count_per_year_for_user(conn: &mut PgConnection, user: &User) -> Vec<FlightCount> {
+/// Get the (earliest) day with the most flights for the specified user.
+pub fn get_most_flights_in_a_day_for_user(conn: &mut PgConnection, user: &User) -> Option<DayFlightCount> {
     sql_query(
-        "SELECT date_part('year', launch_time)::smallint as year,
+        "SELECT launch_time::date as day,
                 count(*) as count
            FROM flights
           WHERE user_id = $1
             AND launch_time IS NOT NULL
-          GROUP BY year
-          ORDER BY year DESC",
+          GROUP BY day
+          ORDER BY count DESC, day ASC
+          LIMIT 1",
     )
     .bind::<Integer, _>(user.id)
-    .load::<FlightCount>(conn)
-    .expect("Error loading flight count stats")
+    .get_result(conn)
+    .optional()
+    .expect("Error loading most flights in a day")
 }
 
-/// Get hike&fly count per year for the specified user.
-pub fn get_hikeandfly_count_per_year_for_user(conn: &mut PgConnection, user: &User) -> Vec<FlightCount> {
+#[derive(Debug, QueryableByName)]
+pub struct LocationFirstFlight {
+    #[diesel(sql_type = Integer)]
+    pub location_id: i32,
+    #[diesel(sql_type = Text)]
+    pub location_name: String,
+    #[diesel(sql_type = Integer)]
+    pub flight_id: i32,
+    #[diesel(sql_type = Nullable<Integer>)]
+    pub number: Option<i32>,
+    #[diesel(sql_type = Nullable<Timestamptz>)]
+    pub launch_time: Option<DateTime<Utc>>,
+}
+
+/// Get the first flight (with either launch or landing) at every location
+/// visited by the specified user, ordered by date of the first visit.
+pub fn get_first_flight_per_location_for_user(
+    conn: &mut PgConnection,
+    user: &User,
+) -> Vec<LocationFirstFlight> {
     sql_query(
-        "SELECT date_part('year', launch_time)::smallint as year,
-                count(*) as count
-           FROM flights
-          WHERE user_id = $1
-            AND launch_time IS NOT NULL
-            AND hikeandfly = true
-          GROUP BY year
-          ORDER BY year DESC",
+        "SELECT *
+           FROM (
+                SELECT DISTINCT ON (l.id)
+                       l.id as location_id,
+                       l.name as location_name,
+                       f.id as flight_id,
+                       f.number,
+                       f.launch_time
+                  FROM locations l
+                       INNER JOIN flights f ON f.launch_at = l.id OR f.landing_at = l.id
+                 WHERE f.user_id = $1
+                 ORDER BY l.id, f.launch_time ASC NULLS LAST, f.number ASC NULLS LAST, f.id ASC
+                ) first_flights
+          ORDER BY launch_time ASC NULLS LAST, flight_id ASC",
     )
     .bind::<Integer, _>(user.id)
-    .load::<FlightCount>(conn)
-    .expect("Error loading hike&fly count stats")
+    .load(conn)
+    .expect("Error loading first flight per location")
+}
+
+#[derive(Debug, PartialEq)]
+pub enum MilestoneKind {
+    /// The n-th flight
+    FlightCount,
+    /// The flight during which the total airtime reached n hours
+    FlightHours,
 }
 
 #[derive(Debug, QueryableByName)]
-pub struct FlightTime {
-    #[diesel(sql_type = SmallInt)]
-    pub year: i16,
+pub struct Milestone {
+    #[diesel(sql_type = Integer)]
+    pub threshold: i32,
+    #[diesel(sql_type = Integer)]
+    pub flight_id: i32,
+    #[diesel(sql_type = Nullable<Integer>)]
+    pub number: Option<i32>,
+    #[diesel(sql_type = Nullable<Timestamptz>)]
+    pub launch_time: Option<DateTime<Utc>>,
+}
+
+/// Get the flights at which the specified milestone thresholds were reached.
+///
+/// Flights are ordered by launch time, flights without launch time are
+/// ignored. Thresholds that were not reached yet are not included.
+pub fn get_milestones_for_user(
+    conn: &mut PgConnection,
+    user: &User,
+    kind: MilestoneKind,
+    thresholds: &[i32],
+) -> Vec<Milestone> {
+    sql_query(format!(
+        "WITH ordered AS (
+                SELECT id,
+                       number,
+                       launch_time,
+                       row_number() OVER w as flight_count,
+                       coalesce(sum(extract(epoch from (landing_time - launch_time))) OVER w, 0) as seconds
+                  FROM flights
+                 WHERE user_id = $1
+                   AND launch_time IS NOT NULL
+                WINDOW w AS (ORDER BY launch_time, id ROWS UNBOUNDED PRECEDING)
+             )
+        SELECT DISTINCT ON (m.threshold)
+               m.threshold,
+               o.id as flight_id,
+               o.number,
+               o.launch_time
+          FROM unnest($2) m(threshold)
+               INNER JOIN ordered o ON {}
+         ORDER BY m.threshold, o.flight_count",
+        match kind {
+            MilestoneKind::FlightCount => "o.flight_count = m.threshold",
+            MilestoneKind::FlightHours => "o.seconds >= m.threshold * 3600",
+        },
+    ))
+    .bind::<Integer, _>(user.id)
+    .bind::<Array<Integer>, _>(thresholds)
+    .load(conn)
+    .expect("Error loading milestones")
+}
+
+/// Summary of the flights at a location.
+#[derive(Debug, QueryableByName)]
+pub struct LocationFlightSummary {
     #[diesel(sql_type = BigInt)]
-    pub seconds: i64,
+    pub launch_count: i64,
+    #[diesel(sql_type = BigInt)]
+    pub landing_count: i64,
+    /// Total flight time of the flights launched at the location
+    #[diesel(sql_type = BigInt)]
+    pub launch_seconds: i64,
+    /// Average flight time of the flights launched at the location
+    #[diesel(sql_type = Nullable<Double>)]
+    pub average_seconds: Option<f64>,
+    #[diesel(sql_type = Nullable<Timestamptz>)]
+    pub first_visit: Option<DateTime<Utc>>,
+    #[diesel(sql_type = Nullable<Timestamptz>)]
+    pub last_visit: Option<DateTime<Utc>>,
 }
 
-/// Get flight hours per year for the specified user.
-pub fn get_flight_time_per_year_for_user(conn: &mut PgConnection, user: &User) -> Vec<FlightTime> {
+/// Get launch and landing counts, flight times and the first and last visit
+/// of the specified location.
+pub fn get_flight_summary_for_location(conn: &mut PgConnection, location_id: i32) -> LocationFlightSummary {
     sql_query(
-        "SELECT date_part('year', launch_time)::smallint as year,
-                extract(epoch from sum(landing_time - launch_time))::bigint as seconds
+        "SELECT count(*) FILTER (WHERE launch_at = $1) as launch_count,
+                count(*) FILTER (WHERE landing_at = $1) as landing_count,
+                coalesce(extract(epoch from sum(landing_time - launch_time) FILTER (WHERE launch_at = $1))::bigint, 0)
+                    as launch_seconds,
+                (avg(extract(epoch from (landing_time - launch_time))) FILTER (WHERE launch_at = $1))::float8
+                    as average_seconds,
+                min(coalesce(launch_time, landing_time)) as first_visit,
+                max(coalesce(launch_time, landing_time)) as last_visit
            FROM flights
-          WHERE user_id = $1
+          WHERE launch_at = $1 OR landing_at = $1",
+    )
+    .bind::<Integer, _>(location_id)
+    .get_result(conn)
+    .expect("Error loading location flight summary")
+}
+
+/// Get the longest flight launched at the specified location (value in
+/// seconds).
+///
+/// If multiple flights share the record, the earliest one is returned.
+pub fn get_longest_flight_from_location(conn: &mut PgConnection, location_id: i32) -> Option<FlightRecord> {
+    sql_query(
+        "SELECT f.id as flight_id,
+                f.number,
+                f.launch_time,
+                f.launch_at,
+                l.name as launch_name,
+                extract(epoch from (f.landing_time - f.launch_time))::float8 as value
+           FROM flights f
+                INNER JOIN locations l ON l.id = f.launch_at
+          WHERE f.launch_at = $1
+            AND f.launch_time IS NOT NULL
+            AND f.landing_time IS NOT NULL
+          ORDER BY value DESC, f.launch_time ASC, f.id ASC
+          LIMIT 1",
+    )
+    .bind::<Integer, _>(location_id)
+    .get_result(conn)
+    .optional()
+    .expect("Error loading longest flight from location")
+}
+
+/// Get the month of the year (1-12) with the most flights launched at the
+/// specified location. Ties are broken by flight time.
+pub fn get_best_month_for_location(conn: &mut PgConnection, location_id: i32) -> Option<FlightBucketStats> {
+    sql_query(
+        "SELECT date_part('month', launch_time)::smallint as bucket,
+                count(*) as count,
+                coalesce(extract(epoch from sum(landing_time - launch_time))::bigint, 0) as seconds
+           FROM flights
+          WHERE launch_at = $1
             AND launch_time IS NOT NULL
-          GROUP BY year
-          ORDER BY year DESC",
+          GROUP BY bucket
+          ORDER BY count DESC, seconds DESC, bucket ASC
+          LIMIT 1",
     )
-    .bind::<Integer, _>(user.id)
-    .load::<FlightTime>(conn)
-    .expect("Error loading flight time stats")
+    .bind::<Integer, _>(location_id)
+    .get_result(conn)
+    .optional()
+    .expect("Error loading best month for location")
 }
 
-pub fn get_flight_count_without_launch_time(conn: &mut PgConnection, user: &User) -> i64 {
-    Flight::belonging_to(user)
-        .filter(flights::launch_time.is_null())
-        .select(count(flights::id))
-        .first::<i64>(conn)
-        .expect("Error loading flight count without launch time")
+/// A glider or location with a flight count.
+#[derive(Debug, QueryableByName)]
+pub struct NamedFlightCount {
+    #[diesel(sql_type = Integer)]
+    pub id: i32,
+    #[diesel(sql_type = Text)]
+    pub name: String,
+    #[diesel(sql_type = BigInt)]
+    pub count: i64,
 }
 
-#[derive(Debug, QueryableByName, Serialize)]
-pub struct FlightDistance {
-    #[diesel(sql_type = SmallInt)]
-    pub year: i16,
-    #[diesel(sql_type = Nullable<Integer>)]
-    pub track: Option<i32>,
-    #[diesel(sql_type = Bool)]
-    pub track_incomplete: bool,
-    #[diesel(sql_type = Nullable<Integer>)]
-    pub scored: Option<i32>,
-    #[diesel(sql_type = Bool)]
-    pub scored_incomplete: bool,
+/// Get the gliders flown from or to the specified location, ordered by flight
+/// count.
+pub fn get_gliders_for_location(conn: &mut PgConnection, location_id: i32) -> Vec<NamedFlightCount> {
+    sql_query(
+        "SELECT g.id,
+                g.manufacturer || ' ' || g.model as name,
+                count(*) as count
+           FROM flights f
+                INNER JOIN gliders g ON g.id = f.glider_id
+          WHERE f.launch_at = $1 OR f.landing_at = $1
+          GROUP BY g.id
+          ORDER BY count DESC, name ASC",
+    )
+    .bind::<Integer, _>(location_id)
+    .load(conn)
+    .expect("Error loading gliders for location")
 }
 
-/// Get flight distance per year for the specified user.
-pub fn get_flight_distance_per_year_for_user(conn: &mut PgConnection, user: &User) -> Vec<FlightDistance> {
+/// Get the most common landing locations of flights launched at the
+/// specified location, ordered by flight count.
+pub fn get_landing_locations_for_launch(
+    conn: &mut PgConnection,
+    location_id: i32,
+    limit: i64,
+) -> Vec<NamedFlightCount> {
     sql_query(
-        "SELECT date_part('year', launch_time)::smallint as year,
-                sum(track_distance)::int as track,
-                count(*) - count(track_distance) > 0 as track_incomplete,
-                sum(xcontest_distance)::int as scored,
-                count(*) - count(xcontest_distance) > 0 as scored_incomplete
-           FROM flights
-          WHERE user_id = $1
-            AND launch_time IS NOT NULL
-          GROUP BY year
-          ORDER BY year DESC",
+        "SELECT l.id,
+                l.name,
+                count(*) as count
+           FROM flights f
+                INNER JOIN locations l ON l.id = f.landing_at
+          WHERE f.launch_at = $1
+          GROUP BY l.id
+          ORDER BY count DESC, l.name ASC
+          LIMIT $2",
     )
-    .bind::<Integer, _>(user.id)
-    .load::<FlightDistance>(conn)
-    .expect("Error loading flight distance stats")
+    .bind::<Integer, _>(location_id)
+    .bind::<BigInt, _>(limit)
+    .load(conn)
+    .expect("Error loading landing locations for launch")
 }
 
 #[cfg(test)]
@@ -673,6 +2538,8 @@ mod tests {
     #[case("email@email.ch", "user", Ok(()))]
     #[case("example@example.com", "testuser1", Err(RegistrationError::NonUniqueUsername))]
     #[case("user1@example.com", "username", Err(RegistrationError::InvalidEmail))]
+    #[case("User1@Example.COM", "username", Err(RegistrationError::InvalidEmail))]
+    #[case(" user1@example.com ", "username", Err(RegistrationError::InvalidEmail))]
     fn test_duplicate_username(
         #[case] email: &str,
         #[case] username: &str,
@@ -702,6 +2569,33 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_consume_email_verification_token_taken() {
+        let ctx = test_utils::DbTestContext::new();
+        let mut conn = ctx.force_get_conn();
+        let token = create_email_verification_token(
+            &mut conn,
+            &ctx.testuser1.user,
+            "User2@Example.com",
+            chrono::Duration::hours(1),
+        )
+        .unwrap();
+
+        // The address is used by another user with different case
+        assert!(consume_email_verification_token(&mut conn, &token).is_err());
+        let user = get_user(&mut conn, ctx.testuser1.user.id).unwrap();
+        assert_eq!(user.email, "user1@example.com");
+    }
+
+    #[test]
+    fn test_hash_token() {
+        // Same hex encoded SHA-256 hash as previously computed by pgcrypto
+        assert_eq!(
+            hash_token("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
     #[test]
     fn test_get_gliders_for_user() {
         let ctx = test_utils::DbTestContext::new();
@@ -776,6 +2670,29 @@ mod tests {
         assert_eq!(user.unwrap().id, ctx.testuser1.user.id);
     }
 
+    #[test]
+    fn validate_login_rehashes_legacy_hash() {
+        let ctx = test_utils::DbTestContext::new();
+        let mut conn = ctx.force_get_conn();
+
+        // Store legacy bcrypt hash, as created by earlier versions with pgcrypto
+        sql_query(
+            "UPDATE users SET password = crypt('legacypass', gen_salt('bf', 4)) WHERE username = 'testuser1'",
+        )
+        .execute(&mut *conn)
+        .unwrap();
+
+        // Wrong password: Hash is not touched
+        assert!(validate_login(&mut conn, "testuser1", "wrongpass").is_none());
+        let user = get_user(&mut conn, ctx.testuser1.user.id).unwrap();
+        assert!(user.password.starts_with("$2a$"));
+
+        // Correct password: Hash is replaced
+        let user = validate_login(&mut conn, "testuser1", "legacypass").unwrap();
+        assert!(user.password.starts_with("$argon2id$"));
+        assert!(validate_login(&mut conn, "testuser1", "legacypass").is_some());
+    }
+
     #[test]
     fn update_login_password() {
         let ctx = test_utils::DbTestContext::new();
@@ -978,4 +2895,225 @@ mod tests {
         let result = get_flight_ids_with_igc_for_user(&mut ctx.force_get_conn(), &ctx.testuser2.user);
         assert_eq!(result, vec![flights[3].id]);
     }
+
+    #[test]
+    fn test_get_flight_streaks_and_periods_for_user() {
+        let ctx = test_utils::DbTestContext::new();
+
+        // No flights
+        let streaks = get_flight_streaks_per_year_for_user(&mut ctx.force_get_conn(), &ctx.testuser1.user);
+        assert_eq!(streaks.len(), 0);
+
+        // Add some flights: Three consecutive days, then two flights six days later
+        let launch_times = [
+            test_utils::utc_datetime(2023, 7, 1, 12, 0, 0),
+            test_utils::utc_datetime(2023, 7, 2, 12, 0, 0),
+            test_utils::utc_datetime(2023, 7, 3, 12, 0, 0),
+            test_utils::utc_datetime(2023, 7, 10, 11, 0, 0),
+            test_utils::utc_datetime(2023, 7, 10, 15, 0, 0),
+            test_utils::utc_datetime(2024, 1, 5, 12, 0, 0),
+        ];
+        diesel::insert_into(flights::table)
+            .values(
+                launch_times
+                    .iter()
+                    .map(|launch_time| NewFlight {
+                        user_id: ctx.testuser1.user.id,
+                        launch_time: Some(*launch_time),
+                        landing_time: Some(*launch_time + chrono::Duration::minutes(30)),
+                        ..Default::default()
+                    })
+                    .collect::<Vec<_>>(),
+            )
+            .execute(&mut *ctx.force_get_conn())
+            .expect("Could not create flights");
+
+        // Streaks per year
+        let streaks = get_flight_streaks_per_year_for_user(&mut ctx.force_get_conn(), &ctx.testuser1.user)
+            .into_iter()
+            .map(|s| (s.year, s.flying_days, s.longest_streak, s.longest_gap))
+            .collect::<Vec<_>>();
+        assert_eq!(streaks, vec![(2024, 1, 1, None), (2023, 4, 3, Some(6))]);
+
+        // Stats per month
+        let months = get_flight_stats_per_period_for_user(
+            &mut ctx.force_get_conn(),
+            &ctx.testuser1.user,
+            StatsPeriod::Month,
+        )
+        .into_iter()
+        .map(|s| (s.year, s.period, s.count, s.seconds))
+        .collect::<Vec<_>>();
+        assert_eq!(months, vec![(2024, 1, 1, 1800), (2023, 7, 5, 9000)]);
+
+        // Stats per ISO week (2023-07-01 and 2023-07-02 are in week 26)
+        let weeks = get_flight_stats_per_period_for_user(
+            &mut ctx.force_get_conn(),
+            &ctx.testuser1.user,
+            StatsPeriod::IsoWeek,
+        )
+        .into_iter()
+        .map(|s| (s.year, s.period, s.count))
+        .collect::<Vec<_>>();
+        assert_eq!(
+            weeks,
+            vec![(2024, 1, 1), (2023, 26, 2), (2023, 27, 1), (2023, 28, 2)]
+        );
+
+        // Stats per launch hour
+        let hours = get_flight_stats_per_bucket_for_user(
+            &mut ctx.force_get_conn(),
+            &ctx.testuser1.user,
+            StatsBucket::LaunchHour,
+        )
+        .into_iter()
+        .map(|s| (s.bucket, s.count))
+        .collect::<Vec<_>>();
+        assert_eq!(hours, vec![(11, 1), (12, 4), (15, 1)]);
+    }
+
+    #[test]
+    fn test_get_flight_stats_per_condition_for_user() {
+        let ctx = test_utils::DbTestContext::new();
+
+        let launch_time = test_utils::utc_datetime(2024, 6, 1, 12, 0, 0);
+        let conditions = [
+            (Some(350), Some(5), Some(1.5), Some(2100), Some(1)),
+            (Some(10), Some(18), Some(3.2), Some(2400), Some(3)),
+            (Some(230), Some(12), Some(3.9), None, Some(3)),
+            (None, None, None, None, None),
+        ];
+        diesel::insert_into(flights::table)
+            .values(
+                conditions
+                    .iter()
+                    .map(
+                        |&(wind_direction, wind_speed, thermal_strength, cloud_base, turbulence)| NewFlight {
+                            user_id: ctx.testuser1.user.id,
+                            launch_time: Some(launch_time),
+                            landing_time: Some(launch_time + chrono::Duration::minutes(30)),
+                            wind_direction,
+                            wind_speed,
+                            thermal_strength,
+                            cloud_base,
+                            turbulence,
+                            ..Default::default()
+                        },
+                    )
+                    .collect::<Vec<_>>(),
+            )
+            .execute(&mut *ctx.force_get_conn())
+            .expect("Could not create flights");
+
+        let stats = |bucket| {
+            get_flight_stats_per_condition_for_user(&mut ctx.force_get_conn(), &ctx.testuser1.user, bucket)
+                .into_iter()
+                .map(|s| (s.bucket, s.count, s.seconds))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            stats(ConditionBucket::WindDirection),
+            vec![(0, 2, 3600), (225, 1, 1800)]
+        );
+        assert_eq!(
+            stats(ConditionBucket::WindSpeed),
+            vec![(0, 1, 1800), (10, 2, 3600)]
+        );
+        assert_eq!(
+            stats(ConditionBucket::ThermalStrength),
+            vec![(1, 1, 1800), (3, 2, 3600)]
+        );
+        assert_eq!(stats(ConditionBucket::CloudBase), vec![(2000, 2, 3600)]);
+        assert_eq!(
+            stats(ConditionBucket::Turbulence),
+            vec![(1, 1, 1800), (3, 2, 3600)]
+        );
+        assert_eq!(
+            get_flight_stats_per_condition_for_user(
+                &mut ctx.force_get_conn(),
+                &ctx.testuser2.user,
+                ConditionBucket::Turbulence
+            )
+            .len(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_get_maintenance_usage_for_user() {
+        let ctx = test_utils::DbTestContext::new();
+        let conn = &mut *ctx.force_get_conn();
+        let glider = create_glider(
+            conn,
+            NewGlider {
+                user_id: ctx.testuser1.user.id,
+                manufacturer: "Advance".into(),
+                model: "Alpha".into(),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        for launch_time in [
+            test_utils::utc_datetime(2024, 5, 1, 10, 0, 0),
+            test_utils::utc_datetime(2024, 5, 2, 10, 0, 0),
+        ] {
+            create_flight(
+                conn,
+                &NewFlight {
+                    user_id: ctx.testuser1.user.id,
+                    glider_id: Some(glider.id),
+                    launch_time: Some(launch_time),
+                    landing_time: Some(launch_time + chrono::Duration::minutes(30)),
+                    ..Default::default()
+                },
+                None,
+            );
+        }
+        let mut create_interval = |kind: &str| {
+            create_maintenance_interval(
+                conn,
+                NewGliderMaintenanceInterval {
+                    glider_id: glider.id,
+                    kind: kind.into(),
+                    interval_seconds: None,
+                    interval_flights: Some(50),
+                    interval_months: None,
+                },
+            )
+            .unwrap()
+        };
+        let check = create_interval("check");
+        let trim = create_interval("trim");
+        for date in [
+            NaiveDate::from_ymd_opt(2024, 4, 1).unwrap(),
+            NaiveDate::from_ymd_opt(2024, 5, 1).unwrap(),
+        ] {
+            create_maintenance_event(
+                conn,
+                NewGliderMaintenanceEvent {
+                    glider_id: glider.id,
+                    kind: "check".into(),
+                    date,
+                    notes: None,
+                },
+            )
+            .unwrap();
+        }
+
+        let usage = get_maintenance_usage_for_user(conn, &ctx.testuser1.user);
+        let usage_for = |interval: &GliderMaintenanceInterval| {
+            let usage = usage
+                .iter()
+                .find(|usage| usage.interval_id == interval.id)
+                .unwrap();
+            (usage.usage.flights, usage.usage.seconds)
+        };
+        assert_eq!(usage.len(), 2);
+        // Never performed, all flights are counted
+        assert_eq!(usage_for(&trim), (2, 3600));
+        // Flights on the day of the last maintenance are not counted
+        assert_eq!(usage_for(&check), (1, 1800));
+
+        assert!(get_maintenance_usage_for_user(conn, &ctx.testuser2.user).is_empty());
+    }
 }
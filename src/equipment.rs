@@ -0,0 +1,704 @@
+//! Equipment views (harness, reserve, instruments, etc).
+
+use chrono::{Months, NaiveDate, Utc};
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use rocket::{delete, error, get, http::Status, post, routes, serde::json::Json, Route};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth, data,
+    maintenance::{self, MaintenanceState},
+    models::{EquipmentRepack, EquipmentWithStats, NewEquipment, NewEquipmentRepack},
+    responders::{ApiError, RocketError},
+};
+
+/// Valid equipment kinds.
+pub const EQUIPMENT_KINDS: [&str; 5] = ["harness", "reserve", "instrument", "helmet", "speed_system"];
+
+/// Repack interval for reserves that don't specify their own interval.
+pub const DEFAULT_REPACK_INTERVAL_MONTHS: u32 = 6;
+
+// Forms
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct EquipmentCreateUpdateForm {
+    kind: String,
+    manufacturer: String,
+    model: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    since: Option<String>, // ISO date (e.g. 2010-11-30)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    until: Option<String>, // ISO date (e.g. 2010-11-30)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cost: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    comment: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    repack_interval_months: Option<i32>,
+}
+
+impl EquipmentCreateUpdateForm {
+    /// Validate the form and convert it to a `NewEquipment` model.
+    ///
+    /// If validation fails, return error message.
+    fn into_new_equipment(self, user_id: i32) -> Result<NewEquipment, String> {
+        if !EQUIPMENT_KINDS.contains(&self.kind.as_str()) {
+            return Err(format!("Invalid equipment kind: {}", self.kind));
+        }
+        if let Some(months) = self.repack_interval_months {
+            if self.kind != "reserve" {
+                return Err("Repack interval can only be specified for reserves".into());
+            }
+            if months < 1 {
+                return Err("Repack interval must be positive".into());
+            }
+        }
+        Ok(NewEquipment {
+            user_id,
+            kind: self.kind,
+            manufacturer: self.manufacturer,
+            model: self.model,
+            since: self
+                .since
+                .and_then(|strval| NaiveDate::parse_from_str(&strval, "%Y-%m-%d").ok()),
+            until: self
+                .until
+                .and_then(|strval| NaiveDate::parse_from_str(&strval, "%Y-%m-%d").ok()),
+            source: self.source,
+            cost: self.cost,
+            comment: self.comment,
+            repack_interval_months: self.repack_interval_months,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RepackCreateForm {
+    date: String, // ISO date (e.g. 2010-11-30)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    notes: Option<String>,
+}
+
+// API types
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiEquipment {
+    id: i32,
+    kind: String,
+    manufacturer: String,
+    model: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    since: Option<String>, // ISO date (e.g. 2010-11-30)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    until: Option<String>, // ISO date (e.g. 2010-11-30)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cost: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    comment: Option<String>,
+    stats: ApiEquipmentStats,
+    /// Reserves only: Repack status
+    #[serde(skip_serializing_if = "Option::is_none")]
+    repack: Option<ApiRepackStatus>,
+}
+
+impl ApiEquipment {
+    fn from_equipment(equipment: EquipmentWithStats, today: NaiveDate) -> Self {
+        let repack = (equipment.kind == "reserve").then(|| {
+            let interval_months = equipment
+                .repack_interval_months
+                .map_or(DEFAULT_REPACK_INTERVAL_MONTHS, |months| months as u32);
+            let due_date = equipment
+                .last_repack
+                .or(equipment.since)
+                .and_then(|date| date.checked_add_months(Months::new(interval_months)));
+            ApiRepackStatus {
+                interval_months,
+                last_repack: equipment.last_repack,
+                repack_count: equipment.repack_count,
+                flights_since_repack: equipment.flights_since_repack,
+                due_date,
+                state: maintenance::date_state(due_date, today),
+            }
+        });
+        ApiEquipment {
+            id: equipment.id,
+            kind: equipment.kind,
+            manufacturer: equipment.manufacturer,
+            model: equipment.model,
+            since: equipment.since.map(|date| date.to_string()),
+            until: equipment.until.map(|date| date.to_string()),
+            source: equipment.source,
+            cost: equipment.cost,
+            comment: equipment.comment,
+            stats: ApiEquipmentStats {
+                flights: equipment.flights,
+                seconds: equipment.seconds,
+            },
+            repack,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiEquipmentStats {
+    flights: i64,
+    seconds: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiRepackStatus {
+    interval_months: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_repack: Option<NaiveDate>,
+    repack_count: i64,
+    flights_since_repack: i64,
+    /// When the next repack is due. Counted from the last repack, or from the
+    /// acquisition date if the reserve was never repacked.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    due_date: Option<NaiveDate>,
+    state: MaintenanceState,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiEquipmentList {
+    /// List of user's equipment.
+    equipment: Vec<ApiEquipment>,
+}
+
+fn conflict_error() -> (Status, Json<RocketError>) {
+    RocketError::new(
+        Status::Conflict,
+        "Conflict",
+        "You can't add the same equipment twice.",
+    )
+}
+
+fn internal_error() -> (Status, Json<RocketError>) {
+    RocketError::new(
+        Status::InternalServerError,
+        "InternalServerError",
+        "Internal server error",
+    )
+}
+
+// API endpoints
+
+#[get("/equipment")]
+pub async fn list(database: data::Database, user: auth::AuthUser) -> Json<ApiEquipmentList> {
+    let user = user.into_inner();
+    let today = Utc::now().date_naive();
+
+    // Get all equipment for user
+    let equipment = database
+        .run(move |db| data::get_equipment_with_stats_for_user(db, &user))
+        .await
+        .into_iter()
+        .map(|equipment| ApiEquipment::from_equipment(equipment, today))
+        .collect();
+
+    Json(ApiEquipmentList { equipment })
+}
+
+#[get("/equipment", rank = 2)]
+pub fn list_nologin() -> ApiError {
+    ApiError::MissingAuthentication
+}
+
+#[post("/equipment", data = "<data>")]
+pub async fn add(
+    user: auth::AuthUser,
+    database: data::Database,
+    data: Json<EquipmentCreateUpdateForm>,
+) -> Result<Status, (Status, Json<RocketError>)> {
+    let user = user.into_inner();
+
+    // Validate data
+    let equipment = data
+        .into_inner()
+        .into_new_equipment(user.id)
+        .map_err(|e| RocketError::new(Status::BadRequest, "InvalidData", e))?;
+
+    // Create database entry
+    match database
+        .run(move |db| data::create_equipment(db, equipment))
+        .await
+    {
+        Ok(_) => {
+            log::info!("Created equipment for user {}", user.id);
+            Ok(Status::Created)
+        }
+        Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _info)) => Err(conflict_error()),
+        Err(other) => {
+            error!("Could not create equipment: {:?}", other);
+            Err(internal_error())
+        }
+    }
+}
+
+#[post("/equipment", rank = 2)]
+pub fn add_nologin() -> ApiError {
+    ApiError::MissingAuthentication
+}
+
+#[post("/equipment/<id>", data = "<data>")]
+pub async fn edit(
+    user: auth::AuthUser,
+    database: data::Database,
+    id: i32,
+    data: Json<EquipmentCreateUpdateForm>,
+) -> Result<Status, (Status, Json<RocketError>)> {
+    let user = user.into_inner();
+    let not_found = || RocketError::new(Status::NotFound, "NotFound", "Equipment not found");
+
+    // Get equipment
+    let mut equipment = database
+        .run(move |db| data::get_equipment_by_id(db, id))
+        .await
+        .ok_or_else(not_found)?;
+
+    // Ownership check
+    if equipment.user_id != user.id {
+        return Err(not_found());
+    }
+
+    // Validate data
+    let new_equipment = data
+        .into_inner()
+        .into_new_equipment(user.id)
+        .map_err(|e| RocketError::new(Status::BadRequest, "InvalidData", e))?;
+
+    // Update model
+    equipment.kind = new_equipment.kind;
+    equipment.manufacturer = new_equipment.manufacturer;
+    equipment.model = new_equipment.model;
+    equipment.since = new_equipment.since;
+    equipment.until = new_equipment.until;
+    equipment.source = new_equipment.source;
+    equipment.cost = new_equipment.cost;
+    equipment.comment = new_equipment.comment;
+    equipment.repack_interval_months = new_equipment.repack_interval_months;
+
+    // Update database
+    match database
+        .run(move |db| data::update_equipment(db, &equipment))
+        .await
+    {
+        Ok(()) => Ok(Status::NoContent),
+        Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _info)) => Err(conflict_error()),
+        Err(other) => {
+            error!("Could not update equipment with ID {}: {:?}", id, other);
+            Err(internal_error())
+        }
+    }
+}
+
+#[post("/equipment/<id>", rank = 2)]
+#[allow(unused_variables)]
+pub fn edit_nologin(id: i32) -> ApiError {
+    ApiError::MissingAuthentication
+}
+
+#[delete("/equipment/<id>")]
+pub async fn delete(
+    user: auth::AuthUser,
+    database: data::Database,
+    id: i32,
+) -> Result<Status, (Status, Json<RocketError>)> {
+    let user = user.into_inner();
+    let not_found = || RocketError::new(Status::NotFound, "NotFound", "Equipment not found");
+
+    // Get data
+    let equipment = database
+        .run(move |db| data::get_equipment_with_stats_by_id(db, id))
+        .await
+        .ok_or_else(not_found)?;
+
+    // Ownership check
+    if equipment.user_id != user.id {
+        return Err(not_found());
+    }
+
+    // Ensure that no related flights exist
+    if equipment.flights > 0 {
+        return Err(RocketError::new(
+            Status::Conflict,
+            "Conflict",
+            "Equipment is still in use by flights",
+        ));
+    }
+
+    // Delete database entry
+    database
+        .run(move |db| data::delete_equipment_by_id(db, id))
+        .await
+        .map(|()| {
+            log::info!("Deleted equipment with ID {}", id);
+            Status::NoContent
+        })
+        .map_err(|e| {
+            log::error!("Could not delete equipment with ID {}: {}", id, e);
+            internal_error()
+        })
+}
+
+#[delete("/equipment/<id>", rank = 2)]
+#[allow(unused_variables)]
+pub fn delete_nologin(id: i32) -> ApiError {
+    ApiError::MissingAuthentication
+}
+
+#[get("/equipment/<id>/repacks")]
+pub async fn list_repacks(
+    user: auth::AuthUser,
+    database: data::Database,
+    id: i32,
+) -> Result<Json<Vec<EquipmentRepack>>, ApiError> {
+    let user = user.into_inner();
+
+    // Get equipment and check ownership
+    let equipment = database
+        .run(move |db| data::get_equipment_by_id(db, id))
+        .await
+        .filter(|equipment| equipment.user_id == user.id)
+        .ok_or(ApiError::NotFound)?;
+
+    Ok(Json(
+        database
+            .run(move |db| data::get_repacks_for_equipment(db, &equipment))
+            .await,
+    ))
+}
+
+#[get("/equipment/<id>/repacks", rank = 2)]
+#[allow(unused_variables)]
+pub fn list_repacks_nologin(id: i32) -> ApiError {
+    ApiError::MissingAuthentication
+}
+
+#[post("/equipment/<id>/repacks", data = "<data>")]
+pub async fn add_repack(
+    user: auth::AuthUser,
+    database: data::Database,
+    id: i32,
+    data: Json<RepackCreateForm>,
+) -> Result<Status, ApiError> {
+    let user = user.into_inner();
+
+    // Get equipment and check ownership
+    let equipment = database
+        .run(move |db| data::get_equipment_by_id(db, id))
+        .await
+        .filter(|equipment| equipment.user_id == user.id)
+        .ok_or(ApiError::NotFound)?;
+    if equipment.kind != "reserve" {
+        return Err(ApiError::InvalidData {
+            message: "Only reserves can be repacked".into(),
+        });
+    }
+
+    // Validate data
+    let RepackCreateForm { date, notes } = data.into_inner();
+    let date = NaiveDate::parse_from_str(&date, "%Y-%m-%d").map_err(|_| ApiError::InvalidData {
+        message: format!("Invalid date: {}", date),
+    })?;
+    let repack = NewEquipmentRepack {
+        equipment_id: id,
+        date,
+        notes: notes.filter(|notes| !notes.trim().is_empty()),
+    };
+
+    // Create database entry
+    match database.run(move |db| data::create_repack(db, repack)).await {
+        Ok(_) => {
+            log::info!("Created repack for equipment {}", id);
+            Ok(Status::Created)
+        }
+        Err(e) => {
+            log::error!("Could not create repack: {}", e);
+            Err(ApiError::IoError {
+                message: "Could not create repack".into(),
+            })
+        }
+    }
+}
+
+#[post("/equipment/<id>/repacks", rank = 2)]
+#[allow(unused_variables)]
+pub fn add_repack_nologin(id: i32) -> ApiError {
+    ApiError::MissingAuthentication
+}
+
+#[delete("/equipment/repacks/<id>")]
+pub async fn delete_repack(
+    user: auth::AuthUser,
+    database: data::Database,
+    id: i32,
+) -> Result<Status, ApiError> {
+    let user = user.into_inner();
+
+    // Get repack and check ownership
+    let repack = database
+        .run(move |db| data::get_repack_by_id(db, id))
+        .await
+        .ok_or(ApiError::NotFound)?;
+    database
+        .run(move |db| data::get_equipment_by_id(db, repack.equipment_id))
+        .await
+        .filter(|equipment| equipment.user_id == user.id)
+        .ok_or(ApiError::NotFound)?;
+
+    // Delete database entry
+    database
+        .run(move |db| data::delete_repack_by_id(db, id))
+        .await
+        .map(|()| {
+            log::info!("Deleted repack with ID {}", id);
+            Status::NoContent
+        })
+        .map_err(|e| {
+            log::error!("Could not delete repack with ID {}: {}", id, e);
+            ApiError::IoError {
+                message: "Could not delete repack".into(),
+            }
+        })
+}
+
+#[delete("/equipment/repacks/<id>", rank = 2)]
+#[allow(unused_variables)]
+pub fn delete_repack_nologin(id: i32) -> ApiError {
+    ApiError::MissingAuthentication
+}
+
+/// Return vec of all API routes.
+pub fn api_routes() -> Vec<Route> {
+    routes![
+        list,
+        list_nologin,
+        add,
+        add_nologin,
+        edit,
+        edit_nologin,
+        delete,
+        delete_nologin,
+        list_repacks,
+        list_repacks_nologin,
+        add_repack,
+        add_repack_nologin,
+        delete_repack,
+        delete_repack_nologin,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use rocket::{self, http::ContentType, local::blocking::Client};
+
+    use crate::{
+        models::{NewEquipment, NewEquipmentRepack, NewFlight},
+        test_utils::{make_test_config, utc_datetime, DbTestContext},
+    };
+
+    use super::*;
+
+    /// Create a new test client. Cookie tracking is disabled.
+    fn make_client() -> Client {
+        let app = rocket::custom(make_test_config())
+            .attach(data::Database::fairing())
+            .mount("/", api_routes());
+        Client::untracked(app).expect("valid rocket instance")
+    }
+
+    #[test]
+    fn add_equipment_and_repack() {
+        let ctx = DbTestContext::new();
+        let client = make_client();
+
+        macro_rules! post {
+            ($url:expr, $body:expr) => {
+                client
+                    .post($url)
+                    .header(ContentType::JSON)
+                    .body($body)
+                    .private_cookie(ctx.auth_cookie_user1())
+                    .cookie(ctx.username_cookie())
+                    .dispatch()
+            };
+        }
+        macro_rules! get_equipment {
+            () => {
+                client
+                    .get("/equipment")
+                    .private_cookie(ctx.auth_cookie_user1())
+                    .cookie(ctx.username_cookie())
+                    .dispatch()
+                    .into_json::<ApiEquipmentList>()
+                    .unwrap()
+                    .equipment
+            };
+        }
+
+        // Invalid kind
+        let resp = post!(
+            "/equipment",
+            r#"{"kind": "wing", "manufacturer": "A", "model": "1"}"#
+        );
+        assert_eq!(resp.status(), Status::BadRequest);
+
+        // Repack interval is only valid for reserves
+        let resp = post!(
+            "/equipment",
+            r#"{"kind": "harness", "manufacturer": "A", "model": "1", "repackIntervalMonths": 6}"#
+        );
+        assert_eq!(resp.status(), Status::BadRequest);
+
+        // Add harness and reserve
+        let resp = post!(
+            "/equipment",
+            r#"{"kind": "harness", "manufacturer": "A", "model": "1"}"#
+        );
+        assert_eq!(resp.status(), Status::Created);
+        let resp = post!(
+            "/equipment",
+            r#"{"kind": "reserve", "manufacturer": "B", "model": "2", "since": "2020-01-15", "repackIntervalMonths": 12}"#
+        );
+        assert_eq!(resp.status(), Status::Created);
+
+        // Cannot add the same equipment twice
+        let resp = post!(
+            "/equipment",
+            r#"{"kind": "harness", "manufacturer": "A", "model": "1"}"#
+        );
+        assert_eq!(resp.status(), Status::Conflict);
+
+        // Only reserves have a repack status
+        let equipment = get_equipment!();
+        assert_eq!(equipment.len(), 2);
+        let (reserve, harness) = (&equipment[0], &equipment[1]);
+        assert_eq!(harness.kind, "harness");
+        assert!(harness.repack.is_none());
+        let repack = reserve.repack.as_ref().unwrap();
+        assert_eq!(repack.repack_count, 0);
+        assert_eq!(repack.due_date, NaiveDate::from_ymd_opt(2021, 1, 15));
+        assert_eq!(repack.state, MaintenanceState::Overdue);
+
+        // Fly with both harness and reserve
+        let launch_time = Utc::now() - Duration::from_secs(7 * 86400);
+        let flight = data::create_flight(
+            &mut ctx.force_get_conn(),
+            &NewFlight {
+                user_id: ctx.testuser1.user.id,
+                launch_time: Some(launch_time),
+                landing_time: Some(launch_time + Duration::from_secs(1800)),
+                ..Default::default()
+            },
+            None,
+        );
+        data::set_flight_equipment(&mut ctx.force_get_conn(), flight.id, &[reserve.id, harness.id]).unwrap();
+
+        // Repack the reserve
+        let today = Utc::now().date_naive();
+        let resp = post!(
+            format!("/equipment/{}/repacks", reserve.id),
+            format!(r#"{{"date": "{}"}}"#, today)
+        );
+        assert_eq!(resp.status(), Status::Created);
+        let resp = post!(
+            format!("/equipment/{}/repacks", harness.id),
+            format!(r#"{{"date": "{}"}}"#, today)
+        );
+        assert_eq!(resp.status(), Status::BadRequest);
+
+        // Verify stats
+        let equipment = get_equipment!();
+        let (reserve, harness) = (&equipment[0], &equipment[1]);
+        assert_eq!(harness.stats.flights, 1);
+        assert_eq!(harness.stats.seconds, 1800);
+        assert_eq!(reserve.stats.flights, 1);
+        let repack = reserve.repack.as_ref().unwrap();
+        assert_eq!(repack.repack_count, 1);
+        assert_eq!(repack.last_repack, Some(today));
+        assert_eq!(repack.flights_since_repack, 0);
+        assert_eq!(repack.state, MaintenanceState::Ok);
+
+        // Equipment in use cannot be deleted
+        let resp = client
+            .delete(format!("/equipment/{}", harness.id))
+            .private_cookie(ctx.auth_cookie_user1())
+            .cookie(ctx.username_cookie())
+            .dispatch();
+        assert_eq!(resp.status(), Status::Conflict);
+
+        // Other users don't see the equipment
+        let resp = client
+            .get("/equipment")
+            .private_cookie(ctx.auth_cookie_user2())
+            .cookie(ctx.username_cookie())
+            .dispatch();
+        assert_eq!(resp.into_json::<ApiEquipmentList>().unwrap().equipment.len(), 0);
+    }
+
+    #[test]
+    fn flights_since_repack() {
+        let ctx = DbTestContext::new();
+        let conn = &mut *ctx.force_get_conn();
+        let reserve = data::create_equipment(
+            conn,
+            NewEquipment {
+                user_id: ctx.testuser1.user.id,
+                kind: "reserve".into(),
+                manufacturer: "B".into(),
+                model: "2".into(),
+                since: None,
+                until: None,
+                source: None,
+                cost: None,
+                comment: None,
+                repack_interval_months: Some(12),
+            },
+        )
+        .unwrap();
+        data::create_repack(
+            conn,
+            NewEquipmentRepack {
+                equipment_id: reserve.id,
+                date: NaiveDate::from_ymd_opt(2024, 5, 10).unwrap(),
+                notes: None,
+            },
+        )
+        .unwrap();
+
+        // Flights on the repack day may have happened before the repack
+        for launch_time in [
+            utc_datetime(2024, 5, 9, 12, 0, 0),
+            utc_datetime(2024, 5, 10, 12, 0, 0),
+            utc_datetime(2024, 5, 11, 8, 0, 0),
+        ] {
+            let flight = data::create_flight(
+                conn,
+                &NewFlight {
+                    user_id: ctx.testuser1.user.id,
+                    launch_time: Some(launch_time),
+                    ..Default::default()
+                },
+                None,
+            );
+            data::set_flight_equipment(conn, flight.id, &[reserve.id]).unwrap();
+        }
+
+        let stats = data::get_equipment_with_stats_by_id(conn, reserve.id).unwrap();
+        assert_eq!(stats.flights, 3);
+        assert_eq!(stats.flights_since_repack, 1);
+    }
+}
@@ -15,16 +15,28 @@ use rocket::{
     response::{self, Responder, Response},
     routes,
     serde::json::Json,
-    Route,
+    FromForm, Route, State,
 };
 use serde::{Deserialize, Serialize};
 
 use crate::{
+    airspace::{Airspaces, Infringement},
     auth, data,
-    models::{Location, NewFlight, User},
+    geodata::Geodata,
+    models::{Flight, Location, NewFlight, User},
+    process_igc,
     responders::ApiError,
 };
 
+/// Maximal plausible wind speed at launch in km/h
+pub const MAX_WIND_SPEED: i16 = 200;
+/// Maximal plausible thermal strength in m/s
+pub const MAX_THERMAL_STRENGTH: f32 = 20.0;
+/// Maximal plausible cloud base in meters MSL
+pub const MAX_CLOUD_BASE: i32 = 10_000;
+/// Default tolerance of the wind direction filter in degrees
+const DEFAULT_WIND_DIRECTION_TOLERANCE: i16 = 45;
+
 // API types
 
 #[derive(Serialize)]
@@ -93,6 +105,24 @@ struct ApiFlightListItem {
     video_url: Option<String>,
     /// Whether you hiked up to launch
     hikeandfly: bool,
+    /// Wind direction at launch in degrees
+    #[serde(skip_serializing_if = "Option::is_none")]
+    wind_direction: Option<i16>,
+    /// Wind speed at launch in km/h
+    #[serde(skip_serializing_if = "Option::is_none")]
+    wind_speed: Option<i16>,
+    /// Thermal strength in m/s
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thermal_strength: Option<f32>,
+    /// Cloud base in meters MSL
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cloud_base: Option<i32>,
+    /// Turbulence rating (1-5)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    turbulence: Option<i16>,
+    /// Weather notes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    weather_notes: Option<String>,
     /// Whether an IGC file is present for this flight
     has_igc: bool,
 }
@@ -118,6 +148,9 @@ pub struct ApiFlight {
     /// The ID of the glider used
     #[serde(skip_serializing_if = "Option::is_none")]
     glider_id: Option<i32>,
+    /// The IDs of other equipment used (e.g. harness or reserve)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    equipment_ids: Vec<i32>,
     /// Launch location
     #[serde(skip_serializing_if = "Option::is_none")]
     launch_at: Option<ApiFlightLocation>,
@@ -153,8 +186,30 @@ pub struct ApiFlight {
     video_url: Option<String>,
     /// Whether you hiked up to launch
     hikeandfly: bool,
+    /// Wind direction at launch in degrees
+    #[serde(skip_serializing_if = "Option::is_none")]
+    wind_direction: Option<i16>,
+    /// Wind speed at launch in km/h
+    #[serde(skip_serializing_if = "Option::is_none")]
+    wind_speed: Option<i16>,
+    /// Thermal strength in m/s
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thermal_strength: Option<f32>,
+    /// Cloud base in meters MSL
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cloud_base: Option<i32>,
+    /// Turbulence rating (1-5)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    turbulence: Option<i16>,
+    /// Weather notes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    weather_notes: Option<String>,
     /// Whether an IGC file is present for this flight
     has_igc: bool,
+    /// Airspace infringements of the IGC track (only checked if an IGC file
+    /// is present and airspaces are configured)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    airspace_infringements: Option<Vec<Infringement>>,
 }
 
 // Forms
@@ -166,6 +221,9 @@ pub struct FlightAddUpdateForm {
     number: Option<i32>,
     /// Glider ID
     glider: Option<i32>,
+    /// Equipment IDs. When editing a flight, the equipment is left unchanged
+    /// if this is not specified.
+    equipment: Option<Vec<i32>>,
     /// Launch site ID
     launch_site: Option<i32>,
     /// Landing site ID
@@ -190,23 +248,112 @@ pub struct FlightAddUpdateForm {
     comment: Option<String>,
     /// Flight video URL
     video_url: Option<String>,
+    /// Wind direction at launch in degrees (0-359)
+    wind_direction: Option<i16>,
+    /// Wind speed at launch in km/h
+    wind_speed: Option<i16>,
+    /// Thermal strength in m/s
+    thermal_strength: Option<f32>,
+    /// Cloud base in meters MSL
+    cloud_base: Option<i32>,
+    /// Turbulence rating from 1 (calm) to 5 (severe)
+    turbulence: Option<i16>,
+    /// Weather notes
+    weather_notes: Option<String>,
     /// IGC file bytes as URL-safe base64 string
     igc_data: Option<String>,
 }
 
+/// Optional filters for the flight list. Flights without a value for a
+/// filtered condition are excluded.
+#[derive(FromForm, Debug, Default)]
+pub struct FlightListFilter {
+    /// Minimal wind speed in km/h
+    #[field(name = "minWindSpeed")]
+    min_wind_speed: Option<i16>,
+    /// Maximal wind speed in km/h
+    #[field(name = "maxWindSpeed")]
+    max_wind_speed: Option<i16>,
+    /// Wind direction in degrees (0-359)
+    #[field(name = "windDirection")]
+    wind_direction: Option<i16>,
+    /// Maximal deviation from `windDirection` in degrees
+    #[field(name = "windDirectionTolerance")]
+    wind_direction_tolerance: Option<i16>,
+    /// Minimal thermal strength in m/s
+    #[field(name = "minThermalStrength")]
+    min_thermal_strength: Option<f32>,
+    /// Maximal thermal strength in m/s
+    #[field(name = "maxThermalStrength")]
+    max_thermal_strength: Option<f32>,
+    /// Minimal cloud base in meters MSL
+    #[field(name = "minCloudBase")]
+    min_cloud_base: Option<i32>,
+    /// Maximal cloud base in meters MSL
+    #[field(name = "maxCloudBase")]
+    max_cloud_base: Option<i32>,
+    /// Maximal turbulence rating
+    #[field(name = "maxTurbulence")]
+    max_turbulence: Option<i16>,
+}
+
+impl FlightListFilter {
+    /// Return whether the flight conditions match this filter.
+    fn matches(&self, flight: &Flight) -> bool {
+        fn check<T>(value: Option<T>, limit: Option<T>, ok: impl Fn(&T, &T) -> bool) -> bool {
+            match (value, limit) {
+                (_, None) => true,
+                (Some(value), Some(limit)) => ok(&value, &limit),
+                (None, Some(_)) => false,
+            }
+        }
+        check(flight.wind_speed, self.min_wind_speed, PartialOrd::ge)
+            && check(flight.wind_speed, self.max_wind_speed, PartialOrd::le)
+            && check(
+                flight.wind_direction,
+                self.wind_direction,
+                |&direction, &filter| {
+                    let tolerance = self
+                        .wind_direction_tolerance
+                        .unwrap_or(DEFAULT_WIND_DIRECTION_TOLERANCE);
+                    angle_difference(direction, filter) <= i32::from(tolerance)
+                },
+            )
+            && check(flight.thermal_strength, self.min_thermal_strength, PartialOrd::ge)
+            && check(flight.thermal_strength, self.max_thermal_strength, PartialOrd::le)
+            && check(flight.cloud_base, self.min_cloud_base, PartialOrd::ge)
+            && check(flight.cloud_base, self.max_cloud_base, PartialOrd::le)
+            && check(flight.turbulence, self.max_turbulence, PartialOrd::le)
+    }
+}
+
+/// Return the difference between two directions in degrees (0-180), taking
+/// into account the wrap around at north.
+fn angle_difference(a: i16, b: i16) -> i32 {
+    let difference = (i32::from(a) - i32::from(b)).rem_euclid(360);
+    difference.min(360 - difference)
+}
+
 // API endpoints
 
-#[get("/flights")]
-pub async fn list(database: data::Database, user: auth::AuthUser) -> Json<ApiFlights> {
+#[get("/flights?<filter..>")]
+pub async fn list(
+    database: data::Database,
+    user: auth::AuthUser,
+    filter: FlightListFilter,
+) -> Json<ApiFlights> {
     let user = Arc::new(user.into_inner());
 
-    // Get all flights for user
+    // Get all flights for user matching the filter
     let flights = database
         .run({
             let user = user.clone();
             move |db| data::get_flights_for_user(db, &user)
         })
-        .await;
+        .await
+        .into_iter()
+        .filter(|flight| filter.matches(flight))
+        .collect::<Vec<_>>();
 
     // Get all gliders for user
     let glider_name_map = database
@@ -281,6 +428,12 @@ pub async fn list(database: data::Database, user: auth::AuthUser) -> Json<ApiFli
                 comment: flight.comment,
                 video_url: flight.video_url,
                 hikeandfly: flight.hikeandfly,
+                wind_direction: flight.wind_direction,
+                wind_speed: flight.wind_speed,
+                thermal_strength: flight.thermal_strength,
+                cloud_base: flight.cloud_base,
+                turbulence: flight.turbulence,
+                weather_notes: flight.weather_notes,
                 has_igc,
             }
         })
@@ -299,7 +452,13 @@ pub fn list_nologin() -> ApiError {
 }
 
 #[get("/flights/<id>")]
-pub async fn get(id: i32, database: data::Database, user: auth::AuthUser) -> Result<Json<ApiFlight>, Status> {
+pub async fn get(
+    id: i32,
+    database: data::Database,
+    user: auth::AuthUser,
+    geodata: &State<Geodata>,
+    airspaces: &State<Airspaces>,
+) -> Result<Json<ApiFlight>, Status> {
     let user = user.into_inner();
 
     // Get flight
@@ -321,6 +480,14 @@ pub async fn get(id: i32, database: data::Database, user: auth::AuthUser) -> Res
         })
         .await;
 
+    // Get equipment used
+    let equipment_ids = database
+        .run({
+            let flight = flight.clone();
+            move |db| data::get_equipment_ids_for_flight(db, &flight)
+        })
+        .await;
+
     // Resolve foreign keys
     let launch_at = database
         .run({
@@ -344,6 +511,34 @@ pub async fn get(id: i32, database: data::Database, user: auth::AuthUser) -> Res
         .await
         .map(|glider| glider.to_string());
 
+    // Check the track against the airspaces (in a blocking task, since this
+    // is CPU bound and may read elevation tiles)
+    let igc = if has_igc && !airspaces.is_empty() {
+        database
+            .run({
+                let flight = flight.clone();
+                move |db| data::get_igc_for_flight(db, &flight)
+            })
+            .await
+    } else {
+        None
+    };
+    let airspace_infringements = match igc {
+        Some(igc) => {
+            let (airspaces, geodata) = (airspaces.inner().clone(), geodata.inner().clone());
+            let infringements = rocket::tokio::task::spawn_blocking(move || {
+                airspaces.check_track(&process_igc::parse_track(&igc.data), &geodata)
+            })
+            .await
+            .map_err(|e| {
+                log::error!("Airspace check failed: {}", e);
+                Status::InternalServerError
+            })?;
+            Some(infringements)
+        }
+        None => None,
+    };
+
     // Calculate duration
     let duration_seconds = match (flight.launch_time, flight.landing_time) {
         (Some(launch), Some(landing)) => {
@@ -364,6 +559,7 @@ pub async fn get(id: i32, database: data::Database, user: auth::AuthUser) -> Res
         number: flight.number,
         glider_name,
         glider_id: flight.glider_id,
+        equipment_ids,
         launch_at,
         landing_at,
         launch_time: flight.launch_time,
@@ -376,7 +572,14 @@ pub async fn get(id: i32, database: data::Database, user: auth::AuthUser) -> Res
         comment: flight.comment,
         video_url: flight.video_url,
         hikeandfly: flight.hikeandfly,
+        wind_direction: flight.wind_direction,
+        wind_speed: flight.wind_speed,
+        thermal_strength: flight.thermal_strength,
+        cloud_base: flight.cloud_base,
+        turbulence: flight.turbulence,
+        weather_notes: flight.weather_notes,
         has_igc,
+        airspace_infringements,
     }))
 }
 
@@ -407,6 +610,22 @@ impl FlightAddUpdateForm {
             }
         }
 
+        // Look up and validate equipment
+        if let Some(ref equipment_ids) = self.equipment {
+            let user_equipment_ids = database
+                .run({
+                    let user = user.clone();
+                    move |db| data::get_equipment_for_user(db, &user)
+                })
+                .await
+                .into_iter()
+                .map(|equipment| equipment.id)
+                .collect::<Vec<_>>();
+            if equipment_ids.iter().any(|id| !user_equipment_ids.contains(id)) {
+                return Err("Invalid equipment".into());
+            }
+        }
+
         // Look up and validate locations
         let user_location_ids = database
             .run({
@@ -448,6 +667,33 @@ impl FlightAddUpdateForm {
             }
         }
 
+        // Validate flight conditions
+        if let Some(direction) = self.wind_direction {
+            if !(0..360).contains(&direction) {
+                return Err(format!("Invalid wind direction: {}", direction));
+            }
+        }
+        if let Some(speed) = self.wind_speed {
+            if !(0..=MAX_WIND_SPEED).contains(&speed) {
+                return Err(format!("Invalid wind speed: {}", speed));
+            }
+        }
+        if let Some(strength) = self.thermal_strength {
+            if !strength.is_finite() || !(0.0..=MAX_THERMAL_STRENGTH).contains(&strength) {
+                return Err(format!("Invalid thermal strength: {}", strength));
+            }
+        }
+        if let Some(cloud_base) = self.cloud_base {
+            if !(0..=MAX_CLOUD_BASE).contains(&cloud_base) {
+                return Err(format!("Invalid cloud base: {}", cloud_base));
+            }
+        }
+        if let Some(turbulence) = self.turbulence {
+            if !(1..=5).contains(&turbulence) {
+                return Err(format!("Invalid turbulence rating: {}", turbulence));
+            }
+        }
+
         // Validate and combine date and time
         let date_parts = self.launch_date.map(|_| 1).unwrap_or_default()
             + self.launch_time.map(|_| 1).unwrap_or_default()
@@ -482,6 +728,12 @@ impl FlightAddUpdateForm {
             xcontest_url: self.xcontest_url,
             comment: self.comment,
             video_url: self.video_url,
+            wind_direction: self.wind_direction,
+            wind_speed: self.wind_speed,
+            thermal_strength: self.thermal_strength,
+            cloud_base: self.cloud_base,
+            turbulence: self.turbulence,
+            weather_notes: self.weather_notes,
         })
     }
 }
@@ -511,6 +763,7 @@ pub async fn add(
     };
 
     // Convert request data into `NewFlight`
+    let equipment_ids = data.equipment.clone();
     let new_flight = data
         .into_inner()
         .into_new_flight(&user, &database)
@@ -522,13 +775,21 @@ pub async fn add(
     // Insert flight into database
     database
         .run(move |db| {
-            data::create_flight(db, &new_flight, igc_bytes);
-            log::info!("Created flight for user {}", user.id);
+            let flight =
+                data::create_flight_with_equipment(db, &new_flight, igc_bytes, equipment_ids.as_deref())?;
+            log::info!("Created flight {} for user {}", flight.id, user.id);
             if let Some(glider_id) = new_flight.glider_id {
                 data::update_user_last_glider(db, &user, glider_id);
             }
+            Ok(())
         })
-        .await;
+        .await
+        .map_err(|e: diesel::result::Error| {
+            log::error!("Could not create flight: {}", e);
+            ApiError::IoError {
+                message: "Could not create flight".into(),
+            }
+        })?;
 
     Ok(Status::Created)
 }
@@ -576,6 +837,7 @@ pub async fn edit(
     // TODO: Test error handling for too-large IGC file
 
     // Convert request data into `NewFlight`
+    let equipment_ids = data.equipment.clone();
     let new_flight = data
         .into_inner()
         .into_new_flight(&user, &database)
@@ -598,21 +860,24 @@ pub async fn edit(
     flight.xcontest_url = new_flight.xcontest_url;
     flight.comment = new_flight.comment;
     flight.video_url = new_flight.video_url;
+    flight.wind_direction = new_flight.wind_direction;
+    flight.wind_speed = new_flight.wind_speed;
+    flight.thermal_strength = new_flight.thermal_strength;
+    flight.cloud_base = new_flight.cloud_base;
+    flight.turbulence = new_flight.turbulence;
+    flight.weather_notes = new_flight.weather_notes;
 
     // Save changes
-    // TODO: Error handling
+    // Note: Only add IGC data if flight doesn't have IGC data yet. Never modify IGC.
     database
-        .run(move |db| {
-            data::update_flight(db, &flight);
-            // Note: Only add IGC data if flight doesn't have IGC data yet. Never modify IGC.
-            let has_igc = data::flight_has_igc(db, &flight);
-            if !has_igc {
-                if let Some(data) = igc_bytes {
-                    data::update_igc(db, &flight, &data);
-                }
+        .run(move |db| data::update_flight_with_equipment(db, &flight, equipment_ids.as_deref(), igc_bytes))
+        .await
+        .map_err(|e| {
+            log::error!("Could not update flight {}: {}", id, e);
+            ApiError::IoError {
+                message: "Could not update flight".into(),
             }
-        })
-        .await;
+        })?;
 
     Ok(Status::NoContent)
 }
@@ -755,10 +1020,17 @@ pub fn api_routes() -> Vec<Route> {
 
 #[cfg(test)]
 mod tests {
-    use rocket::{self, local::blocking::Client};
+    use rocket::{
+        self,
+        http::ContentType,
+        local::blocking::Client,
+        serde::json::{json, Value},
+    };
 
     use crate::{
-        models::NewFlight,
+        airspace::tests::test_airspaces,
+        geodata::tests::test_geodata,
+        models::{NewEquipment, NewFlight},
         test_utils::{make_test_config, DbTestContext},
     };
 
@@ -768,6 +1040,8 @@ mod tests {
     fn make_client() -> Client {
         let app = rocket::custom(make_test_config())
             .attach(data::Database::fairing())
+            .manage(test_geodata())
+            .manage(test_airspaces())
             .mount("/", api_routes());
         Client::untracked(app).expect("valid rocket instance")
     }
@@ -823,4 +1097,231 @@ mod tests {
         let resp = delete_flight!(flight2.id, ctx.auth_cookie_user2());
         assert_eq!(resp.status(), Status::NotFound);
     }
+
+    #[test]
+    fn add_flight_with_equipment() {
+        let ctx = DbTestContext::new();
+        let client = make_client();
+
+        macro_rules! add_flight {
+            ($body:expr) => {
+                client
+                    .post("/flights")
+                    .header(ContentType::JSON)
+                    .body($body)
+                    .private_cookie(ctx.auth_cookie_user1())
+                    .cookie(ctx.username_cookie())
+                    .dispatch()
+            };
+        }
+
+        // Add equipment for both users
+        let mut equipment_ids = vec![];
+        for (user_id, kind) in [
+            (ctx.testuser1.user.id, "harness"),
+            (ctx.testuser1.user.id, "reserve"),
+            (ctx.testuser2.user.id, "harness"),
+        ] {
+            let equipment = data::create_equipment(
+                &mut ctx.force_get_conn(),
+                NewEquipment {
+                    user_id,
+                    kind: kind.into(),
+                    manufacturer: "A".into(),
+                    model: "1".into(),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            equipment_ids.push(equipment.id);
+        }
+
+        // Equipment of other users is rejected
+        let resp = add_flight!(format!(r#"{{"equipment": [{}]}}"#, equipment_ids[2]));
+        assert_eq!(resp.status(), Status::BadRequest);
+
+        // Own equipment is stored
+        let resp = add_flight!(format!(
+            r#"{{"equipment": [{}, {}]}}"#,
+            equipment_ids[0], equipment_ids[1]
+        ));
+        assert_eq!(resp.status(), Status::Created);
+        let flights = data::get_flights_for_user(&mut ctx.force_get_conn(), &ctx.testuser1.user);
+        assert_eq!(flights.len(), 1);
+        assert_eq!(
+            data::get_equipment_ids_for_flight(&mut ctx.force_get_conn(), &flights[0]),
+            equipment_ids[0..2].to_vec()
+        );
+
+        // Foreign equipment is rejected when editing, the flight is unchanged
+        let resp = client
+            .post(format!("/flights/{}", flights[0].id))
+            .header(ContentType::JSON)
+            .body(format!(r#"{{"number": 5, "equipment": [{}]}}"#, equipment_ids[2]))
+            .private_cookie(ctx.auth_cookie_user1())
+            .cookie(ctx.username_cookie())
+            .dispatch();
+        assert_eq!(resp.status(), Status::BadRequest);
+        let flight = data::get_flight_with_id(&mut ctx.force_get_conn(), flights[0].id).unwrap();
+        assert_eq!(flight.number, None);
+
+        // If storing the equipment fails, no flight is created
+        let result = data::create_flight_with_equipment(
+            &mut ctx.force_get_conn(),
+            &NewFlight {
+                user_id: ctx.testuser1.user.id,
+                ..Default::default()
+            },
+            None,
+            Some(&[i32::MAX]),
+        );
+        assert!(result.is_err());
+        let flights = data::get_flights_for_user(&mut ctx.force_get_conn(), &ctx.testuser1.user);
+        assert_eq!(flights.len(), 1);
+    }
+
+    #[test]
+    fn get_flight_with_airspace_infringements() {
+        let ctx = DbTestContext::new();
+        let client = make_client();
+
+        let create_flight = |igc: Option<&[u8]>| {
+            data::create_flight(
+                &mut ctx.force_get_conn(),
+                &NewFlight {
+                    user_id: ctx.testuser1.user.id,
+                    ..Default::default()
+                },
+                igc.map(<[u8]>::to_vec),
+            )
+        };
+        let with_igc = create_flight(Some(include_bytes!("../testdata/skytraxx.igc")));
+        let without_igc = create_flight(None);
+        let get_flight = |id: i32| -> Value {
+            let resp = client
+                .get(format!("/flights/{}", id))
+                .private_cookie(ctx.auth_cookie_user1())
+                .cookie(ctx.username_cookie())
+                .dispatch();
+            assert_eq!(resp.status(), Status::Ok);
+            resp.into_json().unwrap()
+        };
+
+        // The track is checked against the airspaces
+        let flight = get_flight(with_igc.id);
+        let infringements = flight["airspaceInfringements"].as_array().unwrap();
+        assert_eq!(infringements.len(), 3);
+        assert_eq!(infringements[0]["airspaceName"], "Vrin Field");
+        assert_eq!(infringements[0]["airspaceClass"], "D");
+        assert_eq!(infringements[0]["kind"], "horizontal");
+        assert_eq!(infringements[0]["floor"], "GND");
+        assert_eq!(infringements[0]["ceiling"], "2000 m MSL");
+        assert_eq!(infringements[0]["startTimeHms"], json!([13, 43, 52]));
+        assert_eq!(infringements[0]["endTimeHms"], json!([13, 46, 7]));
+        assert_eq!(infringements[1]["kind"], "vertical");
+        assert_eq!(infringements[1]["undetermined"], false);
+
+        // Without IGC file, nothing is checked
+        let flight = get_flight(without_igc.id);
+        assert_eq!(flight["hasIgc"], false);
+        assert!(flight.get("airspaceInfringements").is_none());
+    }
+
+    #[test]
+    fn add_and_filter_flights_with_conditions() {
+        let ctx = DbTestContext::new();
+        let client = make_client();
+
+        macro_rules! add_flight {
+            ($body:expr) => {
+                client
+                    .post("/flights")
+                    .header(ContentType::JSON)
+                    .body($body)
+                    .private_cookie(ctx.auth_cookie_user1())
+                    .cookie(ctx.username_cookie())
+                    .dispatch()
+            };
+        }
+        let list_flights = |query: &str| -> Vec<i64> {
+            let resp = client
+                .get(format!("/flights{}", query))
+                .private_cookie(ctx.auth_cookie_user1())
+                .cookie(ctx.username_cookie())
+                .dispatch();
+            assert_eq!(resp.status(), Status::Ok);
+            let flights: Value = resp.into_json().unwrap();
+            flights["flights"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|flight| flight["number"].as_i64().unwrap())
+                .collect()
+        };
+
+        // Invalid conditions are rejected
+        for body in [
+            r#"{"windDirection": 360}"#,
+            r#"{"windSpeed": -5}"#,
+            r#"{"thermalStrength": 25.0}"#,
+            r#"{"cloudBase": 20000}"#,
+            r#"{"turbulence": 0}"#,
+        ] {
+            let resp = add_flight!(body);
+            assert_eq!(resp.status(), Status::BadRequest, "{}", body);
+        }
+
+        // Valid conditions are stored
+        let resp = add_flight!(
+            r#"{"number": 1, "windDirection": 270, "windSpeed": 25, "thermalStrength": 3.5,
+                "cloudBase": 2800, "turbulence": 4, "weatherNotes": "Gusty in the afternoon"}"#
+        );
+        assert_eq!(resp.status(), Status::Created);
+        let resp = add_flight!(r#"{"number": 2, "windSpeed": 8, "turbulence": 1}"#);
+        assert_eq!(resp.status(), Status::Created);
+        let resp = add_flight!(r#"{"number": 3}"#);
+        assert_eq!(resp.status(), Status::Created);
+        let resp =
+            add_flight!(r#"{"number": 4, "windDirection": 10, "thermalStrength": 1.5, "cloudBase": 1900}"#);
+        assert_eq!(resp.status(), Status::Created);
+
+        let flights = data::get_flights_for_user(&mut ctx.force_get_conn(), &ctx.testuser1.user);
+        let flight = flights.iter().find(|flight| flight.number == Some(1)).unwrap();
+        assert_eq!(flight.wind_direction, Some(270));
+        assert_eq!(flight.thermal_strength, Some(3.5));
+        assert_eq!(flight.weather_notes.as_deref(), Some("Gusty in the afternoon"));
+
+        let resp = client
+            .get(format!("/flights/{}", flight.id))
+            .private_cookie(ctx.auth_cookie_user1())
+            .cookie(ctx.username_cookie())
+            .dispatch();
+        let flight: Value = resp.into_json().unwrap();
+        assert_eq!(flight["windSpeed"], 25);
+        assert_eq!(flight["cloudBase"], 2800);
+        assert_eq!(flight["turbulence"], 4);
+
+        // Filter the flight list
+        assert_eq!(list_flights(""), vec![4, 3, 2, 1]);
+        assert_eq!(list_flights("?minWindSpeed=10"), vec![1]);
+        assert_eq!(list_flights("?maxWindSpeed=10"), vec![2]);
+        assert_eq!(list_flights("?maxTurbulence=2"), vec![2]);
+        assert_eq!(list_flights("?minThermalStrength=3&minCloudBase=2500"), vec![1]);
+        assert_eq!(list_flights("?minCloudBase=3000"), Vec::<i64>::new());
+        assert_eq!(list_flights("?maxThermalStrength=2"), vec![4]);
+        assert_eq!(list_flights("?maxCloudBase=2000"), vec![4]);
+        assert_eq!(list_flights("?windDirection=240"), vec![1]);
+        assert_eq!(
+            list_flights("?windDirection=240&windDirectionTolerance=20"),
+            Vec::<i64>::new()
+        );
+        assert_eq!(
+            list_flights("?windDirection=350&windDirectionTolerance=20"),
+            vec![4]
+        );
+        assert_eq!(
+            list_flights("?windDirection=0&windDirectionTolerance=180"),
+            vec![4, 1]
+        );
+    }
 }
@@ -0,0 +1,502 @@
+//! Offline geographic data.
+//!
+//! The datasets are configured in the `geodata` section of the Rocket config
+//! and loaded on startup. All of them are optional:
+//!
+//! - `places`: Place names in the GeoNames format (tab separated, e.g.
+//!   `cities1000.txt` from <https://download.geonames.org/export/dump/>), used
+//!   to name new locations.
+//! - `countries`: Country boundaries as GeoJSON feature collection with ISO
+//!   3166-1 alpha-2 codes (e.g. the Natural Earth admin 0 countries), used to
+//!   determine the country of new locations.
+//! - `dem_dir`: Directory with SRTM elevation tiles (`.hgt` files named like
+//!   `N46E009.hgt`), used to determine the elevation of new locations.
+//!
+//! If `places` or `countries` are not configured, the default datasets in
+//! [`DEFAULT_DIR`] are used if present. They are downloaded by
+//! `scripts/fetch-geodata.sh` and bundled with the Docker image.
+
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{BufRead, BufReader, ErrorKind},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{bail, Context, Result};
+use rocket::serde::json::{serde_json, Value};
+use serde::Deserialize;
+
+use crate::locations::distance_meters;
+
+/// Feature properties that may contain the ISO 3166-1 alpha-2 country code,
+/// in order of preference.
+const COUNTRY_CODE_PROPERTIES: [&str; 4] = ["ISO_A2_EH", "ISO_A2", "iso_a2", "ISO3166-1-Alpha-2"];
+
+/// Country code for locations with unknown country.
+pub const UNKNOWN_COUNTRY: &str = "XX";
+
+/// Officially assigned ISO 3166-1 alpha-2 country codes, plus the commonly
+/// used user-assigned code for Kosovo.
+const COUNTRY_CODES: &[&str] = &[
+    "AD", "AE", "AF", "AG", "AI", "AL", "AM", "AO", "AQ", "AR", "AS", "AT", "AU", "AW", "AX", "AZ", "BA",
+    "BB", "BD", "BE", "BF", "BG", "BH", "BI", "BJ", "BL", "BM", "BN", "BO", "BQ", "BR", "BS", "BT", "BV",
+    "BW", "BY", "BZ", "CA", "CC", "CD", "CF", "CG", "CH", "CI", "CK", "CL", "CM", "CN", "CO", "CR", "CU",
+    "CV", "CW", "CX", "CY", "CZ", "DE", "DJ", "DK", "DM", "DO", "DZ", "EC", "EE", "EG", "EH", "ER", "ES",
+    "ET", "FI", "FJ", "FK", "FM", "FO", "FR", "GA", "GB", "GD", "GE", "GF", "GG", "GH", "GI", "GL", "GM",
+    "GN", "GP", "GQ", "GR", "GS", "GT", "GU", "GW", "GY", "HK", "HM", "HN", "HR", "HT", "HU", "ID", "IE",
+    "IL", "IM", "IN", "IO", "IQ", "IR", "IS", "IT", "JE", "JM", "JO", "JP", "KE", "KG", "KH", "KI", "KM",
+    "KN", "KP", "KR", "KW", "KY", "KZ", "LA", "LB", "LC", "LI", "LK", "LR", "LS", "LT", "LU", "LV", "LY",
+    "MA", "MC", "MD", "ME", "MF", "MG", "MH", "MK", "ML", "MM", "MN", "MO", "MP", "MQ", "MR", "MS", "MT",
+    "MU", "MV", "MW", "MX", "MY", "MZ", "NA", "NC", "NE", "NF", "NG", "NI", "NL", "NO", "NP", "NR", "NU",
+    "NZ", "OM", "PA", "PE", "PF", "PG", "PH", "PK", "PL", "PM", "PN", "PR", "PS", "PT", "PW", "PY", "QA",
+    "RE", "RO", "RS", "RU", "RW", "SA", "SB", "SC", "SD", "SE", "SG", "SH", "SI", "SJ", "SK", "SL", "SM",
+    "SN", "SO", "SR", "SS", "ST", "SV", "SX", "SY", "SZ", "TC", "TD", "TF", "TG", "TH", "TJ", "TK", "TL",
+    "TM", "TN", "TO", "TR", "TT", "TV", "TW", "TZ", "UA", "UG", "UM", "US", "UY", "UZ", "VA", "VC", "VE",
+    "VG", "VI", "VN", "VU", "WF", "WS", "XK", "YE", "YT", "ZA", "ZM", "ZW",
+];
+
+/// Return whether the code is a valid (uppercase) ISO 3166-1 alpha-2 country
+/// code or the code for unknown countries.
+pub fn is_valid_country_code(code: &str) -> bool {
+    code == UNKNOWN_COUNTRY || COUNTRY_CODES.binary_search(&code).is_ok()
+}
+
+/// Directory with the default datasets (relative to the working directory).
+pub const DEFAULT_DIR: &str = "data/geodata";
+
+/// Sample value for missing data in SRTM tiles.
+const HGT_VOID: i16 = -32768;
+
+/// Maximum number of SRTM tiles kept in memory.
+const MAX_CACHED_TILES: usize = 16;
+
+// Config
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct GeodataConfig {
+    /// Path to a GeoNames place name file.
+    pub places: Option<PathBuf>,
+    /// Path to a GeoJSON file with country boundaries.
+    pub countries: Option<PathBuf>,
+    /// Path to a directory with SRTM `.hgt` elevation tiles.
+    pub dem_dir: Option<PathBuf>,
+}
+
+impl GeodataConfig {
+    /// Fill in the default place name and country boundary datasets from
+    /// `dir` for datasets that are not configured, if they exist.
+    pub fn with_defaults_from(mut self, dir: &Path) -> Self {
+        let existing = |name: &str| Some(dir.join(name)).filter(|path| path.is_file());
+        self.places = self.places.or_else(|| existing("places.txt"));
+        self.countries = self.countries.or_else(|| existing("countries.geojson"));
+        self
+    }
+}
+
+// Data
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Place {
+    pub name: String,
+    pub lat: f64,
+    pub lon: f64,
+    /// ISO 3166-1 alpha-2 country code
+    pub country: String,
+}
+
+/// A polygon with an outer ring and optional holes. Points are (lon, lat).
+#[derive(Debug)]
+struct Polygon {
+    /// Bounding box of the outer ring (min lon, min lat, max lon, max lat)
+    bbox: (f64, f64, f64, f64),
+    outer: Vec<(f64, f64)>,
+    holes: Vec<Vec<(f64, f64)>>,
+}
+
+impl Polygon {
+    fn new(outer: Vec<(f64, f64)>, holes: Vec<Vec<(f64, f64)>>) -> Self {
+        let bbox = outer.iter().fold(
+            (f64::INFINITY, f64::INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+            |(min_lon, min_lat, max_lon, max_lat), &(lon, lat)| {
+                (
+                    min_lon.min(lon),
+                    min_lat.min(lat),
+                    max_lon.max(lon),
+                    max_lat.max(lat),
+                )
+            },
+        );
+        Self { bbox, outer, holes }
+    }
+
+    fn contains(&self, lon: f64, lat: f64) -> bool {
+        let (min_lon, min_lat, max_lon, max_lat) = self.bbox;
+        if lon < min_lon || lon > max_lon || lat < min_lat || lat > max_lat {
+            return false;
+        }
+        ring_contains(&self.outer, lon, lat) && !self.holes.iter().any(|hole| ring_contains(hole, lon, lat))
+    }
+}
+
+/// Point in polygon test (ray casting).
+pub(crate) fn ring_contains(ring: &[(f64, f64)], lon: f64, lat: f64) -> bool {
+    let mut inside = false;
+    let mut j = ring.len().wrapping_sub(1);
+    for i in 0..ring.len() {
+        let ((xi, yi), (xj, yj)) = (ring[i], ring[j]);
+        if (yi > lat) != (yj > lat) && lon < (xj - xi) * (lat - yi) / (yj - yi) + xi {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+#[derive(Debug)]
+struct Country {
+    code: String,
+    polygons: Vec<Polygon>,
+}
+
+/// SRTM tiles by their south west corner (lat, lon), `None` if the tile is
+/// missing or invalid.
+type TileCache = HashMap<(i32, i32), Option<Arc<HgtTile>>>;
+
+/// An SRTM tile with size × size samples, in rows from north to south.
+#[derive(Debug)]
+struct HgtTile {
+    size: usize,
+    samples: Vec<i16>,
+}
+
+/// Offline geographic data, see module documentation.
+///
+/// The datasets are shared between clones.
+#[derive(Debug, Default, Clone)]
+pub struct Geodata {
+    places: Arc<Vec<Place>>,
+    countries: Arc<Vec<Country>>,
+    dem_dir: Option<PathBuf>,
+    dem_tiles: Arc<Mutex<TileCache>>,
+}
+
+impl Geodata {
+    /// Load all configured datasets.
+    pub fn load(config: &GeodataConfig) -> Result<Self> {
+        let mut geodata = Self::default();
+        if let Some(path) = &config.places {
+            let file = File::open(path).with_context(|| format!("Could not open {}", path.display()))?;
+            geodata.places = Arc::new(
+                parse_places(BufReader::new(file))
+                    .with_context(|| format!("Could not read places from {}", path.display()))?,
+            );
+            log::info!("Loaded {} places", geodata.places.len());
+        }
+        if let Some(path) = &config.countries {
+            let file = File::open(path).with_context(|| format!("Could not open {}", path.display()))?;
+            geodata.countries = Arc::new(
+                parse_countries(BufReader::new(file))
+                    .with_context(|| format!("Could not read countries from {}", path.display()))?,
+            );
+            log::info!("Loaded {} country boundaries", geodata.countries.len());
+        }
+        if let Some(path) = &config.dem_dir {
+            if !path.is_dir() {
+                bail!("DEM directory {} does not exist", path.display());
+            }
+            geodata.dem_dir = Some(path.clone());
+        } else {
+            log::warn!("No DEM directory configured, elevations cannot be looked up");
+        }
+        Ok(geodata)
+    }
+
+    /// Return the place closest to the specified coordinates (within
+    /// `max_distance_meters`), together with its distance in meters.
+    pub fn nearest_place(&self, lat: f64, lon: f64, max_distance_meters: f64) -> Option<(&Place, f64)> {
+        // One degree of latitude is at least 110 km, use that as a cheap filter
+        let max_lat_delta = max_distance_meters / 110_000.0;
+        self.places
+            .iter()
+            .filter(|place| (place.lat - lat).abs() <= max_lat_delta)
+            .map(|place| (place, distance_meters(lat, lon, place.lat, place.lon)))
+            .filter(|(_, distance)| *distance <= max_distance_meters)
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+    }
+
+    /// Return the ISO 3166-1 alpha-2 code of the country containing the
+    /// specified coordinates.
+    pub fn country_at(&self, lat: f64, lon: f64) -> Option<&str> {
+        self.countries
+            .iter()
+            .find(|country| country.polygons.iter().any(|polygon| polygon.contains(lon, lat)))
+            .map(|country| country.code.as_str())
+    }
+
+    /// Return whether a DEM directory is configured.
+    pub fn has_elevation_data(&self) -> bool {
+        self.dem_dir.is_some()
+    }
+
+    /// Return the terrain elevation in meters at the specified coordinates.
+    ///
+    /// Tiles are read on demand and kept in memory. Both 1 and 3 arc second
+    /// tiles (and any other square resolution) are supported, the resolution
+    /// is derived from the file size. Returns `None` if the tile is missing or
+    /// has no data at this point.
+    pub fn elevation_at(&self, lat: f64, lon: f64) -> Option<i32> {
+        if !(-90.0..90.0).contains(&lat) || !(-180.0..180.0).contains(&lon) {
+            return None;
+        }
+
+        // Tiles are named after their south west corner. Edge samples overlap
+        // with the neighbouring tiles.
+        let (tile_lat, tile_lon) = (lat.floor(), lon.floor());
+        let tile = self.dem_tile(tile_lat as i32, tile_lon as i32)?;
+        let cells = (tile.size - 1) as f64;
+        let row = ((tile_lat + 1.0 - lat) * cells).round() as usize;
+        let col = ((lon - tile_lon) * cells).round() as usize;
+        match *tile.samples.get(row * tile.size + col)? {
+            HGT_VOID => None,
+            elevation => Some(i32::from(elevation)),
+        }
+    }
+
+    /// Return the SRTM tile with the specified south west corner, reading it
+    /// from the DEM directory on first use.
+    fn dem_tile(&self, lat: i32, lon: i32) -> Option<Arc<HgtTile>> {
+        let dem_dir = self.dem_dir.as_ref()?;
+        let mut tiles = self.dem_tiles.lock().unwrap();
+        if let Some(tile) = tiles.get(&(lat, lon)) {
+            return tile.clone();
+        }
+        if tiles.len() >= MAX_CACHED_TILES {
+            tiles.clear();
+        }
+        let name = format!(
+            "{}{:02}{}{:03}.hgt",
+            if lat < 0 { 'S' } else { 'N' },
+            lat.abs(),
+            if lon < 0 { 'W' } else { 'E' },
+            lon.abs(),
+        );
+        let tile = read_hgt_tile(&dem_dir.join(name)).map(Arc::new);
+        tiles.insert((lat, lon), tile.clone());
+        tile
+    }
+}
+
+/// Read an SRTM tile (size × size big endian samples).
+fn read_hgt_tile(path: &Path) -> Option<HgtTile> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == ErrorKind::NotFound => return None,
+        Err(e) => {
+            log::warn!("Could not read SRTM tile {}: {}", path.display(), e);
+            return None;
+        }
+    };
+    let samples = bytes.len() / 2;
+    let size = (samples as f64).sqrt() as usize;
+    if size < 2 || size * size != samples || bytes.len() % 2 != 0 {
+        log::warn!("Invalid SRTM tile size of {}", path.display());
+        return None;
+    }
+    Some(HgtTile {
+        size,
+        samples: bytes
+            .chunks_exact(2)
+            .map(|sample| i16::from_be_bytes([sample[0], sample[1]]))
+            .collect(),
+    })
+}
+
+/// Parse a GeoNames place name file.
+fn parse_places(reader: impl BufRead) -> Result<Vec<Place>> {
+    let mut places = vec![];
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        let (Some(name), Some(lat), Some(lon), Some(country)) =
+            (fields.get(1), fields.get(4), fields.get(5), fields.get(8))
+        else {
+            bail!("Line {} has too few columns", index + 1);
+        };
+        places.push(Place {
+            name: name.to_string(),
+            lat: lat
+                .parse()
+                .with_context(|| format!("Invalid latitude on line {}", index + 1))?,
+            lon: lon
+                .parse()
+                .with_context(|| format!("Invalid longitude on line {}", index + 1))?,
+            country: country.to_string(),
+        });
+    }
+    Ok(places)
+}
+
+/// Parse a GeoJSON feature collection with country boundaries. Features
+/// without a valid country code or without (multi)polygon geometry are
+/// ignored.
+fn parse_countries(reader: impl BufRead) -> Result<Vec<Country>> {
+    let collection: Value = serde_json::from_reader(reader)?;
+    let Some(features) = collection["features"].as_array() else {
+        bail!("Not a GeoJSON feature collection");
+    };
+    Ok(features
+        .iter()
+        .filter_map(|feature| {
+            let code = COUNTRY_CODE_PROPERTIES
+                .iter()
+                .filter_map(|property| feature["properties"][property].as_str())
+                .find(|code| code.len() == 2 && code.chars().all(|c| c.is_ascii_alphabetic()))?;
+            let polygons = parse_geometry(&feature["geometry"])?;
+            Some(Country {
+                code: code.to_ascii_uppercase(),
+                polygons,
+            })
+        })
+        .collect())
+}
+
+/// Parse a GeoJSON polygon or multipolygon geometry.
+fn parse_geometry(geometry: &Value) -> Option<Vec<Polygon>> {
+    let coordinates = &geometry["coordinates"];
+    match geometry["type"].as_str()? {
+        "Polygon" => Some(vec![parse_polygon(coordinates)?]),
+        "MultiPolygon" => coordinates.as_array()?.iter().map(parse_polygon).collect(),
+        _ => None,
+    }
+}
+
+fn parse_polygon(rings: &Value) -> Option<Polygon> {
+    let mut rings = rings.as_array()?.iter().map(|ring| {
+        ring.as_array()?
+            .iter()
+            .map(|point| Some((point.get(0)?.as_f64()?, point.get(1)?.as_f64()?)))
+            .collect::<Option<Vec<_>>>()
+    });
+    let outer = rings.next()??;
+    let holes = rings.collect::<Option<Vec<_>>>()?;
+    Some(Polygon::new(outer, holes))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// Load the (heavily simplified) test datasets.
+    pub fn test_geodata() -> Geodata {
+        Geodata::load(&GeodataConfig {
+            places: Some("testdata/geodata/places.txt".into()),
+            countries: Some("testdata/geodata/countries.geojson".into()),
+            dem_dir: Some("testdata/geodata/dem".into()),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn nearest_place() {
+        let geodata = test_geodata();
+        let (place, distance) = geodata.nearest_place(46.70665, 9.153933, 10_000.0).unwrap();
+        assert_eq!(place.name, "Vrin");
+        assert_eq!(place.country, "CH");
+        assert!((5000.0..10_000.0).contains(&distance), "distance {}", distance);
+        assert!(geodata.nearest_place(46.70665, 9.153933, 1000.0).is_none());
+        assert!(geodata.nearest_place(0.0, 0.0, 10_000.0).is_none());
+    }
+
+    #[test]
+    fn country_at() {
+        let geodata = test_geodata();
+        assert_eq!(geodata.country_at(46.70665, 9.153933), Some("CH"));
+        // Liechtenstein is a hole in the Swiss test polygon
+        assert_eq!(geodata.country_at(47.14, 9.52), Some("LI"));
+        assert_eq!(geodata.country_at(48.5, 9.0), None);
+        // Multipolygon
+        assert_eq!(geodata.country_at(-13.16, -72.54), Some("PE"));
+    }
+
+    #[test]
+    fn elevation_at() {
+        // The test tile has a resolution of 0.5°, with samples 1000 to 1007
+        // (row by row from north to south) and a void in the south east corner
+        let geodata = test_geodata();
+        assert_eq!(geodata.elevation_at(46.99, 9.0), Some(1000));
+        assert_eq!(geodata.elevation_at(46.9, 9.6), Some(1001));
+        assert_eq!(geodata.elevation_at(46.5, 9.5), Some(1004));
+        assert_eq!(geodata.elevation_at(46.7, 9.9), Some(1005));
+        assert_eq!(geodata.elevation_at(46.1, 9.1), Some(1006));
+        assert_eq!(geodata.elevation_at(46.01, 9.99), None);
+        assert_eq!(geodata.elevation_at(45.5, 9.5), None);
+        assert_eq!(geodata.elevation_at(91.0, 9.5), None);
+
+        // Tiles are cached, also if they are missing
+        let tiles = geodata.dem_tiles.lock().unwrap();
+        assert_eq!(tiles.len(), 2);
+        assert!(tiles[&(46, 9)].is_some());
+        assert!(tiles[&(45, 9)].is_none());
+    }
+
+    #[test]
+    fn country_codes() {
+        assert!(COUNTRY_CODES.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(is_valid_country_code("CH"));
+        assert!(is_valid_country_code("XK"));
+        assert!(is_valid_country_code(UNKNOWN_COUNTRY));
+        assert!(!is_valid_country_code("ch"));
+        assert!(!is_valid_country_code("QQ"));
+        assert!(!is_valid_country_code("CHE"));
+    }
+
+    #[test]
+    fn default_datasets() {
+        let dir = Path::new("testdata/geodata");
+        let config = GeodataConfig::default().with_defaults_from(dir);
+        assert_eq!(config.places, Some(dir.join("places.txt")));
+        assert_eq!(config.countries, Some(dir.join("countries.geojson")));
+        assert_eq!(config.dem_dir, None);
+        let geodata = Geodata::load(&config).unwrap();
+        assert_eq!(geodata.country_at(46.70665, 9.153933), Some("CH"));
+
+        // Configured datasets take precedence
+        let config = GeodataConfig {
+            countries: Some("other.geojson".into()),
+            ..Default::default()
+        }
+        .with_defaults_from(dir);
+        assert_eq!(config.places, Some(dir.join("places.txt")));
+        assert_eq!(config.countries, Some("other.geojson".into()));
+
+        // Missing defaults are skipped
+        let config = GeodataConfig::default().with_defaults_from(Path::new("testdata/nonexistent"));
+        assert_eq!(config.places, None);
+        assert_eq!(config.countries, None);
+    }
+
+    #[test]
+    fn empty_geodata() {
+        let geodata = Geodata::load(&GeodataConfig::default()).unwrap();
+        assert!(geodata.nearest_place(46.7, 9.1, 10_000.0).is_none());
+        assert!(geodata.country_at(46.7, 9.1).is_none());
+        assert!(geodata.elevation_at(46.7, 9.1).is_none());
+        assert!(!geodata.has_elevation_data());
+        assert!(test_geodata().has_elevation_data());
+    }
+
+    #[test]
+    fn invalid_geodata() {
+        assert!(parse_places("1\tName\n".as_bytes()).is_err());
+        assert!(parse_places("1\tName\tName\t\tfoo\t9.1\tP\tPPL\tCH\n".as_bytes()).is_err());
+        assert!(parse_countries("{}".as_bytes()).is_err());
+        assert!(parse_countries("{\"features\": []}".as_bytes())
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -1,12 +1,13 @@
 //! Glider views.
 
-use chrono::NaiveDate;
+use chrono::{NaiveDate, Utc};
 use diesel::result::{DatabaseErrorKind, Error as DieselError};
 use rocket::{delete, error, get, http::Status, post, routes, serde::json::Json, Route};
 use serde::{Deserialize, Serialize};
 
 use crate::{
     auth, data,
+    maintenance::{self, ApiMaintenanceStatus},
     models::{GliderWithStats, NewGlider},
     responders::{ApiError, RocketError},
 };
@@ -49,6 +50,9 @@ pub struct ApiGlider {
     #[serde(skip_serializing_if = "Option::is_none")]
     comment: Option<String>,
     stats: ApiGliderStats,
+    /// Status of all configured maintenance intervals
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    maintenance: Vec<ApiMaintenanceStatus>,
 }
 
 impl From<GliderWithStats> for ApiGlider {
@@ -67,6 +71,7 @@ impl From<GliderWithStats> for ApiGlider {
                 seconds: glider.seconds,
                 seconds_complete: glider.seconds_complete,
             },
+            maintenance: vec![],
         }
     }
 }
@@ -95,15 +100,28 @@ pub struct ApiGliders {
 pub async fn list(database: data::Database, user: auth::AuthUser) -> Json<ApiGliders> {
     let user = user.into_inner();
 
-    // Get all gliders for user
-    let gliders: Vec<ApiGlider> = database
+    // Get all gliders for user, including maintenance status
+    let today = Utc::now().date_naive();
+    let (gliders, mut maintenance) = database
         .run({
             let user = user.clone();
-            move |db| data::get_gliders_with_stats_for_user(db, &user)
+            move |db| {
+                (
+                    data::get_gliders_with_stats_for_user(db, &user),
+                    maintenance::get_maintenance_status_for_user(db, &user, today),
+                )
+            }
         })
-        .await
+        .await;
+    let gliders: Vec<ApiGlider> = gliders
         .into_iter()
-        .map(Into::into)
+        .map(|glider| {
+            let glider_maintenance = maintenance.remove(&glider.id).unwrap_or_default();
+            ApiGlider {
+                maintenance: glider_maintenance,
+                ..glider.into()
+            }
+        })
         .collect();
 
     Json(ApiGliders {
@@ -289,7 +307,7 @@ mod tests {
     use std::time::Duration;
 
     use chrono::{NaiveDate, Utc};
-    use rocket::{self, http::ContentType, local::blocking::Client};
+    use rocket::{self, http::ContentType, local::blocking::Client, serde::json::Value};
 
     use crate::{
         models::NewFlight,
@@ -499,4 +517,123 @@ mod tests {
         assert_eq!(gliders[0].stats.seconds, 800);
         assert_eq!(gliders[0].stats.seconds_complete, false);
     }
+
+    #[test]
+    fn get_gliders_with_maintenance() {
+        let ctx = DbTestContext::new();
+        let app = rocket::custom(make_test_config())
+            .attach(data::Database::fairing())
+            .mount("/", [api_routes(), maintenance::api_routes()].concat());
+        let client = Client::untracked(app).expect("valid rocket instance");
+
+        macro_rules! post {
+            ($url:expr, $body:expr) => {
+                client
+                    .post($url)
+                    .header(ContentType::JSON)
+                    .body($body)
+                    .private_cookie(ctx.auth_cookie_user1())
+                    .cookie(ctx.username_cookie())
+                    .dispatch()
+            };
+        }
+        macro_rules! get_gliders {
+            () => {
+                client
+                    .get("/gliders")
+                    .private_cookie(ctx.auth_cookie_user1())
+                    .cookie(ctx.username_cookie())
+                    .dispatch()
+                    .into_json::<ApiGliders>()
+                    .unwrap()
+                    .gliders
+            };
+        }
+
+        // Add glider, no maintenance intervals
+        let resp = post!(
+            "/gliders",
+            r#"{"manufacturer": "A", "model": "1", "since": "2010-01-01"}"#
+        );
+        assert_eq!(resp.status(), Status::Created);
+        let gliders = get_gliders!();
+        assert!(gliders[0].maintenance.is_empty());
+        let glider_id = gliders[0].id;
+
+        // Check every 100 hours or 2 years. Never checked, acquired long ago.
+        let resp = post!(
+            format!("/gliders/{}/maintenance/intervals", glider_id),
+            r#"{"kind": "check", "intervalSeconds": 360000, "intervalMonths": 24}"#
+        );
+        assert_eq!(resp.status(), Status::Created);
+        let gliders = get_gliders!();
+        assert_eq!(gliders[0].maintenance.len(), 1);
+        assert_eq!(
+            gliders[0].maintenance[0].state,
+            maintenance::MaintenanceState::Overdue
+        );
+        assert_eq!(
+            gliders[0].maintenance[0].due_date,
+            NaiveDate::from_ymd_opt(2012, 1, 1)
+        );
+
+        // Only one interval per kind
+        let resp = post!(
+            format!("/gliders/{}/maintenance/intervals", glider_id),
+            r#"{"kind": "check", "intervalFlights": 100}"#
+        );
+        assert_eq!(resp.status(), Status::Conflict);
+
+        // Add a flight, then record a check
+        let t1 = Utc::now() - Duration::from_secs(3 * 86400);
+        data::create_flight(
+            &mut ctx.force_get_conn(),
+            &NewFlight {
+                user_id: ctx.testuser1.user.id,
+                glider_id: Some(glider_id),
+                launch_time: Some(t1),
+                landing_time: Some(t1 + Duration::from_secs(3600)),
+                ..Default::default()
+            },
+            None,
+        );
+        let today = Utc::now().date_naive();
+        let resp = post!(
+            format!("/gliders/{}/maintenance/events", glider_id),
+            format!(
+                r#"{{"kind": "check", "date": "{}", "notes": "Flycenter"}}"#,
+                today
+            )
+        );
+        assert_eq!(resp.status(), Status::Created);
+
+        // The flight before the check is not counted
+        let gliders = get_gliders!();
+        let status = &gliders[0].maintenance[0];
+        assert_eq!(status.state, maintenance::MaintenanceState::Ok);
+        assert_eq!(status.last_performed, Some(today));
+        assert_eq!(status.remaining_seconds, Some(360000));
+
+        // The status is returned for the single glider as well
+        let maintenance: Value = client
+            .get(format!("/gliders/{}/maintenance", glider_id))
+            .private_cookie(ctx.auth_cookie_user1())
+            .cookie(ctx.username_cookie())
+            .dispatch()
+            .into_json()
+            .unwrap();
+        assert_eq!(maintenance["events"].as_array().unwrap().len(), 1);
+        assert_eq!(maintenance["status"][0]["state"], "ok");
+        assert_eq!(maintenance["status"][0]["remainingSeconds"], 360000);
+
+        // Other users cannot add events to the glider
+        let resp = client
+            .post(format!("/gliders/{}/maintenance/events", glider_id))
+            .header(ContentType::JSON)
+            .body(r#"{"kind": "trim", "date": "2020-01-01"}"#)
+            .private_cookie(ctx.auth_cookie_user2())
+            .cookie(ctx.username_cookie())
+            .dispatch();
+        assert_eq!(resp.status(), Status::NotFound);
+    }
 }
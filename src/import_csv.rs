@@ -10,6 +10,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     auth, data,
+    flights::{MAX_CLOUD_BASE, MAX_THERMAL_STRENGTH, MAX_WIND_SPEED},
     models::{NewFlight, User},
     responders::ApiError,
     xcontest::is_valid_tracktype,
@@ -116,11 +117,29 @@ pub struct ApiCsvFlightPreview {
     pub video_url: Option<String>,
     /// Whether you hiked up to launch
     pub hikeandfly: bool,
+    /// Wind direction at launch in degrees
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wind_direction: Option<i16>,
+    /// Wind speed at launch in km/h
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wind_speed: Option<i16>,
+    /// Thermal strength in m/s
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thermal_strength: Option<f32>,
+    /// Cloud base in meters MSL
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cloud_base: Option<i32>,
+    /// Turbulence rating (1-5)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub turbulence: Option<i16>,
+    /// Weather notes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub weather_notes: Option<String>,
 }
 
 // Helper types
 
-static VALID_HEADERS: [&'static str; 14] = [
+static VALID_HEADERS: [&'static str; 20] = [
     "number",
     "date",
     "glider",
@@ -135,6 +154,17 @@ static VALID_HEADERS: [&'static str; 14] = [
     "xcontest_tracktype",
     "xcontest_scored_distance",
     "video_url",
+    "wind_direction",
+    "wind_speed",
+    "thermal_strength",
+    "cloud_base",
+    "turbulence",
+    "weather_notes",
+];
+
+/// The 16 compass points, in clockwise order starting at north
+static COMPASS_POINTS: [&str; 16] = [
+    "N", "NNE", "NE", "ENE", "E", "ESE", "SE", "SSE", "S", "SSW", "SW", "WSW", "W", "WNW", "NW", "NNW",
 ];
 
 #[derive(Debug, Deserialize)]
@@ -153,6 +183,12 @@ struct CsvRecord {
     xcontest_tracktype: Option<String>,
     xcontest_scored_distance: Option<f32>,
     video_url: Option<String>,
+    wind_direction: Option<String>,
+    wind_speed: Option<i16>,
+    thermal_strength: Option<f32>,
+    cloud_base: Option<i32>,
+    turbulence: Option<i16>,
+    weather_notes: Option<String>,
 }
 
 // API endpoints
@@ -336,6 +372,7 @@ fn analyze_csv(csv_bytes: Vec<u8>, user: &User, conn: &mut PgConnection) -> CsvA
             comment: record.comment.clone(),
             video_url: record.video_url.clone(),
             hikeandfly: record.hikeandfly.unwrap_or(false),
+            weather_notes: record.weather_notes.clone(),
             ..Default::default()
         };
 
@@ -344,6 +381,7 @@ fn analyze_csv(csv_bytes: Vec<u8>, user: &User, conn: &mut PgConnection) -> CsvA
         flight_process_locations(&record, row_number1, &mut flight, &locations, &mut warnings);
         flight_process_date_time(&record, row_number1, &mut flight, &mut warnings);
         flight_process_xcontest_info(&record, row_number1, &mut flight, &mut warnings);
+        flight_process_conditions(&record, row_number1, &mut flight, &mut warnings);
 
         flights.push(flight);
     }
@@ -531,6 +569,85 @@ fn flight_process_xcontest_info(
     }
 }
 
+/// Parse a wind direction, either in degrees or as one of the 16 compass
+/// points (e.g. "NW").
+fn parse_wind_direction(direction: &str) -> Option<i16> {
+    let direction = direction.trim();
+    if let Ok(degrees) = direction.parse::<i16>() {
+        return if (0..=360).contains(&degrees) {
+            Some(degrees % 360)
+        } else {
+            None
+        };
+    }
+    COMPASS_POINTS
+        .iter()
+        .position(|point| point.eq_ignore_ascii_case(direction))
+        .map(|index| (index as f32 * 22.5).round() as i16)
+}
+
+fn flight_process_conditions(
+    record: &CsvRecord,
+    row_number1: usize,
+    flight: &mut ApiCsvFlightPreview,
+    warnings: &mut Vec<Message>,
+) {
+    if let Some(direction) = record.wind_direction.as_ref() {
+        flight.wind_direction = parse_wind_direction(direction);
+        if flight.wind_direction.is_none() {
+            warnings.push(Message::for_field(
+                row_number1,
+                "wind-direction",
+                format!("Invalid wind direction: {direction} (expected degrees or compass point)"),
+            ));
+        }
+    }
+    if let Some(speed) = record.wind_speed {
+        if (0..=MAX_WIND_SPEED).contains(&speed) {
+            flight.wind_speed = Some(speed);
+        } else {
+            warnings.push(Message::for_field(
+                row_number1,
+                "wind-speed",
+                format!("Invalid wind speed: {speed}"),
+            ));
+        }
+    }
+    if let Some(strength) = record.thermal_strength {
+        if strength.is_finite() && (0.0..=MAX_THERMAL_STRENGTH).contains(&strength) {
+            flight.thermal_strength = Some(strength);
+        } else {
+            warnings.push(Message::for_field(
+                row_number1,
+                "thermal-strength",
+                format!("Invalid thermal strength: {strength}"),
+            ));
+        }
+    }
+    if let Some(cloud_base) = record.cloud_base {
+        if (0..=MAX_CLOUD_BASE).contains(&cloud_base) {
+            flight.cloud_base = Some(cloud_base);
+        } else {
+            warnings.push(Message::for_field(
+                row_number1,
+                "cloud-base",
+                format!("Invalid cloud base: {cloud_base}"),
+            ));
+        }
+    }
+    if let Some(turbulence) = record.turbulence {
+        if (1..=5).contains(&turbulence) {
+            flight.turbulence = Some(turbulence);
+        } else {
+            warnings.push(Message::for_field(
+                row_number1,
+                "turbulence",
+                format!("Invalid turbulence rating: {turbulence} (expected 1-5)"),
+            ));
+        }
+    }
+}
+
 /// Import flights into the database
 fn import_flights(
     flights: Vec<ApiCsvFlightPreview>,
@@ -554,6 +671,12 @@ fn import_flights(
             comment: flight.comment,
             video_url: flight.video_url,
             hikeandfly: flight.hikeandfly,
+            wind_direction: flight.wind_direction,
+            wind_speed: flight.wind_speed,
+            thermal_strength: flight.thermal_strength,
+            cloud_base: flight.cloud_base,
+            turbulence: flight.turbulence,
+            weather_notes: flight.weather_notes,
         })
         .collect();
 
@@ -750,6 +873,8 @@ mod tests {
                 elevation: 0,
                 user_id: ctx.testuser1.user.id,
                 geog: None,
+                match_radius: None,
+                area: None,
             },
         );
         let location2 = data::create_location(
@@ -760,6 +885,8 @@ mod tests {
                 elevation: 0,
                 user_id: ctx.testuser1.user.id,
                 geog: None,
+                match_radius: None,
+                area: None,
             },
         );
 
@@ -798,6 +925,8 @@ mod tests {
                 elevation: 0,
                 user_id: ctx.testuser2.user.id,
                 geog: None,
+                match_radius: None,
+                area: None,
             },
         );
         data::create_location(
@@ -808,6 +937,8 @@ mod tests {
                 elevation: 0,
                 user_id: ctx.testuser2.user.id,
                 geog: None,
+                match_radius: None,
+                area: None,
             },
         );
 
@@ -972,6 +1103,8 @@ mod tests {
                 elevation: 0,
                 user_id: ctx.testuser1.user.id,
                 geog: None,
+                match_radius: None,
+                area: None,
             },
         );
         let rappi = data::create_location(
@@ -982,6 +1115,8 @@ mod tests {
                 elevation: 0,
                 user_id: ctx.testuser1.user.id,
                 geog: None,
+                match_radius: None,
+                area: None,
             },
         );
 
@@ -1011,7 +1146,8 @@ mod tests {
                 xcontest_url: Some("https://xcontest.org/myflight/".into()),
                 comment: Some("Some flying, some scratching".into()),
                 video_url: Some("https://youtube.com/myvid".into()),
-                hikeandfly: false
+                hikeandfly: false,
+                ..Default::default()
             }
         );
         assert_eq!(
@@ -1039,4 +1175,55 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn analyze_csv_conditions() {
+        let result = analyze(
+            "wind_direction,wind_speed,thermal_strength,cloud_base,turbulence,weather_notes\n\
+             NW,15,2.5,2800,2,\"Strong inversion, blue\"\n\
+             225,,,,,\n\
+             nnE,,,,,\n\
+             X,250,-1,12000,6,\n",
+            None,
+        );
+        assert_eq!(result.errors, empty_vec());
+        assert_eq!(
+            result.flights[0],
+            ApiCsvFlightPreview {
+                csv_row: 1,
+                wind_direction: Some(315),
+                wind_speed: Some(15),
+                thermal_strength: Some(2.5),
+                cloud_base: Some(2800),
+                turbulence: Some(2),
+                weather_notes: Some("Strong inversion, blue".into()),
+                ..Default::default()
+            }
+        );
+        assert_eq!(result.flights[1].wind_direction, Some(225));
+        assert_eq!(result.flights[2].wind_direction, Some(23));
+
+        // Invalid values are ignored with a warning
+        assert_eq!(
+            result.warnings,
+            vec![
+                Message::for_field(
+                    4,
+                    "wind-direction",
+                    "Invalid wind direction: X (expected degrees or compass point)"
+                ),
+                Message::for_field(4, "wind-speed", "Invalid wind speed: 250"),
+                Message::for_field(4, "thermal-strength", "Invalid thermal strength: -1"),
+                Message::for_field(4, "cloud-base", "Invalid cloud base: 12000"),
+                Message::for_field(4, "turbulence", "Invalid turbulence rating: 6 (expected 1-5)"),
+            ]
+        );
+        assert_eq!(
+            result.flights[3],
+            ApiCsvFlightPreview {
+                csv_row: 4,
+                ..Default::default()
+            }
+        );
+    }
 }
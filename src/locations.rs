@@ -2,22 +2,43 @@
 
 use std::convert::TryFrom;
 
+use chrono::{DateTime, Utc};
 use diesel_geography::types::GeogPoint;
-use rocket::{delete, get, http::Status, post, routes, serde::json::Json, Route};
+use rocket::{
+    delete, get,
+    http::Status,
+    post, routes,
+    serde::json::{Json, Value},
+    Route, State,
+};
 use serde::{Deserialize, Serialize};
 
 use crate::{
     auth, data,
-    models::{LocationWithCount, NewLocation},
-    responders::ApiError,
+    geodata::{self, Geodata},
+    models::{Location, LocationWithCount, NewLocation},
+    polygon::GeogPolygon,
+    responders::{ApiError, RocketError},
 };
 
+/// Default maximum distance of duplicate location suggestions.
+const DEFAULT_DUPLICATE_DISTANCE_METERS: f64 = 100.0;
+
+/// Maximum distance of duplicate location suggestions.
+const MAX_DUPLICATE_DISTANCE_METERS: f64 = 10_000.0;
+
+/// Maximum radius for matching IGC launch and landing points to a location.
+pub const MAX_MATCH_RADIUS_METERS: i32 = 10_000;
+
+/// Number of landing locations in the location stats.
+const STATS_LANDING_LOCATION_COUNT: i64 = 5;
+
 // API types
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ApiCoordinates {
-    lon: f64,
-    lat: f64,
+    pub lon: f64,
+    pub lat: f64,
 }
 
 #[derive(Serialize)]
@@ -29,9 +50,38 @@ pub struct ApiLocation {
     elevation: i32,
     #[serde(skip_serializing_if = "Option::is_none")]
     coordinates: Option<ApiCoordinates>,
+    /// Outline of the location as GeoJSON polygon geometry
+    #[serde(skip_serializing_if = "Option::is_none")]
+    area: Option<Value>,
+    /// The shared site this location was copied from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    site_id: Option<i32>,
+    /// Radius for matching IGC launch and landing points in meters
+    #[serde(skip_serializing_if = "Option::is_none")]
+    match_radius: Option<i32>,
     flight_count: u64,
 }
 
+impl From<Location> for ApiLocation {
+    /// Convert a newly created location (without flights).
+    fn from(location: Location) -> Self {
+        Self {
+            id: location.id,
+            name: location.name,
+            country_code: location.country,
+            elevation: location.elevation,
+            coordinates: location.geog.map(|geog| ApiCoordinates {
+                lon: geog.x,
+                lat: geog.y,
+            }),
+            area: location.area.map(|area| area.to_geojson()),
+            site_id: location.site_id,
+            match_radius: location.match_radius,
+            flight_count: 0,
+        }
+    }
+}
+
 impl From<LocationWithCount> for ApiLocation {
     fn from(location: LocationWithCount) -> Self {
         Self {
@@ -43,6 +93,9 @@ impl From<LocationWithCount> for ApiLocation {
                 lon: geog.x,
                 lat: geog.y,
             }),
+            area: location.area.map(|area| area.to_geojson()),
+            site_id: location.site_id,
+            match_radius: location.match_radius,
             flight_count: u64::try_from(location.count.max(0)).unwrap(),
         }
     }
@@ -54,15 +107,197 @@ pub struct ApiLocations {
     locations: Vec<ApiLocation>,
 }
 
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiLocationLookup {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    country_code: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    elevation: Option<i32>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiDuplicateSuggestion {
+    /// The two locations that might be duplicates
+    locations: Vec<ApiLocation>,
+    /// Distance between the locations in meters (if both have coordinates)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    distance: Option<f64>,
+    /// Whether the locations have similar names
+    similar_name: bool,
+}
+
+#[derive(Serialize)]
+pub struct ApiDuplicateSuggestions {
+    duplicates: Vec<ApiDuplicateSuggestion>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiLocationFlight {
+    flight_id: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    number: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    launch_time: Option<DateTime<Utc>>,
+    flight_seconds: u64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiMonthStats {
+    /// Month of the year (1-12)
+    month: u8,
+    flight_count: u32,
+    flight_seconds: u64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiFlightCount {
+    id: i32,
+    name: String,
+    flight_count: u32,
+}
+
+impl From<data::NamedFlightCount> for ApiFlightCount {
+    fn from(count: data::NamedFlightCount) -> Self {
+        Self {
+            id: count.id,
+            name: count.name,
+            flight_count: count.count as u32,
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiLocationStats {
+    location: ApiLocation,
+    launch_count: u32,
+    landing_count: u32,
+    /// Total flight time of the flights launched here
+    launch_flight_seconds: u64,
+    /// Average flight time of the flights launched here
+    #[serde(skip_serializing_if = "Option::is_none")]
+    average_flight_seconds: Option<u64>,
+    /// Longest flight launched here
+    #[serde(skip_serializing_if = "Option::is_none")]
+    longest_flight: Option<ApiLocationFlight>,
+    /// Month of the year with the most flights launched here
+    #[serde(skip_serializing_if = "Option::is_none")]
+    best_month: Option<ApiMonthStats>,
+    /// Gliders flown from or to this location
+    gliders: Vec<ApiFlightCount>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    first_visit: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_visit: Option<DateTime<Utc>>,
+    /// Most common landing locations of flights launched here
+    landing_locations: Vec<ApiFlightCount>,
+}
+
 // Forms
 
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct LocationAddUpdateForm {
     name: String,
-    country_code: String,
-    elevation: i32,
+    /// ISO 3166-1 alpha-2 country code. If missing, it is looked up from the
+    /// coordinates.
+    country_code: Option<String>,
+    /// Elevation in meters. If missing, it is looked up from the coordinates
+    /// (requires a configured DEM directory).
+    elevation: Option<i32>,
     coordinates: Option<ApiCoordinates>,
+    /// Radius for matching IGC launch and landing points in meters. If
+    /// missing, the user's default radius is used.
+    match_radius: Option<i32>,
+    /// Outline of the location as GeoJSON polygon (geometry or feature).
+    /// Launch and landing points within the area are matched to the location,
+    /// regardless of the matching radius. If coordinates are missing, the
+    /// center of the area is used.
+    area: Option<Value>,
+}
+
+/// Validated location form data.
+struct LocationFields {
+    name: String,
+    country: String,
+    elevation: i32,
+    geog: Option<GeogPoint>,
+    match_radius: Option<i32>,
+    area: Option<GeogPolygon>,
+}
+
+impl LocationAddUpdateForm {
+    /// Validate the form data and fill in missing country code and elevation
+    /// from the offline geodata.
+    fn into_fields(self, geodata: &Geodata) -> Result<LocationFields, (Status, Json<RocketError>)> {
+        let invalid = |message: String| RocketError::new(Status::BadRequest, "InvalidData", message);
+        let area = self
+            .area
+            .map(|area| GeogPolygon::from_geojson(&area))
+            .transpose()
+            .map_err(|e| invalid(format!("Invalid area: {}", e)))?;
+        let coordinates = self
+            .coordinates
+            .map(|ApiCoordinates { lat, lon }| (lat, lon))
+            .or_else(|| area.as_ref().and_then(GeogPolygon::center));
+
+        let country = match self.country_code {
+            Some(country_code) => country_code.trim().to_ascii_uppercase(),
+            None => coordinates
+                .and_then(|(lat, lon)| geodata.country_at(lat, lon))
+                .map(str::to_string)
+                .ok_or_else(|| invalid("Country code is missing and could not be looked up".into()))?,
+        };
+        if !geodata::is_valid_country_code(&country) {
+            return Err(invalid(format!("Invalid ISO 3166-1 country code: {}", country)));
+        }
+
+        let elevation = match self.elevation {
+            Some(elevation) => elevation,
+            None if !geodata.has_elevation_data() => {
+                return Err(invalid(
+                    "Elevation is missing and no elevation data is configured".into(),
+                ))
+            }
+            None => coordinates
+                .and_then(|(lat, lon)| geodata.elevation_at(lat, lon))
+                .ok_or_else(|| invalid("Elevation is missing and could not be looked up".into()))?,
+        };
+
+        if let Some(radius) = self.match_radius {
+            if !(1..=MAX_MATCH_RADIUS_METERS).contains(&radius) {
+                return Err(invalid(format!(
+                    "Matching radius must be between 1 and {} meters",
+                    MAX_MATCH_RADIUS_METERS
+                )));
+            }
+        }
+
+        Ok(LocationFields {
+            name: self.name,
+            country,
+            elevation,
+            geog: coordinates.map(|(lat, lon)| GeogPoint {
+                x: lon,
+                y: lat,
+                srid: None,
+            }),
+            match_radius: self.match_radius,
+            area,
+        })
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct LocationMergeForm {
+    /// Locations that are merged into the target location
+    source_ids: Vec<i32>,
 }
 
 // API endpoints
@@ -122,41 +357,36 @@ pub fn get_nologin(id: i32) -> ApiError {
 pub async fn add(
     user: auth::AuthUser,
     database: data::Database,
+    geodata: &State<Geodata>,
     data: Json<LocationAddUpdateForm>,
-) -> Status {
+) -> Result<(Status, Json<ApiLocation>), (Status, Json<RocketError>)> {
     log::debug!("locations::add");
     let user = user.into_inner();
 
-    // Destructure data
-    let LocationAddUpdateForm {
+    // Create model
+    let LocationFields {
         name,
-        country_code,
+        country,
         elevation,
-        coordinates,
-    } = data.into_inner();
-
-    // Create model
+        geog,
+        match_radius,
+        area,
+    } = data.into_inner().into_fields(geodata)?;
     let location = NewLocation {
         name,
-        country: country_code,
+        country,
         elevation,
         user_id: user.id,
-        geog: if let Some(ApiCoordinates { lat, lon }) = coordinates {
-            Some(GeogPoint {
-                x: lon,
-                y: lat,
-                srid: None,
-            })
-        } else {
-            None
-        },
+        geog,
+        match_radius,
+        area,
     };
 
     // Create database entry
     // TODO: Error handling
-    database.run(move |db| data::create_location(db, location)).await;
+    let location = database.run(move |db| data::create_location(db, location)).await;
     log::info!("Created location for user {}", user.id);
-    Status::Created
+    Ok((Status::Created, Json(ApiLocation::from(location))))
 }
 
 #[post("/locations", rank = 2)]
@@ -168,41 +398,48 @@ pub fn add_nologin() -> ApiError {
 pub async fn edit(
     user: auth::AuthUser,
     database: data::Database,
+    geodata: &State<Geodata>,
     id: i32,
     data: Json<LocationAddUpdateForm>,
-) -> Result<Status, Status> {
+) -> Result<Status, (Status, Json<RocketError>)> {
     let user = user.into_inner();
 
     // Get location
     let mut location = match database.run(move |db| data::get_location_by_id(db, id)).await {
         Some(location) => location,
-        None => return Err(Status::NotFound),
+        None => {
+            return Err(RocketError::new(
+                Status::NotFound,
+                "NotFound",
+                "Location not found",
+            ))
+        }
     };
 
     // Ownership check
     if location.user_id != user.id {
-        return Err(Status::Forbidden);
+        return Err(RocketError::new(
+            Status::Forbidden,
+            "Forbidden",
+            "Location does not belong to user",
+        ));
     }
 
     // Update model
-    let LocationAddUpdateForm {
+    let LocationFields {
         name,
-        country_code,
+        country,
         elevation,
-        coordinates,
-    } = data.into_inner();
+        geog,
+        match_radius,
+        area,
+    } = data.into_inner().into_fields(geodata)?;
     location.name = name;
-    location.country = country_code;
+    location.country = country;
     location.elevation = elevation;
-    if let Some(ApiCoordinates { lat, lon }) = coordinates {
-        location.geog = Some(GeogPoint {
-            x: lon,
-            y: lat,
-            srid: None,
-        });
-    } else {
-        location.geog = None;
-    }
+    location.geog = geog;
+    location.match_radius = match_radius;
+    location.area = area;
 
     // Update database
     // TODO: Error handling
@@ -267,6 +504,212 @@ pub fn delete_nologin(id: i32) -> ApiError {
     ApiError::MissingAuthentication
 }
 
+/// Merge other locations into a location.
+///
+/// All flights of the source locations are reassigned to this location, then
+/// the source locations are deleted.
+///
+/// - Return "HTTP 204 No Content" if the locations were merged.
+/// - Return "HTTP 400 Bad Request" if the source locations are invalid.
+/// - Return "HTTP 404 Not Found" if a location was not found.
+/// - Return "HTTP 403 Forbidden" if a location does not belong to user.
+#[post("/locations/<id>/merge", data = "<data>")]
+pub async fn merge(
+    user: auth::AuthUser,
+    database: data::Database,
+    id: i32,
+    data: Json<LocationMergeForm>,
+) -> Result<Status, (Status, Json<RocketError>)> {
+    let user = user.into_inner();
+    let not_found = || RocketError::new(Status::NotFound, "NotFound", "Location not found");
+    let forbidden = || RocketError::new(Status::Forbidden, "Forbidden", "Location does not belong to user");
+
+    // Validate data
+    let mut source_ids = data.into_inner().source_ids;
+    source_ids.sort_unstable();
+    source_ids.dedup();
+    if source_ids.is_empty() {
+        return Err(RocketError::new(
+            Status::BadRequest,
+            "InvalidData",
+            "At least one source location must be specified",
+        ));
+    }
+    if source_ids.contains(&id) {
+        return Err(RocketError::new(
+            Status::BadRequest,
+            "InvalidData",
+            "A location cannot be merged into itself",
+        ));
+    }
+
+    // Get locations
+    let (target, sources) = database
+        .run({
+            let source_ids = source_ids.clone();
+            move |db| {
+                (
+                    data::get_location_by_id(db, id),
+                    data::get_locations_with_ids(db, &source_ids),
+                )
+            }
+        })
+        .await;
+    let target = target.ok_or_else(not_found)?;
+    if sources.len() != source_ids.len() {
+        return Err(not_found());
+    }
+
+    // Ownership check
+    if target.user_id != user.id || sources.iter().any(|source| source.user_id != user.id) {
+        return Err(forbidden());
+    }
+
+    // Merge
+    database
+        .run(move |db| data::merge_locations(db, &target, &source_ids))
+        .await
+        .map(|()| {
+            log::info!(
+                "Merged locations {:?} into location {}",
+                sources.iter().map(|l| l.id).collect::<Vec<_>>(),
+                id
+            );
+            Status::NoContent
+        })
+        .map_err(|e| {
+            log::error!("Could not merge locations into location {}: {}", id, e);
+            RocketError::new(
+                Status::InternalServerError,
+                "InternalServerError",
+                "Could not merge locations",
+            )
+        })
+}
+
+#[post("/locations/<_id>/merge", rank = 2)]
+pub fn merge_nologin(_id: i32) -> ApiError {
+    ApiError::MissingAuthentication
+}
+
+/// Flight statistics of a location.
+#[get("/locations/<id>/stats")]
+pub async fn stats(
+    user: auth::AuthUser,
+    database: data::Database,
+    id: i32,
+) -> Result<Json<ApiLocationStats>, Status> {
+    let user = user.into_inner();
+
+    database
+        .run(move |db| {
+            let location = data::get_location_with_flight_count_by_id(db, id).ok_or(Status::NotFound)?;
+
+            // Ownership check
+            if location.user_id != user.id {
+                return Err(Status::Forbidden);
+            }
+
+            let summary = data::get_flight_summary_for_location(db, id);
+            Ok(Json(ApiLocationStats {
+                location: location.into(),
+                launch_count: summary.launch_count as u32,
+                landing_count: summary.landing_count as u32,
+                launch_flight_seconds: summary.launch_seconds.max(0) as u64,
+                average_flight_seconds: summary
+                    .average_seconds
+                    .map(|seconds| seconds.max(0.0).round() as u64),
+                longest_flight: data::get_longest_flight_from_location(db, id).map(|record| {
+                    ApiLocationFlight {
+                        flight_id: record.flight_id,
+                        number: record.number,
+                        launch_time: record.launch_time,
+                        flight_seconds: record.value.max(0.0) as u64,
+                    }
+                }),
+                best_month: data::get_best_month_for_location(db, id).map(|month| ApiMonthStats {
+                    month: month.bucket as u8,
+                    flight_count: month.count as u32,
+                    flight_seconds: month.seconds.max(0) as u64,
+                }),
+                gliders: data::get_gliders_for_location(db, id)
+                    .into_iter()
+                    .map(Into::into)
+                    .collect(),
+                first_visit: summary.first_visit,
+                last_visit: summary.last_visit,
+                landing_locations: data::get_landing_locations_for_launch(
+                    db,
+                    id,
+                    STATS_LANDING_LOCATION_COUNT,
+                )
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+            }))
+        })
+        .await
+}
+
+#[get("/locations/<_id>/stats", rank = 2)]
+pub fn stats_nologin(_id: i32) -> ApiError {
+    ApiError::MissingAuthentication
+}
+
+/// Suggest possible duplicates: Locations that are within `distance` meters
+/// of each other (default 100 m) or that have similar names.
+#[get("/locations/duplicates?<distance>")]
+pub async fn duplicates(
+    user: auth::AuthUser,
+    database: data::Database,
+    distance: Option<f64>,
+) -> Json<ApiDuplicateSuggestions> {
+    let user = user.into_inner();
+    let max_distance = distance
+        .unwrap_or(DEFAULT_DUPLICATE_DISTANCE_METERS)
+        .clamp(0.0, MAX_DUPLICATE_DISTANCE_METERS);
+
+    let locations = database
+        .run(move |db| data::get_all_locations_with_stats_for_user(db, &user))
+        .await;
+    let duplicates = find_duplicates(&locations, max_distance)
+        .into_iter()
+        .map(|(a, b, distance, similar_name)| ApiDuplicateSuggestion {
+            locations: vec![locations[a].clone().into(), locations[b].clone().into()],
+            distance,
+            similar_name,
+        })
+        .collect();
+
+    Json(ApiDuplicateSuggestions { duplicates })
+}
+
+#[get("/locations/duplicates", rank = 3)]
+pub fn duplicates_nologin() -> ApiError {
+    ApiError::MissingAuthentication
+}
+
+/// Look up country code and elevation of the specified coordinates in the
+/// offline geodata, to pre-fill the location form. Values that could not be
+/// determined are omitted.
+#[get("/locations/lookup?<lat>&<lon>")]
+pub fn lookup(
+    _user: auth::AuthUser,
+    geodata: &State<Geodata>,
+    lat: f64,
+    lon: f64,
+) -> Json<ApiLocationLookup> {
+    Json(ApiLocationLookup {
+        country_code: geodata.country_at(lat, lon).map(str::to_string),
+        elevation: geodata.elevation_at(lat, lon),
+    })
+}
+
+#[get("/locations/lookup", rank = 3)]
+pub fn lookup_nologin() -> ApiError {
+    ApiError::MissingAuthentication
+}
+
 /// Return vec of all API routes.
 pub fn api_routes() -> Vec<Route> {
     routes![
@@ -280,16 +723,97 @@ pub fn api_routes() -> Vec<Route> {
         edit_nologin,
         delete,
         delete_nologin,
+        merge,
+        merge_nologin,
+        stats,
+        stats_nologin,
+        duplicates,
+        duplicates_nologin,
+        lookup,
+        lookup_nologin,
     ]
 }
 
+// Helpers
+
+/// Great-circle distance between two points in meters.
+pub fn distance_meters(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    const EARTH_RADIUS_METERS: f64 = 6_371_000.0;
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let dlat = lat2 - lat1;
+    let dlon = (lon2 - lon1).to_radians();
+    let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_METERS * a.sqrt().asin()
+}
+
+/// Normalize a location name for comparison: Lowercase, only alphanumeric
+/// characters.
+fn normalize_name(name: &str) -> Vec<char> {
+    name.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Levenshtein edit distance between two strings.
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous + usize::from(ca != cb);
+            previous = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(previous + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Return whether two location names are similar: Equal after normalization,
+/// or at most one edit per six characters apart.
+fn similar_names(a: &str, b: &str) -> bool {
+    let (a, b) = (normalize_name(a), normalize_name(b));
+    if a.is_empty() || b.is_empty() {
+        return false;
+    }
+    a == b || edit_distance(&a, &b) <= a.len().min(b.len()) / 6
+}
+
+/// Find pairs of possibly duplicate locations. Return the indices of both
+/// locations, their distance (if both have coordinates) and whether the names
+/// are similar. Pairs are sorted by distance.
+fn find_duplicates(
+    locations: &[LocationWithCount],
+    max_distance: f64,
+) -> Vec<(usize, usize, Option<f64>, bool)> {
+    let mut duplicates = vec![];
+    for (a, location_a) in locations.iter().enumerate() {
+        for (b, location_b) in locations.iter().enumerate().skip(a + 1) {
+            let distance = match (&location_a.geog, &location_b.geog) {
+                (Some(geog_a), Some(geog_b)) => Some(distance_meters(geog_a.y, geog_a.x, geog_b.y, geog_b.x)),
+                _ => None,
+            };
+            let similar_name = similar_names(&location_a.name, &location_b.name);
+            if similar_name || distance.is_some_and(|distance| distance <= max_distance) {
+                duplicates.push((a, b, distance, similar_name));
+            }
+        }
+    }
+    duplicates.sort_by(|x, y| {
+        x.2.unwrap_or(f64::INFINITY)
+            .total_cmp(&y.2.unwrap_or(f64::INFINITY))
+    });
+    duplicates
+}
+
 #[cfg(test)]
 mod tests {
-    use rocket::{self, http::ContentType, local::blocking::Client};
+    use rocket::{self, http::ContentType, local::blocking::Client, serde::json::json};
 
     use crate::{
-        models::NewFlight,
-        test_utils::{make_test_config, DbTestContext},
+        models::{NewFlight, NewGlider},
+        test_utils::{make_test_config, utc_datetime, DbTestContext},
     };
 
     use super::*;
@@ -298,6 +822,7 @@ mod tests {
     fn make_client() -> Client {
         let app = rocket::custom(make_test_config())
             .attach(data::Database::fairing())
+            .manage(crate::geodata::tests::test_geodata())
             .mount("/", api_routes());
         Client::untracked(app).expect("valid rocket instance")
     }
@@ -336,6 +861,8 @@ mod tests {
                 elevation: 5822,
                 user_id: ctx.testuser1.user.id,
                 geog: None,
+                match_radius: None,
+                area: None,
             },
         );
         data::create_location(
@@ -350,6 +877,8 @@ mod tests {
                     y: -13.163235172208347,
                     srid: None,
                 }),
+                match_radius: None,
+                area: None,
             },
         );
 
@@ -401,6 +930,8 @@ mod tests {
                     y: -13.163235172208347,
                     srid: None,
                 }),
+                match_radius: None,
+                area: None,
             },
         );
 
@@ -453,14 +984,205 @@ mod tests {
             ctx.auth_cookie_user1()
         );
         assert_eq!(resp.status(), Status::Created);
+        let body = resp.into_string().unwrap();
 
         // Verify database
-        let g = data::get_locations_for_user(&mut *ctx.force_get_conn(), &ctx.testuser1.user);
+        let g = data::get_locations_for_user(&mut ctx.force_get_conn(), &ctx.testuser1.user);
         assert_eq!(g.len(), 1);
+        assert!(body.contains(&format!(r#""id":{}"#, g[0].id)), "{}", body);
         assert_eq!(g[0].name, "Testlocation");
         assert_eq!(g[0].country, "CH");
     }
 
+    #[test]
+    fn add_location_with_lookup() {
+        let ctx = DbTestContext::new();
+        let client = make_client();
+
+        macro_rules! add_location {
+            ($body:expr) => {
+                client
+                    .post("/locations")
+                    .header(ContentType::JSON)
+                    .body($body)
+                    .private_cookie(ctx.auth_cookie_user1())
+                    .cookie(ctx.username_cookie())
+                    .dispatch()
+            };
+        }
+
+        // Invalid country code
+        let resp = add_location!(r#"{"name": "Invalid", "countryCode": "QQ", "elevation": 1000}"#);
+        assert_eq!(resp.status(), Status::BadRequest);
+
+        // Invalid matching radius
+        let resp =
+            add_location!(r#"{"name": "Invalid", "countryCode": "CH", "elevation": 1000, "matchRadius": 0}"#);
+        assert_eq!(resp.status(), Status::BadRequest);
+
+        // Country and elevation cannot be looked up without coordinates
+        let resp = add_location!(r#"{"name": "Unknown"}"#);
+        assert_eq!(resp.status(), Status::BadRequest);
+
+        // Country and elevation are looked up from the coordinates
+        let resp = add_location!(r#"{"name": "Vrin", "coordinates": {"lat": 46.5, "lon": 9.5}}"#);
+        assert_eq!(resp.status(), Status::Created);
+
+        // Lowercase country codes are normalized, with custom matching radius
+        let resp = add_location!(
+            r#"{"name": "Vals", "countryCode": "ch", "elevation": 1252, "coordinates": {"lat": 46.6, "lon": 9.2}, "matchRadius": 300}"#
+        );
+        assert_eq!(resp.status(), Status::Created);
+
+        let g = data::get_locations_for_user(&mut *ctx.force_get_conn(), &ctx.testuser1.user);
+        assert_eq!(g.len(), 2);
+        assert_eq!(
+            (g[0].name.as_str(), g[0].country.as_str(), g[0].elevation),
+            ("Vals", "CH", 1252)
+        );
+        assert_eq!(
+            (g[1].name.as_str(), g[1].country.as_str(), g[1].elevation),
+            ("Vrin", "CH", 1004)
+        );
+    }
+
+    #[test]
+    fn add_location_without_dem() {
+        let geodata = Geodata::load(&crate::geodata::GeodataConfig {
+            countries: Some("testdata/geodata/countries.geojson".into()),
+            ..Default::default()
+        })
+        .unwrap();
+        let form = |elevation: Option<i32>| LocationAddUpdateForm {
+            name: "Vrin".into(),
+            country_code: None,
+            elevation,
+            coordinates: Some(ApiCoordinates { lat: 46.5, lon: 9.5 }),
+            match_radius: None,
+            area: None,
+        };
+
+        // Without DEM, the elevation must be specified
+        let Err((status, Json(error))) = form(None).into_fields(&geodata) else {
+            panic!("Expected an error");
+        };
+        assert_eq!(status, Status::BadRequest);
+        assert_eq!(
+            error.error.description,
+            "Elevation is missing and no elevation data is configured"
+        );
+
+        // The country is still looked up
+        let fields = form(Some(1448)).into_fields(&geodata).unwrap();
+        assert_eq!((fields.country.as_str(), fields.elevation), ("CH", 1448));
+    }
+
+    #[test]
+    fn location_area() {
+        let ctx = DbTestContext::new();
+        let client = make_client();
+
+        macro_rules! post_location {
+            ($url:expr, $body:expr) => {
+                client
+                    .post($url)
+                    .header(ContentType::JSON)
+                    .body($body)
+                    .private_cookie(ctx.auth_cookie_user1())
+                    .cookie(ctx.username_cookie())
+                    .dispatch()
+            };
+        }
+        let area = r#"{"type": "Polygon", "coordinates": [[[9.15, 46.7], [9.16, 46.7], [9.16, 46.71], [9.15, 46.71], [9.15, 46.7]]]}"#;
+
+        // Invalid areas
+        for invalid in [
+            r#"{"type": "Point", "coordinates": [9.15, 46.7]}"#,
+            r#"{"type": "Polygon", "coordinates": [[[9.15, 46.7], [9.16, 46.7], [9.15, 46.7]]]}"#,
+            r#"{"type": "Polygon", "coordinates": [[[9.15, 46.7], [9.16, 46.7], [9.16, 46.71], [9.15, 46.71]]]}"#,
+            r#"{"type": "Polygon", "coordinates": [[[9.15, 96.7], [9.16, 46.7], [9.16, 46.71], [9.15, 96.7]]]}"#,
+        ] {
+            let body = format!(
+                r#"{{"name": "Field", "countryCode": "CH", "elevation": 1300, "area": {}}}"#,
+                invalid
+            );
+            let resp = post_location!("/locations", body);
+            assert_eq!(resp.status(), Status::BadRequest, "{}", invalid);
+        }
+
+        // Coordinates, country and elevation are derived from the area
+        let resp = post_location!("/locations", format!(r#"{{"name": "Field", "area": {}}}"#, area));
+        assert_eq!(resp.status(), Status::Created);
+        let location: Value = resp.into_json().unwrap();
+        assert_eq!(location["countryCode"], "CH");
+        assert_eq!(location["elevation"], 1003);
+        assert_eq!(location["area"]["type"], "Polygon");
+        assert_eq!(location["area"]["coordinates"][0][2], json!([9.16, 46.71]));
+        let (lat, lon) = (
+            location["coordinates"]["lat"].as_f64().unwrap(),
+            location["coordinates"]["lon"].as_f64().unwrap(),
+        );
+        assert!(
+            (lat - 46.705).abs() < 0.001 && (lon - 9.155).abs() < 0.001,
+            "{}/{}",
+            lat,
+            lon
+        );
+
+        // Areas can be passed as feature and removed again
+        let id = location["id"].as_i64().unwrap();
+        let resp = post_location!(
+            format!("/locations/{}", id),
+            format!(
+                r#"{{"name": "Field", "countryCode": "CH", "elevation": 1300, "area": {{"type": "Feature", "properties": {{}}, "geometry": {}}}}}"#,
+                area
+            )
+        );
+        assert_eq!(resp.status(), Status::NoContent);
+        let location =
+            data::get_location_with_flight_count_by_id(&mut ctx.force_get_conn(), id as i32).unwrap();
+        assert!(location.area.is_some());
+        assert!(location.geog.is_some());
+        let resp = post_location!(
+            format!("/locations/{}", id),
+            r#"{"name": "Field", "countryCode": "CH", "elevation": 1300}"#
+        );
+        assert_eq!(resp.status(), Status::NoContent);
+        let location =
+            data::get_location_with_flight_count_by_id(&mut ctx.force_get_conn(), id as i32).unwrap();
+        assert_eq!(location.area, None);
+        assert_eq!(location.geog, None);
+    }
+
+    #[test]
+    fn lookup_location() {
+        let ctx = DbTestContext::new();
+        let client = make_client();
+
+        let resp = client.get("/locations/lookup?lat=46.5&lon=9.5").dispatch();
+        assert_eq!(resp.status(), Status::Unauthorized);
+
+        let resp = client
+            .get("/locations/lookup?lat=46.5&lon=9.5")
+            .private_cookie(ctx.auth_cookie_user1())
+            .cookie(ctx.username_cookie())
+            .dispatch();
+        assert_eq!(resp.status(), Status::Ok);
+        assert_eq!(
+            resp.into_string().unwrap(),
+            r#"{"countryCode":"CH","elevation":1004}"#
+        );
+
+        // Nothing known about this point
+        let resp = client
+            .get("/locations/lookup?lat=0&lon=0")
+            .private_cookie(ctx.auth_cookie_user1())
+            .cookie(ctx.username_cookie())
+            .dispatch();
+        assert_eq!(resp.status(), Status::Ok);
+        assert_eq!(resp.into_string().unwrap(), "{}");
+    }
+
     #[test]
     fn edit_location() {
         let ctx = DbTestContext::new();
@@ -491,6 +1213,8 @@ mod tests {
                     y: -13.163235172208347,
                     srid: None,
                 }),
+                match_radius: None,
+                area: None,
             },
         );
 
@@ -549,6 +1273,8 @@ mod tests {
                     y: -13.163235172208347,
                     srid: None,
                 }),
+                match_radius: None,
+                area: None,
             },
         );
         let location2 = data::create_location(
@@ -559,6 +1285,8 @@ mod tests {
                 elevation: 5822,
                 user_id: ctx.testuser1.user.id,
                 geog: None,
+                match_radius: None,
+                area: None,
             },
         );
 
@@ -592,4 +1320,319 @@ mod tests {
         let resp = delete_location!(location2.id + 9999, ctx.auth_cookie_user1());
         assert_eq!(resp.status(), Status::NotFound);
     }
+
+    #[test]
+    fn merge_locations() {
+        let ctx = DbTestContext::new();
+        let client = make_client();
+
+        macro_rules! merge {
+            ($id:expr, $body:expr, $cookie:expr) => {
+                client
+                    .post(format!("/locations/{}/merge", $id))
+                    .header(ContentType::JSON)
+                    .body($body)
+                    .private_cookie($cookie)
+                    .cookie(ctx.username_cookie())
+                    .dispatch()
+            };
+        }
+
+        // Add locations and flights
+        let create = |name: &str, user_id: i32| {
+            data::create_location(
+                &mut ctx.force_get_conn(),
+                NewLocation {
+                    name: name.into(),
+                    country: "CH".into(),
+                    elevation: 1000,
+                    user_id,
+                    geog: None,
+                    match_radius: None,
+                    area: None,
+                },
+            )
+        };
+        let target = create("Fiesch", ctx.testuser1.user.id);
+        let source1 = create("Fiesch Kühboden", ctx.testuser1.user.id);
+        let source2 = create("Kühboden", ctx.testuser1.user.id);
+        let foreign = create("Fiesch", ctx.testuser2.user.id);
+        let flight = data::create_flight(
+            &mut ctx.force_get_conn(),
+            &NewFlight {
+                number: Some(1),
+                user_id: ctx.testuser1.user.id,
+                launch_at: Some(source1.id),
+                landing_at: Some(source2.id),
+                ..Default::default()
+            },
+            None,
+        );
+
+        // Invalid requests
+        let body = format!(r#"{{"sourceIds": [{}, {}]}}"#, source1.id, source2.id);
+        let resp = merge!(target.id, r#"{"sourceIds": []}"#, ctx.auth_cookie_user1());
+        assert_eq!(resp.status(), Status::BadRequest);
+        let resp = merge!(
+            target.id,
+            format!(r#"{{"sourceIds": [{}]}}"#, target.id),
+            ctx.auth_cookie_user1()
+        );
+        assert_eq!(resp.status(), Status::BadRequest);
+        let resp = merge!(
+            target.id,
+            format!(r#"{{"sourceIds": [{}]}}"#, foreign.id),
+            ctx.auth_cookie_user1()
+        );
+        assert_eq!(resp.status(), Status::Forbidden);
+        let resp = merge!(target.id, body.clone(), ctx.auth_cookie_user2());
+        assert_eq!(resp.status(), Status::Forbidden);
+        let resp = merge!(target.id, r#"{"sourceIds": [9999]}"#, ctx.auth_cookie_user1());
+        assert_eq!(resp.status(), Status::NotFound);
+        let resp = client
+            .post(format!("/locations/{}/merge", target.id))
+            .header(ContentType::JSON)
+            .body(body.clone())
+            .dispatch();
+        assert_eq!(resp.status(), Status::Unauthorized);
+
+        // Merge
+        let resp = merge!(target.id, body, ctx.auth_cookie_user1());
+        assert_eq!(resp.status(), Status::NoContent);
+        let conn = &mut *ctx.force_get_conn();
+        let flight = data::get_flight_with_id(conn, flight.id).unwrap();
+        assert_eq!(flight.launch_at, Some(target.id));
+        assert_eq!(flight.landing_at, Some(target.id));
+        let locations = data::get_locations_for_user(conn, &ctx.testuser1.user);
+        assert_eq!(locations.len(), 1);
+        assert_eq!(locations[0].id, target.id);
+    }
+
+    #[test]
+    fn location_stats() {
+        let ctx = DbTestContext::new();
+        let client = make_client();
+
+        macro_rules! get_stats {
+            ($id:expr, $cookie:expr) => {
+                client
+                    .get(format!("/locations/{}/stats", $id))
+                    .private_cookie($cookie)
+                    .cookie(ctx.username_cookie())
+                    .dispatch()
+            };
+        }
+
+        // Add locations, glider and flights
+        let create = |name: &str| {
+            data::create_location(
+                &mut ctx.force_get_conn(),
+                NewLocation {
+                    name: name.into(),
+                    country: "CH".into(),
+                    elevation: 1000,
+                    user_id: ctx.testuser1.user.id,
+                    geog: None,
+                    match_radius: None,
+                    area: None,
+                },
+            )
+            .id
+        };
+        let fiesch = create("Fiesch");
+        let field = create("Field");
+        let lake = create("Lake");
+        let glider = data::create_glider(
+            &mut ctx.force_get_conn(),
+            NewGlider {
+                user_id: ctx.testuser1.user.id,
+                manufacturer: "Advance".into(),
+                model: "Epsilon 8".into(),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        for (launch_at, landing_at, glider_id, times) in [
+            (
+                fiesch,
+                Some(field),
+                Some(glider.id),
+                Some(((2024, 7, 3, 10, 0), (2024, 7, 3, 11, 30))),
+            ),
+            (
+                fiesch,
+                Some(field),
+                Some(glider.id),
+                Some(((2024, 7, 3, 12, 0), (2024, 7, 3, 12, 30))),
+            ),
+            (
+                fiesch,
+                Some(lake),
+                None,
+                Some(((2023, 5, 1, 10, 0), (2023, 5, 1, 10, 20))),
+            ),
+            (
+                field,
+                Some(fiesch),
+                Some(glider.id),
+                Some(((2025, 8, 2, 14, 0), (2025, 8, 2, 15, 0))),
+            ),
+            (fiesch, None, None, None),
+        ] {
+            let time = |(y, m, d, h, min)| utc_datetime(y, m, d, h, min, 0);
+            data::create_flight(
+                &mut ctx.force_get_conn(),
+                &NewFlight {
+                    user_id: ctx.testuser1.user.id,
+                    glider_id,
+                    launch_at: Some(launch_at),
+                    landing_at,
+                    launch_time: times.map(|(launch, _)| time(launch)),
+                    landing_time: times.map(|(_, landing)| time(landing)),
+                    ..Default::default()
+                },
+                None,
+            );
+        }
+
+        // Invalid requests
+        let resp = get_stats!(fiesch, ctx.auth_cookie_user2());
+        assert_eq!(resp.status(), Status::Forbidden);
+        let resp = get_stats!(9999, ctx.auth_cookie_user1());
+        assert_eq!(resp.status(), Status::NotFound);
+        let resp = client.get(format!("/locations/{}/stats", fiesch)).dispatch();
+        assert_eq!(resp.status(), Status::Unauthorized);
+
+        // Launch stats
+        let resp = get_stats!(fiesch, ctx.auth_cookie_user1());
+        assert_eq!(resp.status(), Status::Ok);
+        let stats: Value = resp.into_json().unwrap();
+        assert_eq!(stats["location"]["name"], "Fiesch");
+        assert_eq!(stats["location"]["flightCount"], 5);
+        assert_eq!(stats["launchCount"], 4);
+        assert_eq!(stats["landingCount"], 1);
+        assert_eq!(stats["launchFlightSeconds"], 8400);
+        assert_eq!(stats["averageFlightSeconds"], 2800);
+        assert_eq!(stats["longestFlight"]["flightSeconds"], 5400);
+        assert_eq!(stats["longestFlight"]["launchTime"], "2024-07-03T10:00:00Z");
+        assert_eq!(
+            stats["bestMonth"],
+            json!({"month": 7, "flightCount": 2, "flightSeconds": 7200})
+        );
+        assert_eq!(
+            stats["gliders"],
+            json!([{"id": glider.id, "name": "Advance Epsilon 8", "flightCount": 3}])
+        );
+        assert_eq!(stats["firstVisit"], "2023-05-01T10:00:00Z");
+        assert_eq!(stats["lastVisit"], "2025-08-02T14:00:00Z");
+        assert_eq!(
+            stats["landingLocations"],
+            json!([
+                {"id": field, "name": "Field", "flightCount": 2},
+                {"id": lake, "name": "Lake", "flightCount": 1},
+            ])
+        );
+
+        // Location without launches
+        let resp = get_stats!(lake, ctx.auth_cookie_user1());
+        let stats: Value = resp.into_json().unwrap();
+        assert_eq!(stats["launchCount"], 0);
+        assert_eq!(stats["landingCount"], 1);
+        assert_eq!(stats["launchFlightSeconds"], 0);
+        assert_eq!(stats["averageFlightSeconds"], Value::Null);
+        assert_eq!(stats["longestFlight"], Value::Null);
+        assert_eq!(stats["bestMonth"], Value::Null);
+        assert_eq!(stats["landingLocations"], json!([]));
+    }
+
+    #[test]
+    fn suggest_duplicates() {
+        let ctx = DbTestContext::new();
+        let client = make_client();
+
+        let create = |name: &str, lon: f64, lat: f64| {
+            data::create_location(
+                &mut ctx.force_get_conn(),
+                NewLocation {
+                    name: name.into(),
+                    country: "CH".into(),
+                    elevation: 1000,
+                    user_id: ctx.testuser1.user.id,
+                    geog: Some(GeogPoint {
+                        x: lon,
+                        y: lat,
+                        srid: None,
+                    }),
+                    match_radius: None,
+                    area: None,
+                },
+            )
+        };
+        let kuehboden1 = create("Fiesch Kühboden", 8.1206, 46.4160);
+        let kuehboden2 = create("Kühboden", 8.1210, 46.4162);
+        create("Landeplatz Fiesch", 8.1367, 46.4046);
+        let emmetten1 = create("Emmetten", 8.5166, 46.9565);
+        let emmetten2 = create("emmeten", 8.5400, 46.9700);
+
+        let get = |query: &str| {
+            client
+                .get(format!("/locations/duplicates{}", query))
+                .private_cookie(ctx.auth_cookie_user1())
+                .cookie(ctx.username_cookie())
+                .dispatch()
+                .into_json::<rocket::serde::json::Value>()
+                .unwrap()
+        };
+        let ids = |result: &rocket::serde::json::Value| {
+            result["duplicates"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|duplicate| {
+                    (
+                        duplicate["locations"][0]["id"].as_i64().unwrap() as i32,
+                        duplicate["locations"][1]["id"].as_i64().unwrap() as i32,
+                        duplicate["similarName"].as_bool().unwrap(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Close to each other or similar names, ordered by distance
+        let result = get("");
+        assert_eq!(
+            ids(&result),
+            vec![
+                (kuehboden1.id, kuehboden2.id, false),
+                (emmetten1.id, emmetten2.id, true)
+            ]
+        );
+        let distance = result["duplicates"][0]["distance"].as_f64().unwrap();
+        assert!((30.0..45.0).contains(&distance), "distance {}", distance);
+
+        // Smaller radius
+        assert_eq!(
+            ids(&get("?distance=10")),
+            vec![(emmetten1.id, emmetten2.id, true)]
+        );
+
+        // Without login
+        let resp = client.get("/locations/duplicates").dispatch();
+        assert_eq!(resp.status(), Status::Unauthorized);
+    }
+
+    #[test]
+    fn name_similarity() {
+        assert!(similar_names("Fiesch", "fiesch"));
+        assert!(similar_names("Fiesch-Kühboden", "Fiesch Kühboden"));
+        assert!(similar_names("Emmetten", "Emmeten"));
+        assert!(similar_names("Niederrickenbach", "Niederrikenbach"));
+        assert!(!similar_names("Fiesch", "Fiesch Landeplatz"));
+        assert!(!similar_names("Amden", "Arden"));
+        assert!(!similar_names("", ""));
+        assert_eq!(
+            edit_distance(&normalize_name("kitten"), &normalize_name("sitting")),
+            3
+        );
+    }
 }
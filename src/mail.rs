@@ -0,0 +1,194 @@
+//! Outgoing e-mail.
+//!
+//! Mails are sent through a `MailSender`. Which sender is used is configured
+//! in the `mail` section of the Rocket config:
+//!
+//! - `smtp`: Send mails through an SMTP server (with STARTTLS)
+//! - `file`: Write mails to files in a directory (for local development)
+//! - `log`: Write mails to the log (default)
+
+use std::{
+    fs,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+};
+
+use anyhow::{anyhow, Context, Result};
+use chrono::Utc;
+use lettre::{
+    message::{header::ContentType, Mailbox},
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use serde::Deserialize;
+
+const DEFAULT_FROM: &str = "Flugbuech <noreply@localhost>";
+
+// Config
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MailTransport {
+    Smtp,
+    File,
+    #[default]
+    Log,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct MailConfig {
+    /// Which transport to use for sending mails.
+    pub transport: MailTransport,
+    /// Sender address, e.g. `Flugbuech <noreply@flugbue.ch>`.
+    pub from: Option<String>,
+    /// SMTP server hostname.
+    pub smtp_host: Option<String>,
+    /// SMTP server port. Defaults to the submission port (587).
+    pub smtp_port: Option<u16>,
+    /// SMTP username.
+    pub smtp_username: Option<String>,
+    /// SMTP password.
+    pub smtp_password: Option<String>,
+    /// Target directory of the file transport.
+    pub file_dir: Option<PathBuf>,
+}
+
+// Mail senders
+
+/// A plaintext e-mail.
+#[derive(Debug, Clone)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// A transport that can send mails.
+#[rocket::async_trait]
+pub trait MailSender: Send + Sync {
+    async fn send(&self, from: &str, mail: Mail) -> Result<()>;
+}
+
+/// Send mails through an SMTP server.
+pub struct SmtpSender(AsyncSmtpTransport<Tokio1Executor>);
+
+#[rocket::async_trait]
+impl MailSender for SmtpSender {
+    async fn send(&self, from: &str, mail: Mail) -> Result<()> {
+        let message = Message::builder()
+            .from(from.parse::<Mailbox>().context("Invalid sender address")?)
+            .to(mail.to.parse::<Mailbox>().context("Invalid recipient address")?)
+            .subject(mail.subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(mail.body)
+            .context("Could not build message")?;
+        self.0.send(message).await.context("Could not send mail")?;
+        Ok(())
+    }
+}
+
+/// Write mails to files in a directory, one file per mail.
+pub struct FileSender {
+    dir: PathBuf,
+    counter: AtomicU32,
+}
+
+#[rocket::async_trait]
+impl MailSender for FileSender {
+    async fn send(&self, from: &str, mail: Mail) -> Result<()> {
+        let filename = format!(
+            "{}-{}.txt",
+            Utc::now().format("%Y%m%dT%H%M%S%.f"),
+            self.counter.fetch_add(1, Ordering::Relaxed)
+        );
+        let contents = format!(
+            "From: {}\nTo: {}\nSubject: {}\n\n{}\n",
+            from, mail.to, mail.subject, mail.body
+        );
+        fs::write(self.dir.join(filename), contents).context("Could not write mail to file")?;
+        Ok(())
+    }
+}
+
+/// Write mails to the log.
+pub struct LogSender;
+
+#[rocket::async_trait]
+impl MailSender for LogSender {
+    async fn send(&self, from: &str, mail: Mail) -> Result<()> {
+        log::info!(
+            "Sending mail\nFrom: {}\nTo: {}\nSubject: {}\n\n{}",
+            from,
+            mail.to,
+            mail.subject,
+            mail.body
+        );
+        Ok(())
+    }
+}
+
+// Mailer
+
+/// Managed state used to send mails.
+///
+/// Clones share the same sender, so a mailer can be moved into a spawned task.
+#[derive(Clone)]
+pub struct Mailer {
+    from: String,
+    sender: Arc<dyn MailSender>,
+}
+
+impl Mailer {
+    pub fn new(from: impl Into<String>, sender: Box<dyn MailSender>) -> Self {
+        Self {
+            from: from.into(),
+            sender: Arc::from(sender),
+        }
+    }
+
+    /// Create a mailer from the config.
+    pub fn from_config(config: &MailConfig) -> Result<Self> {
+        let from = config.from.clone().unwrap_or_else(|| DEFAULT_FROM.to_string());
+        from.parse::<Mailbox>()
+            .with_context(|| format!("Invalid mail sender address: {}", from))?;
+        let sender: Box<dyn MailSender> = match config.transport {
+            MailTransport::Smtp => {
+                let host = config
+                    .smtp_host
+                    .as_deref()
+                    .ok_or_else(|| anyhow!("SMTP transport requires mail.smtp_host"))?;
+                let mut builder = AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)
+                    .context("Could not create SMTP transport")?;
+                if let Some(port) = config.smtp_port {
+                    builder = builder.port(port);
+                }
+                if let (Some(username), Some(password)) = (&config.smtp_username, &config.smtp_password) {
+                    builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+                }
+                Box::new(SmtpSender(builder.build()))
+            }
+            MailTransport::File => {
+                let dir = config
+                    .file_dir
+                    .clone()
+                    .ok_or_else(|| anyhow!("File transport requires mail.file_dir"))?;
+                fs::create_dir_all(&dir).context("Could not create mail directory")?;
+                Box::new(FileSender {
+                    dir,
+                    counter: AtomicU32::new(0),
+                })
+            }
+            MailTransport::Log => Box::new(LogSender),
+        };
+        Ok(Self::new(from, sender))
+    }
+
+    /// Send a mail.
+    pub async fn send(&self, mail: Mail) -> Result<()> {
+        self.sender.send(&self.from, mail).await
+    }
+}
@@ -4,33 +4,62 @@
 extern crate diesel;
 extern crate diesel_migrations;
 
+mod account;
+mod admin;
+mod airspace;
 mod auth;
 mod cors;
+mod currency;
 mod data;
+mod equipment;
 mod flights;
+mod geodata;
 mod gliders;
 mod import_csv;
 mod locations;
+mod mail;
+mod maintenance;
 mod models;
+mod oidc;
+mod passwords;
+mod polygon;
 mod process_igc;
 mod profile;
+mod rate_limit;
 mod responders;
 mod schema;
+mod sessions;
+mod sites;
 mod stats;
 #[cfg(test)]
 mod test_utils;
+mod tokens;
+mod totp;
+mod waypoints;
 mod xcontest;
 
+use std::path::Path;
+
 use anyhow::{Context, Result};
 use clap::{Arg, ArgAction, Command};
-use rocket::{catch, catchers, get, request::Request, routes};
+use rocket::{catch, catchers, get, http::Status, request::Request, routes, serde::json::Json};
 use serde::Deserialize;
 
+use crate::{
+    airspace::AirspaceConfig,
+    geodata::GeodataConfig,
+    mail::{MailConfig, MailTransport},
+    oidc::OidcConfig,
+    rate_limit::RateLimitConfig,
+    responders::RocketError,
+};
+
 // Limits
 //
 // Note: Other limits are configured in Rocket.toml!
 pub const MAX_IGC_UPLOAD_BYTES: u64 = 50 * 1024 * 1024;
 pub const MAX_CSV_UPLOAD_BYTES: u64 = 10 * 1024 * 1024;
+pub const MAX_WAYPOINT_UPLOAD_BYTES: u64 = 10 * 1024 * 1024;
 
 pub const NAME: &str = "flugbuech-api";
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -45,6 +74,44 @@ pub struct Config {
     /// - Set to `*` to allow CORS requests from all origins
     /// - Set to a specific origin to allow CORS requests from that origin
     pub cors_allow_origin: Option<String>,
+
+    /// Public base URL of the web frontend (e.g. `https://flugbue.ch`), used
+    /// for links in e-mails. Required if mails are sent through SMTP.
+    pub base_url: Option<String>,
+
+    /// Outgoing e-mail.
+    #[serde(default)]
+    pub mail: MailConfig,
+
+    /// Login rate limiting.
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+
+    /// Login through an external OpenID Connect provider (optional).
+    pub oidc: Option<OidcConfig>,
+
+    /// Offline geographic datasets (optional).
+    #[serde(default)]
+    pub geodata: GeodataConfig,
+
+    /// OpenAir airspace files for infringement checks (optional).
+    #[serde(default)]
+    pub airspace: AirspaceConfig,
+}
+
+impl Config {
+    /// Return the base URL without trailing slash.
+    pub fn base_url(&self) -> &str {
+        self.base_url.as_deref().unwrap_or("").trim_end_matches('/')
+    }
+
+    /// Ensure that the config values are consistent.
+    pub fn validate(&self) -> Result<()> {
+        if self.mail.transport == MailTransport::Smtp && self.base_url().is_empty() {
+            anyhow::bail!("The SMTP mail transport requires base_url to be set");
+        }
+        Ok(())
+    }
 }
 
 // Index
@@ -61,6 +128,28 @@ fn service_not_available(_req: &Request) -> &'static str {
     "Service is not available. (Is the database up?)"
 }
 
+// Handle insufficient API token scopes
+
+#[catch(403)]
+fn forbidden(_req: &Request) -> (Status, Json<RocketError>) {
+    RocketError::new(
+        Status::Forbidden,
+        "Forbidden",
+        "Access to this resource is not permitted",
+    )
+}
+
+// Handle rate limited logins and password reset requests
+
+#[catch(429)]
+fn too_many_requests(_req: &Request) -> (Status, Json<RocketError>) {
+    RocketError::new(
+        Status::TooManyRequests,
+        "TooManyRequests",
+        "Too many attempts, please try again later",
+    )
+}
+
 // Main
 
 #[rocket::main]
@@ -78,6 +167,12 @@ async fn main() -> Result<()> {
                 .action(ArgAction::SetTrue)
                 .help("Run database migrations before starting"),
         )
+        .arg(
+            Arg::new("grant-admin")
+                .long("grant-admin")
+                .value_name("USERNAME")
+                .help("Grant the admin role to the specified user and exit"),
+        )
         .get_matches();
 
     // Decide whether migrations should be run
@@ -87,20 +182,48 @@ async fn main() -> Result<()> {
         data::run_migrations().unwrap();
     }
 
+    // Grant admin role if requested
+    if let Some(username) = args.get_one::<String>("grant-admin") {
+        data::grant_admin(username).map_err(|e| anyhow::anyhow!(e))?;
+        println!("Granted admin role to {}", username);
+        return Ok(());
+    }
+
     // Initialize application
     let app = rocket::build();
 
     // Extract additional config values
     let figment = app.figment();
     let config: Config = figment.extract().context("Could not extract config")?;
+    config.validate().context("Invalid config")?;
 
     // Attach fairings
     let app = app
         .attach(data::Database::fairing())
-        .attach(cors::Cors::from_config(config.cors_allow_origin.as_deref()));
+        .attach(cors::Cors::from_config(config.cors_allow_origin.as_deref()))
+        .attach(rate_limit::LoginRateLimiter::new(config.rate_limit.clone()));
+
+    // Manage state
+    let mailer = mail::Mailer::from_config(&config.mail).context("Could not initialize mailer")?;
+    let oidc_provider = oidc::OidcProvider::new(config.oidc.clone());
+    let geodata_config = config
+        .geodata
+        .clone()
+        .with_defaults_from(Path::new(geodata::DEFAULT_DIR));
+    let geodata = geodata::Geodata::load(&geodata_config).context("Could not load geodata")?;
+    let airspaces = airspace::Airspaces::load(&config.airspace).context("Could not load airspaces")?;
+    let app = app
+        .manage(mailer)
+        .manage(oidc_provider)
+        .manage(geodata)
+        .manage(airspaces)
+        .manage(config);
 
     // Register custom error catchers
-    let app = app.register("/", catchers![service_not_available]);
+    let app = app.register(
+        "/",
+        catchers![service_not_available, forbidden, too_many_requests],
+    );
 
     // Attach routes
     let app = app
@@ -110,13 +233,24 @@ async fn main() -> Result<()> {
             [
                 routes![index],
                 auth::api_routes(),
+                sessions::api_routes(),
+                tokens::api_routes(),
+                totp::api_routes(),
+                oidc::api_routes(),
                 profile::api_routes(),
+                account::api_routes(),
+                admin::api_routes(),
                 stats::api_routes(),
                 locations::api_routes(),
+                sites::api_routes(),
+                waypoints::api_routes(),
                 gliders::api_routes(),
+                equipment::api_routes(),
+                maintenance::api_routes(),
                 flights::api_routes(),
                 process_igc::api_routes(),
                 import_csv::api_routes(),
+                currency::api_routes(),
             ]
             .concat(),
         );
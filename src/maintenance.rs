@@ -0,0 +1,629 @@
+//! Glider maintenance.
+//!
+//! Maintenance events (e.g. a check or a trim) are recorded per glider.
+//! Maintenance intervals define how much airtime, how many flights or how
+//! much calendar time may pass between two events of the same kind.
+
+use std::collections::HashMap;
+
+use chrono::{Months, NaiveDate, Utc};
+use diesel::{
+    result::{DatabaseErrorKind, Error as DieselError},
+    PgConnection,
+};
+use rocket::{delete, get, http::Status, post, routes, serde::json::Json, Route};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth, data,
+    data::GliderUsage,
+    models::{
+        GliderMaintenanceEvent, GliderMaintenanceInterval, NewGliderMaintenanceEvent,
+        NewGliderMaintenanceInterval, User,
+    },
+    responders::{ApiError, RocketError},
+};
+
+/// Valid maintenance kinds.
+pub const MAINTENANCE_KINDS: [&str; 3] = ["check", "trim", "reserve_repack"];
+
+/// Maintenance is due soon if the due date is less than this many days away.
+const DUE_SOON_DAYS: i64 = 30;
+
+/// Maintenance is due soon if less than this percentage of the airtime or
+/// flight interval is remaining.
+const DUE_SOON_PERCENT: i64 = 10;
+
+// Status
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum MaintenanceState {
+    Ok,
+    DueSoon,
+    Overdue,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiMaintenanceStatus {
+    pub interval_id: i32,
+    pub kind: String,
+    pub state: MaintenanceState,
+    /// Date of the last maintenance event of this kind
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_performed: Option<NaiveDate>,
+    /// Calendar date on which the maintenance is due
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub due_date: Option<NaiveDate>,
+    /// Airtime until the maintenance is due (negative if overdue)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remaining_seconds: Option<i64>,
+    /// Number of flights until the maintenance is due (negative if overdue)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remaining_flights: Option<i64>,
+}
+
+/// Return the maintenance state for a calendar based due date.
+pub fn date_state(due_date: Option<NaiveDate>, today: NaiveDate) -> MaintenanceState {
+    match due_date {
+        Some(due_date) if due_date <= today => MaintenanceState::Overdue,
+        Some(due_date) if (due_date - today).num_days() <= DUE_SOON_DAYS => MaintenanceState::DueSoon,
+        _ => MaintenanceState::Ok,
+    }
+}
+
+/// Evaluate a maintenance interval.
+///
+/// The calendar interval is counted from `last_performed`, or from
+/// `glider_since` if the maintenance was never performed. The `usage` must
+/// contain the flights since the last maintenance.
+fn evaluate_interval(
+    interval: &GliderMaintenanceInterval,
+    last_performed: Option<NaiveDate>,
+    glider_since: Option<NaiveDate>,
+    usage: GliderUsage,
+    today: NaiveDate,
+) -> ApiMaintenanceStatus {
+    let due_date = interval.interval_months.and_then(|months| {
+        last_performed
+            .or(glider_since)
+            .and_then(|date| date.checked_add_months(Months::new(months as u32)))
+    });
+    let remaining_seconds = interval
+        .interval_seconds
+        .map(|seconds| i64::from(seconds) - usage.seconds);
+    let remaining_flights = interval
+        .interval_flights
+        .map(|flights| i64::from(flights) - usage.flights);
+
+    // Determine state per limit, the most severe state wins
+    let usage_state = |remaining: Option<i64>, total: Option<i32>| match (remaining, total) {
+        (Some(remaining), _) if remaining <= 0 => MaintenanceState::Overdue,
+        (Some(remaining), Some(total)) if remaining * 100 <= i64::from(total) * DUE_SOON_PERCENT => {
+            MaintenanceState::DueSoon
+        }
+        _ => MaintenanceState::Ok,
+    };
+    let state = date_state(due_date, today)
+        .max(usage_state(remaining_seconds, interval.interval_seconds))
+        .max(usage_state(remaining_flights, interval.interval_flights));
+
+    ApiMaintenanceStatus {
+        interval_id: interval.id,
+        kind: interval.kind.clone(),
+        state,
+        last_performed,
+        due_date,
+        remaining_seconds,
+        remaining_flights,
+    }
+}
+
+/// Return the maintenance status of all gliders of a user, indexed by glider ID.
+pub fn get_maintenance_status_for_user(
+    conn: &mut PgConnection,
+    user: &User,
+    today: NaiveDate,
+) -> HashMap<i32, Vec<ApiMaintenanceStatus>> {
+    let glider_since = data::get_gliders_for_user(conn, user)
+        .into_iter()
+        .map(|glider| (glider.id, glider.since))
+        .collect::<HashMap<_, _>>();
+    let events = data::get_maintenance_events_for_user(conn, user);
+    let usage = data::get_maintenance_usage_for_user(conn, user)
+        .into_iter()
+        .map(|usage| (usage.interval_id, usage.usage))
+        .collect::<HashMap<_, _>>();
+
+    let mut status: HashMap<i32, Vec<ApiMaintenanceStatus>> = HashMap::new();
+    for interval in data::get_maintenance_intervals_for_user(conn, user) {
+        // Events are sorted by date, most recent first
+        let last_performed = events
+            .iter()
+            .find(|event| event.glider_id == interval.glider_id && event.kind == interval.kind)
+            .map(|event| event.date);
+        status
+            .entry(interval.glider_id)
+            .or_default()
+            .push(evaluate_interval(
+                &interval,
+                last_performed,
+                glider_since.get(&interval.glider_id).copied().flatten(),
+                usage.get(&interval.id).copied().unwrap_or_default(),
+                today,
+            ));
+    }
+    status
+}
+
+// API types
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiGliderMaintenance {
+    /// Maintenance events, most recent event first
+    events: Vec<GliderMaintenanceEvent>,
+    intervals: Vec<GliderMaintenanceInterval>,
+    /// Status of all configured maintenance intervals
+    status: Vec<ApiMaintenanceStatus>,
+}
+
+// Forms
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct MaintenanceEventCreateForm {
+    kind: String,
+    date: String, // ISO date (e.g. 2010-11-30)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    notes: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct MaintenanceIntervalCreateUpdateForm {
+    kind: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    interval_seconds: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    interval_flights: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    interval_months: Option<i32>,
+}
+
+impl MaintenanceIntervalCreateUpdateForm {
+    /// Validate the form and convert it to a `NewGliderMaintenanceInterval` model.
+    ///
+    /// If validation fails, return error message.
+    fn into_new_interval(self, glider_id: i32) -> Result<NewGliderMaintenanceInterval, String> {
+        if !MAINTENANCE_KINDS.contains(&self.kind.as_str()) {
+            return Err(format!("Invalid maintenance kind: {}", self.kind));
+        }
+        let limits = [self.interval_seconds, self.interval_flights, self.interval_months];
+        if limits.iter().all(Option::is_none) {
+            return Err("At least one interval must be specified".into());
+        }
+        if limits.iter().flatten().any(|limit| *limit < 1) {
+            return Err("Intervals must be positive".into());
+        }
+        Ok(NewGliderMaintenanceInterval {
+            glider_id,
+            kind: self.kind,
+            interval_seconds: self.interval_seconds,
+            interval_flights: self.interval_flights,
+            interval_months: self.interval_months,
+        })
+    }
+}
+
+/// Ensure that the glider with the specified ID exists and belongs to the user.
+async fn check_glider_ownership(
+    database: &data::Database,
+    user: &User,
+    glider_id: i32,
+) -> Result<(), ApiError> {
+    match database
+        .run(move |db| data::get_glider_by_id(db, glider_id))
+        .await
+    {
+        Some(glider) if glider.user_id == user.id => Ok(()),
+        _ => Err(ApiError::NotFound),
+    }
+}
+
+fn conflict_error() -> (Status, Json<RocketError>) {
+    RocketError::new(
+        Status::Conflict,
+        "Conflict",
+        "An interval for this kind of maintenance already exists.",
+    )
+}
+
+fn internal_error() -> (Status, Json<RocketError>) {
+    RocketError::new(
+        Status::InternalServerError,
+        "InternalServerError",
+        "Internal server error",
+    )
+}
+
+// API endpoints
+
+#[get("/gliders/<id>/maintenance")]
+pub async fn list(
+    database: data::Database,
+    user: auth::AuthUser,
+    id: i32,
+) -> Result<Json<ApiGliderMaintenance>, ApiError> {
+    let user = user.into_inner();
+    check_glider_ownership(&database, &user, id).await?;
+
+    let today = Utc::now().date_naive();
+    let (events, intervals, mut status) = database
+        .run(move |db| {
+            (
+                data::get_maintenance_events_for_user(db, &user),
+                data::get_maintenance_intervals_for_user(db, &user),
+                get_maintenance_status_for_user(db, &user, today),
+            )
+        })
+        .await;
+    Ok(Json(ApiGliderMaintenance {
+        events: events.into_iter().filter(|event| event.glider_id == id).collect(),
+        intervals: intervals
+            .into_iter()
+            .filter(|interval| interval.glider_id == id)
+            .collect(),
+        status: status.remove(&id).unwrap_or_default(),
+    }))
+}
+
+#[get("/gliders/<id>/maintenance", rank = 2)]
+#[allow(unused_variables)]
+pub fn list_nologin(id: i32) -> ApiError {
+    ApiError::MissingAuthentication
+}
+
+#[post("/gliders/<id>/maintenance/events", data = "<data>")]
+pub async fn add_event(
+    user: auth::AuthUser,
+    database: data::Database,
+    id: i32,
+    data: Json<MaintenanceEventCreateForm>,
+) -> Result<Status, ApiError> {
+    let user = user.into_inner();
+    check_glider_ownership(&database, &user, id).await?;
+
+    // Validate data
+    let MaintenanceEventCreateForm { kind, date, notes } = data.into_inner();
+    if !MAINTENANCE_KINDS.contains(&kind.as_str()) {
+        return Err(ApiError::InvalidData {
+            message: format!("Invalid maintenance kind: {}", kind),
+        });
+    }
+    let date = NaiveDate::parse_from_str(&date, "%Y-%m-%d").map_err(|_| ApiError::InvalidData {
+        message: format!("Invalid date: {}", date),
+    })?;
+    if date > Utc::now().date_naive() {
+        return Err(ApiError::InvalidData {
+            message: "Maintenance date must not be in the future".into(),
+        });
+    }
+    let event = NewGliderMaintenanceEvent {
+        glider_id: id,
+        kind,
+        date,
+        notes: notes.filter(|notes| !notes.trim().is_empty()),
+    };
+
+    // Create database entry
+    match database
+        .run(move |db| data::create_maintenance_event(db, event))
+        .await
+    {
+        Ok(_) => {
+            log::info!("Created maintenance event for glider {}", id);
+            Ok(Status::Created)
+        }
+        Err(e) => {
+            log::error!("Could not create maintenance event: {}", e);
+            Err(ApiError::IoError {
+                message: "Could not create maintenance event".into(),
+            })
+        }
+    }
+}
+
+#[post("/gliders/<id>/maintenance/events", rank = 2)]
+#[allow(unused_variables)]
+pub fn add_event_nologin(id: i32) -> ApiError {
+    ApiError::MissingAuthentication
+}
+
+#[delete("/maintenance/events/<id>")]
+pub async fn delete_event(
+    user: auth::AuthUser,
+    database: data::Database,
+    id: i32,
+) -> Result<Status, ApiError> {
+    let user = user.into_inner();
+
+    // Get event and check ownership
+    let event = database
+        .run(move |db| data::get_maintenance_event_by_id(db, id))
+        .await
+        .ok_or(ApiError::NotFound)?;
+    check_glider_ownership(&database, &user, event.glider_id).await?;
+
+    // Delete database entry
+    database
+        .run(move |db| data::delete_maintenance_event_by_id(db, id))
+        .await
+        .map(|()| {
+            log::info!("Deleted maintenance event with ID {}", id);
+            Status::NoContent
+        })
+        .map_err(|e| {
+            log::error!("Could not delete maintenance event with ID {}: {}", id, e);
+            ApiError::IoError {
+                message: "Could not delete maintenance event".into(),
+            }
+        })
+}
+
+#[delete("/maintenance/events/<id>", rank = 2)]
+#[allow(unused_variables)]
+pub fn delete_event_nologin(id: i32) -> ApiError {
+    ApiError::MissingAuthentication
+}
+
+#[post("/gliders/<id>/maintenance/intervals", data = "<data>")]
+pub async fn add_interval(
+    user: auth::AuthUser,
+    database: data::Database,
+    id: i32,
+    data: Json<MaintenanceIntervalCreateUpdateForm>,
+) -> Result<Status, (Status, Json<RocketError>)> {
+    let user = user.into_inner();
+    check_glider_ownership(&database, &user, id)
+        .await
+        .map_err(|_| RocketError::new(Status::NotFound, "NotFound", "Glider not found"))?;
+
+    // Validate data
+    let interval = data
+        .into_inner()
+        .into_new_interval(id)
+        .map_err(|e| RocketError::new(Status::BadRequest, "InvalidData", e))?;
+
+    // Create database entry
+    match database
+        .run(move |db| data::create_maintenance_interval(db, interval))
+        .await
+    {
+        Ok(_) => {
+            log::info!("Created maintenance interval for glider {}", id);
+            Ok(Status::Created)
+        }
+        Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _info)) => Err(conflict_error()),
+        Err(other) => {
+            log::error!("Could not create maintenance interval: {:?}", other);
+            Err(internal_error())
+        }
+    }
+}
+
+#[post("/gliders/<id>/maintenance/intervals", rank = 2)]
+#[allow(unused_variables)]
+pub fn add_interval_nologin(id: i32) -> ApiError {
+    ApiError::MissingAuthentication
+}
+
+#[post("/maintenance/intervals/<id>", data = "<data>")]
+pub async fn edit_interval(
+    user: auth::AuthUser,
+    database: data::Database,
+    id: i32,
+    data: Json<MaintenanceIntervalCreateUpdateForm>,
+) -> Result<Status, (Status, Json<RocketError>)> {
+    let user = user.into_inner();
+    let not_found = || RocketError::new(Status::NotFound, "NotFound", "Maintenance interval not found");
+
+    // Get interval and check ownership
+    let mut interval = database
+        .run(move |db| data::get_maintenance_interval_by_id(db, id))
+        .await
+        .ok_or_else(not_found)?;
+    check_glider_ownership(&database, &user, interval.glider_id)
+        .await
+        .map_err(|_| not_found())?;
+
+    // Validate data
+    let new_interval = data
+        .into_inner()
+        .into_new_interval(interval.glider_id)
+        .map_err(|e| RocketError::new(Status::BadRequest, "InvalidData", e))?;
+
+    // Update interval
+    interval.kind = new_interval.kind;
+    interval.interval_seconds = new_interval.interval_seconds;
+    interval.interval_flights = new_interval.interval_flights;
+    interval.interval_months = new_interval.interval_months;
+    match database
+        .run(move |db| data::update_maintenance_interval(db, &interval))
+        .await
+    {
+        Ok(()) => Ok(Status::NoContent),
+        Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _info)) => Err(conflict_error()),
+        Err(other) => {
+            log::error!(
+                "Could not update maintenance interval with ID {}: {:?}",
+                id,
+                other
+            );
+            Err(internal_error())
+        }
+    }
+}
+
+#[post("/maintenance/intervals/<id>", rank = 2)]
+#[allow(unused_variables)]
+pub fn edit_interval_nologin(id: i32) -> ApiError {
+    ApiError::MissingAuthentication
+}
+
+#[delete("/maintenance/intervals/<id>")]
+pub async fn delete_interval(
+    user: auth::AuthUser,
+    database: data::Database,
+    id: i32,
+) -> Result<Status, ApiError> {
+    let user = user.into_inner();
+
+    // Get interval and check ownership
+    let interval = database
+        .run(move |db| data::get_maintenance_interval_by_id(db, id))
+        .await
+        .ok_or(ApiError::NotFound)?;
+    check_glider_ownership(&database, &user, interval.glider_id).await?;
+
+    // Delete database entry
+    database
+        .run(move |db| data::delete_maintenance_interval_by_id(db, id))
+        .await
+        .map(|()| {
+            log::info!("Deleted maintenance interval with ID {}", id);
+            Status::NoContent
+        })
+        .map_err(|e| {
+            log::error!("Could not delete maintenance interval with ID {}: {}", id, e);
+            ApiError::IoError {
+                message: "Could not delete maintenance interval".into(),
+            }
+        })
+}
+
+#[delete("/maintenance/intervals/<id>", rank = 2)]
+#[allow(unused_variables)]
+pub fn delete_interval_nologin(id: i32) -> ApiError {
+    ApiError::MissingAuthentication
+}
+
+/// Return vec of all API routes.
+pub fn api_routes() -> Vec<Route> {
+    routes![
+        list,
+        list_nologin,
+        add_event,
+        add_event_nologin,
+        delete_event,
+        delete_event_nologin,
+        add_interval,
+        add_interval_nologin,
+        edit_interval,
+        edit_interval_nologin,
+        delete_interval,
+        delete_interval_nologin,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    /// A check every 100 hours or 2 years, whichever comes first
+    fn school_interval() -> GliderMaintenanceInterval {
+        GliderMaintenanceInterval {
+            id: 1,
+            glider_id: 1,
+            kind: "check".into(),
+            interval_seconds: Some(100 * 3600),
+            interval_flights: None,
+            interval_months: Some(24),
+        }
+    }
+
+    #[test]
+    fn evaluate_interval_by_time() {
+        let usage = GliderUsage {
+            flights: 20,
+            seconds: 10 * 3600,
+        };
+        let interval = school_interval();
+
+        // Recently checked
+        let status = evaluate_interval(&interval, Some(date(2024, 3, 1)), None, usage, date(2024, 6, 1));
+        assert_eq!(
+            status,
+            ApiMaintenanceStatus {
+                interval_id: 1,
+                kind: "check".into(),
+                state: MaintenanceState::Ok,
+                last_performed: Some(date(2024, 3, 1)),
+                due_date: Some(date(2026, 3, 1)),
+                remaining_seconds: Some(90 * 3600),
+                remaining_flights: None,
+            }
+        );
+
+        // Due date approaching
+        let status = evaluate_interval(&interval, Some(date(2024, 3, 1)), None, usage, date(2026, 2, 10));
+        assert_eq!(status.state, MaintenanceState::DueSoon);
+
+        // Due date passed
+        let status = evaluate_interval(&interval, Some(date(2024, 3, 1)), None, usage, date(2026, 3, 1));
+        assert_eq!(status.state, MaintenanceState::Overdue);
+
+        // Never checked: Count from the date the glider was acquired
+        let status = evaluate_interval(&interval, None, Some(date(2023, 5, 1)), usage, date(2024, 6, 1));
+        assert_eq!(status.last_performed, None);
+        assert_eq!(status.due_date, Some(date(2025, 5, 1)));
+
+        // Neither checked nor acquisition date known
+        let status = evaluate_interval(&interval, None, None, usage, date(2024, 6, 1));
+        assert_eq!(status.due_date, None);
+        assert_eq!(status.state, MaintenanceState::Ok);
+    }
+
+    #[test]
+    fn evaluate_interval_by_usage() {
+        let interval = school_interval();
+        let last = Some(date(2024, 3, 1));
+        let today = date(2024, 6, 1);
+
+        // 95 hours flown: Due soon
+        let usage = GliderUsage {
+            flights: 80,
+            seconds: 95 * 3600,
+        };
+        let status = evaluate_interval(&interval, last, None, usage, today);
+        assert_eq!(status.state, MaintenanceState::DueSoon);
+        assert_eq!(status.remaining_seconds, Some(5 * 3600));
+
+        // 101 hours flown: Overdue, even though the due date is far away
+        let usage = GliderUsage {
+            flights: 85,
+            seconds: 101 * 3600,
+        };
+        let status = evaluate_interval(&interval, last, None, usage, today);
+        assert_eq!(status.state, MaintenanceState::Overdue);
+        assert_eq!(status.remaining_seconds, Some(-3600));
+
+        // Flight based interval
+        let interval = GliderMaintenanceInterval {
+            kind: "trim".into(),
+            interval_seconds: None,
+            interval_flights: Some(50),
+            interval_months: None,
+            ..school_interval()
+        };
+        let status = evaluate_interval(&interval, last, None, usage, today);
+        assert_eq!(status.state, MaintenanceState::Overdue);
+        assert_eq!(status.remaining_flights, Some(-35));
+        assert_eq!(status.due_date, None);
+    }
+}
@@ -1,20 +1,25 @@
 use std::fmt::{self, Display};
 
 use chrono::{DateTime, NaiveDate, Utc};
-use diesel::sql_types::{BigInt, Bool, Date, Double, Integer, Nullable, Text};
+use diesel::sql_types::{BigInt, Bool, Date, Double, Integer, Nullable, Text, Timestamptz};
 use diesel::{Associations, Identifiable, Queryable};
 use diesel_geography::{sql_types::Geography, types::GeogPoint};
 use serde::Serialize;
 
-use crate::schema::{flights, gliders, igcs, locations, users};
+use crate::polygon::GeogPolygon;
+use crate::schema::{
+    api_tokens, currency_rules, equipment, equipment_repacks, flight_equipment, flights,
+    glider_maintenance_events, glider_maintenance_intervals, gliders, igcs, locations, login_attempts,
+    login_challenges, sessions, sites, totp_credentials, users,
+};
 
 #[derive(Identifiable, Queryable, Serialize, PartialEq, Debug, Clone)]
 #[diesel(table_name = users)]
 pub struct User {
     pub id: i32,
     pub username: String,
-    /// Password is automatically hashed on insert or update
-    /// by a PostgreSQL trigger.
+    /// Password hash (Argon2id in PHC string format, or a legacy bcrypt hash),
+    /// see `passwords` module.
     pub password: String,
     /// Last used glider
     pub last_glider_id: Option<i32>,
@@ -24,6 +29,51 @@ pub struct User {
     pub signed_up: DateTime<Utc>,
     /// Whether the user has opted in to receive news
     pub news_opt_in: bool,
+    /// Whether the e-mail address was confirmed by the user
+    pub email_verified: bool,
+    /// Whether the user may access the administration API
+    pub is_admin: bool,
+    /// Whether the account was disabled by an administrator
+    pub disabled: bool,
+    /// Default radius in meters for matching IGC launch and landing points to
+    /// locations
+    pub match_radius: i32,
+}
+
+#[derive(Debug, QueryableByName, Serialize)]
+#[diesel(table_name = users)]
+pub struct UserWithStats {
+    // For field descriptions, see `User` model
+    #[diesel(sql_type = Integer)]
+    pub id: i32,
+    #[diesel(sql_type = Text)]
+    pub username: String,
+    #[diesel(sql_type = Text)]
+    pub email: String,
+    #[diesel(sql_type = Timestamptz)]
+    pub signed_up: DateTime<Utc>,
+    #[diesel(sql_type = Bool)]
+    pub email_verified: bool,
+    #[diesel(sql_type = Bool)]
+    pub is_admin: bool,
+    #[diesel(sql_type = Bool)]
+    pub disabled: bool,
+    /// Number of flights
+    #[diesel(sql_type = BigInt)]
+    pub flights: i64,
+    /// Total size of all IGC files in bytes
+    #[diesel(sql_type = BigInt)]
+    pub igc_bytes: i64,
+}
+
+/// Number of items created in a month.
+#[derive(Debug, QueryableByName, PartialEq)]
+pub struct MonthCount {
+    /// The month (YYYY-MM)
+    #[diesel(sql_type = Text)]
+    pub month: String,
+    #[diesel(sql_type = BigInt)]
+    pub count: i64,
 }
 
 #[derive(Identifiable, Queryable, Associations, AsChangeset, Serialize, PartialEq, Debug, Clone)]
@@ -98,6 +148,176 @@ pub struct NewGlider {
     pub comment: Option<String>,
 }
 
+#[derive(Identifiable, Queryable, Associations, AsChangeset, Serialize, PartialEq, Debug, Clone)]
+#[diesel(treat_none_as_null = true)]
+#[diesel(belongs_to(Glider, foreign_key = glider_id))]
+#[diesel(table_name = glider_maintenance_events)]
+pub struct GliderMaintenanceEvent {
+    pub id: i32,
+    pub glider_id: i32,
+    /// The kind of maintenance, see `maintenance::MAINTENANCE_KINDS`
+    pub kind: String,
+    /// When was the maintenance performed?
+    pub date: NaiveDate,
+    /// Arbitrary notes, e.g. the name of the workshop
+    pub notes: Option<String>,
+}
+
+#[derive(Insertable, Default)]
+#[diesel(table_name = glider_maintenance_events)]
+pub struct NewGliderMaintenanceEvent {
+    pub glider_id: i32,
+    pub kind: String,
+    pub date: NaiveDate,
+    pub notes: Option<String>,
+}
+
+#[derive(Identifiable, Queryable, Associations, AsChangeset, Serialize, PartialEq, Debug, Clone)]
+#[diesel(treat_none_as_null = true)]
+#[diesel(belongs_to(Glider, foreign_key = glider_id))]
+#[diesel(table_name = glider_maintenance_intervals)]
+pub struct GliderMaintenanceInterval {
+    pub id: i32,
+    pub glider_id: i32,
+    /// The kind of maintenance, see `maintenance::MAINTENANCE_KINDS`
+    pub kind: String,
+    /// Maximum airtime in seconds between two events
+    pub interval_seconds: Option<i32>,
+    /// Maximum number of flights between two events
+    pub interval_flights: Option<i32>,
+    /// Maximum number of calendar months between two events
+    pub interval_months: Option<i32>,
+}
+
+#[derive(Insertable, Default)]
+#[diesel(table_name = glider_maintenance_intervals)]
+pub struct NewGliderMaintenanceInterval {
+    pub glider_id: i32,
+    pub kind: String,
+    pub interval_seconds: Option<i32>,
+    pub interval_flights: Option<i32>,
+    pub interval_months: Option<i32>,
+}
+
+#[derive(Identifiable, Queryable, Associations, AsChangeset, Serialize, PartialEq, Debug, Clone)]
+#[diesel(treat_none_as_null = true)]
+#[diesel(belongs_to(User, foreign_key = user_id))]
+#[diesel(table_name = equipment)]
+pub struct Equipment {
+    pub id: i32,
+    pub user_id: i32,
+    /// The kind of equipment, see `equipment::EQUIPMENT_KINDS`
+    pub kind: String,
+    /// The manufacturer name, e.g. "Advance"
+    pub manufacturer: String,
+    /// The model name, e.g. "Lightness 3"
+    pub model: String,
+    /// When was the equipment acquired?
+    pub since: Option<NaiveDate>,
+    /// When was the equipment sold / given away / thrown away?
+    pub until: Option<NaiveDate>,
+    /// Where did you get the equipment from? (e.g. a shop, or a website)
+    pub source: Option<String>,
+    /// How much did the equipment cost, in your currency?
+    pub cost: Option<i32>,
+    /// Add arbitrary comments about this equipment
+    pub comment: Option<String>,
+    /// Reserves only: Number of months between two repacks
+    pub repack_interval_months: Option<i32>,
+}
+
+impl Display for Equipment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        write!(f, "{} {}", self.manufacturer, self.model)
+    }
+}
+
+#[derive(Debug, QueryableByName, Serialize)]
+#[diesel(table_name = equipment)]
+pub struct EquipmentWithStats {
+    // For field descriptions, see `Equipment` model
+    #[diesel(sql_type = Integer)]
+    pub id: i32,
+    #[diesel(sql_type = Integer)]
+    pub user_id: i32,
+    #[diesel(sql_type = Text)]
+    pub kind: String,
+    #[diesel(sql_type = Text)]
+    pub manufacturer: String,
+    #[diesel(sql_type = Text)]
+    pub model: String,
+    #[diesel(sql_type = Nullable<Date>)]
+    pub since: Option<NaiveDate>,
+    #[diesel(sql_type = Nullable<Date>)]
+    pub until: Option<NaiveDate>,
+    #[diesel(sql_type = Nullable<Text>)]
+    pub source: Option<String>,
+    #[diesel(sql_type = Nullable<Integer>)]
+    pub cost: Option<i32>,
+    #[diesel(sql_type = Nullable<Text>)]
+    pub comment: Option<String>,
+    #[diesel(sql_type = Nullable<Integer>)]
+    pub repack_interval_months: Option<i32>,
+    #[diesel(sql_type = BigInt)]
+    pub flights: i64,
+    #[diesel(sql_type = BigInt)]
+    pub seconds: i64,
+    /// Date of the most recent repack
+    #[diesel(sql_type = Nullable<Date>)]
+    pub last_repack: Option<NaiveDate>,
+    /// Total number of repacks
+    #[diesel(sql_type = BigInt)]
+    pub repack_count: i64,
+    /// Number of flights since the most recent repack (or ever, if never repacked)
+    #[diesel(sql_type = BigInt)]
+    pub flights_since_repack: i64,
+}
+
+#[derive(Insertable, Default)]
+#[diesel(table_name = equipment)]
+pub struct NewEquipment {
+    pub user_id: i32,
+    pub kind: String,
+    pub manufacturer: String,
+    pub model: String,
+    pub since: Option<NaiveDate>,
+    pub until: Option<NaiveDate>,
+    pub source: Option<String>,
+    pub cost: Option<i32>,
+    pub comment: Option<String>,
+    pub repack_interval_months: Option<i32>,
+}
+
+#[derive(Identifiable, Queryable, Associations, Serialize, PartialEq, Debug, Clone)]
+#[diesel(belongs_to(Equipment, foreign_key = equipment_id))]
+#[diesel(table_name = equipment_repacks)]
+pub struct EquipmentRepack {
+    pub id: i32,
+    pub equipment_id: i32,
+    /// When was the reserve repacked?
+    pub date: NaiveDate,
+    /// Arbitrary notes, e.g. the name of the packer
+    pub notes: Option<String>,
+}
+
+#[derive(Insertable, Default)]
+#[diesel(table_name = equipment_repacks)]
+pub struct NewEquipmentRepack {
+    pub equipment_id: i32,
+    pub date: NaiveDate,
+    pub notes: Option<String>,
+}
+
+#[derive(Identifiable, Queryable, Insertable, Associations, PartialEq, Debug, Clone)]
+#[diesel(belongs_to(Flight, foreign_key = flight_id))]
+#[diesel(belongs_to(Equipment, foreign_key = equipment_id))]
+#[diesel(table_name = flight_equipment)]
+#[diesel(primary_key(flight_id, equipment_id))]
+pub struct FlightEquipment {
+    pub flight_id: i32,
+    pub equipment_id: i32,
+}
+
 #[derive(Identifiable, Queryable, Associations, AsChangeset, Serialize, PartialEq, Debug, Clone)]
 #[diesel(treat_none_as_null = true)]
 #[diesel(belongs_to(User, foreign_key = user_id))]
@@ -109,6 +329,14 @@ pub struct Location {
     pub elevation: i32,
     pub user_id: i32,
     pub geog: Option<GeogPoint>,
+    /// The shared site this location was copied from
+    pub site_id: Option<i32>,
+    /// Radius in meters for matching IGC launch and landing points (if not
+    /// set, the user's default radius is used)
+    pub match_radius: Option<i32>,
+    /// Outline of the location (IGC launch and landing points within the
+    /// area are matched to the location, regardless of the matching radius)
+    pub area: Option<GeogPolygon>,
 }
 
 #[derive(Insertable, Default)]
@@ -119,6 +347,8 @@ pub struct NewLocation {
     pub elevation: i32,
     pub user_id: i32,
     pub geog: Option<GeogPoint>,
+    pub match_radius: Option<i32>,
+    pub area: Option<GeogPolygon>,
 }
 
 #[derive(QueryableByName, PartialEq, Debug, Clone)]
@@ -131,10 +361,15 @@ pub struct LocationWithDistance {
     pub country: String,
     #[diesel(sql_type = Integer)]
     pub elevation: i32,
-    #[diesel(sql_type = Integer)]
-    pub user_id: i32,
-    #[diesel(sql_type = Geography)]
-    pub geog: GeogPoint,
+    #[diesel(sql_type = Nullable<Geography>)]
+    pub geog: Option<GeogPoint>,
+    /// The linked shared site (for shared sites, this is equal to `id`)
+    #[diesel(sql_type = Nullable<Integer>)]
+    pub site_id: Option<i32>,
+    /// Whether this is a shared site (in which case `id` is a site ID) or a
+    /// personal location
+    #[diesel(sql_type = Bool)]
+    pub shared: bool,
     #[diesel(sql_type = Double)]
     pub distance: f64,
 }
@@ -154,10 +389,42 @@ pub struct LocationWithCount {
     pub user_id: i32,
     #[diesel(sql_type = Nullable<Geography>)]
     pub geog: Option<GeogPoint>,
+    #[diesel(sql_type = Nullable<Integer>)]
+    pub site_id: Option<i32>,
+    #[diesel(sql_type = Nullable<Integer>)]
+    pub match_radius: Option<i32>,
+    #[diesel(sql_type = Nullable<Geography>)]
+    pub area: Option<GeogPolygon>,
     #[diesel(sql_type = BigInt)]
     pub count: i64,
 }
 
+/// A site from the shared site catalog.
+#[derive(Identifiable, Queryable, QueryableByName, AsChangeset, Serialize, PartialEq, Debug, Clone)]
+#[diesel(table_name = sites)]
+pub struct Site {
+    pub id: i32,
+    pub name: String,
+    pub country: String,
+    pub elevation: i32,
+    pub geog: GeogPoint,
+    /// Either "launch", "landing" or "both"
+    pub site_type: String,
+    /// Alternative names of the site
+    pub aliases: Vec<String>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = sites)]
+pub struct NewSite {
+    pub name: String,
+    pub country: String,
+    pub elevation: i32,
+    pub geog: GeogPoint,
+    pub site_type: String,
+    pub aliases: Vec<String>,
+}
+
 #[derive(Identifiable, Queryable, Associations, AsChangeset, Serialize, PartialEq, Debug, Clone)]
 #[diesel(treat_none_as_null = true)]
 #[diesel(belongs_to(User, foreign_key = user_id))]
@@ -197,6 +464,18 @@ pub struct Flight {
     pub created_at: DateTime<Utc>,
     /// Whether you hiked up to launch
     pub hikeandfly: bool,
+    /// Wind direction at launch in degrees (0-359)
+    pub wind_direction: Option<i16>,
+    /// Wind speed at launch in km/h
+    pub wind_speed: Option<i16>,
+    /// Strength of the strongest thermals in m/s
+    pub thermal_strength: Option<f32>,
+    /// Cloud base in meters MSL
+    pub cloud_base: Option<i32>,
+    /// Turbulence rating from 1 (calm) to 5 (severe)
+    pub turbulence: Option<i16>,
+    /// Free text weather notes
+    pub weather_notes: Option<String>,
 }
 
 #[derive(Insertable, Default)]
@@ -230,6 +509,18 @@ pub struct NewFlight {
     pub video_url: Option<String>,
     /// Whether you hiked up to launch
     pub hikeandfly: bool,
+    /// Wind direction at launch in degrees (0-359)
+    pub wind_direction: Option<i16>,
+    /// Wind speed at launch in km/h
+    pub wind_speed: Option<i16>,
+    /// Strength of the strongest thermals in m/s
+    pub thermal_strength: Option<f32>,
+    /// Cloud base in meters MSL
+    pub cloud_base: Option<i32>,
+    /// Turbulence rating from 1 (calm) to 5 (severe)
+    pub turbulence: Option<i16>,
+    /// Free text weather notes
+    pub weather_notes: Option<String>,
 }
 
 #[derive(Identifiable, Queryable, Insertable, PartialEq, Debug, Clone)]
@@ -241,3 +532,118 @@ pub struct Igc {
     /// IGC file contents
     pub data: Vec<u8>,
 }
+
+/// TOTP two-factor authentication credential of a user.
+#[derive(Identifiable, Queryable, Associations, PartialEq, Debug, Clone)]
+#[diesel(primary_key(user_id))]
+#[diesel(belongs_to(User, foreign_key = user_id))]
+#[diesel(table_name = totp_credentials)]
+pub struct TotpCredential {
+    pub user_id: i32,
+    /// Base32 encoded shared secret
+    pub secret: String,
+    /// Whether the credential was confirmed and is required for login
+    pub enabled: bool,
+    /// The last time step that was used to log in
+    pub last_used_step: Option<i64>,
+    /// When the credential was created (at enrollment)
+    pub created_at: DateTime<Utc>,
+}
+
+/// Failed login attempts for a rate limiting key (client IP or username).
+#[derive(Identifiable, Queryable, QueryableByName, Insertable, AsChangeset, PartialEq, Debug, Clone)]
+#[diesel(treat_none_as_null = true)]
+#[diesel(primary_key(key))]
+#[diesel(table_name = login_attempts)]
+pub struct LoginAttempts {
+    pub key: String,
+    /// Number of consecutive failed attempts
+    pub failures: i32,
+    /// When the last failed attempt happened
+    pub last_failure_at: DateTime<Utc>,
+    /// Until when further attempts are rejected
+    pub locked_until: Option<DateTime<Utc>>,
+}
+
+/// A pending second login step (TOTP code) of a user whose password was
+/// verified.
+#[derive(Identifiable, Queryable, Associations, PartialEq, Debug, Clone)]
+#[diesel(belongs_to(User, foreign_key = user_id))]
+#[diesel(table_name = login_challenges)]
+pub struct LoginChallenge {
+    pub id: i32,
+    pub user_id: i32,
+    /// When the password was verified
+    pub created_at: DateTime<Utc>,
+    /// Number of wrong codes submitted for this challenge
+    pub failures: i32,
+}
+
+#[derive(Identifiable, Queryable, Associations, PartialEq, Debug, Clone)]
+#[diesel(belongs_to(User, foreign_key = user_id))]
+#[diesel(table_name = sessions)]
+pub struct Session {
+    pub id: i32,
+    pub user_id: i32,
+    /// Hex encoded SHA-256 hash of the session token
+    pub token_hash: String,
+    /// User agent of the client that logged in
+    pub user_agent: Option<String>,
+    /// IP address of the client that logged in
+    pub ip_address: Option<String>,
+    /// When the session was created (at login)
+    pub created_at: DateTime<Utc>,
+    /// When the session was last used to authenticate a request
+    pub last_seen_at: DateTime<Utc>,
+}
+
+#[derive(Identifiable, Queryable, Associations, PartialEq, Debug, Clone)]
+#[diesel(belongs_to(User, foreign_key = user_id))]
+#[diesel(table_name = api_tokens)]
+pub struct ApiToken {
+    pub id: i32,
+    pub user_id: i32,
+    /// A descriptive name, e.g. "Landing zone sync"
+    pub name: String,
+    /// Hex encoded SHA-256 hash of the token
+    pub token_hash: String,
+    /// The first few characters of the token, to help identify it
+    pub token_prefix: String,
+    /// The scopes granted to this token, see `tokens::SCOPES`
+    pub scopes: Vec<String>,
+    /// When the token was created
+    pub created_at: DateTime<Utc>,
+    /// When the token was last used to authenticate a request
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Identifiable, Queryable, Associations, AsChangeset, Serialize, PartialEq, Debug, Clone)]
+#[diesel(treat_none_as_null = true)]
+#[diesel(belongs_to(User, foreign_key = user_id))]
+#[diesel(belongs_to(Glider, foreign_key = glider_id))]
+#[diesel(table_name = currency_rules)]
+pub struct CurrencyRule {
+    pub id: i32,
+    pub user_id: i32,
+    /// A descriptive name, e.g. "Passenger flights"
+    pub name: String,
+    /// Minimum number of flights within the window
+    pub min_flights: Option<i32>,
+    /// Minimum airtime in seconds within the window
+    pub min_seconds: Option<i32>,
+    /// Length of the window in days, counted back from today
+    pub window_days: i32,
+    /// If set, only flights with this glider are counted
+    pub glider_id: Option<i32>,
+}
+
+#[derive(Insertable, Default)]
+#[diesel(table_name = currency_rules)]
+pub struct NewCurrencyRule {
+    pub user_id: i32,
+    pub name: String,
+    pub min_flights: Option<i32>,
+    pub min_seconds: Option<i32>,
+    pub window_days: i32,
+    pub glider_id: Option<i32>,
+}
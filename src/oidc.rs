@@ -0,0 +1,888 @@
+//! Login through an external OpenID Connect provider.
+//!
+//! The provider is configured in the `oidc` section of the Rocket config. The
+//! authorization code flow with PKCE is used: `/auth/oidc/login` redirects the
+//! browser to the provider, which redirects back to `/auth/oidc/callback`.
+//!
+//! On the first login, the external identity is linked to the user with the
+//! same verified e-mail address. If there is no such user, a new user is
+//! created (unless disabled with `create_users = false`). Users with
+//! two-factor authentication are never linked automatically. They can link
+//! an identity while logged in, through `/auth/oidc/link`.
+//!
+//! Users with two-factor authentication must complete the login with a TOTP
+//! code at `/auth/login/totp`, like after a password login.
+
+use anyhow::{anyhow, bail, Context, Result};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::Utc;
+use diesel::PgConnection;
+use log::{info, warn};
+use rocket::{
+    get,
+    http::{Cookie, CookieJar, SameSite, Status},
+    response::Redirect,
+    routes,
+    serde::json::{self, Json},
+    time::Duration,
+    tokio::sync::OnceCell,
+    Route, State,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    auth::{self, AuthUser, ClientInfo},
+    data::{self, Database},
+    models::User,
+    responders::ApiError,
+    Config,
+};
+
+const PENDING_LOGIN_COOKIE_ID: &str = "oidc_login";
+
+/// How long the login at the provider may take.
+const PENDING_LOGIN_VALIDITY_MINUTES: i64 = 10;
+
+/// Scopes requested from the provider.
+const SCOPES: &str = "openid profile email";
+
+// Config
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct OidcConfig {
+    /// Issuer URL. The provider metadata is discovered at
+    /// `<issuer>/.well-known/openid-configuration`.
+    pub issuer: String,
+    /// Client ID registered with the provider.
+    pub client_id: String,
+    /// Client secret registered with the provider.
+    pub client_secret: String,
+    /// Redirect URI registered with the provider. It must point to the
+    /// `/auth/oidc/callback` endpoint of this API.
+    pub redirect_uri: String,
+    /// Whether users are created on the first login. Defaults to `true`.
+    #[serde(default = "default_true")]
+    pub create_users: bool,
+}
+
+// Provider
+
+/// Provider metadata (subset), see OpenID Connect Discovery 1.0.
+#[derive(Debug, Deserialize, Clone)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Audience {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+impl Audience {
+    fn contains(&self, client_id: &str) -> bool {
+        match self {
+            Audience::Single(aud) => aud == client_id,
+            Audience::Multiple(auds) => auds.iter().any(|aud| aud == client_id),
+        }
+    }
+}
+
+/// Claims of an ID token (subset).
+#[derive(Debug, Deserialize)]
+struct IdTokenClaims {
+    iss: String,
+    sub: String,
+    aud: Audience,
+    exp: i64,
+    nonce: Option<String>,
+    email: Option<String>,
+    #[serde(default)]
+    email_verified: bool,
+    preferred_username: Option<String>,
+}
+
+/// Managed state for OpenID Connect login. If no provider is configured, the
+/// endpoints respond with "HTTP 404 Not Found".
+pub struct OidcProvider {
+    config: Option<OidcConfig>,
+    http: reqwest::Client,
+    metadata: OnceCell<ProviderMetadata>,
+}
+
+impl OidcProvider {
+    pub fn new(config: Option<OidcConfig>) -> Self {
+        Self {
+            config,
+            http: reqwest::Client::new(),
+            metadata: OnceCell::new(),
+        }
+    }
+
+    /// Return the provider metadata. It is discovered on first use.
+    async fn metadata(&self, config: &OidcConfig) -> Result<&ProviderMetadata> {
+        self.metadata
+            .get_or_try_init(|| async {
+                let url = format!(
+                    "{}/.well-known/openid-configuration",
+                    config.issuer.trim_end_matches('/')
+                );
+                let metadata: ProviderMetadata = self
+                    .http
+                    .get(&url)
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status())
+                    .with_context(|| format!("Could not fetch provider metadata from {}", url))?
+                    .json()
+                    .await
+                    .context("Invalid provider metadata")?;
+                if metadata.issuer.trim_end_matches('/') != config.issuer.trim_end_matches('/') {
+                    bail!("Issuer mismatch in provider metadata: {}", metadata.issuer);
+                }
+                Ok(metadata)
+            })
+            .await
+    }
+
+    /// Exchange an authorization code for an ID token and return its claims.
+    ///
+    /// The signature of the ID token is not verified: The token is received
+    /// directly from the token endpoint, so the TLS server validation is used
+    /// to validate the issuer instead (OpenID Connect Core 1.0, 3.1.3.7).
+    async fn exchange_code(
+        &self,
+        config: &OidcConfig,
+        metadata: &ProviderMetadata,
+        code: &str,
+        verifier: &str,
+    ) -> Result<IdTokenClaims> {
+        let response: TokenResponse = self
+            .http
+            .post(&metadata.token_endpoint)
+            .basic_auth(&config.client_id, Some(&config.client_secret))
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", &config.redirect_uri),
+                ("code_verifier", verifier),
+            ])
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .context("Token request failed")?
+            .json()
+            .await
+            .context("Invalid token response")?;
+        decode_id_token(&response.id_token)
+    }
+}
+
+// Helpers
+
+/// Generate a random URL safe string.
+fn random_string() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Return the PKCE code challenge (method `S256`) for the code verifier.
+fn code_challenge(verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+/// Decode the claims of an ID token (a JWT).
+fn decode_id_token(id_token: &str) -> Result<IdTokenClaims> {
+    let payload = id_token
+        .split('.')
+        .nth(1)
+        .ok_or_else(|| anyhow!("Malformed ID token"))?;
+    let payload = URL_SAFE_NO_PAD
+        .decode(payload.trim_end_matches('='))
+        .context("Malformed ID token payload")?;
+    json::from_slice(&payload).context("Invalid ID token claims")
+}
+
+/// Validate the claims of an ID token.
+fn validate_claims(
+    claims: &IdTokenClaims,
+    issuer: &str,
+    client_id: &str,
+    nonce: &str,
+    now: i64,
+) -> Result<()> {
+    if claims.iss != issuer {
+        bail!("Invalid issuer: {}", claims.iss);
+    }
+    if !claims.aud.contains(client_id) {
+        bail!("ID token was not issued for this client");
+    }
+    if claims.exp <= now {
+        bail!("ID token is expired");
+    }
+    if claims.nonce.as_deref() != Some(nonce) {
+        bail!("Invalid nonce");
+    }
+    Ok(())
+}
+
+/// Return the first free username based on the specified name.
+fn free_username(conn: &mut PgConnection, name: &str) -> String {
+    let base = match name.trim() {
+        "" => "user",
+        name => name,
+    };
+    let mut username = base.to_string();
+    let mut counter = 1;
+    while data::is_username_taken(conn, &username) {
+        counter += 1;
+        username = format!("{}-{}", base, counter);
+    }
+    username
+}
+
+/// Return the user for the external identity. Link or create the user if
+/// necessary.
+fn resolve_user(conn: &mut PgConnection, claims: IdTokenClaims, create_users: bool) -> Result<User> {
+    if let Some(user) = data::get_user_by_oidc_identity(conn, &claims.iss, &claims.sub) {
+        return Ok(user);
+    }
+
+    // Unknown identity, look up user by e-mail address
+    let email = match claims.email {
+        Some(email) if claims.email_verified => email,
+        _ => bail!("No verified e-mail address for subject {}", claims.sub),
+    };
+    let mut users = data::get_users_by_email(conn, &email);
+    match users.len() {
+        0 if create_users => {
+            let name = claims
+                .preferred_username
+                .unwrap_or_else(|| email.split('@').next().unwrap_or_default().to_string());
+            let username = free_username(conn, &name);
+            let user = data::create_oidc_user(conn, &username, &email, &claims.iss, &claims.sub)?;
+            info!("Created user {} for OIDC subject {}", user.id, claims.sub);
+            Ok(user)
+        }
+        0 => bail!("No user with e-mail address {}", email),
+        1 => {
+            // Only link to users that own the e-mail address. Users with
+            // two-factor authentication must link identities explicitly,
+            // otherwise the second factor could be bypassed with an identity
+            // that only shares the e-mail address.
+            let user = users.remove(0);
+            if !user.email_verified {
+                bail!("E-mail address of user {} is not verified", user.id);
+            }
+            if data::is_totp_enabled(conn, &user) {
+                bail!("User {} has two-factor authentication enabled", user.id);
+            }
+            data::link_oidc_identity(conn, &user, &claims.iss, &claims.sub)?;
+            info!("Linked OIDC subject {} to user {}", claims.sub, user.id);
+            Ok(user)
+        }
+        _ => bail!("Multiple users with e-mail address {}", email),
+    }
+}
+
+/// Link the external identity to a logged in user.
+fn link_user(conn: &mut PgConnection, user: &User, claims: IdTokenClaims) -> Result<()> {
+    match data::get_user_by_oidc_identity(conn, &claims.iss, &claims.sub) {
+        Some(linked) if linked.id == user.id => Ok(()),
+        Some(linked) => bail!("OIDC subject {} is linked to user {}", claims.sub, linked.id),
+        None => {
+            data::link_oidc_identity(conn, user, &claims.iss, &claims.sub)?;
+            info!(
+                "Linked OIDC subject {} to user {} on request",
+                claims.sub, user.id
+            );
+            Ok(())
+        }
+    }
+}
+
+/// State of a login in progress, stored in a private cookie.
+#[derive(Serialize, Deserialize)]
+struct PendingLogin {
+    state: String,
+    nonce: String,
+    verifier: String,
+    /// If set, the identity is linked to this (logged in) user instead of
+    /// logging in.
+    #[serde(default)]
+    link_user_id: Option<i32>,
+}
+
+/// Complete the login at the provider. Return the pending login and the
+/// validated claims of the ID token.
+async fn finish_login(
+    provider: &OidcProvider,
+    cookies: &CookieJar<'_>,
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+) -> Result<(PendingLogin, IdTokenClaims)> {
+    let pending: PendingLogin = cookies
+        .get_private(PENDING_LOGIN_COOKIE_ID)
+        .and_then(|cookie| json::from_str(cookie.value()).ok())
+        .ok_or_else(|| anyhow!("No pending login"))?;
+    cookies.remove_private(Cookie::from(PENDING_LOGIN_COOKIE_ID));
+    if let Some(error) = error {
+        bail!("Provider returned error: {}", error);
+    }
+    if state.as_deref() != Some(pending.state.as_str()) {
+        bail!("State mismatch");
+    }
+    let code = code.ok_or_else(|| anyhow!("Missing authorization code"))?;
+
+    let config = provider
+        .config
+        .as_ref()
+        .ok_or_else(|| anyhow!("OIDC is disabled"))?;
+    let metadata = provider.metadata(config).await?;
+    let claims = provider
+        .exchange_code(config, metadata, &code, &pending.verifier)
+        .await?;
+    validate_claims(
+        &claims,
+        &metadata.issuer,
+        &config.client_id,
+        &pending.nonce,
+        Utc::now().timestamp(),
+    )?;
+    Ok((pending, claims))
+}
+
+/// Start a login at the provider. Redirect to its authorization endpoint.
+async fn start_login(
+    cookies: &CookieJar<'_>,
+    provider: &OidcProvider,
+    link_user_id: Option<i32>,
+) -> Result<Redirect, Status> {
+    let config = provider.config.as_ref().ok_or(Status::NotFound)?;
+    let metadata = provider.metadata(config).await.map_err(|e| {
+        warn!("OIDC discovery failed: {:#}", e);
+        Status::ServiceUnavailable
+    })?;
+
+    let pending = PendingLogin {
+        state: random_string(),
+        nonce: random_string(),
+        verifier: random_string(),
+        link_user_id,
+    };
+    let url = reqwest::Url::parse_with_params(
+        &metadata.authorization_endpoint,
+        &[
+            ("response_type", "code"),
+            ("client_id", &config.client_id),
+            ("redirect_uri", &config.redirect_uri),
+            ("scope", SCOPES),
+            ("state", &pending.state),
+            ("nonce", &pending.nonce),
+            ("code_challenge", &code_challenge(&pending.verifier)),
+            ("code_challenge_method", "S256"),
+        ],
+    )
+    .map_err(|e| {
+        warn!("Invalid OIDC authorization endpoint: {}", e);
+        Status::ServiceUnavailable
+    })?;
+
+    let mut cookie = Cookie::new(
+        PENDING_LOGIN_COOKIE_ID,
+        json::to_string(&pending).expect("Could not serialize pending login"),
+    );
+    cookie.set_max_age(Duration::minutes(PENDING_LOGIN_VALIDITY_MINUTES));
+    cookie.set_same_site(SameSite::Lax);
+    cookies.add_private(cookie);
+
+    Ok(Redirect::to(url.to_string()))
+}
+
+// API types
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiOidcInfo {
+    /// Whether login through an OpenID Connect provider is available
+    enabled: bool,
+}
+
+// API endpoints
+
+#[get("/auth/oidc")]
+pub fn info(provider: &State<OidcProvider>) -> Json<ApiOidcInfo> {
+    Json(ApiOidcInfo {
+        enabled: provider.config.is_some(),
+    })
+}
+
+/// Start the login. Redirect to the authorization endpoint of the provider.
+///
+/// - Return "HTTP 404 Not Found" if no provider is configured.
+/// - Return "HTTP 503 Service Unavailable" if the provider metadata could not
+///   be loaded.
+#[get("/auth/oidc/login")]
+pub async fn login(cookies: &CookieJar<'_>, provider: &State<OidcProvider>) -> Result<Redirect, Status> {
+    start_login(cookies, provider, None).await
+}
+
+/// Start linking an external identity to the logged in user. Redirect to the
+/// authorization endpoint of the provider.
+///
+/// - Return "HTTP 404 Not Found" if no provider is configured.
+/// - Return "HTTP 503 Service Unavailable" if the provider metadata could not
+///   be loaded.
+#[get("/auth/oidc/link")]
+pub async fn link(
+    user: AuthUser,
+    cookies: &CookieJar<'_>,
+    provider: &State<OidcProvider>,
+) -> Result<Redirect, Status> {
+    start_login(cookies, provider, Some(user.into_inner().id)).await
+}
+
+#[get("/auth/oidc/link", rank = 2)]
+pub fn link_nologin() -> ApiError {
+    ApiError::MissingAuthentication
+}
+
+/// Callback from the provider. Log in and redirect to the frontend.
+///
+/// - If the user has two-factor authentication enabled, redirect to the
+///   frontend login page with the query parameter `totp=required`. The login
+///   must be completed at `/auth/login/totp`.
+/// - If the login failed, redirect to the frontend login page with the query
+///   parameter `error=oidc`.
+/// - When linking an identity, redirect to the frontend profile page (with
+///   the query parameter `error=oidc` if linking failed).
+#[get("/auth/oidc/callback?<code>&<state>&<error>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn callback(
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+    cookies: &CookieJar<'_>,
+    database: Database,
+    provider: &State<OidcProvider>,
+    config: &State<Config>,
+    client: ClientInfo,
+    current_user: Option<AuthUser>,
+) -> Redirect {
+    let failure = || Redirect::to(format!("{}/auth/login?error=oidc", config.base_url()));
+    let (pending, claims) = match finish_login(provider, cookies, code, state, error).await {
+        Ok(result) => result,
+        Err(e) => {
+            warn!("OIDC login failed: {:#}", e);
+            return failure();
+        }
+    };
+
+    // Link identity to the logged in user
+    if let Some(link_user_id) = pending.link_user_id {
+        let profile = format!("{}/profile", config.base_url());
+        let user = match current_user.map(AuthUser::into_inner) {
+            Some(user) if user.id == link_user_id => user,
+            _ => {
+                warn!("OIDC linking failed: User {} is not logged in", link_user_id);
+                return Redirect::to(format!("{}?error=oidc", profile));
+            }
+        };
+        return match database.run(move |db| link_user(db, &user, claims)).await {
+            Ok(()) => Redirect::to(profile),
+            Err(e) => {
+                warn!("OIDC linking failed: {:#}", e);
+                Redirect::to(format!("{}?error=oidc", profile))
+            }
+        };
+    }
+
+    // Log in
+    let create_users = provider.config.as_ref().is_some_and(|config| config.create_users);
+    let user = match database
+        .run(move |db| resolve_user(db, claims, create_users))
+        .await
+    {
+        Ok(user) if !user.disabled => user,
+        Ok(user) => {
+            warn!("OIDC login failed: User {} is disabled", user.id);
+            return failure();
+        }
+        Err(e) => {
+            warn!("OIDC login failed: {:#}", e);
+            return failure();
+        }
+    };
+
+    // If two-factor authentication is enabled, a second step is required
+    let user_clone = user.clone();
+    if database
+        .run(move |db| data::is_totp_enabled(db, &user_clone))
+        .await
+    {
+        return match auth::start_login_challenge(cookies, &database, user).await {
+            Ok(()) => Redirect::to(format!("{}/auth/login?totp=required", config.base_url())),
+            Err(_) => failure(),
+        };
+    }
+
+    match auth::start_session(cookies, &database, client, user).await {
+        Ok(()) => Redirect::to(format!("{}/", config.base_url())),
+        Err(_) => failure(),
+    }
+}
+
+/// Return vec of all API routes.
+pub fn api_routes() -> Vec<Route> {
+    routes![info, login, link, link_nologin, callback]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    use diesel::prelude::*;
+    use rocket::{
+        http::{ContentType, Header},
+        local::blocking::{Client, LocalRequest},
+        serde::json::serde_json,
+    };
+
+    use crate::{
+        mail::Mailer,
+        test_utils::{make_test_config, DbTestContext, TEST_BASE_URL},
+    };
+
+    use super::*;
+
+    const CLIENT_ID: &str = "flugbuech";
+
+    /// A minimal identity provider that serves the discovery document and a
+    /// token endpoint returning an ID token with the configured claims.
+    struct MockProvider {
+        issuer: String,
+        claims: Arc<Mutex<serde_json::Value>>,
+    }
+
+    impl MockProvider {
+        fn start() -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let issuer = format!("http://{}", listener.local_addr().unwrap());
+            let claims = Arc::new(Mutex::new(serde_json::Value::Null));
+            let (thread_issuer, thread_claims) = (issuer.clone(), claims.clone());
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let mut stream = stream.unwrap();
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+                    // Read request line and headers, then skip the body
+                    let mut request_line = String::new();
+                    reader.read_line(&mut request_line).unwrap();
+                    let mut content_length = 0;
+                    loop {
+                        let mut line = String::new();
+                        reader.read_line(&mut line).unwrap();
+                        if line.trim().is_empty() {
+                            break;
+                        }
+                        if let Some(value) = line.to_lowercase().strip_prefix("content-length:") {
+                            content_length = value.trim().parse().unwrap();
+                        }
+                    }
+                    reader.read_exact(&mut vec![0; content_length]).unwrap();
+
+                    let body = if request_line.contains("/.well-known/openid-configuration") {
+                        serde_json::json!({
+                            "issuer": thread_issuer,
+                            "authorization_endpoint": format!("{}/authorize", thread_issuer),
+                            "token_endpoint": format!("{}/token", thread_issuer),
+                        })
+                    } else {
+                        let claims = thread_claims.lock().unwrap().to_string();
+                        let id_token = format!(
+                            "{}.{}.signature",
+                            URL_SAFE_NO_PAD.encode(r#"{"alg":"RS256"}"#),
+                            URL_SAFE_NO_PAD.encode(claims)
+                        );
+                        serde_json::json!({"access_token": "access", "token_type": "Bearer", "id_token": id_token})
+                    }
+                    .to_string();
+                    write!(
+                        stream,
+                        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    )
+                    .unwrap();
+                }
+            });
+            Self { issuer, claims }
+        }
+    }
+
+    fn make_client(issuer: &str) -> Client {
+        let oidc_config = OidcConfig {
+            issuer: issuer.to_string(),
+            client_id: CLIENT_ID.to_string(),
+            client_secret: "secret".to_string(),
+            redirect_uri: "http://localhost/api/v1/auth/oidc/callback".to_string(),
+            create_users: true,
+        };
+        let app = rocket::custom(make_test_config())
+            .attach(data::Database::fairing())
+            .manage(OidcProvider::new(Some(oidc_config)))
+            .manage(Mailer::from_config(&Default::default()).unwrap())
+            .manage(Config {
+                base_url: Some(TEST_BASE_URL.to_string()),
+                ..Default::default()
+            })
+            .mount("/", [api_routes(), auth::api_routes()].concat());
+        Client::tracked(app).expect("valid rocket instance")
+    }
+
+    /// Start a login, let the mock provider issue an ID token with the
+    /// specified claims and return the callback redirect location.
+    fn login(client: &Client, provider: &MockProvider, claims: serde_json::Value) -> String {
+        authorize(client, provider, "/auth/oidc/login", None, claims)
+    }
+
+    /// Start a login or linking at `path` (authenticated with the `auth`
+    /// cookie, if specified), let the mock provider issue an ID token with the
+    /// specified claims and return the callback redirect location.
+    fn authorize(
+        client: &Client,
+        provider: &MockProvider,
+        path: &str,
+        auth: Option<Cookie<'static>>,
+        claims: serde_json::Value,
+    ) -> String {
+        fn with_auth<'c>(request: LocalRequest<'c>, auth: &Option<Cookie<'static>>) -> LocalRequest<'c> {
+            match auth {
+                Some(cookie) => request
+                    .private_cookie(cookie.clone())
+                    .cookie(Cookie::new(auth::USER_COOKIE_NAME, "testuser")),
+                None => request,
+            }
+        }
+        let resp = with_auth(client.get(path), &auth).dispatch();
+        assert_eq!(resp.status(), Status::SeeOther);
+        let location = reqwest::Url::parse(resp.headers().get_one("Location").unwrap()).unwrap();
+        assert_eq!(location.path(), "/authorize");
+        let param = |name: &str| {
+            location
+                .query_pairs()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.to_string())
+                .unwrap()
+        };
+        assert_eq!(param("code_challenge_method"), "S256");
+
+        let mut claims = claims;
+        claims["iss"] = provider.issuer.clone().into();
+        claims["aud"] = CLIENT_ID.into();
+        claims["exp"] = (Utc::now().timestamp() + 300).into();
+        claims["nonce"] = param("nonce").into();
+        *provider.claims.lock().unwrap() = claims;
+
+        let resp = with_auth(
+            client.get(format!("/auth/oidc/callback?code=abc&state={}", param("state"))),
+            &auth,
+        )
+        .header(Header::new("User-Agent", "Test"))
+        .dispatch();
+        assert_eq!(resp.status(), Status::SeeOther);
+        resp.headers().get_one("Location").unwrap().to_string()
+    }
+
+    #[test]
+    fn id_token_claims() {
+        let claims = IdTokenClaims {
+            iss: "https://id.example.com".into(),
+            sub: "1234".into(),
+            aud: Audience::Multiple(vec!["other".into(), CLIENT_ID.into()]),
+            exp: 1000,
+            nonce: Some("n".into()),
+            email: None,
+            email_verified: false,
+            preferred_username: None,
+        };
+        assert!(validate_claims(&claims, "https://id.example.com", CLIENT_ID, "n", 999).is_ok());
+        assert!(validate_claims(&claims, "https://id.example.com", CLIENT_ID, "n", 1000).is_err());
+        assert!(validate_claims(&claims, "https://id.example.com", CLIENT_ID, "x", 999).is_err());
+        assert!(validate_claims(&claims, "https://id.example.com", "foreign", "n", 999).is_err());
+        assert!(validate_claims(&claims, "https://evil.example.com", CLIENT_ID, "n", 999).is_err());
+
+        // BASE64URL(SHA256(verifier)) without padding
+        assert_eq!(
+            code_challenge("dBjftJeZ4CVP-mJ92K1RqVxEb7xRNSK5x89SMRTTVRY"),
+            "fDmxmZ5ASuFaJZVSHmTx14Hhq2ukCv8fw2dpSQhcKCQ"
+        );
+    }
+
+    #[test]
+    fn login_link_and_create() {
+        let ctx = DbTestContext::new();
+        let provider = MockProvider::start();
+        let client = make_client(&provider.issuer);
+        let success = format!("{}/", TEST_BASE_URL);
+        let failure = format!("{}/auth/login?error=oidc", TEST_BASE_URL);
+
+        // Callback without pending login
+        let resp = client.get("/auth/oidc/callback?code=abc&state=xyz").dispatch();
+        assert_eq!(resp.headers().get_one("Location"), Some(failure.as_str()));
+
+        // Existing user with unverified e-mail address is not linked
+        let claims =
+            serde_json::json!({"sub": "ext-1", "email": "user1@example.com", "email_verified": true});
+        assert_eq!(login(&client, &provider, claims.clone()), failure);
+
+        // Existing user with verified e-mail address is linked
+        diesel::update(&ctx.testuser1.user)
+            .set(crate::schema::users::email_verified.eq(true))
+            .execute(&mut *ctx.force_get_conn())
+            .unwrap();
+        assert_eq!(login(&client, &provider, claims), success);
+        assert!(client.cookies().get_private(auth::SESSION_COOKIE_ID).is_some());
+        let linked = data::get_user_by_oidc_identity(&mut ctx.force_get_conn(), &provider.issuer, "ext-1");
+        assert_eq!(linked.map(|user| user.id), Some(ctx.testuser1.user.id));
+
+        // Linked identity is found by subject, even if the e-mail address changed
+        let claims = serde_json::json!({"sub": "ext-1", "email": "changed@example.com"});
+        assert_eq!(login(&client, &provider, claims), success);
+
+        // Unverified e-mail address of unknown identity
+        let claims = serde_json::json!({"sub": "ext-2", "email": "new@example.com", "email_verified": false});
+        assert_eq!(login(&client, &provider, claims), failure);
+
+        // New user, username is taken
+        let claims = serde_json::json!({
+            "sub": "ext-2",
+            "email": "new@example.com",
+            "email_verified": true,
+            "preferred_username": "testuser2",
+        });
+        assert_eq!(login(&client, &provider, claims), success);
+        let user =
+            data::get_user_by_oidc_identity(&mut ctx.force_get_conn(), &provider.issuer, "ext-2").unwrap();
+        assert_eq!(user.username, "testuser2-2");
+        assert_eq!(user.email, "new@example.com");
+        assert!(user.email_verified);
+
+        // Wrong nonce
+        let resp = client.get("/auth/oidc/login").dispatch();
+        let location = reqwest::Url::parse(resp.headers().get_one("Location").unwrap()).unwrap();
+        let state = location.query_pairs().find(|(key, _)| key == "state").unwrap().1;
+        *provider.claims.lock().unwrap() = serde_json::json!({
+            "iss": provider.issuer, "aud": CLIENT_ID, "exp": Utc::now().timestamp() + 300,
+            "nonce": "replayed", "sub": "ext-2",
+        });
+        let resp = client
+            .get(format!("/auth/oidc/callback?code=abc&state={}", state))
+            .dispatch();
+        assert_eq!(resp.headers().get_one("Location"), Some(failure.as_str()));
+    }
+
+    #[test]
+    fn totp_users() {
+        let ctx = DbTestContext::new();
+        let provider = MockProvider::start();
+        let client = make_client(&provider.issuer);
+        let failure = format!("{}/auth/login?error=oidc", TEST_BASE_URL);
+
+        // Enable two-factor authentication for a user with verified e-mail address
+        let recovery_codes = {
+            let mut conn = ctx.force_get_conn();
+            diesel::update(&ctx.testuser1.user)
+                .set(crate::schema::users::email_verified.eq(true))
+                .execute(&mut *conn)
+                .unwrap();
+            data::create_totp_credential(&mut conn, &ctx.testuser1.user).unwrap();
+            data::enable_totp(&mut conn, &ctx.testuser1.user, 0).unwrap()
+        };
+
+        // The identity is not linked automatically
+        let claims =
+            serde_json::json!({"sub": "ext-1", "email": "user1@example.com", "email_verified": true});
+        assert_eq!(login(&client, &provider, claims.clone()), failure);
+        assert!(client.cookies().get_private(auth::SESSION_COOKIE_ID).is_none());
+        let linked = data::get_user_by_oidc_identity(&mut ctx.force_get_conn(), &provider.issuer, "ext-1");
+        assert!(linked.is_none());
+
+        // Linking requires a login
+        let resp = client.get("/auth/oidc/link").dispatch();
+        assert_eq!(resp.status(), Status::Unauthorized);
+
+        // Link explicitly while logged in
+        let location = authorize(
+            &client,
+            &provider,
+            "/auth/oidc/link",
+            Some(ctx.auth_cookie_user1()),
+            claims.clone(),
+        );
+        assert_eq!(location, format!("{}/profile", TEST_BASE_URL));
+        let linked = data::get_user_by_oidc_identity(&mut ctx.force_get_conn(), &provider.issuer, "ext-1");
+        assert_eq!(linked.map(|user| user.id), Some(ctx.testuser1.user.id));
+
+        // The identity cannot be linked to another user
+        let location = authorize(
+            &client,
+            &provider,
+            "/auth/oidc/link",
+            Some(ctx.auth_cookie_user2()),
+            claims.clone(),
+        );
+        assert_eq!(location, format!("{}/profile?error=oidc", TEST_BASE_URL));
+
+        // Login with the linked identity requires a second step
+        assert_eq!(
+            login(&client, &provider, claims),
+            format!("{}/auth/login?totp=required", TEST_BASE_URL)
+        );
+        assert!(client.cookies().get_private(auth::SESSION_COOKIE_ID).is_none());
+        let resp = client
+            .post("/auth/login/totp")
+            .header(ContentType::JSON)
+            .body(format!(r#"{{"recoveryCode": "{}"}}"#, recovery_codes[0]))
+            .dispatch();
+        assert_eq!(resp.status(), Status::NoContent);
+        assert!(resp.cookies().get_private(auth::SESSION_COOKIE_ID).is_some());
+    }
+
+    #[test]
+    fn disabled() {
+        let client = Client::tracked(
+            rocket::custom(make_test_config())
+                .attach(data::Database::fairing())
+                .manage(OidcProvider::new(None))
+                .manage(Config::default())
+                .mount("/", api_routes()),
+        )
+        .unwrap();
+        let resp = client.get("/auth/oidc").dispatch();
+        assert_eq!(resp.into_string().unwrap(), r#"{"enabled":false}"#);
+        let resp = client.get("/auth/oidc/login").dispatch();
+        assert_eq!(resp.status(), Status::NotFound);
+    }
+}
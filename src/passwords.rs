@@ -0,0 +1,132 @@
+//! Password hashing.
+//!
+//! Passwords are hashed with Argon2id. Hashes created by earlier versions
+//! (bcrypt, hashed by pgcrypto in the database) are still accepted. They are
+//! replaced with an Argon2id hash on the next successful login, as are hashes
+//! created with outdated Argon2 parameters.
+
+use std::convert::TryFrom;
+
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Algorithm, Argon2, Params, Version,
+};
+use log::error;
+
+/// Argon2 memory cost in KiB.
+const ARGON2_MEMORY_KIB: u32 = 19 * 1024;
+
+/// Argon2 number of iterations.
+const ARGON2_ITERATIONS: u32 = 2;
+
+/// Argon2 degree of parallelism.
+const ARGON2_PARALLELISM: u32 = 1;
+
+/// Result of a password verification.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verification {
+    /// The password does not match the hash.
+    Invalid,
+    /// The password matches the hash. If `needs_rehash` is set, the hash uses
+    /// an outdated algorithm or outdated parameters.
+    Valid { needs_rehash: bool },
+}
+
+fn params() -> Params {
+    Params::new(ARGON2_MEMORY_KIB, ARGON2_ITERATIONS, ARGON2_PARALLELISM, None)
+        .expect("Invalid Argon2 parameters")
+}
+
+fn argon2() -> Argon2<'static> {
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params())
+}
+
+/// Hash a password with Argon2id and a random salt. Return the hash in PHC
+/// string format.
+pub fn hash_password(password: &str) -> String {
+    let salt = SaltString::generate(&mut OsRng);
+    argon2()
+        .hash_password(password.as_bytes(), &salt)
+        .expect("Could not hash password")
+        .to_string()
+}
+
+/// Verify a password against a hash. Both Argon2 and legacy bcrypt hashes are
+/// supported.
+pub fn verify_password(password: &str, hash: &str) -> Verification {
+    // Legacy bcrypt hash
+    if hash.starts_with("$2") {
+        return match bcrypt::verify(password, hash) {
+            Ok(true) => Verification::Valid { needs_rehash: true },
+            Ok(false) => Verification::Invalid,
+            Err(e) => {
+                error!("Could not verify bcrypt hash: {}", e);
+                Verification::Invalid
+            }
+        };
+    }
+
+    // Argon2 hash
+    let parsed = match PasswordHash::new(hash) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            error!("Could not parse password hash: {}", e);
+            return Verification::Invalid;
+        }
+    };
+    if argon2().verify_password(password.as_bytes(), &parsed).is_err() {
+        return Verification::Invalid;
+    }
+    let needs_rehash = parsed.algorithm != Algorithm::Argon2id.ident()
+        || Params::try_from(&parsed).map_or(true, |p| {
+            let current = params();
+            (p.m_cost(), p.t_cost(), p.p_cost()) != (current.m_cost(), current.t_cost(), current.p_cost())
+        });
+    Verification::Valid { needs_rehash }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_and_verify() {
+        let hash = hash_password("correct horse");
+        assert!(hash.starts_with("$argon2id$v=19$m=19456,t=2,p=1$"));
+        assert_ne!(hash, hash_password("correct horse"), "Salt must be random");
+        assert_eq!(
+            verify_password("correct horse", &hash),
+            Verification::Valid { needs_rehash: false }
+        );
+        assert_eq!(verify_password("battery staple", &hash), Verification::Invalid);
+        assert_eq!(verify_password("correct horse", "garbage"), Verification::Invalid);
+    }
+
+    #[test]
+    fn outdated_hashes() {
+        // Legacy bcrypt hash (as created by pgcrypto)
+        let bcrypt_hash = bcrypt::hash("correct horse", 4).unwrap().replace("$2b$", "$2a$");
+        assert_eq!(
+            verify_password("correct horse", &bcrypt_hash),
+            Verification::Valid { needs_rehash: true }
+        );
+        assert_eq!(
+            verify_password("battery staple", &bcrypt_hash),
+            Verification::Invalid
+        );
+
+        // Argon2 hash with other parameters
+        let weak = Argon2::new(
+            Algorithm::Argon2id,
+            Version::V0x13,
+            Params::new(1024, 1, 1, None).unwrap(),
+        )
+        .hash_password(b"correct horse", &SaltString::generate(&mut OsRng))
+        .unwrap()
+        .to_string();
+        assert_eq!(
+            verify_password("correct horse", &weak),
+            Verification::Valid { needs_rehash: true }
+        );
+    }
+}
@@ -0,0 +1,215 @@
+//! Polygon geography type, used for location areas.
+//!
+//! diesel-geography only provides points, so this implements the conversion
+//! from and to (E)WKB for polygons, as well as the GeoJSON representation used
+//! in the API. Spatial queries on areas (e.g. containment) are done by PostGIS.
+
+use std::io::{self, Read, Write};
+
+use diesel::{
+    deserialize::{self, FromSql},
+    pg::{Pg, PgValue},
+    serialize::{self, IsNull, Output, ToSql},
+    AsExpression, FromSqlRow,
+};
+use diesel_geography::sql_types::Geography;
+use rocket::serde::json::{json, Value};
+use serde::{Serialize, Serializer};
+
+/// WKB geometry type of polygons
+const WKB_POLYGON: u32 = 3;
+/// EWKB flag indicating that an SRID is present
+const EWKB_SRID_FLAG: u32 = 0x2000_0000;
+/// EWKB flags for Z and M coordinates
+const EWKB_ZM_FLAGS: u32 = 0xC000_0000;
+/// WGS 84
+const SRID_WGS84: u32 = 4326;
+
+/// A polygon with an outer ring and optional holes. Points are (lon, lat), all
+/// rings are closed.
+#[derive(Debug, Clone, PartialEq, FromSqlRow, AsExpression)]
+#[diesel(sql_type = Geography)]
+pub struct GeogPolygon {
+    pub rings: Vec<Vec<(f64, f64)>>,
+}
+
+impl GeogPolygon {
+    /// Parse and validate a GeoJSON polygon (geometry or feature).
+    pub fn from_geojson(value: &Value) -> Result<Self, &'static str> {
+        let geometry = match value["type"].as_str() {
+            Some("Feature") => &value["geometry"],
+            _ => value,
+        };
+        if geometry["type"].as_str() != Some("Polygon") {
+            return Err("Area must be a GeoJSON polygon");
+        }
+        let rings = geometry["coordinates"]
+            .as_array()
+            .ok_or("Polygon has no coordinates")?
+            .iter()
+            .map(|ring| {
+                let points = ring
+                    .as_array()
+                    .ok_or("Polygon ring must be an array")?
+                    .iter()
+                    .map(|point| {
+                        match (
+                            point.get(0).and_then(Value::as_f64),
+                            point.get(1).and_then(Value::as_f64),
+                        ) {
+                            (Some(lon), Some(lat))
+                                if (-180.0..=180.0).contains(&lon) && (-90.0..=90.0).contains(&lat) =>
+                            {
+                                Ok((lon, lat))
+                            }
+                            _ => Err("Polygon contains invalid coordinates"),
+                        }
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                if points.len() < 4 || points.first() != points.last() {
+                    return Err("Polygon rings must be closed and have at least four points");
+                }
+                Ok(points)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if rings.is_empty() {
+            return Err("Polygon has no coordinates");
+        }
+        Ok(Self { rings })
+    }
+
+    /// Return the GeoJSON polygon geometry.
+    pub fn to_geojson(&self) -> Value {
+        let coordinates = self
+            .rings
+            .iter()
+            .map(|ring| ring.iter().map(|&(lon, lat)| [lon, lat]).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        json!({
+            "type": "Polygon",
+            "coordinates": coordinates,
+        })
+    }
+
+    /// Return the center of the polygon as (lat, lon), i.e. the mean of the
+    /// outer ring vertices. This is good enough for the small areas of
+    /// locations.
+    pub fn center(&self) -> Option<(f64, f64)> {
+        let vertices = self.rings.first().map(|ring| &ring[1..])?;
+        let count = vertices.len() as f64;
+        Some((
+            vertices.iter().map(|(_, lat)| lat).sum::<f64>() / count,
+            vertices.iter().map(|(lon, _)| lon).sum::<f64>() / count,
+        ))
+    }
+}
+
+impl Serialize for GeogPolygon {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.to_geojson().serialize(serializer)
+    }
+}
+
+impl FromSql<Geography, Pg> for GeogPolygon {
+    fn from_sql(value: PgValue<'_>) -> deserialize::Result<Self> {
+        let mut bytes = value.as_bytes();
+        let mut byte_order = [0; 1];
+        bytes.read_exact(&mut byte_order)?;
+        let little_endian = match byte_order[0] {
+            0 => false,
+            1 => true,
+            _ => return Err("Invalid EWKB byte order".into()),
+        };
+        let read_u32 = |bytes: &mut &[u8]| -> io::Result<u32> {
+            let mut buf = [0; 4];
+            bytes.read_exact(&mut buf)?;
+            Ok(if little_endian {
+                u32::from_le_bytes(buf)
+            } else {
+                u32::from_be_bytes(buf)
+            })
+        };
+        let read_f64 = |bytes: &mut &[u8]| -> io::Result<f64> {
+            let mut buf = [0; 8];
+            bytes.read_exact(&mut buf)?;
+            Ok(if little_endian {
+                f64::from_le_bytes(buf)
+            } else {
+                f64::from_be_bytes(buf)
+            })
+        };
+
+        let geometry_type = read_u32(&mut bytes)?;
+        if geometry_type & EWKB_ZM_FLAGS != 0 || geometry_type & !EWKB_SRID_FLAG != WKB_POLYGON {
+            return Err(format!("Unsupported EWKB geometry type {:#x}", geometry_type).into());
+        }
+        if geometry_type & EWKB_SRID_FLAG != 0 {
+            read_u32(&mut bytes)?;
+        }
+        let ring_count = read_u32(&mut bytes)?;
+        let mut rings = Vec::new();
+        for _ in 0..ring_count {
+            let point_count = read_u32(&mut bytes)?;
+            let mut ring = Vec::new();
+            for _ in 0..point_count {
+                let lon = read_f64(&mut bytes)?;
+                let lat = read_f64(&mut bytes)?;
+                ring.push((lon, lat));
+            }
+            rings.push(ring);
+        }
+        Ok(Self { rings })
+    }
+}
+
+impl ToSql<Geography, Pg> for GeogPolygon {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(&[1])?;
+        out.write_all(&(WKB_POLYGON | EWKB_SRID_FLAG).to_le_bytes())?;
+        out.write_all(&SRID_WGS84.to_le_bytes())?;
+        out.write_all(&(self.rings.len() as u32).to_le_bytes())?;
+        for ring in &self.rings {
+            out.write_all(&(ring.len() as u32).to_le_bytes())?;
+            for (lon, lat) in ring {
+                out.write_all(&lon.to_le_bytes())?;
+                out.write_all(&lat.to_le_bytes())?;
+            }
+        }
+        Ok(IsNull::No)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_geojson() {
+        let feature = json!({
+            "type": "Feature",
+            "properties": {},
+            "geometry": {
+                "type": "Polygon",
+                "coordinates": [
+                    [[9.0, 46.0], [10.0, 46.0], [10.0, 47.0], [9.0, 47.0], [9.0, 46.0]],
+                    [[9.4, 46.4], [9.6, 46.4], [9.6, 46.6], [9.4, 46.6], [9.4, 46.4]],
+                ],
+            },
+        });
+        let polygon = GeogPolygon::from_geojson(&feature).unwrap();
+        assert_eq!(polygon.rings.len(), 2);
+        assert_eq!(polygon.to_geojson(), feature["geometry"]);
+        assert_eq!(polygon.center(), Some((46.5, 9.5)));
+
+        // Invalid geometries
+        for invalid in [
+            json!({"type": "Point", "coordinates": [9.0, 46.0]}),
+            json!({"type": "Polygon", "coordinates": []}),
+            json!({"type": "Polygon", "coordinates": [[[9.0, 46.0], [10.0, 46.0], [9.0, 46.0]]]}),
+            json!({"type": "Polygon", "coordinates": [[[9.0, 46.0], [10.0, 46.0], [10.0, 47.0], [9.0, 47.0]]]}),
+            json!({"type": "Polygon", "coordinates": [[[9.0, 96.0], [10.0, 46.0], [10.0, 47.0], [9.0, 96.0]]]}),
+        ] {
+            assert!(GeogPolygon::from_geojson(&invalid).is_err(), "{}", invalid);
+        }
+    }
+}
@@ -12,11 +12,11 @@ use rocket::{
     data::{Data, ToByteUnit},
     post, routes,
     serde::json::Json,
-    Route,
+    Route, State,
 };
 use serde::Serialize;
 
-use crate::{auth, data, models};
+use crate::{auth, data, geodata::Geodata, models};
 
 #[derive(Debug, PartialEq, Serialize)]
 struct LatLng {
@@ -32,6 +32,43 @@ struct LaunchLandingInfo {
     time_hms: (u8, u8, u8),
     #[serde(skip_serializing_if = "Option::is_none")]
     location_id: Option<i32>,
+    /// ID of a matching shared site, if no personal location matched. The
+    /// site can be copied to the user's locations.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    site_id: Option<i32>,
+    /// All matching locations and shared sites, ordered by distance. The
+    /// closest one is used for `location_id` or `site_id`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    candidates: Vec<LocationCandidate>,
+    /// Suggested new location, if neither a location nor a shared site
+    /// matched. It can be created through the regular location API.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    suggested_location: Option<Box<SuggestedLocation>>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LocationCandidate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    location_id: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    site_id: Option<i32>,
+    name: String,
+    /// Distance from the launch or landing point in meters.
+    distance: f64,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SuggestedLocation {
+    /// Name from the IGC site header or the nearest known place (if any).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    /// Country determined from offline country boundaries (if available).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    country_code: Option<String>,
+    /// Elevation according to the IGC altitude.
+    elevation: i32,
 }
 
 #[derive(Default, Debug, PartialEq, Serialize)]
@@ -62,6 +99,7 @@ pub struct FlightInfo {
 #[derive(Debug, Serialize)]
 #[serde(tag = "type")]
 #[serde(rename_all = "lowercase")]
+#[allow(clippy::large_enum_variant)]
 pub enum FlightInfoResult {
     Success(FlightInfo),
     Error { msg: String },
@@ -172,6 +210,9 @@ fn parse_igc(reader: impl BufRead, user: &models::User, db: &mut diesel::PgConne
                         alt: b.gps_alt,
                         time_hms: (b.timestamp.hours, b.timestamp.minutes, b.timestamp.seconds),
                         location_id: None,
+                        site_id: None,
+                        candidates: vec![],
+                        suggested_location: None,
                     });
                 } else {
                     info.landing = Some(LaunchLandingInfo {
@@ -179,6 +220,9 @@ fn parse_igc(reader: impl BufRead, user: &models::User, db: &mut diesel::PgConne
                         alt: b.gps_alt,
                         time_hms: (b.timestamp.hours, b.timestamp.minutes, b.timestamp.seconds),
                         location_id: None,
+                        site_id: None,
+                        candidates: vec![],
+                        suggested_location: None,
                     });
                 }
             }
@@ -192,24 +236,94 @@ fn parse_igc(reader: impl BufRead, user: &models::User, db: &mut diesel::PgConne
     }
     info.track_distance = flight_path.length();
 
-    // Find locations within 1000 meters of launch and landing
-    let max_distance = 1000.0;
+    // Find locations or shared sites within their matching radius of launch
+    // and landing, the closest one is preselected
+    let find_candidates = |db: &mut diesel::PgConnection, info: &mut LaunchLandingInfo, usage| {
+        info.candidates = data::get_locations_around_point(db, user, info.pos.lat, info.pos.lng, usage)
+            .into_iter()
+            .map(|location| LocationCandidate {
+                location_id: (!location.shared).then_some(location.id),
+                site_id: location.shared.then_some(location.id),
+                name: location.name,
+                distance: location.distance.round(),
+            })
+            .collect();
+        if let Some(closest) = info.candidates.first() {
+            info.location_id = closest.location_id;
+            info.site_id = closest.site_id;
+        }
+    };
     if let Some(ref mut launch) = info.launch {
-        launch.location_id =
-            data::get_locations_around_point(db, user, launch.pos.lat, launch.pos.lng, max_distance)
-                .get(0)
-                .map(|location| location.id);
+        find_candidates(db, launch, data::SiteUsage::Launch);
     }
     if let Some(ref mut landing) = info.landing {
-        landing.location_id =
-            data::get_locations_around_point(db, user, landing.pos.lat, landing.pos.lng, max_distance)
-                .get(0)
-                .map(|location| location.id);
+        find_candidates(db, landing, data::SiteUsage::Landing);
     }
 
     FlightInfoResult::Success(info)
 }
 
+/// A position fix of an IGC track (B record).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrackFix {
+    pub time_hms: (u8, u8, u8),
+    pub lat: f64,
+    pub lng: f64,
+    /// GPS altitude in meters (MSL)
+    pub gps_alt: i16,
+    /// Pressure altitude in meters (standard atmosphere), 0 if not recorded
+    pub pressure_alt: i16,
+}
+
+/// Return all position fixes of an IGC file. Lines that cannot be parsed are
+/// skipped.
+pub fn parse_track(igc: &[u8]) -> Vec<TrackFix> {
+    igc.split(|byte| *byte == b'\n')
+        .filter_map(|line_bytes| {
+            let line = String::from_utf8_lossy(line_bytes);
+            match Record::parse_line(line.trim()) {
+                Ok(Record::B(b)) => Some(TrackFix {
+                    time_hms: (b.timestamp.hours, b.timestamp.minutes, b.timestamp.seconds),
+                    lat: b.pos.lat.into(),
+                    lng: b.pos.lon.into(),
+                    gps_alt: b.gps_alt,
+                    pressure_alt: b.pressure_alt,
+                }),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Suggest new locations for launch and landing points that did not match
+/// an existing location or shared site.
+fn suggest_locations(info: &mut FlightInfo, geodata: &Geodata) {
+    // Maximal distance from launch or landing to a place to use its name
+    let max_place_distance = 10_000.0;
+    let suggest = |point: &LaunchLandingInfo, site_name: Option<&str>| SuggestedLocation {
+        name: site_name.map(str::to_string).or_else(|| {
+            geodata
+                .nearest_place(point.pos.lat, point.pos.lng, max_place_distance)
+                .map(|(place, _)| place.name.clone())
+        }),
+        country_code: geodata
+            .country_at(point.pos.lat, point.pos.lng)
+            .map(str::to_string),
+        elevation: i32::from(point.alt),
+    };
+    let site_name = info.site.as_deref().filter(|site| !site.is_empty());
+    if let Some(ref mut launch) = info.launch {
+        if launch.location_id.is_none() && launch.site_id.is_none() {
+            launch.suggested_location = Some(Box::new(suggest(launch, site_name)));
+        }
+    }
+    if let Some(ref mut landing) = info.landing {
+        if landing.location_id.is_none() && landing.site_id.is_none() {
+            landing.suggested_location = Some(Box::new(suggest(landing, None)));
+        }
+    }
+}
+
 /// Process IGC file, return parsed data.
 #[post(
     "/flights/add/process_igc",
@@ -219,6 +333,7 @@ fn parse_igc(reader: impl BufRead, user: &models::User, db: &mut diesel::PgConne
 pub async fn process_igc(
     user: auth::AuthUser,
     database: data::Database,
+    geodata: &State<Geodata>,
     data: Data<'_>,
 ) -> Json<FlightInfoResult> {
     let user = user.into_inner();
@@ -241,7 +356,11 @@ pub async fn process_igc(
     let buf_reader = BufReader::new(Cursor::new(igc_bytes));
 
     // Process data
-    Json(database.run(move |db| parse_igc(buf_reader, &user, db)).await)
+    let mut result = database.run(move |db| parse_igc(buf_reader, &user, db)).await;
+    if let FlightInfoResult::Success(ref mut info) = result {
+        suggest_locations(info, geodata);
+    }
+    Json(result)
 }
 
 /// Return vec of all API routes.
@@ -251,7 +370,10 @@ pub fn api_routes() -> Vec<Route> {
 
 #[cfg(test)]
 mod tests {
-    use crate::test_utils::DbTestContext;
+    use diesel_geography::types::GeogPoint;
+    use rocket::serde::json::{json, Value};
+
+    use crate::{polygon::GeogPolygon, test_utils::DbTestContext};
 
     use super::*;
 
@@ -282,7 +404,10 @@ mod tests {
                 },
                 alt: 1568,
                 time_hms: (13, 42, 26),
-                location_id: None
+                location_id: None,
+                site_id: None,
+                candidates: vec![],
+                suggested_location: None,
             })
         );
         assert_eq!(
@@ -295,6 +420,9 @@ mod tests {
                 alt: 1300,
                 time_hms: (13, 46, 7),
                 location_id: None,
+                site_id: None,
+                candidates: vec![],
+                suggested_location: None,
             })
         );
         assert!(
@@ -351,4 +479,165 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn suggest_new_locations() {
+        let data = include_str!("../testdata/skytraxx.igc");
+        let mut info = process(data).unwrap();
+        suggest_locations(&mut info, &crate::geodata::tests::test_geodata());
+
+        // Launch is named after the IGC site header
+        assert_eq!(
+            info.launch.unwrap().suggested_location,
+            Some(Box::new(SuggestedLocation {
+                name: Some("Hitzeggen".into()),
+                country_code: Some("CH".into()),
+                elevation: 1568,
+            }))
+        );
+
+        // Landing is named after the nearest place
+        assert_eq!(
+            info.landing.unwrap().suggested_location,
+            Some(Box::new(SuggestedLocation {
+                name: Some("Vrin".into()),
+                country_code: Some("CH".into()),
+                elevation: 1300,
+            }))
+        );
+    }
+
+    #[test]
+    fn suggest_new_locations_without_geodata() {
+        let data = include_str!("../testdata/skytraxx.igc");
+        let mut info = process(data).unwrap();
+        info.site = None;
+        suggest_locations(&mut info, &Geodata::default());
+        assert_eq!(
+            info.landing.unwrap().suggested_location,
+            Some(Box::new(SuggestedLocation {
+                name: None,
+                country_code: None,
+                elevation: 1300,
+            }))
+        );
+    }
+
+    #[test]
+    fn match_candidates_by_radius() {
+        let ctx = DbTestContext::new();
+        let create_location = |name: &str, lat: f64, match_radius: Option<i32>| {
+            data::create_location(
+                &mut ctx.force_get_conn(),
+                models::NewLocation {
+                    name: name.into(),
+                    country: "CH".into(),
+                    elevation: 1300,
+                    user_id: ctx.testuser1.user.id,
+                    geog: Some(diesel_geography::types::GeogPoint {
+                        x: 9.153933,
+                        y: lat,
+                        srid: None,
+                    }),
+                    match_radius,
+                    area: None,
+                },
+            )
+            .id
+        };
+
+        // Landing is at 46.70665 / 9.153933, launch about 1.5 km further north
+        let near = create_location("Near", 46.7093, None);
+        let wide = create_location("Wide", 46.7201, Some(2000));
+        create_location("Far", 46.6932, None);
+
+        let data = include_str!("../testdata/skytraxx.igc");
+        let reader = BufReader::new(Cursor::new(data));
+        let info = match parse_igc(reader, &ctx.testuser1.user, &mut ctx.force_get_conn()) {
+            FlightInfoResult::Success(info) => info,
+            FlightInfoResult::Error { msg } => panic!("{}", msg),
+        };
+
+        let landing = info.landing.unwrap();
+        assert_eq!(landing.location_id, Some(near));
+        let candidates: Vec<_> = landing
+            .candidates
+            .iter()
+            .map(|candidate| (candidate.location_id, candidate.name.as_str()))
+            .collect();
+        assert_eq!(candidates, vec![(Some(near), "Near"), (Some(wide), "Wide")]);
+        assert!(landing.candidates[0].distance < landing.candidates[1].distance);
+
+        let launch = info.launch.unwrap();
+        assert_eq!(launch.location_id, Some(wide));
+        assert_eq!(launch.candidates.len(), 1);
+    }
+
+    #[test]
+    fn match_candidates_by_area() {
+        let ctx = DbTestContext::new();
+        let create_location = |name: &str, area: Value, with_coordinates: bool| {
+            let area = GeogPolygon::from_geojson(&area).unwrap();
+            let (lat, lon) = area.center().unwrap();
+            data::create_location(
+                &mut ctx.force_get_conn(),
+                models::NewLocation {
+                    name: name.into(),
+                    country: "CH".into(),
+                    elevation: 1300,
+                    user_id: ctx.testuser1.user.id,
+                    geog: with_coordinates.then_some(GeogPoint {
+                        x: lon,
+                        y: lat,
+                        srid: None,
+                    }),
+                    match_radius: None,
+                    area: Some(area),
+                },
+            )
+            .id
+        };
+
+        // Landing is at 46.70665 / 9.153933: The field contains the landing,
+        // the ramp is close but does not contain it and the landing is in the
+        // hole of the meadow
+        let field = create_location(
+            "Field",
+            json!({"type": "Polygon", "coordinates": [[[9.150, 46.700], [9.160, 46.700], [9.160, 46.710], [9.150, 46.710], [9.150, 46.700]]]}),
+            true,
+        );
+        create_location(
+            "Ramp",
+            json!({"type": "Polygon", "coordinates": [[[9.1545, 46.7070], [9.1550, 46.7070], [9.1550, 46.7075], [9.1545, 46.7070]]]}),
+            true,
+        );
+        create_location(
+            "Meadow",
+            json!({"type": "Polygon", "coordinates": [
+                [[9.140, 46.690], [9.170, 46.690], [9.170, 46.712], [9.140, 46.712], [9.140, 46.690]],
+                [[9.150, 46.705], [9.158, 46.705], [9.158, 46.708], [9.150, 46.708], [9.150, 46.705]],
+            ]}),
+            true,
+        );
+
+        // Launch is about 1.5 km further north, in an area without coordinates
+        let slope = create_location(
+            "Slope",
+            json!({"type": "Polygon", "coordinates": [[[9.140, 46.715], [9.170, 46.715], [9.170, 46.730], [9.140, 46.730], [9.140, 46.715]]]}),
+            false,
+        );
+
+        let data = include_str!("../testdata/skytraxx.igc");
+        let reader = BufReader::new(Cursor::new(data));
+        let info = match parse_igc(reader, &ctx.testuser1.user, &mut ctx.force_get_conn()) {
+            FlightInfoResult::Success(info) => info,
+            FlightInfoResult::Error { msg } => panic!("{}", msg),
+        };
+
+        let landing = info.landing.unwrap();
+        assert_eq!(landing.location_id, Some(field));
+        assert_eq!(landing.candidates.len(), 1);
+        assert_eq!(landing.candidates[0].distance, 0.0);
+        assert_eq!(info.launch.unwrap().location_id, Some(slope));
+    }
 }
@@ -2,25 +2,41 @@
 
 use chrono::{DateTime, Utc};
 use log::error;
-use rocket::{get, http::Status, post, routes, serde::json::Json, Route};
+use rocket::{get, http::Status, post, routes, serde::json::Json, Route, State};
 use serde::{Deserialize, Serialize};
 
-use crate::{auth, data, responders::ApiError};
+use crate::{
+    auth, data,
+    locations::MAX_MATCH_RADIUS_METERS,
+    mail::Mailer,
+    responders::{ApiError, RocketError},
+    Config,
+};
 
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ApiProfile {
     username: String,
     email: String,
+    email_verified: bool,
     signed_up: DateTime<Utc>,
     news_opt_in: bool,
+    /// Default radius in meters for matching IGC launch and landing points
+    /// to locations
+    match_radius: i32,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct ApiProfileUpdate {
     #[serde(skip_serializing_if = "Option::is_none")]
     news_opt_in: Option<bool>,
+    /// A new e-mail address. The change only takes effect once the new
+    /// address has been confirmed through the link sent to it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    email: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    match_radius: Option<i32>,
 }
 
 #[get("/profile")]
@@ -29,8 +45,10 @@ pub fn get(user: auth::AuthUser) -> Json<ApiProfile> {
     Json(ApiProfile {
         username: user.username,
         email: user.email,
+        email_verified: user.email_verified,
         signed_up: user.signed_up,
         news_opt_in: user.news_opt_in,
+        match_radius: user.match_radius,
     })
 }
 
@@ -39,19 +57,101 @@ pub fn get_nologin() -> ApiError {
     ApiError::MissingAuthentication
 }
 
+/// Update the profile of the logged in user.
+///
+/// - Return "HTTP 204 No Content" if the profile was updated.
+/// - Return "HTTP 400 Bad Request" if the data is invalid.
+/// - Return "HTTP 403 Forbidden" if the e-mail address should be changed
+///   through an API token. Otherwise, a token could be used to take over the
+///   account through a password reset.
 #[post("/profile", data = "<data>")]
-pub async fn edit(database: data::Database, user: auth::AuthUser, data: Json<ApiProfileUpdate>) -> Status {
+pub async fn edit(
+    database: data::Database,
+    mailer: &State<Mailer>,
+    config: &State<Config>,
+    user: auth::AuthUser,
+    data: Json<ApiProfileUpdate>,
+) -> Result<Status, (Status, Json<RocketError>)> {
+    let authenticated_by_token = user.session_id().is_none();
     let user = user.into_inner();
-    if let Some(news_opt_in) = data.news_opt_in {
+    let ApiProfileUpdate {
+        news_opt_in,
+        email,
+        match_radius,
+    } = data.into_inner();
+
+    // Validate matching radius
+    if let Some(radius) = match_radius {
+        if !(1..=MAX_MATCH_RADIUS_METERS).contains(&radius) {
+            return Err(RocketError::new(
+                Status::BadRequest,
+                "InvalidData",
+                format!("Matching radius must be between 1 and {MAX_MATCH_RADIUS_METERS} meters"),
+            ));
+        }
+    }
+
+    // Validate new e-mail address
+    let new_email = email
+        .map(|email| email.trim().to_string())
+        .filter(|email| *email != user.email);
+    if let Some(email) = new_email.clone() {
+        if authenticated_by_token {
+            return Err(RocketError::new(
+                Status::Forbidden,
+                "Forbidden",
+                "The e-mail address cannot be changed with an API token",
+            ));
+        }
+        let user = user.clone();
+        database
+            .run(move |db| data::validate_email_change(db, &user, &email))
+            .await
+            .map_err(|e| {
+                RocketError::new(
+                    Status::BadRequest,
+                    "InvalidData",
+                    format!("Invalid e-mail address: {e}"),
+                )
+            })?;
+    }
+
+    if let Some(news_opt_in) = news_opt_in {
+        let user = user.clone();
         if let Err(e) = database
             .run(move |db| data::update_news_opt_in(db, &user, news_opt_in))
             .await
         {
             error!("Updating user's news opt-in failed: {e}");
-            return Status::InternalServerError;
+            return Err(RocketError::new(
+                Status::InternalServerError,
+                "IoError",
+                "Could not update news opt-in",
+            ));
         }
     }
-    Status::NoContent
+
+    if let Some(match_radius) = match_radius {
+        let user = user.clone();
+        if let Err(e) = database
+            .run(move |db| data::update_match_radius(db, &user, match_radius))
+            .await
+        {
+            error!("Updating user's matching radius failed: {e}");
+            return Err(RocketError::new(
+                Status::InternalServerError,
+                "IoError",
+                "Could not update matching radius",
+            ));
+        }
+    }
+
+    // Send confirmation link to new e-mail address
+    if let Some(email) = new_email {
+        auth::send_email_verification(&database, mailer, config, user, email).await;
+    }
+
+    Ok(Status::NoContent)
 }
 
 #[post("/profile", rank = 2)]
@@ -72,30 +172,33 @@ mod tests {
     use diesel::PgConnection;
     use rocket::{
         self,
-        http::{ContentType, Status},
+        http::{ContentType, Header, Status},
         local::blocking::Client,
         serde::json,
     };
 
     use crate::{
-        data,
-        test_utils::{make_test_config, DbTestContext},
+        auth, data,
+        test_utils::{make_test_config, DbTestContext, TestMailbox},
     };
 
     use super::*;
 
     /// Create a new test client with cookie tracking.
-    fn make_api_client() -> Client {
+    fn make_api_client(mailbox: &TestMailbox) -> Client {
         let app = rocket::custom(make_test_config())
             .attach(data::Database::fairing())
-            .mount("/", api_routes());
+            .manage(mailbox.mailer())
+            .manage(mailbox.config())
+            .mount("/", [api_routes(), auth::api_routes()].concat());
         Client::tracked(app).expect("valid rocket instance")
     }
 
     #[test]
     fn update_news_opt_in() {
         let ctx = DbTestContext::new();
-        let client = make_api_client();
+        let mailbox = TestMailbox::new();
+        let client = make_api_client(&mailbox);
 
         /// Helper function: Assert news opt-in state
         fn assert_news_opt_in(conn_mutex: &Mutex<PgConnection>, user_id: i32, opted_in: bool) {
@@ -113,6 +216,7 @@ mod tests {
                     .body(
                         json::to_string(&ApiProfileUpdate {
                             news_opt_in: Some($opt_in),
+                            ..Default::default()
                         })
                         .unwrap(),
                     )
@@ -140,4 +244,171 @@ mod tests {
         assert_eq!(resp3.status(), Status::NoContent);
         assert_news_opt_in(&ctx.conn, ctx.testuser1.user.id, false);
     }
+
+    #[test]
+    fn update_match_radius() {
+        let ctx = DbTestContext::new();
+        let mailbox = TestMailbox::new();
+        let client = make_api_client(&mailbox);
+
+        macro_rules! update_profile_match_radius {
+            ($radius:expr) => {
+                client
+                    .post("/profile")
+                    .header(ContentType::JSON)
+                    .body(
+                        json::to_string(&ApiProfileUpdate {
+                            match_radius: Some($radius),
+                            ..Default::default()
+                        })
+                        .unwrap(),
+                    )
+                    .private_cookie(ctx.auth_cookie_user1())
+                    .cookie(ctx.username_cookie())
+                    .dispatch()
+            };
+        }
+        let get_match_radius = || {
+            data::get_user(&mut ctx.force_get_conn(), ctx.testuser1.user.id)
+                .unwrap()
+                .match_radius
+        };
+
+        // Default
+        assert_eq!(get_match_radius(), 1000);
+
+        // Update
+        let resp = update_profile_match_radius!(250);
+        assert_eq!(resp.status(), Status::NoContent);
+        assert_eq!(get_match_radius(), 250);
+
+        // Invalid values
+        for radius in [0, -5, MAX_MATCH_RADIUS_METERS + 1] {
+            let resp = update_profile_match_radius!(radius);
+            assert_eq!(resp.status(), Status::BadRequest);
+        }
+        assert_eq!(get_match_radius(), 250);
+    }
+
+    #[test]
+    fn change_email() {
+        let ctx = DbTestContext::new();
+        let mailbox = TestMailbox::new();
+        let client = make_api_client(&mailbox);
+
+        macro_rules! update_profile_email {
+            ($email:expr) => {
+                client
+                    .post("/profile")
+                    .header(ContentType::JSON)
+                    .body(
+                        json::to_string(&ApiProfileUpdate {
+                            email: Some($email.into()),
+                            ..Default::default()
+                        })
+                        .unwrap(),
+                    )
+                    .private_cookie(ctx.auth_cookie_user1())
+                    .cookie(ctx.username_cookie())
+                    .dispatch()
+            };
+        }
+        macro_rules! get_user {
+            () => {
+                data::get_user(&mut ctx.force_get_conn(), ctx.testuser1.user.id).unwrap()
+            };
+        }
+
+        // Invalid or taken addresses are rejected
+        assert_eq!(update_profile_email!("invalid").status(), Status::BadRequest);
+        assert_eq!(
+            update_profile_email!("user2@example.com").status(),
+            Status::BadRequest
+        );
+        assert_eq!(
+            update_profile_email!("User2@Example.com").status(),
+            Status::BadRequest
+        );
+
+        // Unchanged address: No mail is sent
+        assert_eq!(
+            update_profile_email!("user1@example.com").status(),
+            Status::NoContent
+        );
+        assert!(mailbox.mails().is_empty());
+
+        // Change address: The change is only applied after confirmation
+        assert_eq!(
+            update_profile_email!("new@example.com").status(),
+            Status::NoContent
+        );
+        assert_eq!(get_user!().email, "user1@example.com");
+        let mails = mailbox.mails();
+        assert_eq!(mails.len(), 1);
+        assert!(mails[0].contains("To: new@example.com"));
+
+        // Confirm
+        let token = mailbox.last_token("/auth/email/verify").unwrap();
+        let resp = client
+            .post("/auth/email/verify")
+            .header(ContentType::JSON)
+            .body(format!(r#"{{"token": "{}"}}"#, token))
+            .dispatch();
+        assert_eq!(resp.status(), Status::NoContent);
+        let user = get_user!();
+        assert_eq!(user.email, "new@example.com");
+        assert!(user.email_verified);
+
+        // Profile contains the new address
+        let resp = client
+            .get("/profile")
+            .private_cookie(ctx.auth_cookie_user1())
+            .cookie(ctx.username_cookie())
+            .dispatch();
+        let profile = resp.into_string().unwrap();
+        assert!(profile.contains(r#""email":"new@example.com","emailVerified":true"#));
+    }
+
+    #[test]
+    fn change_email_with_token() {
+        let ctx = DbTestContext::new();
+        let mailbox = TestMailbox::new();
+        let client = make_api_client(&mailbox);
+
+        let (_, token) = data::create_api_token(
+            &mut ctx.force_get_conn(),
+            &ctx.testuser1.user,
+            "Script",
+            &["read".to_string(), "write".to_string()],
+        )
+        .unwrap();
+        macro_rules! update_profile {
+            ($body:expr) => {
+                client
+                    .post("/profile")
+                    .header(ContentType::JSON)
+                    .header(Header::new("Authorization", format!("Bearer {}", token)))
+                    .body(json::to_string(&$body).unwrap())
+                    .dispatch()
+            };
+        }
+
+        // Other settings can be changed with a write token
+        let resp = update_profile!(ApiProfileUpdate {
+            news_opt_in: Some(true),
+            email: Some("user1@example.com".into()),
+            ..Default::default()
+        });
+        assert_eq!(resp.status(), Status::NoContent);
+
+        // The e-mail address cannot
+        let resp = update_profile!(ApiProfileUpdate {
+            email: Some("attacker@example.com".into()),
+            ..Default::default()
+        });
+        assert_eq!(resp.status(), Status::Forbidden);
+        assert!(mailbox.mails().is_empty());
+        let user = data::get_user(&mut ctx.force_get_conn(), ctx.testuser1.user.id).unwrap();
+        assert_eq!(user.email, "user1@example.com");
+    }
 }
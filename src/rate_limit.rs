@@ -0,0 +1,634 @@
+//! Login rate limiting.
+//!
+//! Failed logins are counted per client IP and per username (for failed TOTP
+//! codes in the second login step, the username of the pending login is
+//! taken from the login challenge cookie). After
+//! `max_attempts` consecutive failures, further attempts are rejected with
+//! "HTTP 429 Too Many Requests" for a lockout period that doubles with every
+//! additional failure (up to `max_lockout_seconds`).
+//!
+//! Password reset requests always succeed, so every request is counted (per
+//! client IP and per e-mail address, separately from logins).
+//!
+//! Attempts are stored in memory by default. Deployments with multiple
+//! instances can store them in the database instead.
+
+use std::{collections::HashMap, sync::Mutex};
+
+use chrono::{DateTime, Duration, Utc};
+use log::{error, warn};
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    http::{Header, Method, Status},
+    outcome::Outcome,
+    request::{self, FromRequest},
+    serde::json,
+    Data, Orbit, Request, Response, Rocket,
+};
+use serde::Deserialize;
+
+use crate::{
+    auth,
+    data::{self, Database},
+    models::LoginAttempts,
+};
+
+/// Number of body bytes that are inspected to find the username.
+const PEEK_BYTES: usize = 1024;
+
+/// Number of keys in the memory store above which stale entries are removed.
+const MEMORY_STORE_PRUNE_THRESHOLD: usize = 10_000;
+
+// Config
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RateLimitStore {
+    #[default]
+    Memory,
+    Database,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// Whether login rate limiting is enabled.
+    pub enabled: bool,
+    /// Number of consecutive failed attempts after which a client IP or
+    /// username is locked.
+    pub max_attempts: u32,
+    /// Duration of the first lockout. It doubles with every further failure.
+    pub lockout_seconds: u32,
+    /// Maximum duration of a lockout.
+    pub max_lockout_seconds: u32,
+    /// Failed attempts are forgotten after this period without failures.
+    pub window_seconds: u32,
+    /// Where failed attempts are stored.
+    pub store: RateLimitStore,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_attempts: 5,
+            lockout_seconds: 30,
+            max_lockout_seconds: 3600,
+            window_seconds: 900,
+            store: RateLimitStore::Memory,
+        }
+    }
+}
+
+// Backoff
+
+/// Return the lockout duration after the specified number of consecutive
+/// failures, or `None` if the key should not be locked.
+pub fn lockout_duration(config: &RateLimitConfig, failures: i32) -> Option<Duration> {
+    let excess = failures - config.max_attempts as i32;
+    if excess < 0 {
+        return None;
+    }
+    let seconds = i64::from(config.lockout_seconds)
+        .saturating_mul(1 << excess.min(32))
+        .min(i64::from(config.max_lockout_seconds));
+    Some(Duration::seconds(seconds))
+}
+
+/// Return the attempts after registering a failed attempt at `now`.
+pub fn register_failure(
+    config: &RateLimitConfig,
+    key: &str,
+    previous: Option<LoginAttempts>,
+    now: DateTime<Utc>,
+) -> LoginAttempts {
+    let failures = match previous {
+        Some(previous) if !is_stale(config, &previous, now) => previous.failures + 1,
+        _ => 1,
+    };
+    LoginAttempts {
+        key: key.to_string(),
+        failures,
+        last_failure_at: now,
+        locked_until: lockout_duration(config, failures).map(|duration| now + duration),
+    }
+}
+
+/// Return the remaining lockout duration, if the attempts are locked at `now`.
+pub fn remaining_lockout(attempts: &LoginAttempts, now: DateTime<Utc>) -> Option<Duration> {
+    attempts
+        .locked_until
+        .filter(|locked_until| *locked_until > now)
+        .map(|locked_until| locked_until - now)
+}
+
+/// Return whether the attempts can be forgotten at `now`.
+fn is_stale(config: &RateLimitConfig, attempts: &LoginAttempts, now: DateTime<Utc>) -> bool {
+    let last_activity = attempts.locked_until.map_or(attempts.last_failure_at, |until| {
+        until.max(attempts.last_failure_at)
+    });
+    now - last_activity >= Duration::seconds(i64::from(config.window_seconds))
+}
+
+// Fairing
+
+/// Kind of a rate limited request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LimitedRequest {
+    /// Login with username and password.
+    Login,
+    /// Second login step with a TOTP code.
+    LoginTotp,
+    /// Password reset request.
+    PasswordReset,
+}
+
+/// Rate limiting verdict for the current request, determined by the fairing.
+#[derive(Debug, Clone)]
+enum Verdict {
+    /// The request is not subject to rate limiting.
+    NotApplicable,
+    /// The request is allowed. It will be counted for these keys.
+    Allowed {
+        kind: LimitedRequest,
+        ip_key: Option<String>,
+        user_key: Option<String>,
+    },
+    /// The request is rejected.
+    Locked(Duration),
+}
+
+#[derive(Deserialize)]
+struct LoginUsername {
+    username: String,
+}
+
+#[derive(Deserialize)]
+struct ResetEmail {
+    email: String,
+}
+
+/// Fairing that counts failed logins and locks out clients and usernames.
+///
+/// Login routes and the password reset request route must use the
+/// `LoginAllowed` request guard, which rejects requests that are locked.
+pub struct LoginRateLimiter {
+    config: RateLimitConfig,
+    memory: Mutex<HashMap<String, LoginAttempts>>,
+}
+
+impl LoginRateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            memory: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn load(&self, rocket: &Rocket<Orbit>, key: String) -> Option<LoginAttempts> {
+        match self.config.store {
+            RateLimitStore::Memory => self.memory.lock().unwrap().get(&key).cloned(),
+            RateLimitStore::Database => {
+                let database = Database::get_one(rocket).await?;
+                database
+                    .run(move |db| data::get_login_attempts(db, &key))
+                    .await
+                    .unwrap_or_else(|e| {
+                        error!("Could not load login attempts: {}", e);
+                        None
+                    })
+            }
+        }
+    }
+
+    /// Register a failed attempt for the key and return the updated attempts.
+    ///
+    /// The read-modify-write is atomic, so that concurrent failures are all
+    /// counted.
+    async fn register_failure(&self, rocket: &Rocket<Orbit>, key: String) -> Option<LoginAttempts> {
+        let now = Utc::now();
+        match self.config.store {
+            RateLimitStore::Memory => {
+                let mut memory = self.memory.lock().unwrap();
+                if memory.len() >= MEMORY_STORE_PRUNE_THRESHOLD {
+                    memory.retain(|_, attempts| !is_stale(&self.config, attempts, now));
+                }
+                let attempts = register_failure(&self.config, &key, memory.remove(&key), now);
+                memory.insert(key, attempts.clone());
+                Some(attempts)
+            }
+            RateLimitStore::Database => {
+                let database = Database::get_one(rocket).await?;
+                let config = self.config.clone();
+                database
+                    .run(move |db| {
+                        data::register_login_failure(
+                            db,
+                            &key,
+                            now,
+                            Duration::seconds(i64::from(config.window_seconds)),
+                            |failures| lockout_duration(&config, failures),
+                        )
+                    })
+                    .await
+                    .map_err(|e| error!("Could not save login attempts: {}", e))
+                    .ok()
+            }
+        }
+    }
+
+    async fn clear(&self, rocket: &Rocket<Orbit>, key: String) {
+        match self.config.store {
+            RateLimitStore::Memory => {
+                self.memory.lock().unwrap().remove(&key);
+            }
+            RateLimitStore::Database => {
+                if let Some(database) = Database::get_one(rocket).await {
+                    if let Err(e) = database
+                        .run(move |db| data::delete_login_attempts(db, &key))
+                        .await
+                    {
+                        error!("Could not delete login attempts: {}", e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Return the kind of the request, if it is subject to rate limiting.
+fn limited_request(request: &Request<'_>) -> Option<LimitedRequest> {
+    if request.method() != Method::Post {
+        return None;
+    }
+    let path = request.uri().path();
+    if path.as_str().ends_with("/auth/login") {
+        Some(LimitedRequest::Login)
+    } else if path.as_str().ends_with("/auth/login/totp") {
+        Some(LimitedRequest::LoginTotp)
+    } else if path.as_str().ends_with("/auth/password/reset/request") {
+        Some(LimitedRequest::PasswordReset)
+    } else {
+        None
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for LoginRateLimiter {
+    fn info(&self) -> Info {
+        Info {
+            name: "Login rate limiter",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, data: &mut Data<'_>) {
+        if !self.config.enabled {
+            return;
+        }
+        let kind = match limited_request(request) {
+            Some(kind) => kind,
+            None => return,
+        };
+
+        // Determine keys
+        let body = data.peek(PEEK_BYTES).await;
+        let (ip_key, user_key) = match kind {
+            LimitedRequest::Login => (
+                request.client_ip().map(|ip| format!("ip:{}", ip)),
+                json::from_slice::<LoginUsername>(body)
+                    .ok()
+                    .map(|login| format!("user:{}", login.username.trim().to_lowercase())),
+            ),
+            LimitedRequest::LoginTotp => (
+                request.client_ip().map(|ip| format!("ip:{}", ip)),
+                auth::get_login_challenge(request.cookies())
+                    .map(|challenge| format!("user:{}", challenge.username.trim().to_lowercase())),
+            ),
+            LimitedRequest::PasswordReset => (
+                request.client_ip().map(|ip| format!("reset-ip:{}", ip)),
+                json::from_slice::<ResetEmail>(body)
+                    .ok()
+                    .map(|reset| format!("reset-email:{}", reset.email.trim().to_lowercase())),
+            ),
+        };
+
+        // Check for lockout
+        let now = Utc::now();
+        let mut remaining: Option<Duration> = None;
+        for key in ip_key.iter().chain(user_key.iter()) {
+            if let Some(attempts) = self.load(request.rocket(), key.clone()).await {
+                if let Some(duration) = remaining_lockout(&attempts, now) {
+                    remaining = Some(remaining.map_or(duration, |r| r.max(duration)));
+                }
+            }
+        }
+
+        let verdict = match remaining {
+            Some(duration) => {
+                warn!(
+                    "Rejecting {:?} request, locked for {}s",
+                    kind,
+                    duration.num_seconds()
+                );
+                Verdict::Locked(duration)
+            }
+            None => Verdict::Allowed {
+                kind,
+                ip_key,
+                user_key,
+            },
+        };
+        request.local_cache(|| verdict);
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        match request.local_cache(|| Verdict::NotApplicable) {
+            Verdict::NotApplicable => {}
+            Verdict::Locked(duration) => {
+                // Round up to full seconds
+                let seconds = (duration.num_milliseconds() + 999) / 1000;
+                response.set_header(Header::new("Retry-After", seconds.to_string()));
+            }
+            Verdict::Allowed {
+                kind,
+                ip_key,
+                user_key,
+            } => {
+                let status = response.status();
+                let counted = match kind {
+                    LimitedRequest::Login | LimitedRequest::LoginTotp => status == Status::Forbidden,
+                    LimitedRequest::PasswordReset => true,
+                };
+                if counted {
+                    for key in ip_key.iter().chain(user_key.iter()) {
+                        let attempts = self.register_failure(request.rocket(), key.clone()).await;
+                        if let Some(attempts) = attempts.filter(|attempts| attempts.locked_until.is_some()) {
+                            warn!("Locking {} after {} attempts", key, attempts.failures);
+                        }
+                    }
+                } else if *kind != LimitedRequest::PasswordReset && status == Status::NoContent {
+                    // Only the username is reset. Otherwise, an attacker with
+                    // a valid account could reset the lockout of their IP.
+                    if let Some(key) = user_key {
+                        self.clear(request.rocket(), key.clone()).await;
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Request guard
+
+/// Request guard that rejects login attempts that are locked by the
+/// `LoginRateLimiter` fairing with "HTTP 429 Too Many Requests".
+pub struct LoginAllowed;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for LoginAllowed {
+    type Error = ();
+
+    async fn from_request(request: &'r rocket::Request<'_>) -> request::Outcome<Self, Self::Error> {
+        match request.local_cache(|| Verdict::NotApplicable) {
+            Verdict::Locked(_) => Outcome::Error((Status::TooManyRequests, ())),
+            _ => Outcome::Success(LoginAllowed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use rocket::{
+        catchers,
+        http::{ContentType, Cookie},
+        local::blocking::Client,
+    };
+
+    use crate::{
+        mail::Mailer,
+        test_utils::{make_test_config, DbTestContext},
+        Config,
+    };
+
+    use super::*;
+
+    fn test_config(store: RateLimitStore) -> RateLimitConfig {
+        RateLimitConfig {
+            max_attempts: 3,
+            lockout_seconds: 60,
+            store,
+            ..Default::default()
+        }
+    }
+
+    /// Create a new test client. Cookie tracking is disabled.
+    fn make_client(config: RateLimitConfig) -> Client {
+        let app = rocket::custom(make_test_config())
+            .attach(data::Database::fairing())
+            .attach(LoginRateLimiter::new(config))
+            .manage(Mailer::from_config(&Default::default()).unwrap())
+            .manage(Config::default())
+            .register("/", catchers![crate::too_many_requests])
+            .mount("/", auth::api_routes());
+        Client::untracked(app).expect("valid rocket instance")
+    }
+
+    #[test]
+    fn backoff() {
+        let config = test_config(RateLimitStore::Memory);
+        let now = Utc::now();
+        assert_eq!(lockout_duration(&config, 2), None);
+        assert_eq!(lockout_duration(&config, 3), Some(Duration::seconds(60)));
+        assert_eq!(lockout_duration(&config, 4), Some(Duration::seconds(120)));
+        assert_eq!(lockout_duration(&config, 5), Some(Duration::seconds(240)));
+        assert_eq!(lockout_duration(&config, 100), Some(Duration::seconds(3600)));
+
+        // Failures are counted
+        let attempts = register_failure(&config, "k", None, now);
+        assert_eq!(attempts.failures, 1);
+        assert_eq!(remaining_lockout(&attempts, now), None);
+        let attempts = register_failure(&config, "k", Some(attempts), now);
+        let attempts = register_failure(&config, "k", Some(attempts), now);
+        assert_eq!(attempts.failures, 3);
+        assert_eq!(remaining_lockout(&attempts, now), Some(Duration::seconds(60)));
+        assert_eq!(remaining_lockout(&attempts, now + Duration::seconds(60)), None);
+
+        // Failures after the lockout increase the lockout
+        let later = now + Duration::seconds(61);
+        let attempts = register_failure(&config, "k", Some(attempts), later);
+        assert_eq!(attempts.failures, 4);
+        assert_eq!(remaining_lockout(&attempts, later), Some(Duration::seconds(120)));
+
+        // Failures are forgotten after the window
+        let much_later = later + Duration::seconds(120 + 900);
+        let attempts = register_failure(&config, "k", Some(attempts), much_later);
+        assert_eq!(attempts.failures, 1);
+    }
+
+    #[test]
+    fn database_backoff() {
+        let ctx = DbTestContext::new();
+        let config = test_config(RateLimitStore::Database);
+        let window = Duration::seconds(i64::from(config.window_seconds));
+        // Whole seconds, the database stores microseconds only
+        let now = DateTime::from_timestamp(Utc::now().timestamp(), 0).unwrap();
+        let register = |now| {
+            data::register_login_failure(&mut ctx.force_get_conn(), "k", now, window, |failures| {
+                lockout_duration(&config, failures)
+            })
+            .unwrap()
+        };
+
+        // The database store counts like the memory store
+        let mut expected = None;
+        for offset in [0, 0, 0, 61, 61 + 120 + 900] {
+            let now = now + Duration::seconds(offset);
+            let attempts = register(now);
+            expected = Some(register_failure(&config, "k", expected, now));
+            assert_eq!(Some(&attempts), expected.as_ref());
+        }
+        assert_eq!(expected.unwrap().failures, 1);
+    }
+
+    fn login_status(
+        client: &Client,
+        remote: &str,
+        username: &str,
+        password: &str,
+    ) -> (Status, Option<String>) {
+        let resp = client
+            .post("/auth/login")
+            .header(ContentType::JSON)
+            .remote(remote.parse::<SocketAddr>().unwrap())
+            .body(format!(
+                r#"{{"username": "{}", "password": "{}"}}"#,
+                username, password
+            ))
+            .dispatch();
+        let retry_after = resp.headers().get_one("Retry-After").map(ToString::to_string);
+        let status = resp.status();
+        if status == Status::TooManyRequests {
+            assert!(resp
+                .into_string()
+                .unwrap()
+                .contains(r#""reason":"TooManyRequests""#));
+        }
+        (status, retry_after)
+    }
+
+    fn lockout(store: RateLimitStore) {
+        let ctx = DbTestContext::new();
+        let client = make_client(test_config(store));
+        let attacker = "203.0.113.1:1234";
+
+        // Failed logins
+        for _ in 0..3 {
+            let (status, _) = login_status(&client, attacker, "testuser1", "wrong");
+            assert_eq!(status, Status::Forbidden);
+        }
+
+        // Locked, even with the correct password
+        let (status, retry_after) = login_status(&client, attacker, "testuser1", &ctx.testuser1.password);
+        assert_eq!(status, Status::TooManyRequests);
+        assert_eq!(retry_after.as_deref(), Some("60"));
+
+        // The username is locked for other IPs as well
+        let (status, _) = login_status(&client, "198.51.100.1:1234", "testuser1", &ctx.testuser1.password);
+        assert_eq!(status, Status::TooManyRequests);
+
+        // The IP is locked for other usernames as well
+        let (status, _) = login_status(&client, attacker, "testuser2", &ctx.testuser2.password);
+        assert_eq!(status, Status::TooManyRequests);
+
+        // Other users from other IPs can log in
+        let (status, _) = login_status(&client, "198.51.100.1:1234", "testuser2", &ctx.testuser2.password);
+        assert_eq!(status, Status::NoContent);
+    }
+
+    #[test]
+    fn password_reset_lockout() {
+        let ctx = DbTestContext::new();
+        let client = make_client(test_config(RateLimitStore::Memory));
+        let reset_status = |remote: &str, email: &str| {
+            client
+                .post("/auth/password/reset/request")
+                .header(ContentType::JSON)
+                .remote(remote.parse::<SocketAddr>().unwrap())
+                .body(format!(r#"{{"email": "{}"}}"#, email))
+                .dispatch()
+                .status()
+        };
+
+        // Every request is counted, even though all of them succeed
+        for _ in 0..3 {
+            assert_eq!(
+                reset_status("203.0.113.1:1234", "user1@example.com"),
+                Status::NoContent
+            );
+        }
+        assert_eq!(
+            reset_status("203.0.113.1:1234", "user2@example.com"),
+            Status::TooManyRequests
+        );
+
+        // The e-mail address is locked for other IPs as well
+        assert_eq!(
+            reset_status("198.51.100.1:1234", "User1@Example.com"),
+            Status::TooManyRequests
+        );
+
+        // Reset requests don't lock logins
+        let (status, _) = login_status(&client, "203.0.113.1:1234", "testuser1", &ctx.testuser1.password);
+        assert_eq!(status, Status::NoContent);
+    }
+
+    #[test]
+    fn totp_lockout() {
+        let ctx = DbTestContext::new();
+        let client = make_client(test_config(RateLimitStore::Memory));
+        let challenge = data::create_login_challenge(
+            &mut ctx.force_get_conn(),
+            &ctx.testuser1.user,
+            Duration::minutes(5),
+        )
+        .unwrap();
+
+        // Wrong codes are counted for the user of the login challenge
+        for _ in 0..3 {
+            let status = client
+                .post("/auth/login/totp")
+                .header(ContentType::JSON)
+                .remote("203.0.113.1:1234".parse::<SocketAddr>().unwrap())
+                .private_cookie(Cookie::new(
+                    "login_challenge",
+                    format!("{}:testuser1", challenge.id),
+                ))
+                .body(r#"{"code": "000000"}"#)
+                .dispatch()
+                .status();
+            assert_eq!(status, Status::Forbidden);
+        }
+        let (status, _) = login_status(&client, "198.51.100.1:1234", "testuser1", &ctx.testuser1.password);
+        assert_eq!(status, Status::TooManyRequests);
+    }
+
+    #[test]
+    fn lockout_memory_store() {
+        lockout(RateLimitStore::Memory);
+    }
+
+    #[test]
+    fn lockout_database_store() {
+        lockout(RateLimitStore::Database);
+        let ctx = DbTestContext::new();
+        let client = make_client(test_config(RateLimitStore::Database));
+        login_status(&client, "203.0.113.1:1234", "TestUser1", "wrong");
+        let attempts = data::get_login_attempts(&mut ctx.force_get_conn(), "user:testuser1")
+            .unwrap()
+            .unwrap();
+        assert_eq!(attempts.failures, 1);
+    }
+}
@@ -1,5 +1,91 @@
 #![allow(unused_imports)]
 
+table! {
+    use diesel::sql_types::*;
+    use diesel_geography::sql_types::*;
+
+    api_tokens (id) {
+        id -> Int4,
+        user_id -> Int4,
+        name -> Text,
+        token_hash -> Text,
+        token_prefix -> Text,
+        scopes -> Array<Text>,
+        created_at -> Timestamptz,
+        last_used_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use diesel_geography::sql_types::*;
+
+    currency_rules (id) {
+        id -> Int4,
+        user_id -> Int4,
+        name -> Text,
+        min_flights -> Nullable<Int4>,
+        min_seconds -> Nullable<Int4>,
+        window_days -> Int4,
+        glider_id -> Nullable<Int4>,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use diesel_geography::sql_types::*;
+
+    email_verification_tokens (id) {
+        id -> Int4,
+        user_id -> Int4,
+        email -> Text,
+        token_hash -> Text,
+        created_at -> Timestamptz,
+        expires_at -> Timestamptz,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use diesel_geography::sql_types::*;
+
+    equipment (id) {
+        id -> Int4,
+        user_id -> Int4,
+        kind -> Text,
+        manufacturer -> Varchar,
+        model -> Varchar,
+        since -> Nullable<Date>,
+        until -> Nullable<Date>,
+        source -> Nullable<Text>,
+        cost -> Nullable<Int4>,
+        comment -> Nullable<Text>,
+        repack_interval_months -> Nullable<Int4>,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use diesel_geography::sql_types::*;
+
+    equipment_repacks (id) {
+        id -> Int4,
+        equipment_id -> Int4,
+        date -> Date,
+        notes -> Nullable<Text>,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use diesel_geography::sql_types::*;
+
+    flight_equipment (flight_id, equipment_id) {
+        flight_id -> Int4,
+        equipment_id -> Int4,
+    }
+}
+
 table! {
     use diesel::sql_types::*;
     use diesel_geography::sql_types::*;
@@ -21,6 +107,39 @@ table! {
         video_url -> Nullable<Text>,
         created_at -> Timestamptz,
         hikeandfly -> Bool,
+        wind_direction -> Nullable<Int2>,
+        wind_speed -> Nullable<Int2>,
+        thermal_strength -> Nullable<Float4>,
+        cloud_base -> Nullable<Int4>,
+        turbulence -> Nullable<Int2>,
+        weather_notes -> Nullable<Text>,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use diesel_geography::sql_types::*;
+
+    glider_maintenance_events (id) {
+        id -> Int4,
+        glider_id -> Int4,
+        kind -> Text,
+        date -> Date,
+        notes -> Nullable<Text>,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use diesel_geography::sql_types::*;
+
+    glider_maintenance_intervals (id) {
+        id -> Int4,
+        glider_id -> Int4,
+        kind -> Text,
+        interval_seconds -> Nullable<Int4>,
+        interval_flights -> Nullable<Int4>,
+        interval_months -> Nullable<Int4>,
     }
 }
 
@@ -62,6 +181,89 @@ table! {
         elevation -> Int4,
         user_id -> Int4,
         geog -> Nullable<Geography>,
+        site_id -> Nullable<Int4>,
+        match_radius -> Nullable<Int4>,
+        area -> Nullable<Geography>,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use diesel_geography::sql_types::*;
+
+    login_attempts (key) {
+        key -> Text,
+        failures -> Int4,
+        last_failure_at -> Timestamptz,
+        locked_until -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use diesel_geography::sql_types::*;
+
+    login_challenges (id) {
+        id -> Int4,
+        user_id -> Int4,
+        created_at -> Timestamptz,
+        failures -> Int4,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use diesel_geography::sql_types::*;
+
+    oidc_identities (id) {
+        id -> Int4,
+        user_id -> Int4,
+        issuer -> Text,
+        subject -> Text,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use diesel_geography::sql_types::*;
+
+    password_reset_tokens (id) {
+        id -> Int4,
+        user_id -> Int4,
+        token_hash -> Text,
+        created_at -> Timestamptz,
+        expires_at -> Timestamptz,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use diesel_geography::sql_types::*;
+
+    sessions (id) {
+        id -> Int4,
+        user_id -> Int4,
+        token_hash -> Text,
+        user_agent -> Nullable<Text>,
+        ip_address -> Nullable<Text>,
+        created_at -> Timestamptz,
+        last_seen_at -> Timestamptz,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use diesel_geography::sql_types::*;
+
+    sites (id) {
+        id -> Int4,
+        name -> Text,
+        country -> Bpchar,
+        elevation -> Int4,
+        geog -> Geography,
+        site_type -> Text,
+        aliases -> Array<Text>,
     }
 }
 
@@ -78,6 +280,31 @@ table! {
     }
 }
 
+table! {
+    use diesel::sql_types::*;
+    use diesel_geography::sql_types::*;
+
+    totp_credentials (user_id) {
+        user_id -> Int4,
+        secret -> Text,
+        enabled -> Bool,
+        last_used_step -> Nullable<Int8>,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    use diesel::sql_types::*;
+    use diesel_geography::sql_types::*;
+
+    totp_recovery_codes (id) {
+        id -> Int4,
+        user_id -> Int4,
+        code_hash -> Text,
+        used_at -> Nullable<Timestamptz>,
+    }
+}
+
 table! {
     use diesel::sql_types::*;
     use diesel_geography::sql_types::*;
@@ -90,12 +317,56 @@ table! {
         email -> Text,
         signed_up -> Timestamptz,
         news_opt_in -> Bool,
+        email_verified -> Bool,
+        is_admin -> Bool,
+        disabled -> Bool,
+        match_radius -> Int4,
     }
 }
 
+joinable!(api_tokens -> users (user_id));
+joinable!(currency_rules -> gliders (glider_id));
+joinable!(currency_rules -> users (user_id));
+joinable!(email_verification_tokens -> users (user_id));
+joinable!(equipment -> users (user_id));
+joinable!(equipment_repacks -> equipment (equipment_id));
+joinable!(flight_equipment -> equipment (equipment_id));
+joinable!(flight_equipment -> flights (flight_id));
 joinable!(flights -> gliders (glider_id));
 joinable!(flights -> users (user_id));
+joinable!(glider_maintenance_events -> gliders (glider_id));
+joinable!(glider_maintenance_intervals -> gliders (glider_id));
 joinable!(igcs -> flights (flight_id));
+joinable!(locations -> sites (site_id));
 joinable!(locations -> users (user_id));
+joinable!(login_challenges -> users (user_id));
+joinable!(oidc_identities -> users (user_id));
+joinable!(password_reset_tokens -> users (user_id));
+joinable!(sessions -> users (user_id));
+joinable!(totp_credentials -> users (user_id));
+joinable!(totp_recovery_codes -> users (user_id));
 
-allow_tables_to_appear_in_same_query!(flights, gliders, igcs, locations, spatial_ref_sys, users,);
+allow_tables_to_appear_in_same_query!(
+    api_tokens,
+    currency_rules,
+    email_verification_tokens,
+    equipment,
+    equipment_repacks,
+    flight_equipment,
+    flights,
+    glider_maintenance_events,
+    glider_maintenance_intervals,
+    gliders,
+    igcs,
+    locations,
+    login_attempts,
+    login_challenges,
+    oidc_identities,
+    password_reset_tokens,
+    sessions,
+    sites,
+    spatial_ref_sys,
+    totp_credentials,
+    totp_recovery_codes,
+    users,
+);
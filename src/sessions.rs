@@ -0,0 +1,271 @@
+//! Login session management.
+//!
+//! Every login creates a server-side session. Sessions can be listed and
+//! revoked, e.g. to log out a lost device.
+
+use chrono::{DateTime, Utc};
+use rocket::{delete, get, http::CookieJar, http::Status, routes, serde::json::Json, Route};
+use serde::{Deserialize, Serialize};
+
+use crate::{auth, data, models::Session, responders::ApiError};
+
+// API types
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiSession {
+    id: i32,
+    /// User agent of the client that logged in
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user_agent: Option<String>,
+    /// IP address of the client that logged in
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ip_address: Option<String>,
+    created_at: DateTime<Utc>,
+    last_seen_at: DateTime<Utc>,
+    /// Whether this is the session of the current request
+    current: bool,
+}
+
+impl ApiSession {
+    fn from_session(session: Session, current_id: Option<i32>) -> Self {
+        Self {
+            id: session.id,
+            user_agent: session.user_agent,
+            ip_address: session.ip_address,
+            created_at: session.created_at,
+            last_seen_at: session.last_seen_at,
+            current: Some(session.id) == current_id,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiSessions {
+    sessions: Vec<ApiSession>,
+}
+
+// API endpoints
+
+#[get("/auth/sessions")]
+pub async fn list(database: data::Database, user: auth::AuthUser) -> Json<ApiSessions> {
+    let current_id = user.session_id();
+    let user = user.into_inner();
+    let sessions = database
+        .run(move |db| data::get_sessions_for_user(db, &user))
+        .await
+        .into_iter()
+        .map(|session| ApiSession::from_session(session, current_id))
+        .collect();
+    Json(ApiSessions { sessions })
+}
+
+#[get("/auth/sessions", rank = 2)]
+pub fn list_nologin() -> ApiError {
+    ApiError::MissingAuthentication
+}
+
+/// Revoke a single session. If the current session is revoked, the auth
+/// cookies are removed as well.
+#[delete("/auth/sessions/<id>")]
+pub async fn delete(
+    user: auth::AuthUser,
+    database: data::Database,
+    cookies: &CookieJar<'_>,
+    id: i32,
+) -> Result<Status, ApiError> {
+    let current_id = user.session_id();
+    let user = user.into_inner();
+
+    // Get session and check ownership
+    database
+        .run(move |db| data::get_session_by_id(db, id))
+        .await
+        .filter(|session| session.user_id == user.id)
+        .ok_or(ApiError::NotFound)?;
+
+    // Revoke session
+    database
+        .run(move |db| data::delete_session_by_id(db, id))
+        .await
+        .map_err(|e| {
+            log::error!("Could not delete session with ID {}: {}", id, e);
+            ApiError::IoError {
+                message: "Could not delete session".into(),
+            }
+        })?;
+    log::info!("Revoked session with ID {}", id);
+    if current_id == Some(id) {
+        auth::remove_auth_cookies(cookies);
+    }
+    Ok(Status::NoContent)
+}
+
+#[delete("/auth/sessions/<id>", rank = 2)]
+#[allow(unused_variables)]
+pub fn delete_nologin(id: i32) -> ApiError {
+    ApiError::MissingAuthentication
+}
+
+/// Revoke all sessions except for the current one.
+#[delete("/auth/sessions")]
+pub async fn delete_all(user: auth::AuthUser, database: data::Database) -> Status {
+    let current_id = user.session_id();
+    let user = user.into_inner();
+    auth::revoke_sessions(&database, &user, current_id).await;
+    Status::NoContent
+}
+
+#[delete("/auth/sessions", rank = 2)]
+pub fn delete_all_nologin() -> ApiError {
+    ApiError::MissingAuthentication
+}
+
+/// Return vec of all API routes.
+pub fn api_routes() -> Vec<Route> {
+    routes![
+        list,
+        list_nologin,
+        delete,
+        delete_nologin,
+        delete_all,
+        delete_all_nologin
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::{
+        self,
+        http::{ContentType, Cookie, Header},
+        local::blocking::Client,
+    };
+
+    use crate::{
+        mail::Mailer,
+        test_utils::{make_test_config, DbTestContext},
+        Config,
+    };
+
+    use super::*;
+
+    /// Create a new test client. Cookie tracking is disabled.
+    fn make_client() -> Client {
+        let app = rocket::custom(make_test_config())
+            .attach(data::Database::fairing())
+            .manage(Mailer::from_config(&Default::default()).unwrap())
+            .manage(Config::default())
+            .mount("/", [api_routes(), auth::api_routes()].concat());
+        Client::untracked(app).expect("valid rocket instance")
+    }
+
+    #[test]
+    fn list_and_revoke_sessions() {
+        let ctx = DbTestContext::new();
+        let client = make_client();
+
+        // Log in from a second device
+        let resp = client
+            .post("/auth/login")
+            .header(ContentType::JSON)
+            .header(Header::new("User-Agent", "Phone"))
+            .body(r#"{"username": "testuser1", "password": "testpass"}"#)
+            .dispatch();
+        assert_eq!(resp.status(), Status::NoContent);
+        let phone_cookie: Cookie<'static> = resp
+            .cookies()
+            .get_private(auth::SESSION_COOKIE_ID)
+            .unwrap()
+            .into_owned();
+
+        macro_rules! list_sessions {
+            ($cookie:expr) => {
+                client
+                    .get("/auth/sessions")
+                    .private_cookie($cookie)
+                    .cookie(ctx.username_cookie())
+                    .dispatch()
+            };
+        }
+
+        // List sessions
+        let resp = list_sessions!(ctx.auth_cookie_user1());
+        assert_eq!(resp.status(), Status::Ok);
+        let sessions = resp.into_json::<ApiSessions>().unwrap().sessions;
+        assert_eq!(sessions.len(), 2);
+        let phone = sessions.iter().find(|s| !s.current).unwrap();
+        assert_eq!(phone.user_agent.as_deref(), Some("Phone"));
+        assert_eq!(sessions.iter().filter(|s| s.current).count(), 1);
+
+        // Other users cannot revoke the session
+        let resp = client
+            .delete(format!("/auth/sessions/{}", phone.id))
+            .private_cookie(ctx.auth_cookie_user2())
+            .cookie(ctx.username_cookie())
+            .dispatch();
+        assert_eq!(resp.status(), Status::NotFound);
+
+        // Revoke phone session
+        let resp = client
+            .delete(format!("/auth/sessions/{}", phone.id))
+            .private_cookie(ctx.auth_cookie_user1())
+            .cookie(ctx.username_cookie())
+            .dispatch();
+        assert_eq!(resp.status(), Status::NoContent);
+        let resp = list_sessions!(phone_cookie.clone());
+        assert_eq!(resp.status(), Status::Unauthorized);
+
+        // Log out: The session is revoked on the server
+        let resp = client
+            .post("/auth/logout")
+            .private_cookie(ctx.auth_cookie_user2())
+            .cookie(ctx.username_cookie())
+            .dispatch();
+        assert_eq!(resp.status(), Status::NoContent);
+        let resp = list_sessions!(ctx.auth_cookie_user2());
+        assert_eq!(resp.status(), Status::Unauthorized);
+    }
+
+    #[test]
+    fn revoke_sessions_on_password_change() {
+        let ctx = DbTestContext::new();
+        let client = make_client();
+
+        // Create additional sessions
+        for _ in 0..2 {
+            data::create_session(&mut ctx.force_get_conn(), &ctx.testuser1.user, None, None).unwrap();
+        }
+        let count_sessions =
+            || data::get_sessions_for_user(&mut ctx.force_get_conn(), &ctx.testuser1.user).len();
+        assert_eq!(count_sessions(), 3);
+
+        // Change password: Only the current session remains
+        let resp = client
+            .post("/auth/password/change")
+            .header(ContentType::JSON)
+            .body(r#"{"currentPassword": "testpass", "newPassword": "newpassword"}"#)
+            .private_cookie(ctx.auth_cookie_user1())
+            .cookie(ctx.username_cookie())
+            .dispatch();
+        assert_eq!(resp.status(), Status::NoContent);
+        assert_eq!(count_sessions(), 1);
+
+        // Create more sessions, then revoke all other sessions
+        data::create_session(&mut ctx.force_get_conn(), &ctx.testuser1.user, None, None).unwrap();
+        assert_eq!(count_sessions(), 2);
+        let resp = client
+            .delete("/auth/sessions")
+            .private_cookie(ctx.auth_cookie_user1())
+            .cookie(ctx.username_cookie())
+            .dispatch();
+        assert_eq!(resp.status(), Status::NoContent);
+        assert_eq!(count_sessions(), 1);
+        let resp = client
+            .get("/auth/sessions")
+            .private_cookie(ctx.auth_cookie_user1())
+            .cookie(ctx.username_cookie())
+            .dispatch();
+        assert_eq!(resp.status(), Status::Ok);
+    }
+}
@@ -0,0 +1,576 @@
+//! Shared site catalog.
+//!
+//! Sites are public launch and landing sites that are shared by all users.
+//! They are maintained by admins. Users can copy a site to their personal
+//! locations, the copy stays linked to the site.
+
+use std::collections::HashSet;
+
+use diesel_geography::types::GeogPoint;
+use rocket::{delete, get, http::Status, post, routes, serde::json::Json, Route};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth, data, geodata,
+    locations::ApiCoordinates,
+    models::{NewSite, Site},
+    responders::ApiError,
+};
+
+/// Valid values for the site type.
+pub const SITE_TYPES: [&str; 3] = ["launch", "landing", "both"];
+
+/// Default number of sites returned by the site search.
+const DEFAULT_SITE_LIMIT: i64 = 100;
+
+/// Maximum number of sites returned by the site search.
+const MAX_SITE_LIMIT: i64 = 1000;
+
+// API types
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiSite {
+    id: i32,
+    name: String,
+    country_code: String,
+    elevation: i32,
+    coordinates: ApiCoordinates,
+    site_type: String,
+    aliases: Vec<String>,
+}
+
+impl From<Site> for ApiSite {
+    fn from(site: Site) -> Self {
+        Self {
+            id: site.id,
+            name: site.name,
+            country_code: site.country,
+            elevation: site.elevation,
+            coordinates: ApiCoordinates {
+                lon: site.geog.x,
+                lat: site.geog.y,
+            },
+            site_type: site.site_type,
+            aliases: site.aliases,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ApiSites {
+    sites: Vec<ApiSite>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiCopiedSite {
+    /// ID of the personal location linked to the site
+    location_id: i32,
+}
+
+// Forms
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SiteAddUpdateForm {
+    name: String,
+    country_code: String,
+    elevation: i32,
+    coordinates: ApiCoordinates,
+    site_type: Option<String>,
+    aliases: Option<Vec<String>>,
+}
+
+impl SiteAddUpdateForm {
+    /// Validate and normalize the form data.
+    fn into_new_site(self) -> Result<NewSite, ApiError> {
+        let name = self.name.trim().to_string();
+        if name.is_empty() {
+            return Err(ApiError::InvalidData {
+                message: "Site name must not be empty".into(),
+            });
+        }
+        let country = self.country_code.trim().to_ascii_uppercase();
+        if country == geodata::UNKNOWN_COUNTRY || !geodata::is_valid_country_code(&country) {
+            return Err(ApiError::InvalidData {
+                message: format!("Invalid ISO 3166-1 country code: {}", self.country_code),
+            });
+        }
+        let ApiCoordinates { lon, lat } = self.coordinates;
+        if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+            return Err(ApiError::InvalidData {
+                message: format!("Invalid coordinates: {}, {}", lat, lon),
+            });
+        }
+        let site_type = self.site_type.unwrap_or_else(|| "both".into());
+        if !SITE_TYPES.contains(&site_type.as_str()) {
+            return Err(ApiError::InvalidData {
+                message: format!("Invalid site type: {}", site_type),
+            });
+        }
+        // Remove empty and duplicate (case insensitive) aliases, keeping the order
+        let mut seen = HashSet::new();
+        let aliases: Vec<String> = self
+            .aliases
+            .unwrap_or_default()
+            .iter()
+            .map(|alias| alias.trim().to_string())
+            .filter(|alias| !alias.is_empty() && seen.insert(alias.to_lowercase()))
+            .collect();
+        Ok(NewSite {
+            name,
+            country,
+            elevation: self.elevation,
+            geog: GeogPoint {
+                x: lon,
+                y: lat,
+                srid: None,
+            },
+            site_type,
+            aliases,
+        })
+    }
+}
+
+// API endpoints
+
+/// Search shared sites by name or alias.
+#[get("/sites?<search>&<limit>")]
+pub async fn list(
+    _user: auth::AuthUser,
+    database: data::Database,
+    search: Option<String>,
+    limit: Option<i64>,
+) -> Json<ApiSites> {
+    let limit = limit.unwrap_or(DEFAULT_SITE_LIMIT).clamp(1, MAX_SITE_LIMIT);
+    let sites = database
+        .run(move |db| data::search_sites(db, search.as_deref().filter(|s| !s.trim().is_empty()), limit))
+        .await
+        .into_iter()
+        .map(Into::into)
+        .collect();
+    Json(ApiSites { sites })
+}
+
+#[get("/sites", rank = 2)]
+pub fn list_nologin() -> ApiError {
+    ApiError::MissingAuthentication
+}
+
+#[get("/sites/<id>")]
+pub async fn get(
+    _user: auth::AuthUser,
+    database: data::Database,
+    id: i32,
+) -> Result<Json<ApiSite>, ApiError> {
+    database
+        .run(move |db| data::get_site_by_id(db, id))
+        .await
+        .map(|site| Json(site.into()))
+        .ok_or(ApiError::NotFound)
+}
+
+#[get("/sites/<_id>", rank = 2)]
+pub fn get_nologin(_id: i32) -> ApiError {
+    ApiError::MissingAuthentication
+}
+
+/// Copy a shared site to the personal locations of the user. If the site was
+/// already copied, the existing location is returned.
+#[post("/sites/<id>/copy")]
+pub async fn copy(
+    user: auth::AuthUser,
+    database: data::Database,
+    id: i32,
+) -> Result<Json<ApiCopiedSite>, ApiError> {
+    let user = user.into_inner();
+    let site = database
+        .run(move |db| data::get_site_by_id(db, id))
+        .await
+        .ok_or(ApiError::NotFound)?;
+    let user_id = user.id;
+    database
+        .run(move |db| data::get_or_create_location_from_site(db, &user, &site))
+        .await
+        .map(|location| {
+            Json(ApiCopiedSite {
+                location_id: location.id,
+            })
+        })
+        .map_err(|e| {
+            log::error!("Could not copy site {} for user {}: {}", id, user_id, e);
+            ApiError::IoError {
+                message: "Could not copy site".into(),
+            }
+        })
+}
+
+#[post("/sites/<_id>/copy", rank = 2)]
+pub fn copy_nologin(_id: i32) -> ApiError {
+    ApiError::MissingAuthentication
+}
+
+/// Add a shared site (admins only).
+#[post("/sites", data = "<data>")]
+pub async fn add(
+    _admin: auth::AdminUser,
+    database: data::Database,
+    data: Json<SiteAddUpdateForm>,
+) -> Result<(Status, Json<ApiSite>), ApiError> {
+    let site = data.into_inner().into_new_site()?;
+    match database.run(move |db| data::create_site(db, site)).await {
+        Ok(site) => {
+            log::info!("Created site {}", site.id);
+            Ok((Status::Created, Json(site.into())))
+        }
+        Err(e) => {
+            log::error!("Could not create site: {}", e);
+            Err(ApiError::IoError {
+                message: "Could not create site".into(),
+            })
+        }
+    }
+}
+
+#[post("/sites", rank = 2)]
+pub fn add_nologin() -> ApiError {
+    ApiError::MissingAuthentication
+}
+
+/// Update a shared site (admins only).
+#[post("/sites/<id>", data = "<data>")]
+pub async fn edit(
+    _admin: auth::AdminUser,
+    database: data::Database,
+    id: i32,
+    data: Json<SiteAddUpdateForm>,
+) -> Result<Status, ApiError> {
+    let NewSite {
+        name,
+        country,
+        elevation,
+        geog,
+        site_type,
+        aliases,
+    } = data.into_inner().into_new_site()?;
+    let mut site = database
+        .run(move |db| data::get_site_by_id(db, id))
+        .await
+        .ok_or(ApiError::NotFound)?;
+    site.name = name;
+    site.country = country;
+    site.elevation = elevation;
+    site.geog = geog;
+    site.site_type = site_type;
+    site.aliases = aliases;
+    database
+        .run(move |db| data::update_site(db, &site))
+        .await
+        .map(|()| Status::NoContent)
+        .map_err(|e| {
+            log::error!("Could not update site {}: {}", id, e);
+            ApiError::IoError {
+                message: "Could not update site".into(),
+            }
+        })
+}
+
+#[post("/sites/<_id>", rank = 2)]
+pub fn edit_nologin(_id: i32) -> ApiError {
+    ApiError::MissingAuthentication
+}
+
+/// Delete a shared site (admins only). Personal locations copied from the
+/// site are not affected.
+#[delete("/sites/<id>")]
+pub async fn delete(_admin: auth::AdminUser, database: data::Database, id: i32) -> Result<Status, ApiError> {
+    match database.run(move |db| data::delete_site_by_id(db, id)).await {
+        Ok(0) => Err(ApiError::NotFound),
+        Ok(_) => {
+            log::info!("Deleted site {}", id);
+            Ok(Status::NoContent)
+        }
+        Err(e) => {
+            log::error!("Could not delete site {}: {}", id, e);
+            Err(ApiError::IoError {
+                message: "Could not delete site".into(),
+            })
+        }
+    }
+}
+
+#[delete("/sites/<_id>", rank = 2)]
+pub fn delete_nologin(_id: i32) -> ApiError {
+    ApiError::MissingAuthentication
+}
+
+/// Return vec of all API routes.
+pub fn api_routes() -> Vec<Route> {
+    routes![
+        list,
+        list_nologin,
+        get,
+        get_nologin,
+        copy,
+        copy_nologin,
+        add,
+        add_nologin,
+        edit,
+        edit_nologin,
+        delete,
+        delete_nologin,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::{catchers, http::ContentType, local::blocking::Client};
+
+    use crate::{
+        models::NewLocation,
+        test_utils::{make_test_config, DbTestContext},
+    };
+
+    use super::*;
+
+    fn make_client() -> Client {
+        let app = rocket::custom(make_test_config())
+            .attach(data::Database::fairing())
+            .register("/", catchers![crate::forbidden])
+            .mount("/", api_routes());
+        Client::untracked(app).expect("valid rocket instance")
+    }
+
+    fn create_site(
+        ctx: &DbTestContext,
+        name: &str,
+        site_type: &str,
+        aliases: &[&str],
+        lon: f64,
+        lat: f64,
+    ) -> Site {
+        data::create_site(
+            &mut ctx.force_get_conn(),
+            NewSite {
+                name: name.into(),
+                country: "CH".into(),
+                elevation: 2200,
+                geog: GeogPoint {
+                    x: lon,
+                    y: lat,
+                    srid: None,
+                },
+                site_type: site_type.into(),
+                aliases: aliases.iter().map(|alias| alias.to_string()).collect(),
+            },
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn manage_sites() {
+        let ctx = DbTestContext::new();
+        let client = make_client();
+        let body = r#"{
+            "name": " Fiesch Kühboden ",
+            "countryCode": "ch",
+            "elevation": 2212,
+            "coordinates": {"lon": 8.1206, "lat": 46.4160},
+            "siteType": "launch",
+            "aliases": ["Kühboden", " ", "Fiesch", "kühboden", "FIESCH "]
+        }"#;
+
+        // Non-admins may not add sites
+        let resp = client
+            .post("/sites")
+            .header(ContentType::JSON)
+            .body(body)
+            .private_cookie(ctx.auth_cookie_user1())
+            .cookie(ctx.username_cookie())
+            .dispatch();
+        assert_eq!(resp.status(), Status::Forbidden);
+
+        // Admins may
+        data::set_user_admin(&mut ctx.force_get_conn(), &ctx.testuser1.user.username, true).unwrap();
+        let resp = client
+            .post("/sites")
+            .header(ContentType::JSON)
+            .body(body)
+            .private_cookie(ctx.auth_cookie_user1())
+            .cookie(ctx.username_cookie())
+            .dispatch();
+        assert_eq!(resp.status(), Status::Created);
+        let site = resp.into_json::<ApiSite>().unwrap();
+        assert_eq!(site.name, "Fiesch Kühboden");
+        assert_eq!(site.country_code, "CH");
+        assert_eq!(site.site_type, "launch");
+        assert_eq!(site.aliases, vec!["Kühboden", "Fiesch"]);
+
+        // Invalid site type
+        let resp = client
+            .post(format!("/sites/{}", site.id))
+            .header(ContentType::JSON)
+            .body(body.replace("\"launch\"", "\"takeoff\""))
+            .private_cookie(ctx.auth_cookie_user1())
+            .cookie(ctx.username_cookie())
+            .dispatch();
+        assert_eq!(resp.status(), Status::BadRequest);
+
+        // Invalid coordinates
+        for coordinates in [
+            r#"{"lon": 8.1206, "lat": 91.0}"#,
+            r#"{"lon": 8.1206, "lat": -90.5}"#,
+            r#"{"lon": 180.5, "lat": 46.4160}"#,
+        ] {
+            let resp = client
+                .post(format!("/sites/{}", site.id))
+                .header(ContentType::JSON)
+                .body(body.replace(r#"{"lon": 8.1206, "lat": 46.4160}"#, coordinates))
+                .private_cookie(ctx.auth_cookie_user1())
+                .cookie(ctx.username_cookie())
+                .dispatch();
+            assert_eq!(resp.status(), Status::BadRequest, "{}", coordinates);
+        }
+
+        // Invalid country code
+        let resp = client
+            .post(format!("/sites/{}", site.id))
+            .header(ContentType::JSON)
+            .body(body.replace("\"ch\"", "\"QQ\""))
+            .private_cookie(ctx.auth_cookie_user1())
+            .cookie(ctx.username_cookie())
+            .dispatch();
+        assert_eq!(resp.status(), Status::BadRequest);
+
+        // Update
+        let resp = client
+            .post(format!("/sites/{}", site.id))
+            .header(ContentType::JSON)
+            .body(body.replace("\"launch\"", "\"both\""))
+            .private_cookie(ctx.auth_cookie_user1())
+            .cookie(ctx.username_cookie())
+            .dispatch();
+        assert_eq!(resp.status(), Status::NoContent);
+        let updated = data::get_site_by_id(&mut ctx.force_get_conn(), site.id).unwrap();
+        assert_eq!(updated.site_type, "both");
+
+        // Delete
+        let resp = client
+            .delete(format!("/sites/{}", site.id))
+            .private_cookie(ctx.auth_cookie_user1())
+            .cookie(ctx.username_cookie())
+            .dispatch();
+        assert_eq!(resp.status(), Status::NoContent);
+        let resp = client
+            .delete(format!("/sites/{}", site.id))
+            .private_cookie(ctx.auth_cookie_user1())
+            .cookie(ctx.username_cookie())
+            .dispatch();
+        assert_eq!(resp.status(), Status::NotFound);
+    }
+
+    #[test]
+    fn search_and_copy_sites() {
+        let ctx = DbTestContext::new();
+        let client = make_client();
+        let fiesch = create_site(&ctx, "Fiesch Kühboden", "launch", &["Kuehboden"], 8.1206, 46.4160);
+        create_site(&ctx, "Fiesch Landeplatz", "landing", &[], 8.1367, 46.4046);
+
+        let search = |query: &str| {
+            client
+                .get(format!("/sites{}", query))
+                .private_cookie(ctx.auth_cookie_user2())
+                .cookie(ctx.username_cookie())
+                .dispatch()
+                .into_json::<ApiSites>()
+                .unwrap()
+                .sites
+        };
+        assert_eq!(search("").len(), 2);
+        assert_eq!(search("?search=fiesch").len(), 2);
+        let sites = search("?search=kuehboden");
+        assert_eq!(sites.len(), 1);
+        assert_eq!(sites[0].name, "Fiesch Kühboden");
+        assert_eq!(search("?search=fiesch&limit=1").len(), 1);
+
+        // Without login
+        let resp = client.get("/sites").dispatch();
+        assert_eq!(resp.status(), Status::Unauthorized);
+
+        // Copying twice returns the same location
+        let copy = || {
+            client
+                .post(format!("/sites/{}/copy", fiesch.id))
+                .private_cookie(ctx.auth_cookie_user2())
+                .cookie(ctx.username_cookie())
+                .dispatch()
+                .into_json::<ApiCopiedSite>()
+                .unwrap()
+                .location_id
+        };
+        let location_id = copy();
+        assert_eq!(copy(), location_id);
+        let location = data::get_location_by_id(&mut ctx.force_get_conn(), location_id).unwrap();
+        assert_eq!(location.name, "Fiesch Kühboden");
+        assert_eq!(location.user_id, ctx.testuser2.user.id);
+        assert_eq!(location.site_id, Some(fiesch.id));
+        assert_eq!(location.geog, Some(fiesch.geog));
+    }
+
+    #[test]
+    fn locations_around_point() {
+        let ctx = DbTestContext::new();
+        let launch = create_site(&ctx, "Fiesch Kühboden", "launch", &[], 8.1206, 46.4160);
+        let both = create_site(&ctx, "Kühboden Ost", "both", &[], 8.1226, 46.4160);
+        let conn = &mut *ctx.force_get_conn();
+        let user = &ctx.testuser1.user;
+        let around = |conn: &mut diesel::PgConnection, usage| {
+            data::get_locations_around_point(conn, user, 46.4160, 8.1207, usage)
+                .into_iter()
+                .map(|location| (location.id, location.shared))
+                .collect::<Vec<_>>()
+        };
+
+        // Only shared sites, filtered by type
+        assert_eq!(
+            around(conn, data::SiteUsage::Launch),
+            vec![(launch.id, true), (both.id, true)]
+        );
+        assert_eq!(around(conn, data::SiteUsage::Landing), vec![(both.id, true)]);
+
+        // Personal locations are included, copied sites are not returned twice
+        let personal = data::create_location(
+            conn,
+            NewLocation {
+                name: "Kühboden".into(),
+                country: "CH".into(),
+                elevation: 2200,
+                user_id: user.id,
+                geog: Some(GeogPoint {
+                    x: 8.1210,
+                    y: 46.4160,
+                    srid: None,
+                }),
+                match_radius: None,
+                area: None,
+            },
+        );
+        let copied = data::get_or_create_location_from_site(conn, user, &launch).unwrap();
+        assert_eq!(
+            around(conn, data::SiteUsage::Launch),
+            vec![(copied.id, false), (personal.id, false), (both.id, true)]
+        );
+
+        // Other users still see the shared site
+        let others = data::get_locations_around_point(
+            conn,
+            &ctx.testuser2.user,
+            46.4160,
+            8.1207,
+            data::SiteUsage::Launch,
+        );
+        assert_eq!(others.len(), 2);
+        assert!(others.iter().all(|location| location.shared));
+    }
+}
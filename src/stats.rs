@@ -2,6 +2,8 @@
 
 use std::{collections::BTreeMap, convert::TryInto};
 
+use chrono::{DateTime, NaiveDate, Utc};
+
 use rocket::{
     get, routes,
     serde::{json::Json, Serialize},
@@ -10,11 +12,19 @@ use rocket::{
 
 use crate::{
     auth,
-    data::{self, LocationAggregateBy},
+    data::{
+        self, ConditionBucket, FlightRecordBy, LocationAggregateBy, MilestoneKind, StatsBucket, StatsPeriod,
+    },
     locations::ApiLocation,
     responders::ApiError,
 };
 
+/// Flight count milestones (n-th flight).
+const FLIGHT_COUNT_MILESTONES: [i32; 7] = [1, 10, 50, 100, 250, 500, 1000];
+
+/// Airtime milestones (in hours).
+const FLIGHT_HOURS_MILESTONES: [i32; 6] = [10, 50, 100, 250, 500, 1000];
+
 // API types
 
 #[derive(Default, Serialize)]
@@ -33,6 +43,43 @@ struct ApiYearStats {
     distance: ApiDistance,
     distance_track_incomplete: bool,
     distance_scored_incomplete: bool,
+    flying_days: u32,
+    longest_streak_days: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    longest_gap_days: Option<u32>,
+}
+
+#[derive(Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiPeriodStats {
+    flight_count: u32,
+    flight_seconds: u64,
+}
+
+impl ApiPeriodStats {
+    fn from_counts(count: i64, seconds: i64) -> Self {
+        Self {
+            flight_count: count as u32,
+            flight_seconds: seconds.max(0) as u64,
+        }
+    }
+}
+
+/// Stats per flight condition. Only flights with a value for the respective
+/// condition are counted.
+#[derive(Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiConditionStats {
+    /// Stats per wind direction (rounded to 0, 45, ..., 315 degrees)
+    wind_direction: BTreeMap<u16, ApiPeriodStats>,
+    /// Stats per wind speed (in steps of 10 km/h)
+    wind_speed: BTreeMap<u16, ApiPeriodStats>,
+    /// Stats per thermal strength (in steps of 1 m/s)
+    thermal_strength: BTreeMap<u16, ApiPeriodStats>,
+    /// Stats per cloud base (in steps of 500 m)
+    cloud_base: BTreeMap<u16, ApiPeriodStats>,
+    /// Stats per turbulence rating (1-5)
+    turbulence: BTreeMap<u16, ApiPeriodStats>,
 }
 
 #[derive(Serialize)]
@@ -41,6 +88,16 @@ pub struct ApiStats {
     launch_locations: Vec<ApiLocation>,
     landing_locations: Vec<ApiLocation>,
     yearly_stats: BTreeMap<u16, ApiYearStats>,
+    /// Stats per month (1-12), keyed by year
+    monthly_stats: BTreeMap<u16, BTreeMap<u8, ApiPeriodStats>>,
+    /// Stats per ISO week (1-53), keyed by ISO week-numbering year
+    weekly_stats: BTreeMap<u16, BTreeMap<u8, ApiPeriodStats>>,
+    /// Stats per ISO day of week (1 = Monday, 7 = Sunday)
+    weekday_stats: BTreeMap<u8, ApiPeriodStats>,
+    /// Stats per launch hour (0-23)
+    launch_hour_stats: BTreeMap<u8, ApiPeriodStats>,
+    /// Stats per flight condition
+    condition_stats: ApiConditionStats,
     flight_count_total: u32,
     hikeandfly_count_total: u32,
     flight_time_total: u64,
@@ -48,6 +105,107 @@ pub struct ApiStats {
     flights_without_launch_time: u64,
 }
 
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiRecordFlight {
+    flight_id: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    number: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    launch_time: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    launch_at: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    launch_name: Option<String>,
+    value: f64,
+}
+
+impl From<data::FlightRecord> for ApiRecordFlight {
+    fn from(record: data::FlightRecord) -> Self {
+        Self {
+            flight_id: record.flight_id,
+            number: record.number,
+            launch_time: record.launch_time,
+            launch_at: record.launch_at,
+            launch_name: record.launch_name,
+            value: record.value,
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiDayRecord {
+    date: NaiveDate,
+    flight_count: u32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiLocationFirstFlight {
+    location_id: i32,
+    location_name: String,
+    flight_id: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    number: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    launch_time: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiMilestone {
+    threshold: u32,
+    flight_id: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    number: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    launch_time: Option<DateTime<Utc>>,
+}
+
+impl From<data::Milestone> for ApiMilestone {
+    fn from(milestone: data::Milestone) -> Self {
+        Self {
+            threshold: milestone.threshold as u32,
+            flight_id: milestone.flight_id,
+            number: milestone.number,
+            launch_time: milestone.launch_time,
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiMilestones {
+    /// The n-th flight
+    flights: Vec<ApiMilestone>,
+    /// The flight during which the total airtime reached n hours
+    hours: Vec<ApiMilestone>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiRecords {
+    /// Longest flight, value in seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    longest_duration: Option<ApiRecordFlight>,
+    /// Longest GPS track, value in km
+    #[serde(skip_serializing_if = "Option::is_none")]
+    longest_track_distance: Option<ApiRecordFlight>,
+    /// Highest XContest scored distance per tracktype, value in km
+    best_scored_distance: BTreeMap<String, ApiRecordFlight>,
+    /// Highest launch location, value in meters
+    #[serde(skip_serializing_if = "Option::is_none")]
+    highest_launch: Option<ApiRecordFlight>,
+    /// Day with the most flights
+    #[serde(skip_serializing_if = "Option::is_none")]
+    most_flights_in_a_day: Option<ApiDayRecord>,
+    /// First flight at every visited location
+    first_flights: Vec<ApiLocationFirstFlight>,
+    /// Reached milestones
+    milestones: ApiMilestones,
+}
+
 #[derive(Serialize)]
 pub struct ApiGlobalStats {
     user_count: i64,
@@ -131,11 +289,84 @@ pub async fn stats(database: data::Database, user: auth::AuthUser) -> Json<ApiSt
                 scored: yearly_stats.values().map(|s| s.distance.scored).sum(),
             };
 
+            // Get streaks and gaps per year
+            for streaks in data::get_flight_streaks_per_year_for_user(db, &user) {
+                let stats = yearly_stats.entry(streaks.year as u16).or_default();
+                stats.flying_days = streaks.flying_days as u32;
+                stats.longest_streak_days = streaks.longest_streak as u32;
+                stats.longest_gap_days = streaks.longest_gap.map(|gap| gap as u32);
+            }
+
+            // Get stats per month and per ISO week
+            let mut monthly_stats: BTreeMap<u16, BTreeMap<u8, ApiPeriodStats>> = BTreeMap::new();
+            for stats in data::get_flight_stats_per_period_for_user(db, &user, StatsPeriod::Month) {
+                monthly_stats.entry(stats.year as u16).or_default().insert(
+                    stats.period as u8,
+                    ApiPeriodStats::from_counts(stats.count, stats.seconds),
+                );
+            }
+            let mut weekly_stats: BTreeMap<u16, BTreeMap<u8, ApiPeriodStats>> = BTreeMap::new();
+            for stats in data::get_flight_stats_per_period_for_user(db, &user, StatsPeriod::IsoWeek) {
+                weekly_stats.entry(stats.year as u16).or_default().insert(
+                    stats.period as u8,
+                    ApiPeriodStats::from_counts(stats.count, stats.seconds),
+                );
+            }
+
+            // Get stats per day of week and per launch hour
+            let weekday_stats = data::get_flight_stats_per_bucket_for_user(db, &user, StatsBucket::Weekday)
+                .into_iter()
+                .map(|stats| {
+                    (
+                        stats.bucket as u8,
+                        ApiPeriodStats::from_counts(stats.count, stats.seconds),
+                    )
+                })
+                .collect();
+            let launch_hour_stats =
+                data::get_flight_stats_per_bucket_for_user(db, &user, StatsBucket::LaunchHour)
+                    .into_iter()
+                    .map(|stats| {
+                        (
+                            stats.bucket as u8,
+                            ApiPeriodStats::from_counts(stats.count, stats.seconds),
+                        )
+                    })
+                    .collect();
+
+            // Get stats per flight condition
+            let mut condition_stats = ApiConditionStats::default();
+            for (bucket, map) in [
+                (
+                    ConditionBucket::WindDirection,
+                    &mut condition_stats.wind_direction,
+                ),
+                (ConditionBucket::WindSpeed, &mut condition_stats.wind_speed),
+                (
+                    ConditionBucket::ThermalStrength,
+                    &mut condition_stats.thermal_strength,
+                ),
+                (ConditionBucket::CloudBase, &mut condition_stats.cloud_base),
+                (ConditionBucket::Turbulence, &mut condition_stats.turbulence),
+            ] {
+                for stats in data::get_flight_stats_per_condition_for_user(db, &user, bucket) {
+                    map.insert(
+                        stats.bucket as u16,
+                        ApiPeriodStats::from_counts(stats.count, stats.seconds),
+                    );
+                }
+            }
+
             // Render template
             ApiStats {
                 launch_locations,
                 landing_locations,
                 yearly_stats,
+                monthly_stats,
+                weekly_stats,
+                weekday_stats,
+                launch_hour_stats,
+                condition_stats,
                 flight_count_total,
                 hikeandfly_count_total,
                 flight_time_total,
@@ -153,6 +384,77 @@ pub fn stats_nologin() -> ApiError {
     ApiError::MissingAuthentication
 }
 
+#[get("/stats/records")]
+pub async fn records(database: data::Database, user: auth::AuthUser) -> Json<ApiRecords> {
+    let user = user.into_inner();
+
+    let records = database
+        .run(move |db| {
+            let best_scored_distance = data::get_scored_distance_records_for_user(db, &user)
+                .into_iter()
+                .map(|record| (record.tracktype, record.record.into()))
+                .collect();
+            let first_flights = data::get_first_flight_per_location_for_user(db, &user)
+                .into_iter()
+                .map(|first| ApiLocationFirstFlight {
+                    location_id: first.location_id,
+                    location_name: first.location_name,
+                    flight_id: first.flight_id,
+                    number: first.number,
+                    launch_time: first.launch_time,
+                })
+                .collect();
+            ApiRecords {
+                longest_duration: data::get_flight_record_for_user(db, &user, FlightRecordBy::Duration)
+                    .map(Into::into),
+                longest_track_distance: data::get_flight_record_for_user(
+                    db,
+                    &user,
+                    FlightRecordBy::TrackDistance,
+                )
+                .map(Into::into),
+                best_scored_distance,
+                highest_launch: data::get_flight_record_for_user(db, &user, FlightRecordBy::LaunchElevation)
+                    .map(Into::into),
+                most_flights_in_a_day: data::get_most_flights_in_a_day_for_user(db, &user).map(|day| {
+                    ApiDayRecord {
+                        date: day.day,
+                        flight_count: day.count as u32,
+                    }
+                }),
+                first_flights,
+                milestones: ApiMilestones {
+                    flights: data::get_milestones_for_user(
+                        db,
+                        &user,
+                        MilestoneKind::FlightCount,
+                        &FLIGHT_COUNT_MILESTONES,
+                    )
+                    .into_iter()
+                    .map(Into::into)
+                    .collect(),
+                    hours: data::get_milestones_for_user(
+                        db,
+                        &user,
+                        MilestoneKind::FlightHours,
+                        &FLIGHT_HOURS_MILESTONES,
+                    )
+                    .into_iter()
+                    .map(Into::into)
+                    .collect(),
+                },
+            }
+        })
+        .await;
+
+    Json(records)
+}
+
+#[get("/stats/records", rank = 2)]
+pub fn records_nologin() -> ApiError {
+    ApiError::MissingAuthentication
+}
+
 #[get("/global-stats")]
 pub async fn global_stats(database: data::Database) -> Json<ApiGlobalStats> {
     let (user_count, glider_count, flight_count) = database
@@ -173,5 +475,159 @@ pub async fn global_stats(database: data::Database) -> Json<ApiGlobalStats> {
 
 /// Return vec of all API routes.
 pub fn api_routes() -> Vec<Route> {
-    routes![stats, stats_nologin, global_stats]
+    routes![stats, stats_nologin, records, records_nologin, global_stats]
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use rocket::{self, http::Status, local::blocking::Client, serde::json::Value};
+
+    use crate::{
+        models::{NewFlight, NewLocation},
+        test_utils::{make_test_config, utc_datetime, DbTestContext},
+    };
+
+    use super::*;
+
+    /// Create a new test client. Cookie tracking is disabled.
+    fn make_client() -> Client {
+        let app = rocket::custom(make_test_config())
+            .attach(data::Database::fairing())
+            .mount("/", api_routes());
+        Client::untracked(app).expect("valid rocket instance")
+    }
+
+    #[test]
+    fn get_records() {
+        let ctx = DbTestContext::new();
+        let client = make_client();
+
+        macro_rules! get_records {
+            ($cookie:expr) => {
+                client
+                    .get("/stats/records")
+                    .private_cookie($cookie)
+                    .cookie(ctx.username_cookie())
+                    .dispatch()
+            };
+        }
+
+        // No flights
+        let resp = get_records!(ctx.auth_cookie_user1());
+        assert_eq!(resp.status(), Status::Ok);
+        assert_eq!(
+            resp.into_string().unwrap(),
+            r#"{"bestScoredDistance":{},"firstFlights":[],"milestones":{"flights":[],"hours":[]}}"#
+        );
+
+        // Add locations and flights
+        let high = data::create_location(
+            &mut ctx.force_get_conn(),
+            NewLocation {
+                name: "Niesen".into(),
+                country: "CH".into(),
+                elevation: 2336,
+                user_id: ctx.testuser1.user.id,
+                geog: None,
+                match_radius: None,
+                area: None,
+            },
+        );
+        let low = data::create_location(
+            &mut ctx.force_get_conn(),
+            NewLocation {
+                name: "Amisbühl".into(),
+                country: "CH".into(),
+                elevation: 1350,
+                user_id: ctx.testuser1.user.id,
+                geog: None,
+                match_radius: None,
+                area: None,
+            },
+        );
+        let flights = [
+            (
+                utc_datetime(2022, 5, 1, 10, 0, 0),
+                90,
+                low.id,
+                12.5,
+                "free_flight",
+                10.0,
+            ),
+            (
+                utc_datetime(2022, 5, 1, 13, 0, 0),
+                180,
+                high.id,
+                8.0,
+                "free_flight",
+                30.0,
+            ),
+            (
+                utc_datetime(2022, 6, 3, 11, 0, 0),
+                600,
+                low.id,
+                40.0,
+                "fai_triangle",
+                25.0,
+            ),
+        ]
+        .iter()
+        .map(|(launch, minutes, location, track, tracktype, scored)| {
+            data::create_flight(
+                &mut ctx.force_get_conn(),
+                &NewFlight {
+                    user_id: ctx.testuser1.user.id,
+                    launch_at: Some(*location),
+                    launch_time: Some(*launch),
+                    landing_time: Some(*launch + Duration::minutes(*minutes)),
+                    track_distance: Some(*track),
+                    xcontest_tracktype: Some(tracktype.to_string()),
+                    xcontest_distance: Some(*scored),
+                    ..Default::default()
+                },
+                None,
+            )
+        })
+        .collect::<Vec<_>>();
+
+        // Query records
+        let resp = get_records!(ctx.auth_cookie_user1());
+        assert_eq!(resp.status(), Status::Ok);
+        let records: Value = resp.into_json().unwrap();
+        assert_eq!(records["longestDuration"]["flightId"], flights[2].id);
+        assert_eq!(records["longestDuration"]["value"], 36000.0);
+        assert_eq!(records["longestTrackDistance"]["flightId"], flights[2].id);
+        assert_eq!(
+            records["bestScoredDistance"]["free_flight"]["flightId"],
+            flights[1].id
+        );
+        assert_eq!(
+            records["bestScoredDistance"]["fai_triangle"]["flightId"],
+            flights[2].id
+        );
+        assert_eq!(records["highestLaunch"]["flightId"], flights[1].id);
+        assert_eq!(records["highestLaunch"]["launchName"], "Niesen");
+        assert_eq!(records["highestLaunch"]["value"], 2336.0);
+        assert_eq!(records["mostFlightsInADay"]["date"], "2022-05-01");
+        assert_eq!(records["mostFlightsInADay"]["flightCount"], 2);
+        assert_eq!(records["firstFlights"][0]["locationName"], "Amisbühl");
+        assert_eq!(records["firstFlights"][0]["flightId"], flights[0].id);
+        assert_eq!(records["firstFlights"][1]["locationName"], "Niesen");
+        assert_eq!(records["firstFlights"][1]["flightId"], flights[1].id);
+        assert_eq!(records["milestones"]["flights"][0]["threshold"], 1);
+        assert_eq!(records["milestones"]["flights"][0]["flightId"], flights[0].id);
+        assert_eq!(records["milestones"]["flights"].as_array().unwrap().len(), 1);
+        assert_eq!(records["milestones"]["hours"][0]["threshold"], 10);
+        assert_eq!(records["milestones"]["hours"][0]["flightId"], flights[2].id);
+
+        // Records of other users are not visible
+        let resp = get_records!(ctx.auth_cookie_user2());
+        let records: Value = resp.into_json().unwrap();
+        assert!(records.get("longestDuration").is_none());
+
+        // Without login
+        let resp = client.get("/stats/records").dispatch();
+        assert_eq!(resp.status(), Status::Unauthorized);
+    }
 }
@@ -1,7 +1,11 @@
 use std::{
     collections::HashMap,
-    env,
-    sync::{Mutex, MutexGuard},
+    env, fs,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Mutex, MutexGuard,
+    },
 };
 
 use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Utc};
@@ -13,6 +17,7 @@ use rocket::{config::Config, figment::Figment, http::Cookie};
 
 use crate::{
     data::{self, create_user},
+    mail::{MailConfig, MailTransport, Mailer},
     models::User,
 };
 
@@ -23,6 +28,8 @@ lazy_static! {
 pub struct TestUser {
     pub user: User,
     pub password: String,
+    /// Token of a pre-created login session.
+    pub session_token: String,
 }
 
 pub struct DbTestContext<'a> {
@@ -73,16 +80,20 @@ impl<'a> DbTestContext<'a> {
         // Create test user
         let testuser1 = create_user(&mut conn, "testuser1", "user1@example.com", "testpass", false);
         let testuser2 = create_user(&mut conn, "testuser2", "user2@example.com", "testpass", false);
+        let (_, session_token1) = data::create_session(&mut conn, &testuser1, None, None).unwrap();
+        let (_, session_token2) = data::create_session(&mut conn, &testuser2, None, None).unwrap();
 
         DbTestContext {
             conn: Mutex::new(conn),
             testuser1: TestUser {
                 user: testuser1,
                 password: "testpass".into(),
+                session_token: session_token1,
             },
             testuser2: TestUser {
                 user: testuser2,
                 password: "testpass".into(),
+                session_token: session_token2,
             },
             db_mutex,
         }
@@ -98,7 +109,7 @@ impl<'a> DbTestContext<'a> {
     }
 
     fn auth_cookie(&self, user: &TestUser) -> Cookie<'static> {
-        Cookie::new(crate::auth::USER_COOKIE_ID, user.user.id.to_string())
+        Cookie::new(crate::auth::SESSION_COOKIE_ID, user.session_token.clone())
     }
 
     /// Create an auth cookie for testuser1.
@@ -117,6 +128,89 @@ impl<'a> DbTestContext<'a> {
     }
 }
 
+/// Base URL used in test mails.
+pub const TEST_BASE_URL: &str = "https://flugbuech.example.com";
+
+/// A temporary directory that receives mails sent through the file transport.
+///
+/// The directory is removed when the mailbox is dropped.
+pub struct TestMailbox {
+    pub dir: PathBuf,
+}
+
+impl TestMailbox {
+    pub fn new() -> Self {
+        static COUNTER: AtomicU32 = AtomicU32::new(0);
+        let dir = env::temp_dir().join(format!(
+            "flugbuech-test-mails-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        Self { dir }
+    }
+
+    /// Return an app config that sends mails to this mailbox.
+    pub fn config(&self) -> crate::Config {
+        crate::Config {
+            base_url: Some(format!("{}/", TEST_BASE_URL)),
+            mail: MailConfig {
+                transport: MailTransport::File,
+                file_dir: Some(self.dir.clone()),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    /// Return a mailer that sends mails to this mailbox.
+    pub fn mailer(&self) -> Mailer {
+        Mailer::from_config(&self.config().mail).unwrap()
+    }
+
+    /// Return all mails in this mailbox, oldest first.
+    pub fn mails(&self) -> Vec<String> {
+        let mut paths = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries.map(|entry| entry.unwrap().path()).collect::<Vec<_>>(),
+            Err(_) => return vec![],
+        };
+        paths.sort();
+        paths
+            .iter()
+            .map(|path| fs::read_to_string(path).unwrap())
+            .collect()
+    }
+
+    /// Wait until the mailbox contains at least `count` mails (e.g. sent by a
+    /// background task) and return all mails. Panic after a timeout.
+    pub fn wait_for_mails(&self, count: usize) -> Vec<String> {
+        for _ in 0..500 {
+            let mails = self.mails();
+            if mails.len() >= count {
+                return mails;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        panic!("Timeout while waiting for {} mail(s)", count);
+    }
+
+    /// Return the token of the last link to the specified frontend path.
+    pub fn last_token(&self, path: &str) -> Option<String> {
+        let link_prefix = format!("{}{}?token=", TEST_BASE_URL, path);
+        self.mails()
+            .iter()
+            .rev()
+            .find_map(|mail| mail.lines().find_map(|line| line.strip_prefix(&link_prefix)))
+            .map(ToString::to_string)
+    }
+}
+
+impl Drop for TestMailbox {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
 pub fn make_test_config() -> rocket::figment::Figment {
     // Load env
     let _ = dotenvy::dotenv();
@@ -0,0 +1,367 @@
+//! Personal API tokens for scripted access.
+//!
+//! Tokens are sent in an `Authorization: Bearer <token>` header and are
+//! accepted by the `auth::AuthUser` request guard. Every token is limited to
+//! a set of scopes.
+
+use chrono::{DateTime, Utc};
+use rocket::{
+    delete, get,
+    http::{Method, Status},
+    post, routes,
+    serde::json::Json,
+    Route,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{auth, data, models::ApiToken, responders::ApiError};
+
+/// Read access to all endpoints.
+pub const SCOPE_READ: &str = "read";
+/// Write access to flights (including IGC and CSV upload).
+pub const SCOPE_FLIGHTS_WRITE: &str = "flights:write";
+/// Write access to all endpoints.
+pub const SCOPE_WRITE: &str = "write";
+
+/// Valid token scopes.
+pub const SCOPES: [&str; 3] = [SCOPE_READ, SCOPE_FLIGHTS_WRITE, SCOPE_WRITE];
+
+/// Scopes of tokens created without explicit scopes.
+const DEFAULT_SCOPES: [&str; 2] = [SCOPE_READ, SCOPE_WRITE];
+
+/// Return whether a token with the specified scopes may access the endpoint
+/// identified by `method` and `path`.
+///
+/// Authentication endpoints (e.g. password change or token management),
+/// account deletion and the administration API can never be accessed with a
+/// token.
+/// E-mail address changes through `/profile` are rejected by the endpoint.
+pub fn scopes_permit(scopes: &[String], method: Method, path: &str) -> bool {
+    let path = path.strip_prefix("/api/v1").unwrap_or(path);
+    let has_scope = |scope: &str| scopes.iter().any(|s| s == scope);
+    let path_matches = |prefix: &str| path == prefix || path.starts_with(&format!("{}/", prefix));
+
+    if path_matches("/auth") || path_matches("/admin") || (path == "/account" && method == Method::Delete) {
+        return false;
+    }
+    match method {
+        Method::Get | Method::Head => has_scope(SCOPE_READ),
+        _ => has_scope(SCOPE_WRITE) || (has_scope(SCOPE_FLIGHTS_WRITE) && path_matches("/flights")),
+    }
+}
+
+// API types
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiTokenInfo {
+    id: i32,
+    name: String,
+    /// The first few characters of the token
+    prefix: String,
+    scopes: Vec<String>,
+    created_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_used_at: Option<DateTime<Utc>>,
+}
+
+impl From<ApiToken> for ApiTokenInfo {
+    fn from(token: ApiToken) -> Self {
+        Self {
+            id: token.id,
+            name: token.name,
+            prefix: token.token_prefix,
+            scopes: token.scopes,
+            created_at: token.created_at,
+            last_used_at: token.last_used_at,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiTokens {
+    tokens: Vec<ApiTokenInfo>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiCreatedToken {
+    #[serde(flatten)]
+    info: ApiTokenInfo,
+    /// The plaintext token. It is only returned once and cannot be retrieved later.
+    token: String,
+}
+
+// Forms
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenCreateForm {
+    name: String,
+    /// Scopes of the token. If not specified, the token has full read and
+    /// write access.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scopes: Option<Vec<String>>,
+}
+
+// API endpoints
+
+#[get("/auth/tokens")]
+pub async fn list(database: data::Database, user: auth::AuthUser) -> Json<ApiTokens> {
+    let user = user.into_inner();
+    let tokens = database
+        .run(move |db| data::get_api_tokens_for_user(db, &user))
+        .await
+        .into_iter()
+        .map(Into::into)
+        .collect();
+    Json(ApiTokens { tokens })
+}
+
+#[get("/auth/tokens", rank = 2)]
+pub fn list_nologin() -> ApiError {
+    ApiError::MissingAuthentication
+}
+
+#[post("/auth/tokens", data = "<data>")]
+pub async fn add(
+    user: auth::AuthUser,
+    database: data::Database,
+    data: Json<TokenCreateForm>,
+) -> Result<(Status, Json<ApiCreatedToken>), ApiError> {
+    let user = user.into_inner();
+
+    // Validate data
+    let TokenCreateForm { name, scopes } = data.into_inner();
+    if name.trim().is_empty() {
+        return Err(ApiError::InvalidData {
+            message: "Token name must not be empty".into(),
+        });
+    }
+    let mut scopes = scopes.unwrap_or_else(|| DEFAULT_SCOPES.iter().map(|s| s.to_string()).collect());
+    if let Some(invalid) = scopes.iter().find(|scope| !SCOPES.contains(&scope.as_str())) {
+        return Err(ApiError::InvalidData {
+            message: format!("Invalid token scope: {}", invalid),
+        });
+    }
+    if scopes.is_empty() {
+        return Err(ApiError::InvalidData {
+            message: "At least one scope must be specified".into(),
+        });
+    }
+    scopes.sort_unstable();
+    scopes.dedup();
+
+    // Create token
+    let user_id = user.id;
+    match database
+        .run(move |db| data::create_api_token(db, &user, name.trim(), &scopes))
+        .await
+    {
+        Ok((api_token, token)) => {
+            log::info!("Created API token for user {}", user_id);
+            Ok((
+                Status::Created,
+                Json(ApiCreatedToken {
+                    info: api_token.into(),
+                    token,
+                }),
+            ))
+        }
+        Err(e) => {
+            log::error!("Could not create API token: {}", e);
+            Err(ApiError::IoError {
+                message: "Could not create API token".into(),
+            })
+        }
+    }
+}
+
+#[post("/auth/tokens", rank = 2)]
+pub fn add_nologin() -> ApiError {
+    ApiError::MissingAuthentication
+}
+
+#[delete("/auth/tokens/<id>")]
+pub async fn delete(user: auth::AuthUser, database: data::Database, id: i32) -> Result<Status, ApiError> {
+    let user = user.into_inner();
+
+    // Get token and check ownership
+    database
+        .run(move |db| data::get_api_token_by_id(db, id))
+        .await
+        .filter(|token| token.user_id == user.id)
+        .ok_or(ApiError::NotFound)?;
+
+    // Revoke token
+    database
+        .run(move |db| data::delete_api_token_by_id(db, id))
+        .await
+        .map(|()| {
+            log::info!("Revoked API token with ID {}", id);
+            Status::NoContent
+        })
+        .map_err(|e| {
+            log::error!("Could not delete API token with ID {}: {}", id, e);
+            ApiError::IoError {
+                message: "Could not delete API token".into(),
+            }
+        })
+}
+
+#[delete("/auth/tokens/<id>", rank = 2)]
+#[allow(unused_variables)]
+pub fn delete_nologin(id: i32) -> ApiError {
+    ApiError::MissingAuthentication
+}
+
+/// Return vec of all API routes.
+pub fn api_routes() -> Vec<Route> {
+    routes![list, list_nologin, add, add_nologin, delete, delete_nologin]
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::{
+        self, catchers,
+        http::{ContentType, Header},
+        local::blocking::Client,
+    };
+
+    use crate::{
+        gliders,
+        test_utils::{make_test_config, DbTestContext},
+    };
+
+    use super::*;
+
+    /// Create a new test client. Cookie tracking is disabled.
+    fn make_client() -> Client {
+        let app = rocket::custom(make_test_config())
+            .attach(data::Database::fairing())
+            .register("/", catchers![crate::forbidden])
+            .mount("/", [api_routes(), gliders::api_routes()].concat());
+        Client::untracked(app).expect("valid rocket instance")
+    }
+
+    #[test]
+    fn scopes() {
+        let read = vec![SCOPE_READ.to_string()];
+        let flights_write = vec![SCOPE_FLIGHTS_WRITE.to_string()];
+        let write = vec![SCOPE_WRITE.to_string()];
+
+        assert!(scopes_permit(&read, Method::Get, "/api/v1/flights"));
+        assert!(!scopes_permit(&read, Method::Post, "/api/v1/flights"));
+        assert!(!scopes_permit(&flights_write, Method::Get, "/api/v1/flights"));
+        assert!(scopes_permit(&flights_write, Method::Post, "/api/v1/flights"));
+        assert!(scopes_permit(
+            &flights_write,
+            Method::Post,
+            "/api/v1/flights/add/process_igc"
+        ));
+        assert!(!scopes_permit(&flights_write, Method::Post, "/api/v1/gliders"));
+        assert!(!scopes_permit(&flights_write, Method::Post, "/api/v1/flightsfoo"));
+        assert!(scopes_permit(&write, Method::Delete, "/api/v1/gliders/1"));
+
+        // Tokens cannot access authentication endpoints
+        assert!(!scopes_permit(&read, Method::Get, "/api/v1/auth/tokens"));
+        assert!(!scopes_permit(
+            &write,
+            Method::Post,
+            "/api/v1/auth/password/change"
+        ));
+        assert!(!scopes_permit(&write, Method::Delete, "/api/v1/account"));
+        assert!(!scopes_permit(&read, Method::Get, "/api/v1/admin/users"));
+        assert!(scopes_permit(&read, Method::Get, "/api/v1/account/export"));
+    }
+
+    #[test]
+    fn create_use_and_revoke_token() {
+        let ctx = DbTestContext::new();
+        let client = make_client();
+
+        macro_rules! create_token {
+            ($body:expr) => {
+                client
+                    .post("/auth/tokens")
+                    .header(ContentType::JSON)
+                    .body($body)
+                    .private_cookie(ctx.auth_cookie_user1())
+                    .cookie(ctx.username_cookie())
+                    .dispatch()
+            };
+        }
+        macro_rules! bearer {
+            ($token:expr) => {
+                Header::new("Authorization", format!("Bearer {}", $token))
+            };
+        }
+
+        // Invalid scope
+        let resp = create_token!(r#"{"name": "Pi", "scopes": ["admin"]}"#);
+        assert_eq!(resp.status(), Status::BadRequest);
+
+        // Create read-only token
+        let resp = create_token!(r#"{"name": "Pi", "scopes": ["read"]}"#);
+        assert_eq!(resp.status(), Status::Created);
+        let created = resp.into_json::<ApiCreatedToken>().unwrap();
+        assert!(created.token.starts_with(data::API_TOKEN_PREFIX));
+        assert!(created.token.starts_with(&created.info.prefix));
+        assert_eq!(created.info.scopes, vec!["read"]);
+
+        // The token is stored hashed
+        let stored = data::get_api_token_by_id(&mut ctx.force_get_conn(), created.info.id).unwrap();
+        assert_ne!(stored.token_hash, created.token);
+
+        // Token can be used for reading, but not for writing
+        let resp = client.get("/gliders").header(bearer!(created.token)).dispatch();
+        assert_eq!(resp.status(), Status::Ok);
+        let resp = client
+            .post("/gliders")
+            .header(ContentType::JSON)
+            .header(bearer!(created.token))
+            .body(r#"{"manufacturer": "A", "model": "1"}"#)
+            .dispatch();
+        assert_eq!(resp.status(), Status::Forbidden);
+
+        // Tokens cannot be used to manage tokens
+        let resp = client
+            .get("/auth/tokens")
+            .header(bearer!(created.token))
+            .dispatch();
+        assert_eq!(resp.status(), Status::Forbidden);
+
+        // Invalid token
+        let resp = client.get("/gliders").header(bearer!("flb_invalid")).dispatch();
+        assert_eq!(resp.status(), Status::Unauthorized);
+
+        // List tokens, usage was recorded
+        let resp = client
+            .get("/auth/tokens")
+            .private_cookie(ctx.auth_cookie_user1())
+            .cookie(ctx.username_cookie())
+            .dispatch();
+        let tokens = resp.into_json::<ApiTokens>().unwrap().tokens;
+        assert_eq!(tokens.len(), 1);
+        assert!(tokens[0].last_used_at.is_some());
+
+        // Other users cannot revoke the token
+        let resp = client
+            .delete(format!("/auth/tokens/{}", created.info.id))
+            .private_cookie(ctx.auth_cookie_user2())
+            .cookie(ctx.username_cookie())
+            .dispatch();
+        assert_eq!(resp.status(), Status::NotFound);
+
+        // Revoke token
+        let resp = client
+            .delete(format!("/auth/tokens/{}", created.info.id))
+            .private_cookie(ctx.auth_cookie_user1())
+            .cookie(ctx.username_cookie())
+            .dispatch();
+        assert_eq!(resp.status(), Status::NoContent);
+        let resp = client.get("/gliders").header(bearer!(created.token)).dispatch();
+        assert_eq!(resp.status(), Status::Unauthorized);
+    }
+}
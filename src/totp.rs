@@ -0,0 +1,501 @@
+//! TOTP two-factor authentication (RFC 6238).
+//!
+//! When TOTP is enabled, logging in requires a second step: After the password
+//! was verified by `auth::login`, a code from the authenticator app (or one of
+//! the recovery codes) must be submitted to `auth::login_totp`.
+
+use chrono::{DateTime, Utc};
+use diesel::{PgConnection, QueryResult};
+use hmac::{Hmac, Mac};
+use log::error;
+use rocket::{
+    get,
+    http::{RawStr, Status},
+    post, routes,
+    serde::json::Json,
+    Route,
+};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+
+use crate::{
+    auth, data,
+    models::User,
+    responders::{ApiError, RocketError},
+};
+
+/// Number of digits of a code.
+const DIGITS: usize = 6;
+
+/// Duration of a time step in seconds.
+const STEP_SECONDS: i64 = 30;
+
+/// Number of time steps before or after the current one that are accepted,
+/// to allow for clock drift.
+const ALLOWED_DRIFT_STEPS: i64 = 1;
+
+/// Issuer shown in authenticator apps.
+const ISSUER: &str = "Flugbuech";
+
+// TOTP algorithm
+
+/// Return the time step at the specified time.
+pub fn time_step(now: DateTime<Utc>) -> i64 {
+    now.timestamp().div_euclid(STEP_SECONDS)
+}
+
+/// Calculate the HOTP code (RFC 4226) for the specified counter.
+fn hotp(secret: &[u8], counter: u64) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    // Dynamic truncation
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([hash[offset], hash[offset + 1], hash[offset + 2], hash[offset + 3]])
+        & 0x7fff_ffff;
+    format!("{:0width$}", binary % 10u32.pow(DIGITS as u32), width = DIGITS)
+}
+
+/// Decode a base32 encoded secret.
+fn decode_secret(secret: &str) -> Option<Vec<u8>> {
+    base32::decode(base32::Alphabet::Rfc4648 { padding: false }, secret)
+}
+
+/// Compare two strings in constant time (for strings of equal length).
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Return the code for the base32 encoded secret at the specified time step.
+#[cfg(test)]
+pub fn code_at_step(secret: &str, step: i64) -> Option<String> {
+    decode_secret(secret).map(|secret| hotp(&secret, step as u64))
+}
+
+/// Verify a code for the base32 encoded secret. Return the matching time
+/// step if the code is valid.
+pub fn verify_code(secret: &str, code: &str, now: DateTime<Utc>) -> Option<i64> {
+    let secret = decode_secret(secret)?;
+    let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+    let current = time_step(now);
+    (current - ALLOWED_DRIFT_STEPS..=current + ALLOWED_DRIFT_STEPS)
+        .find(|step| constant_time_eq(&hotp(&secret, *step as u64), &code))
+}
+
+/// Return the `otpauth://` URI used to add the secret to an authenticator app.
+pub fn otpauth_uri(secret: &str, username: &str) -> String {
+    let label = RawStr::new(&format!("{}:{}", ISSUER, username))
+        .percent_encode()
+        .to_string();
+    format!(
+        "otpauth://totp/{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        label, secret, ISSUER, DIGITS, STEP_SECONDS
+    )
+}
+
+// API types
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiTotpStatus {
+    enabled: bool,
+    /// Number of unused recovery codes
+    recovery_codes_remaining: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiTotpEnrollment {
+    /// Base32 encoded secret, for manual entry
+    secret: String,
+    /// URI for authenticator apps (usually shown as QR code)
+    otpauth_uri: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiRecoveryCodes {
+    /// Single-use recovery codes. They are only returned once.
+    recovery_codes: Vec<String>,
+}
+
+/// Verify a code from the authenticator app or, if no code is provided, a
+/// recovery code of the user. Used codes cannot be used again.
+pub fn verify_second_factor(
+    conn: &mut PgConnection,
+    user: &User,
+    code: Option<&str>,
+    recovery_code: Option<&str>,
+) -> QueryResult<bool> {
+    match (code, recovery_code) {
+        (Some(code), _) => match data::get_totp_credential(conn, user)
+            .filter(|credential| credential.enabled)
+            .and_then(|credential| verify_code(&credential.secret, code, Utc::now()))
+        {
+            Some(step) => data::use_totp_step(conn, user, step),
+            None => Ok(false),
+        },
+        (None, Some(recovery_code)) => data::use_totp_recovery_code(conn, user, recovery_code),
+        (None, None) => Ok(false),
+    }
+}
+
+// Forms
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TotpConfirmForm {
+    code: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TotpDisableForm {
+    password: String,
+}
+
+fn totp_error(message: &'static str) -> (Status, Json<RocketError>) {
+    RocketError::new(Status::UnprocessableEntity, "TotpError", message)
+}
+
+// API endpoints
+
+#[get("/auth/totp")]
+pub async fn status(database: data::Database, user: auth::AuthUser) -> Json<ApiTotpStatus> {
+    let user = user.into_inner();
+    let (enabled, recovery_codes_remaining) = database
+        .run(move |db| {
+            (
+                data::is_totp_enabled(db, &user),
+                data::count_unused_totp_recovery_codes(db, &user),
+            )
+        })
+        .await;
+    Json(ApiTotpStatus {
+        enabled,
+        recovery_codes_remaining,
+    })
+}
+
+#[get("/auth/totp", rank = 2)]
+pub fn status_nologin() -> ApiError {
+    ApiError::MissingAuthentication
+}
+
+/// Start TOTP enrollment. A new secret is generated, which must be confirmed
+/// with a code through the `confirm` endpoint.
+///
+/// - Return "HTTP 422 Unprocessable Entity" if TOTP is already enabled.
+#[post("/auth/totp/enroll")]
+pub async fn enroll(
+    database: data::Database,
+    user: auth::AuthUser,
+) -> Result<Json<ApiTotpEnrollment>, (Status, Json<RocketError>)> {
+    let user = user.into_inner();
+    let username = user.username.clone();
+    let credential = database
+        .run(move |db| {
+            if data::is_totp_enabled(db, &user) {
+                return Ok(None);
+            }
+            data::create_totp_credential(db, &user).map(Some)
+        })
+        .await
+        .map_err(|e| {
+            error!("Could not create TOTP credential: {}", e);
+            RocketError::new(
+                Status::InternalServerError,
+                "TotpError",
+                "Could not create TOTP credential",
+            )
+        })?
+        .ok_or_else(|| totp_error("Two-factor authentication is already enabled"))?;
+    Ok(Json(ApiTotpEnrollment {
+        otpauth_uri: otpauth_uri(&credential.secret, &username),
+        secret: credential.secret,
+    }))
+}
+
+#[post("/auth/totp/enroll", rank = 2)]
+pub fn enroll_nologin() -> ApiError {
+    ApiError::MissingAuthentication
+}
+
+/// Confirm TOTP enrollment with a code. On success, TOTP is enabled and the
+/// recovery codes are returned.
+///
+/// - Return "HTTP 422 Unprocessable Entity" if there is no pending enrollment
+///   or if the code is invalid.
+#[post("/auth/totp/confirm", data = "<data>")]
+pub async fn confirm(
+    database: data::Database,
+    user: auth::AuthUser,
+    data: Json<TotpConfirmForm>,
+) -> Result<Json<ApiRecoveryCodes>, (Status, Json<RocketError>)> {
+    let user = user.into_inner();
+    let user_id = user.id;
+    let code = data.into_inner().code;
+    let recovery_codes = database
+        .run(move |db| {
+            let credential = match data::get_totp_credential(db, &user) {
+                Some(credential) if !credential.enabled => credential,
+                _ => return Ok(Err(totp_error("No pending two-factor authentication enrollment"))),
+            };
+            match verify_code(&credential.secret, &code, Utc::now()) {
+                Some(step) => data::enable_totp(db, &user, step).map(Ok),
+                None => Ok(Err(totp_error("Invalid code"))),
+            }
+        })
+        .await
+        .map_err(|e| {
+            error!("Could not enable TOTP: {}", e);
+            RocketError::new(
+                Status::InternalServerError,
+                "TotpError",
+                "Could not enable two-factor authentication",
+            )
+        })??;
+    log::info!("Enabled TOTP for user {}", user_id);
+    Ok(Json(ApiRecoveryCodes { recovery_codes }))
+}
+
+#[post("/auth/totp/confirm", rank = 2)]
+pub fn confirm_nologin() -> ApiError {
+    ApiError::MissingAuthentication
+}
+
+/// Disable TOTP. The current password must be provided.
+///
+/// - Return "HTTP 422 Unprocessable Entity" if the password is wrong.
+#[post("/auth/totp/disable", data = "<data>")]
+pub async fn disable(
+    database: data::Database,
+    user: auth::AuthUser,
+    data: Json<TotpDisableForm>,
+) -> Result<Status, (Status, Json<RocketError>)> {
+    let user = user.into_inner();
+    let user_id = user.id;
+    let password = data.into_inner().password;
+    let result = database
+        .run(
+            move |db| match data::validate_login(db, &user.username, &password) {
+                Some(u) if u.id == user.id => data::delete_totp(db, &user).map(|()| true),
+                _ => Ok(false),
+            },
+        )
+        .await;
+    match result {
+        Ok(true) => {
+            log::info!("Disabled TOTP for user {}", user_id);
+            Ok(Status::NoContent)
+        }
+        Ok(false) => Err(totp_error("Invalid password")),
+        Err(e) => {
+            error!("Could not disable TOTP: {}", e);
+            Err(RocketError::new(
+                Status::InternalServerError,
+                "TotpError",
+                "Could not disable two-factor authentication",
+            ))
+        }
+    }
+}
+
+#[post("/auth/totp/disable", rank = 2)]
+pub fn disable_nologin() -> ApiError {
+    ApiError::MissingAuthentication
+}
+
+/// Return vec of all API routes.
+pub fn api_routes() -> Vec<Route> {
+    routes![
+        status,
+        status_nologin,
+        enroll,
+        enroll_nologin,
+        confirm,
+        confirm_nologin,
+        disable,
+        disable_nologin,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use rocket::{
+        http::{ContentType, Cookie},
+        local::blocking::Client,
+    };
+
+    use crate::{
+        mail::Mailer,
+        test_utils::{make_test_config, utc_datetime, DbTestContext},
+        Config,
+    };
+
+    use super::*;
+
+    /// Create a new test client with cookie tracking.
+    fn make_client() -> Client {
+        let app = rocket::custom(make_test_config())
+            .attach(data::Database::fairing())
+            .manage(Mailer::from_config(&Default::default()).unwrap())
+            .manage(Config::default())
+            .mount("/", [api_routes(), auth::api_routes()].concat());
+        Client::tracked(app).expect("valid rocket instance")
+    }
+
+    #[test]
+    fn rfc6238_test_vectors() {
+        // Secret "12345678901234567890", last 6 digits of the 8 digit codes
+        let secret = base32::encode(
+            base32::Alphabet::Rfc4648 { padding: false },
+            b"12345678901234567890",
+        );
+        let at = |seconds: i64| time_step(DateTime::from_timestamp(seconds, 0).unwrap());
+        assert_eq!(code_at_step(&secret, at(59)).unwrap(), "287082");
+        assert_eq!(code_at_step(&secret, at(1111111109)).unwrap(), "081804");
+        assert_eq!(code_at_step(&secret, at(1234567890)).unwrap(), "005924");
+        assert_eq!(code_at_step(&secret, at(2000000000)).unwrap(), "279037");
+
+        // Clock drift of one step is accepted
+        let now = utc_datetime(2025, 1, 1, 12, 0, 0);
+        let code = code_at_step(&secret, time_step(now - Duration::seconds(30))).unwrap();
+        assert_eq!(verify_code(&secret, &code, now), Some(time_step(now) - 1));
+        assert_eq!(verify_code(&secret, &code, now + Duration::seconds(60)), None);
+        assert_eq!(verify_code(&secret, "000000x", now), None);
+    }
+
+    #[test]
+    fn otpauth() {
+        assert_eq!(
+            otpauth_uri("JBSWY3DPEHPK3PXP", "chrigel maurer"),
+            "otpauth://totp/Flugbuech:chrigel%20maurer?secret=JBSWY3DPEHPK3PXP&issuer=Flugbuech&algorithm=SHA1&digits=6&period=30"
+        );
+    }
+
+    #[test]
+    fn enroll_login_and_disable() {
+        let ctx = DbTestContext::new();
+        let client = make_client();
+
+        macro_rules! post {
+            ($path:expr, $body:expr) => {
+                client
+                    .post($path)
+                    .header(ContentType::JSON)
+                    .body($body)
+                    .private_cookie(ctx.auth_cookie_user1())
+                    .cookie(ctx.username_cookie())
+                    .dispatch()
+            };
+        }
+        macro_rules! login {
+            () => {
+                client
+                    .post("/auth/login")
+                    .header(ContentType::JSON)
+                    .body(r#"{"username": "testuser1", "password": "testpass"}"#)
+                    .dispatch()
+            };
+        }
+        macro_rules! login_totp {
+            ($body:expr) => {
+                client
+                    .post("/auth/login/totp")
+                    .header(ContentType::JSON)
+                    .body($body)
+                    .dispatch()
+            };
+        }
+
+        // Enroll
+        let resp = post!("/auth/totp/enroll", "");
+        assert_eq!(resp.status(), Status::Ok);
+        let enrollment = resp.into_json::<ApiTotpEnrollment>().unwrap();
+        assert!(enrollment
+            .otpauth_uri
+            .starts_with("otpauth://totp/Flugbuech:testuser1?secret="));
+        let secret = enrollment.secret;
+
+        // Confirm with wrong code
+        let resp = post!("/auth/totp/confirm", r#"{"code": "abcdef"}"#);
+        assert_eq!(resp.status(), Status::UnprocessableEntity);
+
+        // Confirm
+        let code = code_at_step(&secret, time_step(Utc::now())).unwrap();
+        let resp = post!("/auth/totp/confirm", format!(r#"{{"code": "{}"}}"#, code));
+        assert_eq!(resp.status(), Status::Ok);
+        let recovery_codes = resp.into_json::<ApiRecoveryCodes>().unwrap().recovery_codes;
+        assert_eq!(recovery_codes.len(), data::TOTP_RECOVERY_CODE_COUNT);
+
+        // Enrolling again is not possible
+        let resp = post!("/auth/totp/enroll", "");
+        assert_eq!(resp.status(), Status::UnprocessableEntity);
+
+        // Second step without password login
+        let resp = login_totp!(format!(r#"{{"code": "{}"}}"#, code));
+        assert_eq!(resp.status(), Status::Unauthorized);
+
+        // Password login requires a second step
+        let resp = login!();
+        assert_eq!(resp.status(), Status::Accepted);
+        assert!(resp.cookies().get_private(auth::SESSION_COOKIE_ID).is_none());
+
+        // Codes cannot be reused
+        let resp = login_totp!(format!(r#"{{"code": "{}"}}"#, code));
+        assert_eq!(resp.status(), Status::Forbidden);
+
+        // Log in with the code of the next time step
+        let next_code = code_at_step(&secret, time_step(Utc::now()) + 1).unwrap();
+        let resp = login_totp!(format!(r#"{{"code": "{}"}}"#, next_code));
+        assert_eq!(resp.status(), Status::NoContent);
+        assert!(resp.cookies().get_private(auth::SESSION_COOKIE_ID).is_some());
+
+        // The challenge is consumed
+        let resp = login_totp!(format!(r#"{{"recoveryCode": "{}"}}"#, recovery_codes[0]));
+        assert_eq!(resp.status(), Status::Unauthorized);
+
+        // Log in with recovery code (only once)
+        client
+            .cookies()
+            .remove_private(Cookie::from(auth::SESSION_COOKIE_ID));
+        assert_eq!(login!().status(), Status::Accepted);
+        let resp = login_totp!(format!(r#"{{"recoveryCode": "{}"}}"#, recovery_codes[0]));
+        assert_eq!(resp.status(), Status::NoContent);
+        assert_eq!(login!().status(), Status::Accepted);
+        let resp = login_totp!(format!(r#"{{"recoveryCode": "{}"}}"#, recovery_codes[0]));
+        assert_eq!(resp.status(), Status::Forbidden);
+
+        // The challenge is invalidated after too many wrong codes
+        assert_eq!(login!().status(), Status::Accepted);
+        for _ in 0..3 {
+            let resp = login_totp!(r#"{"code": "000000"}"#);
+            assert_eq!(resp.status(), Status::Forbidden);
+        }
+        let resp = login_totp!(format!(r#"{{"recoveryCode": "{}"}}"#, recovery_codes[1]));
+        assert_eq!(resp.status(), Status::Unauthorized);
+
+        // Status
+        let resp = client
+            .get("/auth/totp")
+            .private_cookie(ctx.auth_cookie_user1())
+            .cookie(ctx.username_cookie())
+            .dispatch();
+        let status = resp.into_json::<ApiTotpStatus>().unwrap();
+        assert!(status.enabled);
+        assert_eq!(
+            status.recovery_codes_remaining,
+            data::TOTP_RECOVERY_CODE_COUNT as i64 - 1
+        );
+
+        // Disable (requires password)
+        let resp = post!("/auth/totp/disable", r#"{"password": "wrong"}"#);
+        assert_eq!(resp.status(), Status::UnprocessableEntity);
+        let resp = post!("/auth/totp/disable", r#"{"password": "testpass"}"#);
+        assert_eq!(resp.status(), Status::NoContent);
+        assert_eq!(login!().status(), Status::NoContent);
+    }
+}
@@ -0,0 +1,1231 @@
+//! Import and export of locations in waypoint file formats.
+//!
+//! Supported formats:
+//!
+//! - `cup`: SeeYou waypoint file
+//! - `wpt`: OziExplorer waypoint file
+//! - `compegps`: CompeGPS waypoint file (with lat/lon coordinates)
+//! - `gpx`: GPX waypoints
+//! - `kml`: KML placemarks
+
+use std::{collections::HashSet, convert::TryFrom, fmt::Write};
+
+use diesel::PgConnection;
+use diesel_geography::types::GeogPoint;
+use log::{error, info};
+use rocket::{data::ToByteUnit, get, http::ContentType, post, routes, serde::json::Json, Data, Route, State};
+use serde::Serialize;
+
+use crate::{
+    auth, data,
+    flights::FileAttachment,
+    geodata::{self, Geodata, UNKNOWN_COUNTRY},
+    locations::{distance_meters, ApiCoordinates},
+    models::{Location, NewLocation, User},
+    responders::ApiError,
+};
+
+/// Waypoints closer than this to an existing location are considered
+/// duplicates.
+const DUPLICATE_DISTANCE_METERS: f64 = 100.0;
+
+const FEET_PER_METER: f64 = 3.28084;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WaypointFormat {
+    Cup,
+    OziExplorer,
+    CompeGps,
+    Gpx,
+    Kml,
+}
+
+impl WaypointFormat {
+    fn from_param(param: &str) -> Option<Self> {
+        match param {
+            "cup" => Some(Self::Cup),
+            "wpt" => Some(Self::OziExplorer),
+            "compegps" => Some(Self::CompeGps),
+            "gpx" => Some(Self::Gpx),
+            "kml" => Some(Self::Kml),
+            _ => None,
+        }
+    }
+
+    fn filename(self) -> &'static str {
+        match self {
+            Self::Cup => "locations.cup",
+            Self::OziExplorer => "locations.wpt",
+            Self::CompeGps => "locations-compegps.wpt",
+            Self::Gpx => "locations.gpx",
+            Self::Kml => "locations.kml",
+        }
+    }
+
+    fn content_type(self) -> ContentType {
+        match self {
+            Self::Cup | Self::OziExplorer | Self::CompeGps => ContentType::new("application", "octet-stream"),
+            Self::Gpx => ContentType::new("application", "gpx+xml"),
+            Self::Kml => ContentType::new("application", "vnd.google-earth.kml+xml"),
+        }
+    }
+}
+
+/// A waypoint parsed from a file.
+#[derive(Debug, Clone, PartialEq)]
+struct Waypoint {
+    /// Line number in the file (starting with 1)
+    row: usize,
+    name: String,
+    lat: f64,
+    lon: f64,
+    /// Elevation in meters
+    elevation: Option<i32>,
+    /// ISO 3166-1 alpha-2 country code
+    country: Option<String>,
+}
+
+// API types
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Message {
+    message: String,
+    /// The line number in the file (starting with 1)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    row: Option<usize>,
+}
+
+impl Message {
+    fn without_row(message: impl Into<String>) -> Self {
+        Message {
+            message: message.into(),
+            row: None,
+        }
+    }
+
+    fn for_row(row: usize, message: impl Into<String>) -> Self {
+        Message {
+            message: message.into(),
+            row: Some(row),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiWaypointPreview {
+    /// The line number in the file (starting with 1)
+    row: usize,
+    name: String,
+    country_code: String,
+    elevation: i32,
+    coordinates: ApiCoordinates,
+    /// ID of an existing location with the same name or at (almost) the same
+    /// position. Duplicates are not imported.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duplicate_of: Option<i32>,
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WaypointAnalyzeResult {
+    warnings: Vec<Message>,
+    errors: Vec<Message>,
+    locations: Vec<ApiWaypointPreview>,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WaypointImportResult {
+    success: bool,
+    /// Number of imported locations
+    count: usize,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum WaypointAnalyzeOrImportResult {
+    Analyze(WaypointAnalyzeResult),
+    Import(WaypointImportResult),
+}
+
+// API endpoints
+
+/// Process a waypoint file
+///
+/// The `format` GET parameter is required, see module documentation for the
+/// supported formats.
+///
+/// The `mode` GET parameter is required:
+///
+/// - analyze: Process and analyze the waypoints, but don't store them yet
+/// - import: Process and store the waypoints as locations (except duplicates)
+///
+/// Missing countries and elevations are looked up in the geodata. Waypoints
+/// whose elevation cannot be determined are ignored.
+#[post(
+    "/locations/import?<format>&<mode>",
+    format = "application/octet-stream",
+    data = "<data>"
+)]
+pub async fn import(
+    user: auth::AuthUser,
+    database: data::Database,
+    geodata: &State<Geodata>,
+    format: &str,
+    mode: &str,
+    data: Data<'_>,
+) -> Result<Json<WaypointAnalyzeOrImportResult>, ApiError> {
+    info!("Processing waypoints with format '{format}' and mode '{mode}'");
+    let user = user.into_inner();
+
+    // Validate parameters
+    let format = WaypointFormat::from_param(format).ok_or_else(|| ApiError::InvalidData {
+        message: format!("Invalid format: {format}"),
+    })?;
+    if !["analyze", "import"].contains(&mode) {
+        return Err(ApiError::InvalidData {
+            message: format!("Invalid mode: {mode}"),
+        });
+    }
+
+    // Read file
+    let bytes = match data
+        .open(crate::MAX_WAYPOINT_UPLOAD_BYTES.bytes())
+        .into_bytes()
+        .await
+    {
+        Ok(capped_bytes) if capped_bytes.is_complete() => capped_bytes.into_inner(),
+        Ok(_) => {
+            return Err(ApiError::InvalidData {
+                message: "Waypoint file is too large".into(),
+            })
+        }
+        Err(e) => {
+            error!("Failed to read waypoint data: {e:?}");
+            return Err(ApiError::IoError {
+                message: "Failed to read waypoint data".into(),
+            });
+        }
+    };
+
+    // Process and analyze the waypoints
+    let analyze_result = database
+        .run({
+            let user = user.clone();
+            let geodata = geodata.inner().clone();
+            move |db| analyze_waypoints(format, &bytes, &user, &geodata, db)
+        })
+        .await;
+
+    // If desired, import locations
+    if mode == "import" {
+        if !analyze_result.errors.is_empty() {
+            return Err(ApiError::InvalidData {
+                message: "Submitted waypoint data with analyze errors".into(),
+            });
+        }
+
+        let import_result = database
+            .run(move |db| import_locations(analyze_result.locations, &user, db))
+            .await;
+
+        Ok(Json(WaypointAnalyzeOrImportResult::Import(import_result)))
+    } else {
+        Ok(Json(WaypointAnalyzeOrImportResult::Analyze(analyze_result)))
+    }
+}
+
+#[post("/locations/import", rank = 3)]
+pub fn import_nologin() -> ApiError {
+    ApiError::MissingAuthentication
+}
+
+/// Export all locations with coordinates of the user.
+#[get("/locations/export?<format>")]
+pub async fn export(
+    user: auth::AuthUser,
+    database: data::Database,
+    format: &str,
+) -> Result<FileAttachment, ApiError> {
+    let user = user.into_inner();
+    let format = WaypointFormat::from_param(format).ok_or_else(|| ApiError::InvalidData {
+        message: format!("Invalid format: {format}"),
+    })?;
+    let locations = database
+        .run(move |db| data::get_locations_for_user(db, &user))
+        .await;
+    Ok(FileAttachment::new(
+        write_waypoints(format, &locations),
+        format.content_type(),
+        format.filename().into(),
+    ))
+}
+
+#[get("/locations/export", rank = 3)]
+pub fn export_nologin() -> ApiError {
+    ApiError::MissingAuthentication
+}
+
+/// Return vec of all API routes.
+pub fn api_routes() -> Vec<Route> {
+    routes![import, import_nologin, export, export_nologin]
+}
+
+// Import
+
+/// Parse and analyze (but don't save) waypoints
+fn analyze_waypoints(
+    format: WaypointFormat,
+    bytes: &[u8],
+    user: &User,
+    geodata: &Geodata,
+    conn: &mut PgConnection,
+) -> WaypointAnalyzeResult {
+    let mut result = WaypointAnalyzeResult::default();
+
+    let text = decode_text(bytes);
+    let waypoints = match parse_waypoints(format, &text, &mut result.warnings) {
+        Ok(waypoints) => waypoints,
+        Err(message) => {
+            result.errors.push(Message::without_row(message));
+            return result;
+        }
+    };
+    if waypoints.is_empty() {
+        result
+            .errors
+            .push(Message::without_row("File does not contain any waypoints"));
+        return result;
+    }
+
+    // Detect duplicates
+    let existing = data::get_locations_for_user(conn, user);
+    let mut names = HashSet::new();
+    for waypoint in waypoints {
+        if !names.insert(waypoint.name.to_lowercase()) {
+            result.warnings.push(Message::for_row(
+                waypoint.row,
+                format!(
+                    "Waypoint \"{}\" is contained multiple times, ignoring",
+                    waypoint.name
+                ),
+            ));
+            continue;
+        }
+        let elevation = match waypoint
+            .elevation
+            .or_else(|| geodata.elevation_at(waypoint.lat, waypoint.lon))
+        {
+            Some(elevation) => elevation,
+            None => {
+                result.warnings.push(Message::for_row(
+                    waypoint.row,
+                    format!(
+                        "Elevation of waypoint \"{}\" is missing and could not be looked up, ignoring",
+                        waypoint.name
+                    ),
+                ));
+                continue;
+            }
+        };
+        let country_code = match waypoint
+            .country
+            .clone()
+            .or_else(|| geodata.country_at(waypoint.lat, waypoint.lon).map(Into::into))
+        {
+            Some(country_code) => country_code,
+            None => {
+                result.warnings.push(Message::for_row(
+                    waypoint.row,
+                    format!(
+                        "Country of waypoint \"{}\" is missing and could not be looked up, using \"{}\"",
+                        waypoint.name, UNKNOWN_COUNTRY
+                    ),
+                ));
+                UNKNOWN_COUNTRY.into()
+            }
+        };
+        let duplicate_of = find_duplicate(&waypoint, &existing);
+        if let Some(location) = duplicate_of {
+            result.warnings.push(Message::for_row(
+                waypoint.row,
+                format!(
+                    "Waypoint \"{}\" already exists as location \"{}\", will not be imported",
+                    waypoint.name, location.name
+                ),
+            ));
+        }
+        result.locations.push(ApiWaypointPreview {
+            row: waypoint.row,
+            name: waypoint.name,
+            country_code,
+            elevation,
+            coordinates: ApiCoordinates {
+                lon: waypoint.lon,
+                lat: waypoint.lat,
+            },
+            duplicate_of: duplicate_of.map(|location| location.id),
+        });
+    }
+
+    result
+}
+
+/// Return an existing location with the same name (case insensitive) or
+/// close to the waypoint.
+fn find_duplicate<'a>(waypoint: &Waypoint, locations: &'a [Location]) -> Option<&'a Location> {
+    let name = waypoint.name.to_lowercase();
+    locations
+        .iter()
+        .find(|location| location.name.trim().to_lowercase() == name)
+        .or_else(|| {
+            locations.iter().find(|location| {
+                location.geog.as_ref().is_some_and(|geog| {
+                    distance_meters(waypoint.lat, waypoint.lon, geog.y, geog.x) < DUPLICATE_DISTANCE_METERS
+                })
+            })
+        })
+}
+
+/// Import all non-duplicate waypoints as locations
+fn import_locations(
+    waypoints: Vec<ApiWaypointPreview>,
+    user: &User,
+    conn: &mut PgConnection,
+) -> WaypointImportResult {
+    let new_locations: Vec<NewLocation> = waypoints
+        .into_iter()
+        .filter(|waypoint| waypoint.duplicate_of.is_none())
+        .map(|waypoint| NewLocation {
+            name: waypoint.name,
+            country: waypoint.country_code,
+            elevation: waypoint.elevation,
+            user_id: user.id,
+            geog: Some(GeogPoint {
+                x: waypoint.coordinates.lon,
+                y: waypoint.coordinates.lat,
+                srid: None,
+            }),
+            match_radius: None,
+            area: None,
+        })
+        .collect();
+
+    match data::create_locations(conn, &new_locations) {
+        Ok(count) => {
+            info!("Imported {} locations for user {}", count, user.id);
+            WaypointImportResult { success: true, count }
+        }
+        Err(e) => {
+            error!("Failed to import locations: {e}");
+            WaypointImportResult {
+                success: false,
+                count: 0,
+            }
+        }
+    }
+}
+
+/// Decode a text file. Waypoint files are either UTF-8 or, especially for
+/// older Windows software, Latin-1.
+fn decode_text(bytes: &[u8]) -> String {
+    let bytes = bytes.strip_prefix(b"\xef\xbb\xbf").unwrap_or(bytes);
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        Err(_) => bytes.iter().map(|&b| b as char).collect(),
+    }
+}
+
+/// Encode a text file as Latin-1 (for formats that are read by older Windows
+/// software). Unsupported characters are replaced by `?`.
+fn encode_latin1(text: &str) -> Vec<u8> {
+    text.chars()
+        .map(|c| u8::try_from(u32::from(c)).unwrap_or(b'?'))
+        .collect()
+}
+
+fn parse_waypoints(
+    format: WaypointFormat,
+    text: &str,
+    warnings: &mut Vec<Message>,
+) -> Result<Vec<Waypoint>, String> {
+    let waypoints = match format {
+        WaypointFormat::Cup => parse_cup(text, warnings)?,
+        WaypointFormat::OziExplorer => parse_ozi(text, warnings)?,
+        WaypointFormat::CompeGps => parse_compegps(text, warnings)?,
+        WaypointFormat::Gpx => parse_gpx(text, warnings)?,
+        WaypointFormat::Kml => parse_kml(text, warnings)?,
+    };
+
+    // Validate waypoints
+    Ok(waypoints
+        .into_iter()
+        .filter(|waypoint| {
+            if waypoint.name.is_empty() {
+                warnings.push(Message::for_row(waypoint.row, "Waypoint without name, ignoring"));
+                false
+            } else if !(-90.0..=90.0).contains(&waypoint.lat) || !(-180.0..=180.0).contains(&waypoint.lon) {
+                warnings.push(Message::for_row(
+                    waypoint.row,
+                    format!("Waypoint \"{}\" has invalid coordinates, ignoring", waypoint.name),
+                ));
+                false
+            } else {
+                true
+            }
+        })
+        .collect())
+}
+
+/// Parse a SeeYou CUP file.
+fn parse_cup(text: &str, warnings: &mut Vec<Message>) -> Result<Vec<Waypoint>, String> {
+    // Tasks may follow after the waypoints
+    let text = text.split("-----Related Tasks-----").next().unwrap_or_default();
+
+    // Waypoints never span multiple lines, so every line is parsed as a
+    // separate record to get reliable line numbers
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty());
+    let headers = match lines.next().map(|(_, line)| parse_csv_line(line)) {
+        Some(Ok(record)) => record
+            .iter()
+            .map(|header| header.to_lowercase())
+            .collect::<Vec<_>>(),
+        Some(Err(e)) => return Err(format!("Could not read CUP header: {e}")),
+        None => return Err("CUP file is empty".into()),
+    };
+    let column = |name: &str| headers.iter().position(|header| header == name);
+    let (Some(name_col), Some(lat_col), Some(lon_col)) = (column("name"), column("lat"), column("lon"))
+    else {
+        return Err("CUP header must contain the columns name, lat and lon".into());
+    };
+    let (country_col, elev_col) = (column("country"), column("elev"));
+
+    let mut waypoints = vec![];
+    for (index, line) in lines {
+        let row = index + 1;
+        let record = match parse_csv_line(line) {
+            Ok(record) => record,
+            Err(e) => {
+                warnings.push(Message::for_row(row, format!("Could not read waypoint: {e}")));
+                continue;
+            }
+        };
+        let field = |col: Option<usize>| col.and_then(|col| record.get(col)).unwrap_or_default();
+        let (Some(lat), Some(lon)) = (
+            parse_cup_coordinate(field(Some(lat_col)), 2),
+            parse_cup_coordinate(field(Some(lon_col)), 3),
+        ) else {
+            warnings.push(Message::for_row(row, "Invalid coordinates, ignoring waypoint"));
+            continue;
+        };
+        let country = field(country_col).to_ascii_uppercase();
+        waypoints.push(Waypoint {
+            row,
+            name: field(Some(name_col)).to_string(),
+            lat,
+            lon,
+            elevation: parse_cup_elevation(field(elev_col)),
+            country: geodata::is_valid_country_code(&country).then_some(country),
+        });
+    }
+    Ok(waypoints)
+}
+
+/// Parse a single CSV line.
+fn parse_csv_line(line: &str) -> csv::Result<csv::StringRecord> {
+    csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(line.as_bytes())
+        .records()
+        .next()
+        .unwrap_or_else(|| Ok(csv::StringRecord::new()))
+}
+
+/// Parse a CUP coordinate (e.g. `4624.960N` or `00807.236E`), where the
+/// degrees have `degree_digits` digits.
+fn parse_cup_coordinate(value: &str, degree_digits: usize) -> Option<f64> {
+    let (number, sign) = parse_hemisphere(value)?;
+    let degrees: f64 = number.get(..degree_digits)?.parse().ok()?;
+    let minutes: f64 = number.get(degree_digits..)?.parse().ok()?;
+    if !(0.0..60.0).contains(&minutes) {
+        return None;
+    }
+    Some(sign * (degrees + minutes / 60.0))
+}
+
+/// Parse a CUP elevation (e.g. `1234.0m` or `4000ft`) and return it in meters.
+fn parse_cup_elevation(value: &str) -> Option<i32> {
+    let (number, factor) = if let Some(number) = value.strip_suffix("ft") {
+        (number, 1.0 / FEET_PER_METER)
+    } else {
+        (value.strip_suffix('m').unwrap_or(value), 1.0)
+    };
+    number
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .map(|elevation| (elevation * factor).round() as i32)
+}
+
+/// Parse an OziExplorer waypoint file.
+fn parse_ozi(text: &str, warnings: &mut Vec<Message>) -> Result<Vec<Waypoint>, String> {
+    if !text.starts_with("OziExplorer Waypoint File") {
+        return Err("Not an OziExplorer waypoint file".into());
+    }
+    let mut waypoints = vec![];
+    // The first four lines are the header
+    for (index, line) in text.lines().enumerate().skip(4) {
+        let row = index + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let (Some(lat), Some(lon)) = (
+            fields.get(2).and_then(|lat| lat.parse().ok()),
+            fields.get(3).and_then(|lon| lon.parse().ok()),
+        ) else {
+            warnings.push(Message::for_row(row, "Invalid coordinates, ignoring waypoint"));
+            continue;
+        };
+        // Altitude is in feet, -777 means "not valid"
+        let elevation = fields
+            .get(14)
+            .and_then(|alt| alt.parse::<f64>().ok())
+            .filter(|&alt| alt != -777.0)
+            .map(|alt| (alt / FEET_PER_METER).round() as i32);
+        waypoints.push(Waypoint {
+            row,
+            // Commas in names are stored as character 209
+            name: fields.get(1).unwrap_or(&"").replace('\u{d1}', ","),
+            lat,
+            lon,
+            elevation,
+            country: None,
+        });
+    }
+    Ok(waypoints)
+}
+
+/// Parse a CompeGPS waypoint file.
+fn parse_compegps(text: &str, warnings: &mut Vec<Message>) -> Result<Vec<Waypoint>, String> {
+    let mut waypoints = vec![];
+    for (index, line) in text.lines().enumerate() {
+        let row = index + 1;
+        let mut fields = line.split_whitespace();
+        match fields.next() {
+            Some("U") if fields.next() != Some("1") => {
+                return Err("Only CompeGPS files with lat/lon coordinates are supported".into());
+            }
+            Some("W") => {
+                let name = fields.next().unwrap_or_default().replace('_', " ");
+                let _ = fields.next(); // Always "A"
+                let (Some(lat), Some(lon)) = (
+                    fields.next().and_then(parse_compegps_coordinate),
+                    fields.next().and_then(parse_compegps_coordinate),
+                ) else {
+                    warnings.push(Message::for_row(row, "Invalid coordinates, ignoring waypoint"));
+                    continue;
+                };
+                let elevation = fields
+                    .nth(2) // Skip date and time
+                    .and_then(|alt| alt.parse::<f64>().ok())
+                    .map(|alt| alt.round() as i32);
+                waypoints.push(Waypoint {
+                    row,
+                    name,
+                    lat,
+                    lon,
+                    elevation,
+                    country: None,
+                });
+            }
+            _ => {}
+        }
+    }
+    Ok(waypoints)
+}
+
+/// Parse a CompeGPS coordinate (e.g. `46.4160000000ºN`).
+fn parse_compegps_coordinate(value: &str) -> Option<f64> {
+    let (number, sign) = parse_hemisphere(value)?;
+    let number: f64 = number.trim_end_matches(['º', '°']).parse().ok()?;
+    Some(sign * number)
+}
+
+/// Split off the hemisphere letter (N, S, E or W) at the end of a coordinate
+/// and return the remaining value and the sign of the coordinate.
+fn parse_hemisphere(value: &str) -> Option<(&str, f64)> {
+    let sign = match value.chars().next_back()?.to_ascii_uppercase() {
+        'N' | 'E' => 1.0,
+        'S' | 'W' => -1.0,
+        _ => return None,
+    };
+    Some((&value[..value.len() - 1], sign))
+}
+
+/// Parse the GPX waypoints (`wpt` elements) from a GPX file.
+fn parse_gpx(text: &str, warnings: &mut Vec<Message>) -> Result<Vec<Waypoint>, String> {
+    let doc = roxmltree::Document::parse(text).map_err(|e| format!("Invalid GPX file: {e}"))?;
+    if doc.root_element().tag_name().name() != "gpx" {
+        return Err("Not a GPX file".into());
+    }
+    let mut waypoints = vec![];
+    for node in doc.descendants().filter(|node| node.has_tag_name("wpt")) {
+        let row = doc.text_pos_at(node.range().start).row as usize;
+        let child_text = |name: &str| {
+            node.children()
+                .find(|child| child.has_tag_name(name))
+                .and_then(|child| child.text())
+                .map(str::trim)
+        };
+        let (Some(lat), Some(lon)) = (
+            node.attribute("lat").and_then(|lat| lat.trim().parse().ok()),
+            node.attribute("lon").and_then(|lon| lon.trim().parse().ok()),
+        ) else {
+            warnings.push(Message::for_row(row, "Invalid coordinates, ignoring waypoint"));
+            continue;
+        };
+        waypoints.push(Waypoint {
+            row,
+            name: child_text("name").unwrap_or_default().to_string(),
+            lat,
+            lon,
+            elevation: child_text("ele")
+                .and_then(|ele| ele.parse::<f64>().ok())
+                .map(|ele| ele.round() as i32),
+            country: None,
+        });
+    }
+    Ok(waypoints)
+}
+
+/// Parse the point placemarks from a KML file.
+fn parse_kml(text: &str, warnings: &mut Vec<Message>) -> Result<Vec<Waypoint>, String> {
+    let doc = roxmltree::Document::parse(text).map_err(|e| format!("Invalid KML file: {e}"))?;
+    if doc.root_element().tag_name().name() != "kml" {
+        return Err("Not a KML file".into());
+    }
+    let mut waypoints = vec![];
+    for node in doc.descendants().filter(|node| node.has_tag_name("Placemark")) {
+        let row = doc.text_pos_at(node.range().start).row as usize;
+        let name = node
+            .children()
+            .find(|child| child.has_tag_name("name"))
+            .and_then(|child| child.text())
+            .unwrap_or_default()
+            .trim()
+            .to_string();
+        let Some(coordinates) = node
+            .descendants()
+            .find(|child| child.has_tag_name("Point"))
+            .and_then(|point| point.children().find(|child| child.has_tag_name("coordinates")))
+            .and_then(|coordinates| coordinates.text())
+        else {
+            warnings.push(Message::for_row(
+                row,
+                format!("Placemark \"{name}\" is not a point, ignoring"),
+            ));
+            continue;
+        };
+        // Coordinates are "lon,lat[,alt]"
+        let values: Vec<Option<f64>> = coordinates
+            .trim()
+            .split(',')
+            .map(|v| v.trim().parse().ok())
+            .collect();
+        let (Some(Some(lon)), Some(Some(lat))) = (values.first(), values.get(1)) else {
+            warnings.push(Message::for_row(row, "Invalid coordinates, ignoring placemark"));
+            continue;
+        };
+        waypoints.push(Waypoint {
+            row,
+            name,
+            lat: *lat,
+            lon: *lon,
+            elevation: values.get(2).copied().flatten().map(|alt| alt.round() as i32),
+            country: None,
+        });
+    }
+    Ok(waypoints)
+}
+
+// Export
+
+/// Write all locations with coordinates in the specified format.
+fn write_waypoints(format: WaypointFormat, locations: &[Location]) -> Vec<u8> {
+    let locations = locations
+        .iter()
+        .filter_map(|location| location.geog.as_ref().map(|geog| (location, geog.y, geog.x)));
+    let mut out = String::new();
+    match format {
+        WaypointFormat::Cup => {
+            out.push_str("name,code,country,lat,lon,elev,style,rwdir,rwlen,freq,desc\r\n");
+            for (location, lat, lon) in locations {
+                let name = location.name.replace('"', "'");
+                let _ = write!(
+                    out,
+                    "\"{}\",\"{}\",{},{},{},{}.0m,1,,,,\r\n",
+                    name,
+                    name,
+                    location.country.trim(),
+                    format_cup_coordinate(lat, 2, ('N', 'S')),
+                    format_cup_coordinate(lon, 3, ('E', 'W')),
+                    location.elevation,
+                );
+            }
+            out.into_bytes()
+        }
+        WaypointFormat::OziExplorer => {
+            out.push_str("OziExplorer Waypoint File Version 1.1\r\nWGS 84\r\nReserved 2\r\nReserved 3\r\n");
+            for (number, (location, lat, lon)) in locations.enumerate() {
+                let name = location.name.replace(',', "\u{d1}");
+                let _ = write!(
+                    out,
+                    "{},{},{:.6},{:.6},,0,1,3,0,65535,{},0,0,0,{},6,0,17\r\n",
+                    number + 1,
+                    name,
+                    lat,
+                    lon,
+                    name,
+                    (f64::from(location.elevation) * FEET_PER_METER).round(),
+                );
+            }
+            encode_latin1(&out)
+        }
+        WaypointFormat::CompeGps => {
+            out.push_str("G  WGS 84\r\nU  1\r\n");
+            for (location, lat, lon) in locations {
+                let _ = write!(
+                    out,
+                    "W  {} A {:.10}º{} {:.10}º{} 27-MAR-62 00:00:00 {:.6} {}\r\n",
+                    location.name.replace(' ', "_"),
+                    lat.abs(),
+                    if lat < 0.0 { 'S' } else { 'N' },
+                    lon.abs(),
+                    if lon < 0.0 { 'W' } else { 'E' },
+                    f64::from(location.elevation),
+                    location.name,
+                );
+            }
+            encode_latin1(&out)
+        }
+        WaypointFormat::Gpx => {
+            out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+            out.push_str(
+                "<gpx version=\"1.1\" creator=\"Flugbuech\" xmlns=\"http://www.topografix.com/GPX/1/1\">\n",
+            );
+            for (location, lat, lon) in locations {
+                let _ = writeln!(
+                    out,
+                    "  <wpt lat=\"{}\" lon=\"{}\"><ele>{}</ele><name>{}</name></wpt>",
+                    lat,
+                    lon,
+                    location.elevation,
+                    escape_xml(&location.name),
+                );
+            }
+            out.push_str("</gpx>\n");
+            out.into_bytes()
+        }
+        WaypointFormat::Kml => {
+            out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+            out.push_str("<kml xmlns=\"http://www.opengis.net/kml/2.2\">\n<Document>\n<name>Flugbuech Locations</name>\n");
+            for (location, lat, lon) in locations {
+                let _ = writeln!(
+                    out,
+                    "  <Placemark><name>{}</name><Point><coordinates>{},{},{}</coordinates></Point></Placemark>",
+                    escape_xml(&location.name),
+                    lon,
+                    lat,
+                    location.elevation,
+                );
+            }
+            out.push_str("</Document>\n</kml>\n");
+            out.into_bytes()
+        }
+    }
+}
+
+/// Format a coordinate in the CUP format (degrees and decimal minutes).
+fn format_cup_coordinate(value: f64, degree_digits: usize, hemispheres: (char, char)) -> String {
+    let thousandths_of_minutes = (value.abs() * 60_000.0).round() as i64;
+    format!(
+        "{:0width$}{:06.3}{}",
+        thousandths_of_minutes / 60_000,
+        (thousandths_of_minutes % 60_000) as f64 / 1000.0,
+        if value < 0.0 { hemispheres.1 } else { hemispheres.0 },
+        width = degree_digits,
+    )
+}
+
+fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use rocket::{http::Status, local::blocking::Client};
+
+    use crate::test_utils::{make_test_config, DbTestContext};
+
+    use super::*;
+
+    fn parse(format: WaypointFormat, text: &str) -> (Vec<Waypoint>, Vec<Message>) {
+        let mut warnings = vec![];
+        let waypoints = parse_waypoints(format, text, &mut warnings).unwrap();
+        (waypoints, warnings)
+    }
+
+    fn assert_close(waypoint: &Waypoint, lat: f64, lon: f64) {
+        assert!(
+            (waypoint.lat - lat).abs() < 1e-5,
+            "lat {} != {}",
+            waypoint.lat,
+            lat
+        );
+        assert!(
+            (waypoint.lon - lon).abs() < 1e-5,
+            "lon {} != {}",
+            waypoint.lon,
+            lon
+        );
+    }
+
+    fn location(id: i32, name: &str, lat: f64, lon: f64) -> Location {
+        Location {
+            id,
+            name: name.into(),
+            country: "CH".into(),
+            elevation: 2212,
+            user_id: 1,
+            geog: Some(GeogPoint {
+                x: lon,
+                y: lat,
+                srid: None,
+            }),
+            site_id: None,
+            match_radius: None,
+            area: None,
+        }
+    }
+
+    #[test]
+    fn parse_cup_file() {
+        let text = "name,code,country,lat,lon,elev,style,rwdir,rwlen,freq,desc\r\n\
+                    \"Fiesch Kühboden\",\"FIES\",ch,4624.960N,00807.236E,2212.0m,1,,,,\r\n\
+                    \"Atlanta\",\"ATL\",US,3338.400N,08425.680W,1026ft,1,,,,\r\n\
+                    \"Broken\",\"BRK\",CH,4624.960X,00807.236E,2212.0m,1,,,,\r\n\
+                    -----Related Tasks-----\r\n\
+                    \"Task\",\"Fiesch Kühboden\"\r\n";
+        let (waypoints, warnings) = parse(WaypointFormat::Cup, text);
+        assert_eq!(waypoints.len(), 2);
+        assert_eq!(waypoints[0].name, "Fiesch Kühboden");
+        assert_eq!(waypoints[0].row, 2);
+        assert_eq!(waypoints[0].country.as_deref(), Some("CH"));
+        assert_eq!(waypoints[0].elevation, Some(2212));
+        assert_close(&waypoints[0], 46.416, 8.1206);
+        assert_close(&waypoints[1], 33.64, -84.428);
+        assert_eq!(waypoints[1].elevation, Some(313));
+        assert_eq!(
+            warnings,
+            vec![Message::for_row(4, "Invalid coordinates, ignoring waypoint")]
+        );
+
+        // Missing columns
+        let mut warnings = vec![];
+        assert!(parse_waypoints(WaypointFormat::Cup, "name,code\nA,B\n", &mut warnings).is_err());
+    }
+
+    #[test]
+    fn parse_ozi_file() {
+        let text = "OziExplorer Waypoint File Version 1.1\r\n\
+                    WGS 84\r\n\
+                    Reserved 2\r\n\
+                    garmin\r\n\
+                    1,Fiesch\u{d1} Kühboden,  46.416000,   8.120600,,0, 1, 3,0,65535,Desc,0,0,0,7257,6,0,17\r\n\
+                    2,NoAlt,-33.5,-70.5,,0,1,3,0,65535,,0,0,0,-777,6,0,17\r\n";
+        let (waypoints, warnings) = parse(WaypointFormat::OziExplorer, text);
+        assert!(warnings.is_empty());
+        assert_eq!(waypoints.len(), 2);
+        assert_eq!(waypoints[0].name, "Fiesch, Kühboden");
+        assert_eq!(waypoints[0].row, 5);
+        assert_eq!(waypoints[0].elevation, Some(2212));
+        assert_close(&waypoints[0], 46.416, 8.1206);
+        assert_eq!(waypoints[1].elevation, None);
+        assert_close(&waypoints[1], -33.5, -70.5);
+
+        let mut warnings = vec![];
+        assert!(parse_waypoints(WaypointFormat::OziExplorer, "foo", &mut warnings).is_err());
+    }
+
+    #[test]
+    fn parse_compegps_file() {
+        let text = "G  WGS 84\r\n\
+                    U  1\r\n\
+                    W  Fiesch_Kuehboden A 46.4160000000ºN 8.1206000000ºE 27-MAR-62 00:00:00 2212.000000 Fiesch\r\n\
+                    w Waypoint,0,-1.0,16777215,255,1,7,,0.0\r\n\
+                    W  South A 33.5°S 70.5°W 27-MAR-62 00:00:00 500.0 South\r\n";
+        let (waypoints, warnings) = parse(WaypointFormat::CompeGps, text);
+        assert!(warnings.is_empty());
+        assert_eq!(waypoints.len(), 2);
+        assert_eq!(waypoints[0].name, "Fiesch Kuehboden");
+        assert_eq!(waypoints[0].row, 3);
+        assert_eq!(waypoints[0].elevation, Some(2212));
+        assert_close(&waypoints[0], 46.416, 8.1206);
+        assert_close(&waypoints[1], -33.5, -70.5);
+
+        // Latin-1 encoded degree sign
+        let text = decode_text(b"U  1\nW  A A 46.5\xbaN 8.5\xbaE 27-MAR-62 00:00:00 1.0 A\n");
+        let (waypoints, _) = parse(WaypointFormat::CompeGps, &text);
+        assert_close(&waypoints[0], 46.5, 8.5);
+
+        // UTM coordinates are not supported
+        let mut warnings = vec![];
+        assert!(parse_waypoints(WaypointFormat::CompeGps, "U  0\n", &mut warnings).is_err());
+    }
+
+    #[test]
+    fn parse_gpx_file() {
+        let text = r#"<?xml version="1.0" encoding="UTF-8"?>
+<gpx version="1.1" creator="Test" xmlns="http://www.topografix.com/GPX/1/1">
+  <wpt lat="46.416" lon="8.1206">
+    <ele>2212.4</ele>
+    <name>Fiesch &amp; Kühboden</name>
+  </wpt>
+  <wpt lat="foo" lon="8.1"><name>Broken</name></wpt>
+  <trk><trkseg><trkpt lat="1" lon="1"/></trkseg></trk>
+</gpx>"#;
+        let (waypoints, warnings) = parse(WaypointFormat::Gpx, text);
+        assert_eq!(waypoints.len(), 1);
+        assert_eq!(waypoints[0].name, "Fiesch & Kühboden");
+        assert_eq!(waypoints[0].row, 3);
+        assert_eq!(waypoints[0].elevation, Some(2212));
+        assert_close(&waypoints[0], 46.416, 8.1206);
+        assert_eq!(
+            warnings,
+            vec![Message::for_row(7, "Invalid coordinates, ignoring waypoint")]
+        );
+
+        let mut warnings = vec![];
+        assert!(parse_waypoints(WaypointFormat::Gpx, "<kml/>", &mut warnings).is_err());
+        assert!(parse_waypoints(WaypointFormat::Gpx, "not xml", &mut warnings).is_err());
+    }
+
+    #[test]
+    fn parse_kml_file() {
+        let text = r#"<?xml version="1.0" encoding="UTF-8"?>
+<kml xmlns="http://www.opengis.net/kml/2.2">
+  <Document>
+    <Folder>
+      <Placemark>
+        <name>Fiesch</name>
+        <Point><coordinates> 8.1206,46.416,2212 </coordinates></Point>
+      </Placemark>
+      <Placemark>
+        <name>Area</name>
+        <Polygon/>
+      </Placemark>
+    </Folder>
+  </Document>
+</kml>"#;
+        let (waypoints, warnings) = parse(WaypointFormat::Kml, text);
+        assert_eq!(waypoints.len(), 1);
+        assert_eq!(waypoints[0].name, "Fiesch");
+        assert_eq!(waypoints[0].elevation, Some(2212));
+        assert_close(&waypoints[0], 46.416, 8.1206);
+        assert_eq!(
+            warnings,
+            vec![Message::for_row(9, "Placemark \"Area\" is not a point, ignoring")]
+        );
+    }
+
+    #[test]
+    fn export_roundtrip() {
+        let locations = vec![
+            location(1, "Fiesch, \"Kühboden\" & Co", 46.416, 8.1206),
+            location(2, "Atlanta", 33.64, -84.428),
+            Location {
+                geog: None,
+                ..location(3, "Nowhere", 0.0, 0.0)
+            },
+        ];
+        for format in [
+            WaypointFormat::Cup,
+            WaypointFormat::OziExplorer,
+            WaypointFormat::CompeGps,
+            WaypointFormat::Gpx,
+            WaypointFormat::Kml,
+        ] {
+            let text = decode_text(&write_waypoints(format, &locations));
+            let (waypoints, warnings) = parse(format, &text);
+            assert!(warnings.is_empty(), "{:?}: {:?}", format, warnings);
+            assert_eq!(waypoints.len(), 2, "{:?}", format);
+            assert_close(&waypoints[0], 46.416, 8.1206);
+            assert_close(&waypoints[1], 33.64, -84.428);
+            assert_eq!(waypoints[0].elevation, Some(2212), "{:?}", format);
+            assert_eq!(waypoints[1].name, "Atlanta", "{:?}", format);
+            if format == WaypointFormat::Cup {
+                assert_eq!(waypoints[0].name, "Fiesch, 'Kühboden' & Co");
+                assert_eq!(waypoints[0].country.as_deref(), Some("CH"));
+            } else if format != WaypointFormat::CompeGps {
+                assert_eq!(waypoints[0].name, "Fiesch, \"Kühboden\" & Co", "{:?}", format);
+            }
+        }
+    }
+
+    #[test]
+    fn cup_coordinates() {
+        assert_eq!(format_cup_coordinate(46.416, 2, ('N', 'S')), "4624.960N");
+        assert_eq!(format_cup_coordinate(-8.1206, 3, ('E', 'W')), "00807.236W");
+        assert_eq!(format_cup_coordinate(46.9999999, 2, ('N', 'S')), "4700.000N");
+        assert_eq!(parse_cup_coordinate("4624.960N", 2), Some(46.416));
+        assert_eq!(parse_cup_coordinate("4660.000N", 2), None);
+        assert_eq!(parse_cup_coordinate("N", 2), None);
+        assert_eq!(parse_cup_elevation("1000ft"), Some(305));
+        assert_eq!(parse_cup_elevation("12.6m"), Some(13));
+        assert_eq!(parse_cup_elevation(""), None);
+    }
+
+    #[test]
+    fn duplicates() {
+        let locations = vec![location(1, "Fiesch", 46.416, 8.1206)];
+        let waypoint = |name: &str, lat: f64, lon: f64| Waypoint {
+            row: 1,
+            name: name.into(),
+            lat,
+            lon,
+            elevation: None,
+            country: None,
+        };
+        assert_eq!(
+            find_duplicate(&waypoint("FIESCH", 0.0, 0.0), &locations).map(|l| l.id),
+            Some(1)
+        );
+        assert_eq!(
+            find_duplicate(&waypoint("Kühboden", 46.4165, 8.1206), &locations).map(|l| l.id),
+            Some(1)
+        );
+        assert_eq!(
+            find_duplicate(&waypoint("Kühboden", 46.418, 8.1206), &locations),
+            None
+        );
+    }
+
+    #[test]
+    fn import_and_export() {
+        let ctx = DbTestContext::new();
+        let app = rocket::custom(make_test_config())
+            .attach(data::Database::fairing())
+            .manage(crate::geodata::tests::test_geodata())
+            .mount("/", api_routes());
+        let client = Client::untracked(app).expect("valid rocket instance");
+        data::create_location(
+            &mut ctx.force_get_conn(),
+            NewLocation {
+                name: "Fiesch".into(),
+                country: "CH".into(),
+                elevation: 2212,
+                user_id: ctx.testuser1.user.id,
+                geog: None,
+                match_radius: None,
+                area: None,
+            },
+        );
+        let gpx = r#"<gpx>
+            <wpt lat="46.416" lon="8.1206"><name>Fiesch</name><ele>2212</ele></wpt>
+            <wpt lat="46.404" lon="8.136"><name>Landeplatz</name><ele>1050</ele></wpt>
+            <wpt lat="46.404" lon="8.136"><name>landeplatz</name></wpt>
+            <wpt lat="46.5" lon="9.5"><name>Crap Masegn</name></wpt>
+            <wpt lat="48.5" lon="9.0"><name>Nowhere</name><ele>500</ele></wpt>
+            <wpt lat="46.01" lon="9.99"><name>Void</name></wpt>
+        </gpx>"#;
+        let post = |mode: &str| {
+            client
+                .post(format!("/locations/import?format=gpx&mode={}", mode))
+                .header(ContentType::new("application", "octet-stream"))
+                .body(gpx)
+                .private_cookie(ctx.auth_cookie_user1())
+                .cookie(ctx.username_cookie())
+                .dispatch()
+                .into_json::<rocket::serde::json::Value>()
+                .unwrap()
+        };
+
+        // Analyze
+        let result = post("analyze");
+        let locations = result["locations"].as_array().unwrap();
+        assert_eq!(locations.len(), 4);
+        assert_eq!(locations[0]["duplicateOf"], 1);
+        assert_eq!(locations[1]["name"], "Landeplatz");
+        assert_eq!(locations[1]["countryCode"], "CH");
+        assert_eq!(locations[1]["elevation"], 1050);
+        assert_eq!(locations[2]["name"], "Crap Masegn");
+        assert_eq!(locations[2]["countryCode"], "CH");
+        assert_eq!(locations[2]["elevation"], 1004);
+        assert_eq!(locations[3]["name"], "Nowhere");
+        assert_eq!(locations[3]["countryCode"], "XX");
+        let warning_rows = result["warnings"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|warning| warning["row"].as_u64().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(warning_rows, vec![2, 4, 6, 7]);
+        assert_eq!(
+            data::get_locations_for_user(&mut ctx.force_get_conn(), &ctx.testuser1.user).len(),
+            1
+        );
+
+        // Import
+        let result = post("import");
+        assert_eq!(result["success"], true);
+        assert_eq!(result["count"], 3);
+        let locations = data::get_locations_for_user(&mut ctx.force_get_conn(), &ctx.testuser1.user);
+        assert_eq!(locations.len(), 4);
+        let location = |name: &str| locations.iter().find(|location| location.name == name).unwrap();
+        assert_eq!(location("Landeplatz").elevation, 1050);
+        assert_eq!(location("Crap Masegn").elevation, 1004);
+        assert_eq!(location("Nowhere").country, "XX");
+        assert!(locations.iter().all(|location| location.name != "Void"));
+
+        // Invalid format
+        let resp = client
+            .post("/locations/import?format=foo&mode=analyze")
+            .header(ContentType::new("application", "octet-stream"))
+            .body(gpx)
+            .private_cookie(ctx.auth_cookie_user1())
+            .cookie(ctx.username_cookie())
+            .dispatch();
+        assert_eq!(resp.status(), Status::BadRequest);
+
+        // Export (only locations with coordinates)
+        let resp = client
+            .get("/locations/export?format=kml")
+            .private_cookie(ctx.auth_cookie_user1())
+            .cookie(ctx.username_cookie())
+            .dispatch();
+        assert_eq!(resp.status(), Status::Ok);
+        let (waypoints, _) = parse(WaypointFormat::Kml, &resp.into_string().unwrap());
+        let mut names = waypoints
+            .iter()
+            .map(|waypoint| waypoint.name.as_str())
+            .collect::<Vec<_>>();
+        names.sort_unstable();
+        assert_eq!(names, vec!["Crap Masegn", "Landeplatz", "Nowhere"]);
+
+        // Without login
+        let resp = client.get("/locations/export?format=kml").dispatch();
+        assert_eq!(resp.status(), Status::Unauthorized);
+    }
+}